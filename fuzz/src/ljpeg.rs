@@ -0,0 +1,13 @@
+#[macro_use]
+extern crate afl;
+extern crate rawloader;
+
+use rawloader::ljpeg::LjpegDecompressor;
+
+fn main() {
+  fuzz_nohook!(|data: &[u8]| {
+    if let Ok(decompressor) = LjpegDecompressor::new(data) {
+      decompressor.decode_image().ok();
+    }
+  });
+}
@@ -0,0 +1,9 @@
+#[macro_use]
+extern crate afl;
+extern crate rawloader;
+
+fn main() {
+  fuzz_nohook!(|data: &[u8]| {
+    rawloader::tiff::TiffIFD::parse(data).ok();
+  });
+}
@@ -0,0 +1,174 @@
+#ifndef RAWLOADER_H
+#define RAWLOADER_H
+
+/* Generated with cbindgen:0.29.4 */
+
+/* Generated with cbindgen from src/capi.rs, don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Result of the functions that can fail
+typedef enum RawloaderStatus {
+  // it worked
+  RAWLOADER_OK = 0,
+  // see `RawLoaderError::Unsupported`
+  RAWLOADER_ERROR_UNSUPPORTED = 1,
+  // see `RawLoaderError::Corrupt`
+  RAWLOADER_ERROR_CORRUPT = 2,
+  // see `RawLoaderError::Io`
+  RAWLOADER_ERROR_IO = 3,
+  // see `RawLoaderError::Unimplemented`
+  RAWLOADER_ERROR_UNIMPLEMENTED = 4,
+  // see `RawLoaderError::LimitExceeded`
+  RAWLOADER_ERROR_LIMIT_EXCEEDED = 5,
+  // see `RawLoaderError::NoSuchImage`
+  RAWLOADER_ERROR_NO_SUCH_IMAGE = 6,
+  // see `RawLoaderError::OutputSize`
+  RAWLOADER_ERROR_OUTPUT_SIZE = 7,
+  // see `RawLoaderError::Cancelled`
+  RAWLOADER_ERROR_CANCELLED = 8,
+  // see `RawLoaderError::TimedOut`
+  RAWLOADER_ERROR_TIMED_OUT = 9,
+  // a pointer was NULL or a path wasn't valid UTF-8
+  RAWLOADER_ERROR_INVALID_ARGUMENT = 10,
+} RawloaderStatus;
+
+// A decoded image, opaque to C
+typedef struct RawloaderImage RawloaderImage;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Decodes the file at `path`, a NUL terminated UTF-8 string, storing the image in `*out`
+//
+// # Safety
+// `path` must be a valid C string and `out` must point to writable memory for a pointer
+enum RawloaderStatus rawloader_decode_file(const char *path, struct RawloaderImage **out);
+
+// Decodes a file held in memory, `len` bytes at `data`, storing the image in `*out`
+//
+// # Safety
+// `data` must point to `len` readable bytes and `out` to writable memory for a pointer
+enum RawloaderStatus rawloader_decode_buffer(const uint8_t *data,
+                                             size_t len,
+                                             struct RawloaderImage **out);
+
+// Frees an image returned by the decode functions, doing nothing for NULL
+//
+// # Safety
+// `image` must come from a decode function and not have been freed before
+void rawloader_image_free(struct RawloaderImage *image);
+
+// Message of the last error in this thread, an empty string if there wasn't one. Valid
+// until the next call that fails in the same thread.
+const char *rawloader_last_error(void);
+
+// Width of the image in pixels
+//
+// # Safety
+// `image` must be a valid image
+size_t rawloader_image_width(const struct RawloaderImage *image);
+
+// Height of the image in pixels
+//
+// # Safety
+// `image` must be a valid image
+size_t rawloader_image_height(const struct RawloaderImage *image);
+
+// Number of components per pixel, 1 for bayer images and 3 for linear ones
+//
+// # Safety
+// `image` must be a valid image
+size_t rawloader_image_cpp(const struct RawloaderImage *image);
+
+// Integer image data, storing the number of values in `*len`. NULL if the data is floating
+// point, see `rawloader_image_data_f32()`.
+//
+// # Safety
+// `image` must be a valid image and `len` NULL or writable
+const uint16_t *rawloader_image_data_u16(const struct RawloaderImage *image, size_t *len);
+
+// Floating point image data, storing the number of values in `*len`. NULL if the data is
+// integer, see `rawloader_image_data_u16()`.
+//
+// # Safety
+// `image` must be a valid image and `len` NULL or writable
+const float *rawloader_image_data_f32(const struct RawloaderImage *image, size_t *len);
+
+// Make of the camera as found in the file
+//
+// # Safety
+// `image` must be a valid image
+const char *rawloader_image_make(const struct RawloaderImage *image);
+
+// Model of the camera as found in the file
+//
+// # Safety
+// `image` must be a valid image
+const char *rawloader_image_model(const struct RawloaderImage *image);
+
+// Make of the camera cleaned up to be consistent and short
+//
+// # Safety
+// `image` must be a valid image
+const char *rawloader_image_clean_make(const struct RawloaderImage *image);
+
+// Model of the camera cleaned up to be consistent and short
+//
+// # Safety
+// `image` must be a valid image
+const char *rawloader_image_clean_model(const struct RawloaderImage *image);
+
+// CFA pattern as a string like "RGGB", empty for images that aren't bayer
+//
+// # Safety
+// `image` must be a valid image
+const char *rawloader_image_cfa(const struct RawloaderImage *image);
+
+// White balance coefficients of the image, stored in `out`
+//
+// # Safety
+// `image` must be a valid image and `out` writable for 4 values
+void rawloader_image_wb_coeffs(const struct RawloaderImage *image, float *out);
+
+// Black levels of the image, stored in `out`
+//
+// # Safety
+// `image` must be a valid image and `out` writable for 4 values
+void rawloader_image_blacklevels(const struct RawloaderImage *image, uint16_t *out);
+
+// White levels of the image, stored in `out`
+//
+// # Safety
+// `image` must be a valid image and `out` writable for 4 values
+void rawloader_image_whitelevels(const struct RawloaderImage *image, uint16_t *out);
+
+// Matrix to convert XYZ to camera RGBE, 4 rows of 3 values stored in `out`
+//
+// # Safety
+// `image` must be a valid image and `out` writable for 12 values
+void rawloader_image_xyz_to_cam(const struct RawloaderImage *image, float *out);
+
+// How much to crop the image to get rid of the borders, as top, right, bottom and left
+// stored in `out`
+//
+// # Safety
+// `image` must be a valid image and `out` writable for 4 values
+void rawloader_image_crops(const struct RawloaderImage *image, size_t *out);
+
+// Orientation of the image as the values of the EXIF tag, 0 if it's unknown
+//
+// # Safety
+// `image` must be a valid image
+uint16_t rawloader_image_orientation(const struct RawloaderImage *image);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RAWLOADER_H */
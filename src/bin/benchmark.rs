@@ -28,7 +28,7 @@ fn main() {
   };
   let buffer = match rawloader::Buffer::new(&mut f) {
     Ok(val) => val,
-    Err(e) => {error(&e); return},
+    Err(e) => {error(&e.to_string()); return},
   };
   let rawloader = rawloader::RawLoader::new();
   let from_time = Instant::now();
@@ -36,11 +36,11 @@ fn main() {
     for _ in 0..ITERATIONS {
       let decoder = match rawloader.get_decoder(&buffer) {
         Ok(val) => val,
-        Err(e) => {error(&e); return},
+        Err(e) => {error(&e.to_string()); return},
       };
       match decoder.image(false) {
         Ok(_) => {},
-        Err(e) => error(&e),
+        Err(e) => error(&e.to_string()),
       }
     }
   }
@@ -0,0 +1,350 @@
+//! C API, for linking rawloader into C and C++ applications and for the bindings of other
+//! languages. Needs the `capi` feature, and building the crate as a library C can link to:
+//!
+//! ```sh
+//! cargo rustc --lib --release --features capi --crate-type cdylib
+//! ```
+//!
+//! The declarations are in `include/rawloader.h`, generated from this module with `cbindgen`:
+//!
+//! ```sh
+//! cbindgen --config cbindgen.toml --output include/rawloader.h
+//! ```
+//!
+//! # Example
+//! ```c
+//! #include "rawloader.h"
+//!
+//! RawloaderImage *image;
+//! if (rawloader_decode_file("photo.nef", &image) != RAWLOADER_OK) {
+//!   fprintf(stderr, "%s\n", rawloader_last_error());
+//!   return 1;
+//! }
+//! size_t len;
+//! const uint16_t *data = rawloader_image_data_u16(image, &len);
+//! printf("%s %s %zux%zu\n", rawloader_image_make(image), rawloader_image_model(image),
+//!        rawloader_image_width(image), rawloader_image_height(image));
+//! rawloader_image_free(image);
+//! ```
+//!
+//! Images are owned by the caller and freed with `rawloader_image_free()`. The pointers the
+//! accessors return point into the image and are valid until it's freed.
+
+#![allow(unsafe_code)]
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+
+use crate::{RawImage, RawImageData, RawLoaderError, LOADER};
+
+/// Result of the functions that can fail
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RawloaderStatus {
+  /// it worked
+  RawloaderOk = 0,
+  /// see `RawLoaderError::Unsupported`
+  RawloaderErrorUnsupported = 1,
+  /// see `RawLoaderError::Corrupt`
+  RawloaderErrorCorrupt = 2,
+  /// see `RawLoaderError::Io`
+  RawloaderErrorIo = 3,
+  /// see `RawLoaderError::Unimplemented`
+  RawloaderErrorUnimplemented = 4,
+  /// see `RawLoaderError::LimitExceeded`
+  RawloaderErrorLimitExceeded = 5,
+  /// see `RawLoaderError::NoSuchImage`
+  RawloaderErrorNoSuchImage = 6,
+  /// see `RawLoaderError::OutputSize`
+  RawloaderErrorOutputSize = 7,
+  /// see `RawLoaderError::Cancelled`
+  RawloaderErrorCancelled = 8,
+  /// see `RawLoaderError::TimedOut`
+  RawloaderErrorTimedOut = 9,
+  /// a pointer was NULL or a path wasn't valid UTF-8
+  RawloaderErrorInvalidArgument = 10,
+}
+
+/// A decoded image, opaque to C
+#[derive(Debug)]
+pub struct RawloaderImage {
+  image: RawImage,
+  make: CString,
+  model: CString,
+  clean_make: CString,
+  clean_model: CString,
+  cfa: CString,
+}
+
+thread_local! {
+  static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn status(err: &RawLoaderError) -> RawloaderStatus {
+  match err {
+    RawLoaderError::Unsupported { .. } => RawloaderStatus::RawloaderErrorUnsupported,
+    RawLoaderError::Corrupt(_) => RawloaderStatus::RawloaderErrorCorrupt,
+    RawLoaderError::Io(_) => RawloaderStatus::RawloaderErrorIo,
+    RawLoaderError::Unimplemented(_) => RawloaderStatus::RawloaderErrorUnimplemented,
+    RawLoaderError::LimitExceeded(_) => RawloaderStatus::RawloaderErrorLimitExceeded,
+    RawLoaderError::NoSuchImage { .. } => RawloaderStatus::RawloaderErrorNoSuchImage,
+    RawLoaderError::OutputSize { .. } => RawloaderStatus::RawloaderErrorOutputSize,
+    RawLoaderError::Cancelled => RawloaderStatus::RawloaderErrorCancelled,
+    RawLoaderError::TimedOut(_) => RawloaderStatus::RawloaderErrorTimedOut,
+  }
+}
+
+fn set_error(msg: String) {
+  let msg = CString::new(msg.replace('\0', "")).unwrap_or_default();
+  LAST_ERROR.with(|e| *e.borrow_mut() = msg);
+}
+
+fn cstring(s: &str) -> CString {
+  CString::new(s.replace('\0', "")).unwrap_or_default()
+}
+
+// Hands the result of a decode over to C
+fn finish(result: Result<RawImage, RawLoaderError>, out: *mut *mut RawloaderImage) -> RawloaderStatus {
+  match result {
+    Ok(image) => {
+      let image = Box::new(RawloaderImage {
+        make: cstring(&image.make),
+        model: cstring(&image.model),
+        clean_make: cstring(&image.clean_make),
+        clean_model: cstring(&image.clean_model),
+        cfa: cstring(&image.cfa.name),
+        image,
+      });
+      // Safety: checked for NULL by the callers
+      unsafe { *out = Box::into_raw(image) };
+      RawloaderStatus::RawloaderOk
+    },
+    Err(err) => {
+      set_error(err.to_string());
+      status(&err)
+    },
+  }
+}
+
+fn invalid(msg: &str) -> RawloaderStatus {
+  set_error(msg.to_string());
+  RawloaderStatus::RawloaderErrorInvalidArgument
+}
+
+/// Decodes the file at `path`, a NUL terminated UTF-8 string, storing the image in `*out`
+///
+/// # Safety
+/// `path` must be a valid C string and `out` must point to writable memory for a pointer
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_decode_file(path: *const c_char, out: *mut *mut RawloaderImage) -> RawloaderStatus {
+  if path.is_null() || out.is_null() {
+    return invalid("path and out can't be NULL")
+  }
+  let path = match CStr::from_ptr(path).to_str() {
+    Ok(path) => path,
+    Err(_) => return invalid("path isn't valid UTF-8"),
+  };
+  finish(LOADER.decode_file(Path::new(path)), out)
+}
+
+/// Decodes a file held in memory, `len` bytes at `data`, storing the image in `*out`
+///
+/// # Safety
+/// `data` must point to `len` readable bytes and `out` to writable memory for a pointer
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_decode_buffer(data: *const u8, len: usize, out: *mut *mut RawloaderImage) -> RawloaderStatus {
+  if data.is_null() || out.is_null() {
+    return invalid("data and out can't be NULL")
+  }
+  finish(LOADER.decode_slice(slice::from_raw_parts(data, len)), out)
+}
+
+/// Frees an image returned by the decode functions, doing nothing for NULL
+///
+/// # Safety
+/// `image` must come from a decode function and not have been freed before
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_free(image: *mut RawloaderImage) {
+  if !image.is_null() {
+    drop(Box::from_raw(image));
+  }
+}
+
+/// Message of the last error in this thread, an empty string if there wasn't one. Valid
+/// until the next call that fails in the same thread.
+#[no_mangle]
+pub extern "C" fn rawloader_last_error() -> *const c_char {
+  LAST_ERROR.with(|e| e.borrow().as_ptr())
+}
+
+// Safety: all accessors take an image from a decode function that hasn't been freed
+unsafe fn get<'a>(image: *const RawloaderImage) -> &'a RawloaderImage {
+  &*image
+}
+
+/// Width of the image in pixels
+///
+/// # Safety
+/// `image` must be a valid image
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_width(image: *const RawloaderImage) -> usize {
+  get(image).image.width
+}
+
+/// Height of the image in pixels
+///
+/// # Safety
+/// `image` must be a valid image
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_height(image: *const RawloaderImage) -> usize {
+  get(image).image.height
+}
+
+/// Number of components per pixel, 1 for bayer images and 3 for linear ones
+///
+/// # Safety
+/// `image` must be a valid image
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_cpp(image: *const RawloaderImage) -> usize {
+  get(image).image.cpp
+}
+
+/// Integer image data, storing the number of values in `*len`. NULL if the data is floating
+/// point, see `rawloader_image_data_f32()`.
+///
+/// # Safety
+/// `image` must be a valid image and `len` NULL or writable
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_data_u16(image: *const RawloaderImage, len: *mut usize) -> *const u16 {
+  let (data, size) = match get(image).image.data {
+    RawImageData::Integer(ref data) => (data.as_ptr(), data.len()),
+    RawImageData::Float(_) => (ptr::null(), 0),
+  };
+  if !len.is_null() {
+    *len = size;
+  }
+  data
+}
+
+/// Floating point image data, storing the number of values in `*len`. NULL if the data is
+/// integer, see `rawloader_image_data_u16()`.
+///
+/// # Safety
+/// `image` must be a valid image and `len` NULL or writable
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_data_f32(image: *const RawloaderImage, len: *mut usize) -> *const f32 {
+  let (data, size) = match get(image).image.data {
+    RawImageData::Float(ref data) => (data.as_ptr(), data.len()),
+    RawImageData::Integer(_) => (ptr::null(), 0),
+  };
+  if !len.is_null() {
+    *len = size;
+  }
+  data
+}
+
+/// Make of the camera as found in the file
+///
+/// # Safety
+/// `image` must be a valid image
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_make(image: *const RawloaderImage) -> *const c_char {
+  get(image).make.as_ptr()
+}
+
+/// Model of the camera as found in the file
+///
+/// # Safety
+/// `image` must be a valid image
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_model(image: *const RawloaderImage) -> *const c_char {
+  get(image).model.as_ptr()
+}
+
+/// Make of the camera cleaned up to be consistent and short
+///
+/// # Safety
+/// `image` must be a valid image
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_clean_make(image: *const RawloaderImage) -> *const c_char {
+  get(image).clean_make.as_ptr()
+}
+
+/// Model of the camera cleaned up to be consistent and short
+///
+/// # Safety
+/// `image` must be a valid image
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_clean_model(image: *const RawloaderImage) -> *const c_char {
+  get(image).clean_model.as_ptr()
+}
+
+/// CFA pattern as a string like "RGGB", empty for images that aren't bayer
+///
+/// # Safety
+/// `image` must be a valid image
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_cfa(image: *const RawloaderImage) -> *const c_char {
+  get(image).cfa.as_ptr()
+}
+
+/// White balance coefficients of the image, stored in `out`
+///
+/// # Safety
+/// `image` must be a valid image and `out` writable for 4 values
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_wb_coeffs(image: *const RawloaderImage, out: *mut f32) {
+  ptr::copy_nonoverlapping(get(image).image.wb_coeffs.as_ptr(), out, 4);
+}
+
+/// Black levels of the image, stored in `out`
+///
+/// # Safety
+/// `image` must be a valid image and `out` writable for 4 values
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_blacklevels(image: *const RawloaderImage, out: *mut u16) {
+  ptr::copy_nonoverlapping(get(image).image.blacklevels.as_ptr(), out, 4);
+}
+
+/// White levels of the image, stored in `out`
+///
+/// # Safety
+/// `image` must be a valid image and `out` writable for 4 values
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_whitelevels(image: *const RawloaderImage, out: *mut u16) {
+  ptr::copy_nonoverlapping(get(image).image.whitelevels.as_ptr(), out, 4);
+}
+
+/// Matrix to convert XYZ to camera RGBE, 4 rows of 3 values stored in `out`
+///
+/// # Safety
+/// `image` must be a valid image and `out` writable for 12 values
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_xyz_to_cam(image: *const RawloaderImage, out: *mut f32) {
+  for (i, row) in get(image).image.xyz_to_cam.iter().enumerate() {
+    ptr::copy_nonoverlapping(row.as_ptr(), out.add(i*3), 3);
+  }
+}
+
+/// How much to crop the image to get rid of the borders, as top, right, bottom and left
+/// stored in `out`
+///
+/// # Safety
+/// `image` must be a valid image and `out` writable for 4 values
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_crops(image: *const RawloaderImage, out: *mut usize) {
+  ptr::copy_nonoverlapping(get(image).image.crops.as_ptr(), out, 4);
+}
+
+/// Orientation of the image as the values of the EXIF tag, 0 if it's unknown
+///
+/// # Safety
+/// `image` must be a valid image
+#[no_mangle]
+pub unsafe extern "C" fn rawloader_image_orientation(image: *const RawloaderImage) -> u16 {
+  get(image).image.orientation.to_u16()
+}
@@ -23,22 +23,26 @@ impl<'a> AriDecoder<'a> {
 }
 
 impl<'a> Decoder for AriDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "ARI"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let offset = LEu32(self.buffer, 8) as usize;
     let width = LEu32(self.buffer, 20) as usize;
     let height = LEu32(self.buffer, 24) as usize;
     let model = String::from_utf8_lossy(&self.buffer[668..]).split_terminator("\0").next().unwrap_or("").to_string();
     let camera = self.rawloader.check_supported_with_everything("ARRI", &model, "")?;
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
 
-    let image = decode_12be_msb32(src, width, height, dummy);
+    let image = decode_12be_msb32(src, width, height, dummy)?;
 
     ok_image(camera, width, height, self.get_wb()?, image)
   }
 }
 
 impl<'a> AriDecoder<'a> {
-  fn get_wb(&self) -> Result<[f32;4], String> {
+  fn get_wb(&self) -> Result<[f32;4], RawLoaderError> {
     Ok([LEf32(self.buffer, 100), LEf32(self.buffer, 104), LEf32(self.buffer, 108), NAN])
   }
 }
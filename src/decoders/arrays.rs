@@ -0,0 +1,47 @@
+use ::ndarray::{s, ArrayView2, ArrayView3};
+
+use crate::decoders::*;
+
+/// A view of the image data as an `ndarray` of rows, columns and components, holding the
+/// values in the type they were decoded as
+#[derive(Debug, Clone)]
+pub enum RawArrayView<'a> {
+  /// view of `RawImageData::Integer` data
+  Integer(ArrayView3<'a, u16>),
+  /// view of `RawImageData::Float` data
+  Float(ArrayView3<'a, f32>),
+}
+
+impl<'a> RawArrayView<'a> {
+  /// Shape of the view as (rows, columns, components)
+  pub fn dim(&self) -> (usize, usize, usize) {
+    match self {
+      RawArrayView::Integer(view) => view.dim(),
+      RawArrayView::Float(view) => view.dim(),
+    }
+  }
+}
+
+fn view<'a, T>(image: &RawImage, data: &'a [T]) -> Result<ArrayView3<'a, T>, RawLoaderError> {
+  let area = image.areas().active_area;
+  let full = ArrayView3::from_shape((image.height, image.width, image.cpp), data)
+    .map_err(|_| RawLoaderError::Corrupt("image has no data to view".to_string()))?;
+  Ok(full.slice_move(s![area.y..area.y+area.height, area.x..area.x+area.width, ..]))
+}
+
+pub(crate) fn array(image: &RawImage) -> Result<RawArrayView<'_>, RawLoaderError> {
+  match image.data {
+    RawImageData::Integer(ref data) => Ok(RawArrayView::Integer(view(image, data)?)),
+    RawImageData::Float(ref data) => Ok(RawArrayView::Float(view(image, data)?)),
+  }
+}
+
+pub(crate) fn cfa(image: &RawImage) -> Result<ArrayView2<'_, u16>, RawLoaderError> {
+  if image.cpp != 1 {
+    return Err(RawLoaderError::Unimplemented(format!("CFA view of an image with {} components per pixel", image.cpp)))
+  }
+  match image.data {
+    RawImageData::Integer(ref data) => Ok(view(image, data)?.index_axis_move(::ndarray::Axis(2), 0)),
+    RawImageData::Float(_) => Err(RawLoaderError::Unimplemented("CFA view of floating point data".to_string())),
+  }
+}
@@ -3,6 +3,7 @@ use std::cmp;
 
 use crate::decoders::*;
 use crate::decoders::tiff::*;
+use crate::decoders::makernotes::Makernote;
 use crate::decoders::basics::*;
 
 #[derive(Debug, Clone)]
@@ -23,8 +24,12 @@ impl<'a> ArwDecoder<'a> {
 }
 
 impl<'a> Decoder for ArwDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
-    let camera = self.rawloader.check_supported(&self.tiff)?;
+  fn format(&self) -> &'static str {
+    "ARW"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
+    let mut camera = self.rawloader.check_supported(&self.tiff)?;
     let data = self.tiff.find_ifds_with_tag(Tag::StripOffsets);
     if data.len() == 0 {
       if camera.model == "DSLR-A100" {
@@ -46,25 +51,25 @@ impl<'a> Decoder for ArwDecoder<'a> {
     };
     let mut white = camera.whitelevels[0];
     let mut black = camera.blacklevels[0];
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
 
     let image = match compression {
       1 => {
         if camera.model == "DSC-R1" {
-          decode_14be_unpacked(src, width, height, dummy)
+          decode_14be_unpacked(src, width, height, dummy)?
         } else {
-          decode_16le(src, width, height, dummy)
+          decode_16le(src, width, height, dummy)?
         }
       }
       32767 => {
         if (width*height*bps) != count*8 {
           height += 8;
-          ArwDecoder::decode_arw1(src, width, height, dummy)
+          ArwDecoder::decode_arw1(src, width, height, dummy)?
         } else {
           match bps {
             8 => {
               let curve = ArwDecoder::get_curve(raw)?;
-              ArwDecoder::decode_arw2(src, width, height, &curve, dummy)
+              ArwDecoder::decode_arw2(src, width, height, &curve, dummy)?
             },
             12 => {
               /*
@@ -77,39 +82,40 @@ impl<'a> Decoder for ArwDecoder<'a> {
               */
               white >>= 2;
               black >>= 2;
-              decode_12le(src, width, height, dummy)
+              decode_12le(src, width, height, dummy)?
             },
-            _ => return Err(format!("ARW2: Don't know how to decode images with {} bps", bps)),
+            _ => return Err(RawLoaderError::Unimplemented(format!("ARW2: Don't know how to decode images with {} bps", bps))),
           }
         }
       },
-      _ => return Err(format!("ARW: Don't know how to decode type {}", compression).to_string()),
+      _ => return Err(RawLoaderError::Unimplemented(format!("ARW: Don't know how to decode type {}", compression))),
     };
 
-    ok_image_with_black_white(camera, width, height, self.get_wb()?, black, white, image)
+    let wb = self.get_wb(&mut camera)?;
+    ok_image_with_black_white(camera, width, height, wb, black, white, image)
   }
 }
 
 impl<'a> ArwDecoder<'a> {
-  fn image_a100(&self, camera: Camera, dummy: bool) -> Result<RawImage,String> {
+  fn image_a100(&self, camera: Camera, dummy: bool) -> Result<RawImage, RawLoaderError> {
     // We've caught the elusive A100 in the wild, a transitional format
     // between the simple sanity of the MRW custom format and the wordly
     // wonderfullness of the Tiff-based ARW format, let's shoot from the hip
     let data = self.tiff.find_ifds_with_tag(Tag::SubIFDs);
     if data.len() == 0 {
-      return Err("ARW: Couldn't find the data IFD!".to_string())
+      return Err(RawLoaderError::Corrupt("ARW: Couldn't find the data IFD!".to_string()))
     }
     let raw = data[0];
     let width = 3881;
     let height = 2608;
     let offset = fetch_tag!(raw, Tag::SubIFDs).get_usize(0);
 
-    let src = &self.buffer[offset..];
-    let image = ArwDecoder::decode_arw1(src, width, height, dummy);
+    let src = rest(self.buffer, offset);
+    let image = ArwDecoder::decode_arw1(src, width, height, dummy)?;
 
     // Get the WB the MRW way
     let priv_offset = fetch_tag!(self.tiff, Tag::DNGPrivateArea).get_force_u32(0) as usize;
-    let buf = &self.buffer[priv_offset..];
+    let buf = rest(self.buffer, priv_offset);
     let mut currpos: usize = 8;
     let mut wb_coeffs: [f32;4] = [0.0, 0.0, 0.0, NAN];
     // At most we read 20 bytes from currpos so check we don't step outside that
@@ -128,10 +134,10 @@ impl<'a> ArwDecoder<'a> {
     ok_image(camera, width, height, wb_coeffs, image)
   }
 
-  fn image_srf(&self, camera: Camera, dummy: bool) -> Result<RawImage,String> {
+  fn image_srf(&self, camera: Camera, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let data = self.tiff.find_ifds_with_tag(Tag::ImageWidth);
     if data.len() == 0 {
-      return Err("ARW: Couldn't find the data IFD!".to_string())
+      return Err(RawLoaderError::Corrupt("ARW: Couldn't find the data IFD!".to_string()))
     }
     let raw = data[0];
 
@@ -156,14 +162,14 @@ impl<'a> ArwDecoder<'a> {
 
       // "Decrypt" the whole image buffer
       let image_data = ArwDecoder::sony_decrypt(self.buffer, off, len, second_key);
-      decode_16be(&image_data, width, height, dummy)
+      decode_16be(&image_data, width, height, dummy)?
     };
 
     ok_image(camera, width, height, [NAN,NAN,NAN,NAN], image)
   }
 
-  pub(crate) fn decode_arw1(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
-    let mut out: Vec<u16> = alloc_image!(width, height, dummy);
+  pub(crate) fn decode_arw1(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+    let mut out: Vec<u16> = alloc_image_ok!(width, height, dummy);
     let mut pump = BitPumpMSB::new(buf);
 
     let mut sum: i32 = 0;
@@ -192,10 +198,10 @@ impl<'a> ArwDecoder<'a> {
         row += 2
       }
     }
-    out
+    Ok(out)
   }
 
-  pub(crate) fn decode_arw2(buf: &[u8], width: usize, height: usize, curve: &LookupTable, dummy: bool) -> Vec<u16> {
+  pub(crate) fn decode_arw2(buf: &[u8], width: usize, height: usize, curve: &LookupTable, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     decode_threaded(width, height, dummy, &(|out: &mut [u16], row| {
       let mut pump = BitPumpLSB::new(&buf[(row*width)..]);
 
@@ -227,14 +233,18 @@ impl<'a> ArwDecoder<'a> {
     }))
   }
 
-  fn get_wb(&self) -> Result<[f32;4], String> {
+  fn get_wb(&self, camera: &mut Camera) -> Result<[f32;4], RawLoaderError> {
     let priv_offset = fetch_tag!(self.tiff, Tag::DNGPrivateArea).get_force_u32(0) as usize;
     let priv_tiff = TiffIFD::new(self.buffer, priv_offset, 0, 0, 0, LITTLE_ENDIAN)?;
     let sony_offset = fetch_tag!(priv_tiff, Tag::SonyOffset).get_usize(0);
     let sony_length = fetch_tag!(priv_tiff, Tag::SonyLength).get_usize(0);
     let sony_key = fetch_tag!(priv_tiff, Tag::SonyKey).get_u32(0);
     let decrypted_buf = ArwDecoder::sony_decrypt(self.buffer, sony_offset, sony_length, sony_key);
-    let decrypted_tiff = TiffIFD::new(&decrypted_buf, 0, sony_offset, 0, 0, LITTLE_ENDIAN).unwrap();
+    let decrypted_tiff = TiffIFD::new_unfiltered(&decrypted_buf, 0, sony_offset, 0, LITTLE_ENDIAN).unwrap();
+    if let Some(Makernote::Sony(ref mut sony)) = camera.exif.makernote {
+      sony.parse_sr2(&decrypted_tiff);
+      camera.exif.wb_presets = sony.wb_presets.clone();
+    }
     let grgb_levels = decrypted_tiff.find_entry(Tag::SonyGRBG);
     let rggb_levels = decrypted_tiff.find_entry(Tag::SonyRGGB);
     if grgb_levels.is_some() {
@@ -244,11 +254,11 @@ impl<'a> ArwDecoder<'a> {
       let levels = rggb_levels.unwrap();
       Ok([levels.get_u32(0) as f32, levels.get_u32(1) as f32, levels.get_u32(3) as f32, NAN])
     } else {
-      Err("ARW: Couldn't find GRGB or RGGB levels".to_string())
+      Err(RawLoaderError::Corrupt("ARW: Couldn't find GRGB or RGGB levels".to_string()))
     }
   }
 
-  fn get_curve(raw: &TiffIFD) -> Result<LookupTable, String> {
+  fn get_curve(raw: &TiffIFD) -> Result<LookupTable, RawLoaderError> {
     let centry = fetch_tag!(raw, Tag::SonyCurve);
     let mut curve: [usize;6] = [ 0, 0, 0, 0, 0, 4095 ];
 
@@ -1,5 +1,9 @@
 use byteorder::{BigEndian, LittleEndian, ByteOrder};
 use rayon::prelude::*;
+use std::panic;
+use std::sync::Mutex;
+
+use crate::decoders::RawLoaderError;
 
 pub use crate::decoders::packed::*;
 pub use crate::decoders::pumps::*;
@@ -46,58 +50,127 @@ impl Endian {
     }
   }
 
+  pub fn wu16(&self, val: u16) -> [u8;2] {
+    if self.big { val.to_be_bytes() } else { val.to_le_bytes() }
+  }
+
+  pub fn wu32(&self, val: u32) -> [u8;4] {
+    if self.big { val.to_be_bytes() } else { val.to_le_bytes() }
+  }
+
   pub fn little(&self) -> bool { !self.big }
 }
 
 pub static BIG_ENDIAN: Endian = Endian{big: true};
 pub static LITTLE_ENDIAN: Endian = Endian{big: false};
 
+// The bytes of a value, reading past the end of the data failing the decode as corrupt
+#[inline(always)]
+fn bytes(buf: &[u8], pos: usize, len: usize) -> &[u8] {
+  match pos.checked_add(len).and_then(|end| buf.get(pos..end)) {
+    Some(bytes) => bytes,
+    None => crate::decoders::corrupt("Tried to read past the end of the data"),
+  }
+}
+
+// The data from an offset on, failing the decode as corrupt when the data ends before it
+pub fn rest(buf: &[u8], offset: usize) -> &[u8] {
+  match buf.get(offset..) {
+    Some(rest) => rest,
+    None => crate::decoders::corrupt("Image data ends before the image does"),
+  }
+}
+
 #[allow(non_snake_case)] #[inline] pub fn BEi32(buf: &[u8], pos: usize) -> i32 {
-  BigEndian::read_i32(&buf[pos..pos+4])
+  BigEndian::read_i32(bytes(buf, pos, 4))
 }
 
 #[allow(non_snake_case)] #[inline] pub fn LEi32(buf: &[u8], pos: usize) -> i32 {
-  LittleEndian::read_i32(&buf[pos..pos+4])
+  LittleEndian::read_i32(bytes(buf, pos, 4))
 }
 
 #[allow(non_snake_case)] #[inline] pub fn BEu32(buf: &[u8], pos: usize) -> u32 {
-  BigEndian::read_u32(&buf[pos..pos+4])
+  BigEndian::read_u32(bytes(buf, pos, 4))
 }
 
 #[allow(non_snake_case)] #[inline] pub fn LEu32(buf: &[u8], pos: usize) -> u32 {
-  LittleEndian::read_u32(&buf[pos..pos+4])
+  LittleEndian::read_u32(bytes(buf, pos, 4))
 }
 
 #[allow(non_snake_case)] #[inline] pub fn LEf32(buf: &[u8], pos: usize) -> f32 {
-  LittleEndian::read_f32(&buf[pos..pos+4])
+  LittleEndian::read_f32(bytes(buf, pos, 4))
 }
 
 #[allow(non_snake_case)] #[inline] pub fn BEu16(buf: &[u8], pos: usize) -> u16 {
-  BigEndian::read_u16(&buf[pos..pos+2])
+  BigEndian::read_u16(bytes(buf, pos, 2))
 }
 
 #[allow(non_snake_case)] #[inline] pub fn LEu16(buf: &[u8], pos: usize) -> u16 {
-  LittleEndian::read_u16(&buf[pos..pos+2])
+  LittleEndian::read_u16(bytes(buf, pos, 2))
 }
 
-pub fn decode_threaded<F>(width: usize, height: usize, dummy: bool, closure: &F) -> Vec<u16>
+pub fn decode_threaded<F>(width: usize, height: usize, dummy: bool, closure: &F) -> Result<Vec<u16>, RawLoaderError>
   where F : Fn(&mut [u16], usize)+Sync {
 
-  let mut out: Vec<u16> = alloc_image!(width, height, dummy);
-    out.par_chunks_mut(width).enumerate().for_each(|(row, line)| {
-    closure(line, row);
+  let mut out: Vec<u16> = alloc_image_ok!(width, height, dummy);
+  let abort = crate::decoders::Abort::current();
+  let failed = crate::decoders::recover_rows().then(|| Mutex::new(None));
+  // Once the decode is aborted the rest of the rows are skipped
+  out.par_chunks_mut(width).enumerate().for_each(|(row, line)| {
+    if abort.reason().is_none() {
+      decode_rows(closure, line, row, failed.as_ref());
+    }
   });
-  out
+  crate::decoders::check_abort()?;
+  failed_rows(failed);
+  Ok(out)
 }
 
-pub fn decode_threaded_multiline<F>(width: usize, height: usize, lines: usize, dummy: bool, closure: &F) -> Vec<u16>
+pub fn decode_threaded_multiline<F>(width: usize, height: usize, lines: usize, dummy: bool, closure: &F) -> Result<Vec<u16>, RawLoaderError>
   where F : Fn(&mut [u16], usize)+Sync {
 
-  let mut out: Vec<u16> = alloc_image!(width, height, dummy);
+  let mut out: Vec<u16> = alloc_image_ok!(width, height, dummy);
+  let abort = crate::decoders::Abort::current();
+  let failed = crate::decoders::recover_rows().then(|| Mutex::new(None));
   out.par_chunks_mut(width*lines).enumerate().for_each(|(row, line)| {
-    closure(line, row*lines);
+    if abort.reason().is_none() {
+      decode_rows(closure, line, row*lines, failed.as_ref());
+    }
   });
-  out
+  crate::decoders::check_abort()?;
+  failed_rows(failed);
+  Ok(out)
+}
+
+// Decodes the rows starting at `row`. In a lenient decode the rows that fail as corrupt,
+// usually because the file ends before them, are left as far as they got instead of failing
+// the decode, and the rows after the first one that failed are skipped.
+fn decode_rows<F>(closure: &F, line: &mut [u16], row: usize, failed: Option<&Mutex<Option<(usize, RawLoaderError)>>>)
+  where F : Fn(&mut [u16], usize)+Sync {
+  let failed = match failed {
+    Some(failed) => failed,
+    None => return closure(line, row),
+  };
+  if failed.lock().unwrap().as_ref().is_some_and(|&(first, _)| first < row) {
+    return
+  }
+  if let Err(payload) = panic::catch_unwind(panic::AssertUnwindSafe(|| closure(line, row))) {
+    match payload.downcast::<RawLoaderError>() {
+      Ok(err) => {
+        let mut failed = failed.lock().unwrap();
+        if failed.as_ref().is_none_or(|&(first, _)| row < first) {
+          *failed = Some((row, *err));
+        }
+      },
+      Err(payload) => panic::resume_unwind(payload),
+    }
+  }
+}
+
+fn failed_rows(failed: Option<Mutex<Option<(usize, RawLoaderError)>>>) {
+  if let Some((_, err)) = failed.and_then(|failed| failed.into_inner().unwrap()) {
+    crate::decoders::fail_rows(err);
+  }
 }
 
 #[derive(Debug, Clone)]
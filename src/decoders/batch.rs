@@ -0,0 +1,19 @@
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+
+use crate::decoders::{RawImage, RawLoaderError};
+
+/// The results of `RawLoader::decode_many()`, in the order the decodes finish rather than the
+/// order of the paths. Dropping it stops the batch once the decodes already running finish.
+#[derive(Debug)]
+pub struct DecodeMany {
+  pub(crate) results: Receiver<(PathBuf, Result<RawImage, RawLoaderError>)>,
+}
+
+impl Iterator for DecodeMany {
+  type Item = (PathBuf, Result<RawImage, RawLoaderError>);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.results.recv().ok()
+  }
+}
@@ -1,21 +1,23 @@
 use std::io::{Read, Seek, SeekFrom, Cursor};
 
+use crate::decoders::RawLoaderError;
+
 #[derive(Debug)]
 pub struct Bmff {
     reader: Cursor<Vec<u8>>,
 }
 
 impl Bmff {
-    pub fn new(data: &[u8]) -> Result<Self, String> {
+    pub fn new(data: &[u8]) -> Result<Self, RawLoaderError> {
         Ok(Bmff {
             reader: Cursor::new(data.to_vec())
         })
     }
 
-    pub fn get_brands(&mut self) -> Result<Vec<[u8; 4]>, String> {
+    pub fn get_brands(&mut self) -> Result<Vec<[u8; 4]>, RawLoaderError> {
         // Reset to start of file
         if self.reader.seek(SeekFrom::Start(0)).is_err() {
-            return Err("Failed to seek to start of file".to_string());
+            return Err(RawLoaderError::Corrupt("Failed to seek to start of file".to_string()));
         }
 
         // Read file type box
@@ -26,12 +28,12 @@ impl Bmff {
         if self.reader.read_exact(&mut size).is_err() ||
            self.reader.read_exact(&mut box_type).is_err() ||
            self.reader.read_exact(&mut major_brand).is_err() {
-            return Err("Failed to read ftyp box header".to_string());
+            return Err(RawLoaderError::Corrupt("Failed to read ftyp box header".to_string()));
         }
 
         // Check if it's a 'ftyp' box
         if &box_type != b"ftyp" {
-            return Err("Not a valid BMFF file (no ftyp box)".to_string());
+            return Err(RawLoaderError::Corrupt("Not a valid BMFF file (no ftyp box)".to_string()));
         }
 
         let mut brands = vec![major_brand];
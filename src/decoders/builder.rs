@@ -0,0 +1,204 @@
+use std::sync::Arc;
+
+use crate::decoders::*;
+
+/// Largest image in pixels a RawLoader decodes unless configured otherwise
+pub const DEFAULT_MAX_PIXELS: usize = 500_000_000;
+
+/// Largest width and height in values a RawLoader decodes unless configured otherwise
+pub const DEFAULT_MAX_DIMENSION: usize = 50_000;
+
+/// Most entries an IFD can have unless configured otherwise
+pub const DEFAULT_MAX_TIFF_ENTRIES: usize = 4000;
+
+// The limits a decode enforces, checked before anything is allocated for the sizes the file
+// claims
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Limits {
+  pub(crate) max_pixels: usize,
+  pub(crate) max_width: usize,
+  pub(crate) max_height: usize,
+  pub(crate) max_output_bytes: Option<usize>,
+  pub(crate) max_tiff_entries: usize,
+}
+
+impl Limits {
+  pub(crate) const DEFAULT: Limits = Limits {
+    max_pixels: DEFAULT_MAX_PIXELS,
+    max_width: DEFAULT_MAX_DIMENSION,
+    max_height: DEFAULT_MAX_DIMENSION,
+    max_output_bytes: None,
+    max_tiff_entries: DEFAULT_MAX_TIFF_ENTRIES,
+  };
+}
+
+/// How much the decoders report on stderr while decoding
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+  /// nothing is printed
+  Quiet,
+  /// problems that were worked around, such as falling back to the main image
+  Warnings,
+  /// details of the parsing, only useful when adding support for a format
+  Debug,
+}
+
+/// How to treat files that don't quite follow their format
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DecodeMode {
+  /// work around the problems that can be recovered from, like truncated makernotes, entries
+  /// pointing outside the file or files only recognized by their size, reporting them as
+  /// warnings
+  #[default]
+  Lenient,
+  /// fail on any of those problems, for tools that validate files
+  Strict,
+}
+
+/// Which image to decode from files that have more than one
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SubImage {
+  /// the main image, which is what every file has
+  Primary,
+  /// the brighter frame of a dual gain readout
+  HighGain,
+  /// the darker frame of a dual gain readout
+  LowGain,
+  /// the image of the left photodiodes of a Dual Pixel raw
+  DualPixelA,
+  /// the image of the right photodiodes of a Dual Pixel raw
+  DualPixelB,
+}
+
+/// Configures a RawLoader, created with `RawLoader::builder()`
+///
+/// # Example
+/// ```rust,no_run
+/// use rawloader::{RawLoader, DecodeOptions, Verbosity};
+///
+/// let loader = RawLoader::builder()
+///   .threads(2)
+///   .strict(true)
+///   .verbosity(Verbosity::Warnings)
+///   .max_file_size(200 << 20)
+///   .options(DecodeOptions { fix_bad_pixels: true, ..Default::default() })
+///   .build();
+/// let image = loader.decode_file(std::path::Path::new("path/to/your/file.RAW")).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct RawLoaderBuilder {
+  threads: Option<usize>,
+  mode: DecodeMode,
+  verbosity: Verbosity,
+  limits: Limits,
+  max_file_size: Option<usize>,
+  sub_image: SubImage,
+  options: DecodeOptions,
+}
+
+impl Default for RawLoaderBuilder {
+  fn default() -> Self {
+    RawLoaderBuilder {
+      threads: None,
+      mode: DecodeMode::Lenient,
+      verbosity: Verbosity::Quiet,
+      limits: Limits::DEFAULT,
+      max_file_size: None,
+      sub_image: SubImage::Primary,
+      options: DecodeOptions::default(),
+    }
+  }
+}
+
+impl RawLoaderBuilder {
+  /// Number of threads to decode with, in a pool of the loader's own. By default the global
+  /// rayon pool is used.
+  pub fn threads(mut self, threads: usize) -> Self {
+    self.threads = Some(threads.max(1));
+    self
+  }
+
+  /// How to treat files that don't quite follow their format, leniently by default. Besides
+  /// the problems in the files themselves a strict loader fails when the `sub_image()` asked
+  /// for isn't there instead of decoding the main image.
+  pub fn mode(mut self, mode: DecodeMode) -> Self {
+    self.mode = mode;
+    self
+  }
+
+  /// Same as `mode(DecodeMode::Strict)` when true and `mode(DecodeMode::Lenient)` otherwise
+  pub fn strict(self, strict: bool) -> Self {
+    self.mode(if strict { DecodeMode::Strict } else { DecodeMode::Lenient })
+  }
+
+  /// What to print on stderr while decoding, nothing by default
+  pub fn verbosity(mut self, verbosity: Verbosity) -> Self {
+    self.verbosity = verbosity;
+    self
+  }
+
+  /// Largest image to decode in pixels, anything bigger fails with
+  /// `RawLoaderError::LimitExceeded` before its memory is allocated.
+  pub fn max_pixels(mut self, max_pixels: usize) -> Self {
+    self.limits.max_pixels = max_pixels;
+    self
+  }
+
+  /// Largest width and height of an image, in values per row and rows, anything bigger fails
+  /// with `RawLoaderError::LimitExceeded` before its memory is allocated. Both are
+  /// `DEFAULT_MAX_DIMENSION` by default.
+  pub fn max_dimensions(mut self, max_width: usize, max_height: usize) -> Self {
+    self.limits.max_width = max_width;
+    self.limits.max_height = max_height;
+    self
+  }
+
+  /// Largest image data to allocate in bytes, anything bigger fails with
+  /// `RawLoaderError::LimitExceeded`, unlimited by default other than by `max_pixels()`
+  pub fn max_output_bytes(mut self, max_output_bytes: usize) -> Self {
+    self.limits.max_output_bytes = Some(max_output_bytes);
+    self
+  }
+
+  /// Most entries a TIFF IFD can have, files with more fail with
+  /// `RawLoaderError::LimitExceeded`. It's `DEFAULT_MAX_TIFF_ENTRIES` by default.
+  pub fn max_tiff_entries(mut self, max_tiff_entries: usize) -> Self {
+    self.limits.max_tiff_entries = max_tiff_entries;
+    self
+  }
+
+  /// Largest file to read in bytes, unlimited by default
+  pub fn max_file_size(mut self, max_file_size: usize) -> Self {
+    self.max_file_size = Some(max_file_size);
+    self
+  }
+
+  /// Image to decode from files that have more than one, the main one by default
+  pub fn sub_image(mut self, sub_image: SubImage) -> Self {
+    self.sub_image = sub_image;
+    self
+  }
+
+  /// Processing to do on the images decoded by the methods that don't take their own
+  /// DecodeOptions
+  pub fn options(mut self, options: DecodeOptions) -> Self {
+    self.options = options;
+    self
+  }
+
+  /// Creates the RawLoader, using the global thread pool if one of the requested size
+  /// can't be created
+  pub fn build(self) -> RawLoader {
+    let mut loader = RawLoader::new();
+    loader.pool = self.threads.and_then(|threads| {
+      rayon::ThreadPoolBuilder::new().num_threads(threads).build().ok().map(Arc::new)
+    });
+    loader.mode = self.mode;
+    loader.verbosity = self.verbosity;
+    loader.limits = self.limits;
+    loader.max_file_size = self.max_file_size;
+    loader.sub_image = self.sub_image;
+    loader.options = self.options;
+    loader
+  }
+}
@@ -0,0 +1,170 @@
+use crate::decoders::*;
+use crate::decoders::cfa::CFAColor;
+
+/// Per unit sensor calibration that medium format backs store in their files, as measured at
+/// the factory. The corrections work on black subtracted values in the range of the
+/// calibration, which is the raw data multiplied by `scale`.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SensorCalibration {
+  /// factor from the raw data to the range the corrections were measured in
+  pub scale: u16,
+  /// black level in the range of the corrections
+  pub black: u16,
+  /// defective pixels and columns listed by the manufacturer
+  pub defects: Vec<SensorDefect>,
+  /// curves mapping black subtracted values to linear ones, in the order they apply
+  pub curves: Vec<CalibrationCurve>,
+  /// gain maps correcting the sensor's sensitivity across the frame
+  pub flat_fields: Vec<FlatField>,
+  /// first column of the right half for sensors read out in two halves
+  pub split_col: Option<usize>,
+  /// first row of the bottom half for sensors read out in two halves
+  pub split_row: Option<usize>,
+  /// black offset of each row, for the columns left and right of `split_col`, to add
+  /// to the data before subtracting `black`
+  pub row_blacks: Vec<[i16;2]>,
+  /// black offset of each column, for the rows above and below `split_row`, to add to
+  /// the data before subtracting `black`
+  pub col_blacks: Vec<[i16;2]>,
+  /// calibration entries whose layout isn't known, as tag and data
+  pub other: Vec<(u32, Vec<u8>)>,
+}
+
+/// A pixel or column of the sensor the manufacturer lists as defective
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SensorDefect {
+  /// column of the defect
+  pub col: u32,
+  /// row of the defect, meaningless when the whole column is bad
+  pub row: u32,
+  /// if the whole column is defective
+  pub column: bool,
+}
+
+/// Lookup table applied from a column on to the right edge of the sensor
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CalibrationCurve {
+  /// first column the curve applies to, 0 for the whole sensor
+  pub first_col: usize,
+  /// output for each input value
+  pub table: Vec<u16>,
+}
+
+/// Gains on a grid of nodes over an area of the sensor, interpolated linearly between them
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FlatField {
+  /// column of the first node
+  pub x: usize,
+  /// row of the first node
+  pub y: usize,
+  /// width of the area covered by the grid
+  pub width: usize,
+  /// height of the area covered by the grid
+  pub height: usize,
+  /// horizontal distance between nodes
+  pub cell_width: usize,
+  /// vertical distance between nodes
+  pub cell_height: usize,
+  /// number of nodes in each row
+  pub columns: usize,
+  /// number of rows of nodes
+  pub rows: usize,
+  /// if there are separate gains for the red and blue pixels, with the green ones left as
+  /// they are, instead of one set for all pixels
+  pub red_blue: bool,
+  /// gain of each node in row major order, with the red and blue ones next to each other
+  /// when `red_blue` is set
+  pub gains: Vec<f32>,
+}
+
+impl FlatField {
+  /// Number of gains per node
+  pub fn planes(&self) -> usize {
+    if self.red_blue { 2 } else { 1 }
+  }
+
+  /// Gain at a pixel of the sensor for a plane (0 for all pixels or red, 1 for blue), None
+  /// outside of the area the grid covers
+  pub fn gain(&self, row: usize, col: usize, plane: usize) -> Option<f32> {
+    if row < self.y || col < self.x || plane >= self.planes() || self.cell_width == 0 ||
+       self.cell_height == 0 || self.columns < 2 || self.rows < 2 {
+      return None
+    }
+    let (fx, fy) = ((col - self.x) as f32 / self.cell_width as f32, (row - self.y) as f32 / self.cell_height as f32);
+    if fx > (self.columns - 1) as f32 || fy > (self.rows - 1) as f32 {
+      return None
+    }
+    let (x0, y0) = ((fx as usize).min(self.columns - 2), (fy as usize).min(self.rows - 2));
+    let (dx, dy) = (fx - x0 as f32, fy - y0 as f32);
+    let node = |x: usize, y: usize| self.gains[(y*self.columns + x)*self.planes() + plane];
+    let top = node(x0, y0) * (1.0 - dx) + node(x0+1, y0) * dx;
+    let bottom = node(x0, y0+1) * (1.0 - dx) + node(x0+1, y0+1) * dx;
+    Some(top * (1.0 - dy) + bottom * dy)
+  }
+}
+
+// Black offsets, curves and flat fields are done in one pass in the range of the calibration
+// and then the defects are interpolated over. `other` is all that's left afterwards.
+pub(crate) fn apply(image: &mut RawImage) {
+  let calib = match image.calibration.take() {
+    Some(calib) => calib,
+    None => return,
+  };
+  if image.cpp != 1 || image.data_len() != image.width*image.height {
+    image.calibration = Some(calib);
+    return
+  }
+
+  let scale = calib.scale.max(1) as f32;
+  let (red, blue) = (image.cfa.color_index(CFAColor::Red), image.cfa.color_index(CFAColor::Blue));
+  for row in 0..image.height {
+    for col in 0..image.width {
+      let idx = row*image.width + col;
+      let color = image.color_for_index(idx);
+      let mut x = image.data.get_f32(idx) * scale - calib.black as f32;
+      if let Some(blacks) = calib.row_blacks.get(row) {
+        x += blacks[calib.split_col.is_some_and(|split| col >= split) as usize] as f32;
+      }
+      if let Some(blacks) = calib.col_blacks.get(col) {
+        x += blacks[calib.split_row.is_some_and(|split| row >= split) as usize] as f32;
+      }
+      for curve in calib.curves.iter().filter(|c| col >= c.first_col && !c.table.is_empty()) {
+        x = curve.table[(x.max(0.0) as usize).min(curve.table.len() - 1)] as f32;
+      }
+      for field in &calib.flat_fields {
+        let plane = if !field.red_blue || Some(color) == red {
+          Some(0)
+        } else if Some(color) == blue {
+          Some(1)
+        } else {
+          None
+        };
+        if let Some(gain) = plane.and_then(|plane| field.gain(row, col, plane)) {
+          x *= gain;
+        }
+      }
+      image.data.set_f32(idx, x / scale + image.blacklevels[color] as f32);
+    }
+  }
+
+  let mut pixels = Vec::new();
+  for defect in &calib.defects {
+    if defect.column {
+      pixels.extend((0..image.height as u32).map(|row| (row, defect.col)));
+    } else {
+      pixels.push((defect.row, defect.col));
+    }
+  }
+  image.interpolate_pixels(&pixels);
+
+  image.calibration = Some(SensorCalibration {
+    scale: calib.scale,
+    black: calib.black,
+    other: calib.other,
+    ..Default::default()
+  });
+}
@@ -0,0 +1,140 @@
+/// What can be extracted from the files of a format, to adapt to it without trying
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FormatCapabilities {
+  /// short name of the format, usually its main file extension in upper case
+  pub format: &'static str,
+  /// what the format is used by
+  pub description: &'static str,
+  /// file extensions of the format in lower case
+  pub extensions: &'static [&'static str],
+  /// the image data can be decoded, otherwise decoding fails with
+  /// `RawLoaderError::Unimplemented`
+  pub raw_data: bool,
+  /// the metadata can be read without decoding the image data, as with
+  /// `rawloader::decode_dummy()`
+  pub metadata_only: bool,
+  /// the files usually have embedded previews, see `rawloader::thumbnail()`
+  pub thumbnails: bool,
+  /// files can have more than one raw image, see `RawDecodeParams`
+  pub multi_frame: bool,
+  /// files can have the two frames of a dual gain readout, see `rawloader::decode_dual_gain()`
+  pub dual_gain: bool,
+  /// files can be Dual Pixel raws, see `rawloader::decode_dual_pixel()`
+  pub dual_pixel: bool,
+  /// the image data can be floating point, in `RawImageData::Float`
+  pub float_data: bool,
+}
+
+macro_rules! caps {
+  ($format:expr, $description:expr, [$($ext:expr),*], $raw_data:expr, $thumbnails:expr, $multi_frame:expr, $dual_gain:expr, $dual_pixel:expr) => (
+    FormatCapabilities {
+      format: $format,
+      description: $description,
+      extensions: &[$($ext),*],
+      raw_data: $raw_data,
+      metadata_only: $raw_data,
+      thumbnails: $thumbnails,
+      multi_frame: $multi_frame,
+      dual_gain: $dual_gain,
+      dual_pixel: $dual_pixel,
+      float_data: false,
+    }
+  );
+}
+
+static FORMATS: [FormatCapabilities; 24] = [
+  //    format   description                          extensions              data   thumb  multi  dgain  dpixel
+  caps!("3FR",   "Hasselblad",                        ["3fr", "fff"],         true,  true,  false, false, false),
+  caps!("ARI",   "ARRI",                              ["ari"],                true,  false, false, false, false),
+  caps!("ARW",   "Sony",                              ["arw", "srf", "sr2"],  true,  true,  false, false, false),
+  caps!("CR2",   "Canon",                             ["cr2"],                true,  true,  true,  false, true),
+  caps!("CR3",   "Canon",                             ["cr3"],                true,  true,  false, false, false),
+  caps!("CRW",   "Canon",                             ["crw"],                true,  true,  false, false, false),
+  caps!("DCR",   "Kodak",                             ["dcr"],                true,  true,  false, false, false),
+  caps!("DCS",   "Kodak",                             ["dcs", "tif"],         true,  true,  false, false, false),
+  caps!("DNG",   "Adobe Digital Negative",            ["dng"],                true,  true,  true,  false, false),
+  caps!("ERF",   "Epson",                             ["erf"],                true,  true,  false, false, false),
+  caps!("IIQ",   "Phase One and Leaf",                ["iiq"],                true,  true,  false, false, false),
+  caps!("KDC",   "Kodak",                             ["kdc"],                true,  true,  false, false, false),
+  caps!("MEF",   "Mamiya",                            ["mef"],                true,  true,  false, false, false),
+  caps!("MOS",   "Leaf",                              ["mos"],                true,  true,  false, false, false),
+  caps!("MRW",   "Minolta",                           ["mrw"],                true,  true,  false, false, false),
+  caps!("NEF",   "Nikon",                             ["nef"],                true,  true,  false, false, false),
+  caps!("NRW",   "Nikon",                             ["nrw"],                true,  true,  false, false, false),
+  caps!("Naked", "Headerless files of CHDK cameras",  ["raw", "crw"],         true,  false, false, false, false),
+  caps!("ORF",   "Olympus",                           ["orf"],                true,  true,  false, false, false),
+  caps!("PEF",   "Pentax and Ricoh",                  ["pef"],                true,  true,  false, false, false),
+  caps!("RAF",   "Fujifilm",                          ["raf"],                true,  true,  true,  true,  false),
+  caps!("RW2",   "Panasonic and Leica",               ["rw2", "rwl", "raw"],  true,  true,  false, false, false),
+  caps!("SRW",   "Samsung",                           ["srw"],                true,  true,  false, false, false),
+  caps!("X3F",   "Sigma",                             ["x3f"],                false, true,  false, false, false),
+];
+
+/// All the formats rawloader can read
+pub fn formats() -> &'static [FormatCapabilities] {
+  &FORMATS
+}
+
+/// Capabilities of a format by its name as in `FormatCapabilities::format`
+pub fn for_format(format: &str) -> Option<&'static FormatCapabilities> {
+  FORMATS.iter().find(|f| f.format == format)
+}
+
+/// Formats that use a file extension, given in any case. There can be several as some
+/// extensions are shared.
+pub fn for_extension(extension: &str) -> Vec<&'static FormatCapabilities> {
+  let extension = extension.trim_start_matches('.').to_lowercase();
+  FORMATS.iter().filter(|f| f.extensions.contains(&extension.as_str())).collect()
+}
+
+// Formats are serialized by their name and deserialized by finding the one with that name
+#[cfg(feature = "serde")]
+pub(crate) mod format_name {
+  use serde::{Deserialize, Deserializer, Serialize, Serializer};
+  use super::FormatCapabilities;
+
+  pub fn serialize<S: Serializer>(format: &Option<&'static FormatCapabilities>, serializer: S) -> Result<S::Ok, S::Error> {
+    format.map(|f| f.format).serialize(serializer)
+  }
+
+  pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<&'static FormatCapabilities>, D::Error> {
+    let name = Option::<String>::deserialize(deserializer)?;
+    Ok(name.and_then(|name| super::for_format(&name)))
+  }
+}
+
+/// What a file is, as found by `rawloader::identify()` without decoding the image data
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FormatInfo {
+  /// the format of the file, None if it isn't one rawloader can read
+  pub format: Option<&'static FormatCapabilities>,
+  /// camera make as encoded in the file
+  pub make: Option<String>,
+  /// camera model as encoded in the file
+  pub model: Option<String>,
+  /// width of the raw image in pixels
+  pub width: Option<usize>,
+  /// height of the raw image in pixels
+  pub height: Option<usize>,
+  /// the camera is supported so the file can be decoded
+  pub supported: bool,
+}
+
+/// A camera in the database of supported cameras, see `rawloader::supported_cameras()`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SupportedCamera {
+  /// make cleaned up to be consistent and short
+  pub clean_make: String,
+  /// model cleaned up to be consistent and short
+  pub clean_model: String,
+  /// make as encoded in the files
+  pub make: String,
+  /// model as encoded in the files
+  pub model: String,
+  /// the other modes the camera is supported in besides the default one (e.g., "sRaw1" or
+  /// "4:3" depending on the make)
+  pub modes: Vec<String>,
+}
@@ -1,5 +1,6 @@
 use std::fmt;
 
+use crate::decoders::{RawLoaderError, Orientation};
 use crate::decoders::tiff::*;
 
 /// Representation of the color filter array pattern in raw cameras
@@ -19,6 +20,7 @@ use crate::decoders::tiff::*;
 /// designed to be fast so it can be called inside the inner loop of demosaic or other
 /// color-aware algorithms that work on pre-demosaic data
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CFA {
   /// CFA pattern as a String
   pub name: String,
@@ -27,79 +29,357 @@ pub struct CFA {
   /// Height of the repeating pattern
   pub height: usize,
 
-  pattern: [[usize;48];48],
+  pattern: Vec<usize>,
+  colors: Vec<CFAColor>,
+  monochrome: bool,
+}
+
+/// The colors that can be found in a color filter array
+///
+/// Each color in a pattern gets assigned one of the four color indexes returned by
+/// `CFA::color_at()` and used to address the four element arrays of `RawImage` (whitebalance
+/// coefficients, black and white levels and color matrix rows). The indexes are handed out in
+/// the order the colors are listed here, skipping the ones not present in the pattern. For the
+/// usual Bayer patterns that gives the classic red=0, green=1, blue=2, while a RYYB pattern has
+/// yellow in index 1 instead of green and the complementary CMYG pattern of early digicams ends
+/// up as cyan=0, green=1, yellow=2, magenta=3.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CFAColor {
+  /// Red, 'R' in pattern strings
+  Red,
+  /// Cyan, 'C' in pattern strings (CMYG complementary sensors)
+  Cyan,
+  /// Green, 'G' in pattern strings
+  Green,
+  /// Yellow, 'Y' in pattern strings (e.g., RYYB phone sensors)
+  Yellow,
+  /// Blue, 'B' in pattern strings
+  Blue,
+  /// Magenta, 'M' in pattern strings (CMYG complementary sensors)
+  Magenta,
+  /// The fourth color in RGBE sensors, 'E' in pattern strings
+  Emerald,
+  /// Unfiltered pixels in RGBW sensors, 'W' in pattern strings
+  White,
+}
+
+const CFA_COLOR_ORDER: [CFAColor;8] = [
+  CFAColor::Red,
+  CFAColor::Cyan,
+  CFAColor::Green,
+  CFAColor::Yellow,
+  CFAColor::Blue,
+  CFAColor::Magenta,
+  CFAColor::Emerald,
+  CFAColor::White,
+];
+
+impl CFAColor {
+  /// Get the color that corresponds to a character in a pattern string
+  pub fn from_char(c: char) -> Option<CFAColor> {
+    match c {
+      'R' => Some(CFAColor::Red),
+      'C' => Some(CFAColor::Cyan),
+      'G' => Some(CFAColor::Green),
+      'Y' => Some(CFAColor::Yellow),
+      'B' => Some(CFAColor::Blue),
+      'M' => Some(CFAColor::Magenta),
+      'E' => Some(CFAColor::Emerald),
+      'W' => Some(CFAColor::White),
+      _   => None,
+    }
+  }
+
+  // Colors as numbered in the TIFF/EP and DNG CFAPattern and CFAPlaneColor tags
+  pub(crate) fn from_tiff(code: u32) -> Option<CFAColor> {
+    match code {
+      0 => Some(CFAColor::Red),
+      1 => Some(CFAColor::Green),
+      2 => Some(CFAColor::Blue),
+      3 => Some(CFAColor::Cyan),
+      4 => Some(CFAColor::Magenta),
+      5 => Some(CFAColor::Yellow),
+      6 => Some(CFAColor::White),
+      _ => None,
+    }
+  }
+
+  // Does the opposite of from_tiff(), emerald has no code
+  pub(crate) fn to_tiff(self) -> Option<u8> {
+    match self {
+      CFAColor::Red     => Some(0),
+      CFAColor::Green   => Some(1),
+      CFAColor::Blue    => Some(2),
+      CFAColor::Cyan    => Some(3),
+      CFAColor::Magenta => Some(4),
+      CFAColor::Yellow  => Some(5),
+      CFAColor::White   => Some(6),
+      CFAColor::Emerald => None,
+    }
+  }
+
+  /// Get the character used for this color in pattern strings
+  pub fn to_char(&self) -> char {
+    match *self {
+      CFAColor::Red     => 'R',
+      CFAColor::Cyan    => 'C',
+      CFAColor::Green   => 'G',
+      CFAColor::Yellow  => 'Y',
+      CFAColor::Blue    => 'B',
+      CFAColor::Magenta => 'M',
+      CFAColor::Emerald => 'E',
+      CFAColor::White   => 'W',
+    }
+  }
+
+  /// Idealized response of the filter to linear sRGB primaries. The complementary colors are
+  /// the sum of the two primaries they let through (e.g., cyan is green+blue).
+  pub fn to_rgb(&self) -> [f32;3] {
+    match *self {
+      CFAColor::Red     => [1.0, 0.0, 0.0],
+      CFAColor::Cyan    => [0.0, 1.0, 1.0],
+      CFAColor::Green   => [0.0, 1.0, 0.0],
+      CFAColor::Yellow  => [1.0, 1.0, 0.0],
+      CFAColor::Blue    => [0.0, 0.0, 1.0],
+      CFAColor::Magenta => [1.0, 0.0, 1.0],
+      CFAColor::Emerald => [0.0, 1.0, 1.0],
+      CFAColor::White   => [1.0, 1.0, 1.0],
+    }
+  }
+
+  /// Get a human readable name for the color
+  pub fn name(&self) -> &'static str {
+    match *self {
+      CFAColor::Red     => "red",
+      CFAColor::Cyan    => "cyan",
+      CFAColor::Green   => "green",
+      CFAColor::Yellow  => "yellow",
+      CFAColor::Blue    => "blue",
+      CFAColor::Magenta => "magenta",
+      CFAColor::Emerald => "emerald",
+      CFAColor::White   => "white",
+    }
+  }
+}
+
+/// The commonly found CFA patterns, so they can be matched on without comparing strings
+///
+/// The X-Trans variants are the same 6x6 pattern at different phases and are named after
+/// their first row.
+///
+/// # Example
+/// ```
+/// use rawloader::{CFA, CFAPattern};
+/// let cfa = CFA::new("GRBG");
+/// assert_eq!(cfa.known_pattern(), Some(CFAPattern::Grbg));
+/// assert_eq!(CFAPattern::from_name("RGGB"), Some(CFAPattern::Rggb));
+/// assert_eq!(CFAPattern::Bggr.name(), "BGGR");
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CFAPattern {
+  /// Bayer starting with red
+  Rggb,
+  /// Bayer starting with blue
+  Bggr,
+  /// Bayer starting with green followed by red
+  Grbg,
+  /// Bayer starting with green followed by blue
+  Gbrg,
+  /// X-Trans starting with the row RBGBRG
+  XTransRbgbrg,
+  /// X-Trans starting with the row GBGGRG
+  XTransGbggrg,
+  /// X-Trans starting with the row GGRGGB
+  XTransGgrggb,
+}
+
+const CFA_PATTERNS: [CFAPattern;7] = [
+  CFAPattern::Rggb,
+  CFAPattern::Bggr,
+  CFAPattern::Grbg,
+  CFAPattern::Gbrg,
+  CFAPattern::XTransRbgbrg,
+  CFAPattern::XTransGbggrg,
+  CFAPattern::XTransGgrggb,
+];
+
+impl CFAPattern {
+  /// Get the pattern from its string form as used in the camera database
+  pub fn from_name(name: &str) -> Option<CFAPattern> {
+    CFA_PATTERNS.iter().cloned().find(|p| p.name() == name)
+  }
+
+  /// Get the string form of the pattern as used in the camera database
+  pub fn name(&self) -> &'static str {
+    match *self {
+      CFAPattern::Rggb         => "RGGB",
+      CFAPattern::Bggr         => "BGGR",
+      CFAPattern::Grbg         => "GRBG",
+      CFAPattern::Gbrg         => "GBRG",
+      CFAPattern::XTransRbgbrg => "RBGBRGGGRGGBGGBGGRBRGRBGGGBGGRGGRGGB",
+      CFAPattern::XTransGbggrg => "GBGGRGRGRBGBGBGGRGGRGGBGBGBRGRGRGGBG",
+      CFAPattern::XTransGgrggb => "GGRGGBGGBGGRBRGRBGGGBGGRGGRGGBRBGBRG",
+    }
+  }
+
+  /// Checks if this is one of the X-Trans variants
+  pub fn is_xtrans(&self) -> bool {
+    matches!(*self, CFAPattern::XTransRbgbrg | CFAPattern::XTransGbggrg | CFAPattern::XTransGgrggb)
+  }
+
+  /// Get the color at a given position of the pattern
+  pub fn color_at(&self, row: usize, col: usize) -> CFAColor {
+    let name = self.name();
+    let size = if self.is_xtrans() { 6 } else { 2 };
+    let c = name.as_bytes()[(row%size)*size + col%size] as char;
+    CFAColor::from_char(c).unwrap()
+  }
+
+  /// Create the full CFA for this pattern
+  pub fn to_cfa(&self) -> CFA {
+    CFA::new(self.name())
+  }
 }
 
 impl CFA {
-  #[doc(hidden)] pub fn new_from_tag(pat: &TiffEntry) -> CFA {
+  #[doc(hidden)] pub fn new_from_tag(pat: &TiffEntry) -> Result<CFA, RawLoaderError> {
+    let patname = CFA::pattern_from_tag(pat)?;
+    match CFA::pattern_size(patname.len()) {
+      Some((width, height)) => CFA::new_checked(&patname, width, height),
+      None => Err(RawLoaderError::Corrupt(format!("CFA pattern of {} colors isn't a known shape", patname.len()))),
+    }
+  }
+
+  #[doc(hidden)] pub fn new_from_tag_with_size(pat: &TiffEntry, width: usize, height: usize) -> Result<CFA, RawLoaderError> {
+    CFA::new_checked(&CFA::pattern_from_tag(pat)?, width, height)
+  }
+
+  // Patterns from files are checked for what would make new_with_size() panic
+  fn new_checked(patname: &str, width: usize, height: usize) -> Result<CFA, RawLoaderError> {
+    let mut colors: Vec<char> = patname.chars().collect();
+    colors.sort_unstable();
+    colors.dedup();
+    if patname.len() != width.saturating_mul(height) || colors.len() > 4 ||
+       colors.iter().any(|&c| CFAColor::from_char(c).is_none()) {
+      return Err(RawLoaderError::Corrupt(format!("CFA pattern \"{}\" isn't valid for a {}x{} CFA", patname, width, height)))
+    }
+    Ok(CFA::new_with_size(patname, width, height))
+  }
+
+  fn pattern_size(len: usize) -> Option<(usize, usize)> {
+    let side = (len as f64).sqrt() as usize;
+    match len {
+      16 => Some((2,8)),
+      _ if side*side == len => Some((side,side)),
+      _ => None,
+    }
+  }
+
+  fn pattern_from_tag(pat: &TiffEntry) -> Result<String, RawLoaderError> {
+    // The types get_u32() reads without failing the decode
+    if ![1, 3, 4, 8, 9, 13].contains(&pat.typ()) {
+      return Err(RawLoaderError::Corrupt(format!("CFA pattern of TIFF type {} isn't a list of colors", pat.typ())))
+    }
     let mut patname = String::new();
     for i in 0..pat.count() {
-      patname.push(match pat.get_u32(i as usize) {
-        0 => 'R',
-        1 => 'G',
-        2 => 'B',
-        _ => 'U',
+      patname.push(match CFAColor::from_tiff(pat.get_u32(i as usize)) {
+        Some(color) => color.to_char(),
+        None => 'U',
       });
     }
-    CFA::new(&patname)
+    Ok(patname)
+  }
+
+  /// Create the CFA of a monochrome sensor, one that has no color filters at all and thus
+  /// produces a single channel of gray data that should not be demosaiced
+  ///
+  /// # Example
+  /// ```
+  /// use rawloader::CFA;
+  /// let cfa = CFA::new_monochrome();
+  /// assert!(cfa.is_monochrome());
+  /// assert!(!cfa.is_valid());
+  /// assert_eq!(cfa.color_at(3,5), 0);
+  /// ```
+  pub fn new_monochrome() -> CFA {
+    let mut cfa = CFA::new("");
+    cfa.monochrome = true;
+    cfa
   }
 
   /// Create a new CFA from a string describing it. For simplicity the pattern is specified
-  /// as each pixel being one of R/G/B/E representing the 0/1/2/3 colors in a 4 color image.
+  /// as each pixel being one of the `CFAColor` characters (R/G/Y/B/E/W). Each color present gets
+  /// one of the 0/1/2/3 indexes of a 4 color image, so RGGB and RGEB end up with their usual
+  /// R=0, G=1, B=2, E=3, RYYB with R=0, Y=1, B=2 and RGBW with the white pixels in index 3.
   /// The pattern is specified as the colors in each row concatenated so RGGB means that
   /// the first row is RG and the second row GB. Row size is determined by pattern size
-  /// (e.g., the xtrans pattern is 6x6 and thus 36 characters long). In theory this could
-  /// lead to confusion between different pattern sizes but in practice there are only
-  /// a few oddball cameras no one cares about that do anything but 2x2 and 6x6 (and those
-  /// work fine with this as well).
+  /// (e.g., the xtrans pattern is 6x6 and thus 36 characters long). Square patterns work
+  /// for any size, and the 16 character patterns of the old interlaced CMYG cameras are
+  /// taken to be 2 wide and 8 tall. Any other shape needs `new_with_size()`.
   pub fn new(patname: &str) -> CFA {
-    let (width, height) = match patname.len() {
-      0 => (0,0),
-      4 => (2,2),
-      36 => (6,6),
-      16 => (2,8),
-      144 => (12,12),
-      _ => panic!("Unknown CFA size \"{}\"", patname),
+    let (width, height) = match CFA::pattern_size(patname.len()) {
+      Some(size) => size,
+      None => panic!("Unknown CFA size \"{}\"", patname),
     };
-    let mut pattern: [[usize;48];48] = [[0;48];48];
-
-    if width > 0 {
-      // copy the pattern into the top left
-      for (i,c) in patname.bytes().enumerate() {
-        pattern[i/width][i%width] = match c {
-          b'R' => 0,
-          b'G' => 1,
-          b'B' => 2,
-          b'E' => 3,
-          b'M' => 1,
-          b'Y' => 3,
-          _    => {
-              let unknown_char = patname[i..].chars().next().unwrap();
-              panic!("Unknown CFA color \"{}\" in pattern \"{}\"", unknown_char, patname)
-          },
-        };
-      }
+    CFA::new_with_size(patname, width, height)
+  }
 
-      // extend the pattern into the full matrix
-      for row in 0..48 {
-        for col in 0..48 {
-          pattern[row][col] = pattern[row%height][col%width];
-        }
+  /// Create a new CFA from a string describing it and the size of the repeating pattern. The
+  /// pattern string is the same as in `new()` and needs to have exactly `width`*`height`
+  /// characters.
+  ///
+  /// # Example
+  /// ```
+  /// use rawloader::CFA;
+  /// let cfa = CFA::new_with_size("RGBGGRGB", 4, 2);
+  /// assert_eq!(cfa.width, 4);
+  /// assert_eq!(cfa.height, 2);
+  /// assert_eq!(cfa.color_at(0,2), 2);
+  /// assert_eq!(cfa.color_at(3,4), 1);
+  /// ```
+  pub fn new_with_size(patname: &str, width: usize, height: usize) -> CFA {
+    if patname.chars().count() != width*height {
+      panic!("CFA pattern \"{}\" doesn't have {}x{} colors", patname, width, height);
+    }
+
+    // Find out which colors are in the pattern and give each of them an index
+    let mut present = Vec::new();
+    for c in patname.chars() {
+      match CFAColor::from_char(c) {
+        Some(color) => if !present.contains(&color) { present.push(color) },
+        None => panic!("Unknown CFA color \"{}\" in pattern \"{}\"", c, patname),
       }
     }
+    let colors: Vec<CFAColor> = CFA_COLOR_ORDER.iter().cloned().filter(|c| present.contains(c)).collect();
+    if colors.len() > 4 {
+      panic!("Too many colors in CFA pattern \"{}\"", patname);
+    }
+
+    let pattern = patname.chars().map(|c| {
+      let color = CFAColor::from_char(c).unwrap();
+      colors.iter().position(|&x| x == color).unwrap()
+    }).collect();
 
     CFA {
       name: patname.to_string(),
-      pattern: pattern,
-      width: width,
-      height: height,
+      pattern,
+      colors,
+      monochrome: false,
+      width,
+      height,
     }
   }
 
   /// Get the color index at the given position. Designed to be fast so it can be called
   /// from inner loops without performance issues.
+  #[inline(always)]
   pub fn color_at(&self, row: usize, col: usize) -> usize {
-    self.pattern[(row+48) % 48][(col+48) % 48]
+    if self.width == 0 {
+      0
+    } else {
+      self.pattern[(row % self.height)*self.width + (col % self.width)]
+    }
   }
 
   /// Shift the pattern left and/or down. This is useful when cropping the image to get
@@ -121,34 +401,51 @@ impl CFA {
   /// assert_eq!(shifted.color_at(1,1), 0);
   /// ```
   pub fn shift(&self, x: usize, y: usize) -> CFA {
-    let mut pattern: [[usize;48];48] = [[0;48];48];
-    for row in 0..48 {
-      for col in 0..48 {
-        pattern[row][col] = self.color_at(row+y,col+x);
-      }
-    }
-
+    let mut pattern = Vec::with_capacity(self.pattern.len());
     let mut name = "".to_string();
     for row in 0..self.height {
       for col in 0..self.width {
-        name.push_str(match pattern[row][col] {
-          0 => "R",
-          1 => "G",
-          2 => "B",
-          3 => "E",
-          x => panic!("Unknown CFA color \"{}\"", x),
-        });
+        let color = self.color_at(row+y,col+x);
+        pattern.push(color);
+        name.push(self.colors[color].to_char());
       }
     }
 
     CFA {
-      name: name,
-      pattern: pattern,
+      name,
+      pattern,
+      colors: self.colors.clone(),
+      monochrome: self.monochrome,
       width: self.width,
       height: self.height,
     }
   }
 
+  // The pattern of an image of width by height once an orientation is applied to it
+  pub(crate) fn orient(&self, orientation: Orientation, width: usize, height: usize) -> CFA {
+    let (transpose, _, _) = orientation.to_flips();
+    let (pwidth, pheight) = if transpose { (self.height, self.width) } else { (self.width, self.height) };
+    let mut pattern = Vec::with_capacity(self.pattern.len());
+    let mut name = "".to_string();
+    for row in 0..pheight {
+      for col in 0..pwidth {
+        let (srow, scol) = orientation.unoriented(row, col, width, height);
+        let color = self.color_at(srow, scol);
+        pattern.push(color);
+        name.push(self.colors[color].to_char());
+      }
+    }
+
+    CFA {
+      name,
+      pattern,
+      colors: self.colors.clone(),
+      monochrome: self.monochrome,
+      width: pwidth,
+      height: pheight,
+    }
+  }
+
   /// Test if this is actually a valid CFA pattern
   ///
   /// # Example
@@ -164,6 +461,95 @@ impl CFA {
     self.width != 0 && self.height != 0
   }
 
+  /// Test if this is the CFA of a monochrome sensor (see `new_monochrome()`). All pixels of a
+  /// monochrome image are of color index 0 and there's nothing to demosaic.
+  pub fn is_monochrome(&self) -> bool {
+    self.monochrome
+  }
+
+  /// Get which of the well-known patterns this is, if any
+  pub fn known_pattern(&self) -> Option<CFAPattern> {
+    CFAPattern::from_name(&self.name)
+  }
+
+  /// Get the color at a given position, as opposed to `color_at()` that returns its index.
+  /// Returns None for monochrome or invalid CFAs.
+  ///
+  /// # Example
+  /// ```
+  /// use rawloader::{CFA, CFAColor};
+  /// let cfa = CFA::new("BGGR");
+  /// assert_eq!(cfa.cfa_color_at(0,0), Some(CFAColor::Blue));
+  /// assert_eq!(cfa.cfa_color_at(1,1).unwrap().name(), "red");
+  /// ```
+  pub fn cfa_color_at(&self, row: usize, col: usize) -> Option<CFAColor> {
+    if self.monochrome || !self.is_valid() {
+      None
+    } else {
+      Some(self.colors[self.color_at(row, col)])
+    }
+  }
+
+  /// Get the color each of the indexes returned by `color_at()` stands for
+  ///
+  /// # Example
+  /// ```
+  /// use rawloader::{CFA, CFAColor};
+  /// let cfa = CFA::new("RYYB");
+  /// assert_eq!(cfa.color_at(0,1), 1);
+  /// assert_eq!(cfa.colors(), &[CFAColor::Red, CFAColor::Yellow, CFAColor::Blue]);
+  /// ```
+  pub fn colors(&self) -> &[CFAColor] {
+    &self.colors
+  }
+
+  /// Checks if the pattern uses complementary (cyan/magenta/yellow) filters, which need a color
+  /// conversion before they can be treated as RGB
+  pub fn is_complementary(&self) -> bool {
+    self.colors.iter().any(|&c| c == CFAColor::Cyan || c == CFAColor::Magenta)
+  }
+
+  /// Build an idealized XYZ to camera matrix out of the filter colors in the pattern, one row
+  /// per color index. Real cameras should have a measured matrix in the camera database but
+  /// this gives a usable conversion for old sensors where none is available.
+  ///
+  /// # Example
+  /// ```
+  /// use rawloader::CFA;
+  /// let cfa = CFA::new("CMYG");
+  /// let m = cfa.ideal_xyz_to_cam();
+  /// // white (D65 XYZ) lands on 2 for the complementary colors and 1 for green
+  /// let white = [0.950456, 1.0, 1.088754];
+  /// let cyan: f32 = (0..3).map(|i| m[0][i]*white[i]).sum();
+  /// let green: f32 = (0..3).map(|i| m[1][i]*white[i]).sum();
+  /// assert!((cyan - 2.0).abs() < 0.01);
+  /// assert!((green - 1.0).abs() < 0.01);
+  /// ```
+  pub fn ideal_xyz_to_cam(&self) -> [[f32;3];4] {
+    let xyz_to_rgb = [
+    // sRGB D65
+      [ 3.240479, -1.53715,  -0.498535 ],
+      [-0.969256,  1.875992,  0.041556 ],
+      [ 0.055648, -0.204043,  1.057311 ],
+    ];
+
+    let mut out = [[0.0;3];4];
+    for (i, color) in self.colors.iter().enumerate() {
+      let rgb = color.to_rgb();
+      for j in 0..3 {
+        for k in 0..3 {
+          out[i][j] += rgb[k] * xyz_to_rgb[k][j];
+        }
+      }
+    }
+    out
+  }
+
+  /// Get the index used for a given color in this pattern, if the color is present
+  pub fn color_index(&self, color: CFAColor) -> Option<usize> {
+    self.colors.iter().position(|&c| c == color)
+  }
+
   /// Convert the CFA back into a pattern string
   ///
   /// # Example
@@ -182,7 +568,11 @@ impl CFA {
 
 impl fmt::Debug for CFA {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "CFA {{ {} }}", self.name)
+    if self.monochrome {
+      write!(f, "CFA {{ monochrome }}")
+    } else {
+      write!(f, "CFA {{ {} }}", self.name)
+    }
   }
 }
 
@@ -191,3 +581,85 @@ impl fmt::Display for CFA {
     write!(f, "{}", self.name)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn indexes(cfa: &CFA) -> Vec<usize> {
+    (0..cfa.height).flat_map(|row| (0..cfa.width).map(move |col| (row, col)))
+      .map(|(row, col)| cfa.color_at(row, col)).collect()
+  }
+
+  #[test]
+  fn bayer_and_rgeb_indexes() {
+    // The same indexes as before the colors were a list, R=0, G=1, B=2, E=3
+    assert_eq!(indexes(&CFA::new("RGGB")), [0, 1, 1, 2]);
+    assert_eq!(indexes(&CFA::new("BGGR")), [2, 1, 1, 0]);
+    assert_eq!(indexes(&CFA::new("GRBG")), [1, 0, 2, 1]);
+    assert_eq!(indexes(&CFA::new("GBRG")), [1, 2, 0, 1]);
+    assert_eq!(indexes(&CFA::new("RGEB")), [0, 1, 3, 2]);
+  }
+
+  #[test]
+  fn xtrans_indexes() {
+    let name = CFAPattern::XTransRbgbrg.name();
+    let cfa = CFA::new(name);
+    assert_eq!((cfa.width, cfa.height), (6, 6));
+    let expected: Vec<usize> = name.chars().map(|c| match c { 'R' => 0, 'G' => 1, _ => 2 }).collect();
+    assert_eq!(indexes(&cfa), expected);
+  }
+
+  #[test]
+  fn rgbw_indexes() {
+    let cfa = CFA::new_with_size("RGBWGRWB", 4, 2);
+    assert_eq!(indexes(&cfa), [0, 1, 2, 3, 1, 0, 3, 2]);
+    assert_eq!(cfa.colors(), &[CFAColor::Red, CFAColor::Green, CFAColor::Blue, CFAColor::White]);
+    assert_eq!(cfa.color_index(CFAColor::White), Some(3));
+    assert_eq!(cfa.color_at(3, 6), 3);
+  }
+
+  #[test]
+  fn checked_patterns() {
+    assert!(CFA::new_checked("RGBW", 2, 2).is_ok());
+    assert!(CFA::new_checked("RGBWGRWB", 4, 2).is_ok());
+    assert!(CFA::new_checked("RGBW", 4, 2).is_err());
+    assert!(CFA::new_checked("RGBX", 2, 2).is_err());
+    assert!(CFA::new_checked("RGBWEGBW", 4, 2).is_err());
+    assert!(CFA::new_checked("RGB", usize::MAX, 2).is_err());
+  }
+
+  #[test]
+  fn shift_rgbw() {
+    let cfa = CFA::new_with_size("RGBWGRWB", 4, 2);
+    let shifted = cfa.shift(1, 1);
+    assert_eq!(shifted.to_string(), "RWBGGBWR");
+    assert_eq!(shifted.colors(), cfa.colors());
+    for row in 0..4 {
+      for col in 0..8 {
+        assert_eq!(shifted.color_at(row, col), cfa.color_at(row+1, col+1));
+      }
+    }
+  }
+
+  #[test]
+  fn orient_rgbw() {
+    let cfa = CFA::new("RGBW");
+    assert_eq!(cfa.orient(Orientation::Normal, 4, 4).to_string(), "RGBW");
+    assert_eq!(cfa.orient(Orientation::Rotate180, 4, 4).to_string(), "WBGR");
+    assert_eq!(cfa.orient(Orientation::Rotate90, 4, 4).to_string(), "BRWG");
+    assert_eq!(cfa.orient(Orientation::HorizontalFlip, 4, 4).to_string(), "GRWB");
+    // With an odd width the flip lands on the other phase
+    assert_eq!(cfa.orient(Orientation::HorizontalFlip, 5, 4).to_string(), "RGBW");
+
+    let cfa = CFA::new_with_size("RGBWGRWB", 4, 2);
+    let rotated = cfa.orient(Orientation::Rotate90, 8, 4);
+    assert_eq!((rotated.width, rotated.height), (2, 4));
+    for row in 0..8 {
+      for col in 0..4 {
+        let (srow, scol) = Orientation::Rotate90.unoriented(row, col, 8, 4);
+        assert_eq!(rotated.color_at(row, col), cfa.color_at(srow, scol));
+      }
+    }
+  }
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 
 use crate::decoders::basics::*;
+use crate::decoders::RawLoaderError;
 use crate::decoders::Buffer;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -8,11 +9,15 @@ pub enum CiffTag {
 //Null         = 0x0000,
   ColorInfo1   = 0x0032,
   MakeModel    = 0x080a,
-//ShotInfo     = 0x102a,
+  OwnerName    = 0x0810,
+  ShotInfo     = 0x102a,
   ColorInfo2   = 0x102c,
+  CameraSettings = 0x102d,
   WhiteBalance = 0x10a9,
   SensorInfo   = 0x1031,
-//ImageInfo    = 0x1810,
+  SerialNumber = 0x180b,
+  CapturedTime = 0x180e,
+  ImageInfo    = 0x1810,
   DecoderTable = 0x1835,
 //RawData      = 0x2005,
 //SubIFD       = 0x300a,
@@ -44,12 +49,12 @@ pub fn is_ciff(buf: &[u8]) -> bool {
 }
 
 impl<'a> CiffIFD<'a> {
-  pub fn new_file(buf: &'a Buffer) -> Result<CiffIFD<'a>,String> {
+  pub fn new_file(buf: &'a Buffer) -> Result<CiffIFD<'a>, RawLoaderError> {
     let data = &buf.buf;
     CiffIFD::new(data, LEu32(data,2) as usize, buf.size, 1)
   }
 
-  pub fn new(buf: &'a[u8], start: usize, end: usize, depth: u32) -> Result<CiffIFD<'a>, String> {
+  pub fn new(buf: &'a[u8], start: usize, end: usize, depth: u32) -> Result<CiffIFD<'a>, RawLoaderError> {
     let mut entries = HashMap::new();
     let mut subifds = Vec::new();
 
@@ -94,7 +99,7 @@ impl<'a> CiffIFD<'a> {
 }
 
 impl<'a> CiffEntry<'a> {
-  pub fn new(buf: &'a[u8], value_data: usize, offset: usize) -> Result<CiffEntry<'a>, String> {
+  pub fn new(buf: &'a[u8], value_data: usize, offset: usize) -> Result<CiffEntry<'a>, RawLoaderError> {
     let p = LEu16(buf, offset);
     let tag = p & 0x3fff;
     let datalocation = (p & 0xc000) as usize;
@@ -105,7 +110,7 @@ impl<'a> CiffEntry<'a> {
       0x0000 => (LEu32(buf, offset+2) as usize, LEu32(buf, offset+6) as usize + value_data),
       // Data is stored directly in entry
       0x4000 => (8, offset+2),
-      val => return Err(format!("CIFF: Don't know about data location {:x}", val).to_string()),
+      val => return Err(RawLoaderError::Corrupt(format!("CIFF: Don't know about data location {:x}", val))),
     };
     let data = &buf[data_offset..data_offset+bytesize];
     let count = bytesize >> CiffEntry::element_shift(typ);
@@ -0,0 +1,314 @@
+use crate::decoders::tiff::*;
+
+// Temperatures of the EXIF LightSource codes as used for interpolating between the
+// calibration illuminants, with the fluorescent ranges at their midpoints
+const ILLUMINANTS: [(u16, f32);20] = [
+  (1, 5500.0),  // Daylight
+  (2, 4150.0),  // Fluorescent
+  (3, 2850.0),  // Tungsten
+  (4, 5500.0),  // Flash
+  (9, 5500.0),  // Fine weather
+  (10, 6500.0), // Cloudy
+  (11, 7500.0), // Shade
+  (12, 6430.0), // Daylight fluorescent
+  (13, 5000.0), // Day white fluorescent
+  (14, 4150.0), // Cool white fluorescent
+  (15, 3450.0), // White fluorescent
+  (16, 2940.0), // Warm white fluorescent
+  (17, 2856.0), // Standard light A
+  (18, 4874.0), // Standard light B
+  (19, 6774.0), // Standard light C
+  (20, 5503.0), // D55
+  (21, 6504.0), // D65
+  (22, 7504.0), // D75
+  (23, 5003.0), // D50
+  (24, 3200.0), // ISO studio tungsten
+];
+
+/// The full set of color matrices of a DNG, or just the one from the camera database for
+/// other formats. As with `RawImage::xyz_to_cam` the per color rows and columns are in the
+/// order of the CFA color indexes and not the order the file stores them in.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ColorMatrices {
+  /// EXIF LightSource code of the first calibration illuminant
+  pub calibration_illuminant1: Option<u16>,
+  /// EXIF LightSource code of the second calibration illuminant
+  pub calibration_illuminant2: Option<u16>,
+  /// matrix to convert XYZ to camera colors under the first calibration illuminant
+  pub color_matrix1: Option<[[f32;3];4]>,
+  /// matrix to convert XYZ to camera colors under the second calibration illuminant
+  pub color_matrix2: Option<[[f32;3];4]>,
+  /// matrix to convert white balanced camera colors to XYZ D50 for the first calibration
+  /// illuminant
+  pub forward_matrix1: Option<[[f32;4];3]>,
+  /// matrix to convert white balanced camera colors to XYZ D50 for the second calibration
+  /// illuminant
+  pub forward_matrix2: Option<[[f32;4];3]>,
+  /// per unit calibration of the camera applied on top of the first color matrix
+  pub camera_calibration1: Option<[[f32;4];4]>,
+  /// per unit calibration of the camera applied on top of the second color matrix
+  pub camera_calibration2: Option<[[f32;4];4]>,
+  /// gain applied to each color by the camera's analog amplifiers
+  pub analog_balance: Option<[f32;4]>,
+}
+
+impl ColorMatrices {
+  // The matrices are stored in the order of CFAPlaneColor, `planes` is where each of those
+  // ends up in our color indexes
+  pub(crate) fn from_tiff(tiff: &TiffIFD, planes: &[usize]) -> ColorMatrices {
+    let plane = |i: usize| planes.get(i).cloned().unwrap_or(i);
+
+    let color_matrix = |tag| {
+      tiff.find_entry(tag).filter(|e| e.count() % 3 == 0 && e.count() <= 12).and_then(|e| {
+        let mut m = [[0.0;3];4];
+        for i in 0..e.count() {
+          let row = plane(i/3);
+          if row >= 4 { return None }
+          m[row][i%3] = e.get_f32(i);
+        }
+        Some(m)
+      })
+    };
+    let forward_matrix = |tag| {
+      tiff.find_entry(tag).filter(|e| e.count() % 3 == 0 && e.count() <= 12).and_then(|e| {
+        let cols = e.count() / 3;
+        let mut m = [[0.0;4];3];
+        for i in 0..e.count() {
+          let col = plane(i%cols);
+          if col >= 4 { return None }
+          m[i/cols][col] = e.get_f32(i);
+        }
+        Some(m)
+      })
+    };
+    let calibration = |tag| {
+      tiff.find_entry(tag).filter(|e| [1, 4, 9, 16].contains(&e.count())).and_then(|e| {
+        let n = (e.count() as f32).sqrt() as usize;
+        let mut m = [[0.0;4];4];
+        for i in 0..e.count() {
+          let (row, col) = (plane(i/n), plane(i%n));
+          if row >= 4 || col >= 4 { return None }
+          m[row][col] = e.get_f32(i);
+        }
+        Some(m)
+      })
+    };
+
+    let illuminant = |tag| tiff.find_entry(tag).filter(|e| e.count() > 0).map(|e| e.get_u32(0) as u16);
+
+    ColorMatrices {
+      calibration_illuminant1: illuminant(Tag::CalibIlluminant1),
+      calibration_illuminant2: illuminant(Tag::CalibIlluminant2),
+      color_matrix1: color_matrix(Tag::ColorMatrix1),
+      color_matrix2: color_matrix(Tag::ColorMatrix2),
+      forward_matrix1: forward_matrix(Tag::ForwardMatrix1),
+      forward_matrix2: forward_matrix(Tag::ForwardMatrix2),
+      camera_calibration1: calibration(Tag::CameraCalib1),
+      camera_calibration2: calibration(Tag::CameraCalib2),
+      analog_balance: tiff.find_entry(Tag::AnalogBalance).filter(|e| e.count() <= 4).and_then(|e| {
+        let mut ab = [1.0;4];
+        for i in 0..e.count() {
+          let idx = plane(i);
+          if idx >= 4 { return None }
+          ab[idx] = e.get_f32(i);
+        }
+        Some(ab)
+      }),
+    }
+  }
+
+  // The camera database matrices are all for D65
+  pub(crate) fn from_single(xyz_to_cam: [[f32;3];4]) -> ColorMatrices {
+    if xyz_to_cam.iter().all(|row| row.iter().all(|&v| v == 0.0)) {
+      return ColorMatrices::default()
+    }
+    ColorMatrices {
+      calibration_illuminant1: Some(21),
+      color_matrix1: Some(xyz_to_cam),
+      ..Default::default()
+    }
+  }
+
+  /// Color temperature in Kelvin of an EXIF LightSource code, for the ones that have a
+  /// defined one
+  pub fn illuminant_temperature(light_source: u16) -> Option<f32> {
+    ILLUMINANTS.iter().find(|&&(code, _)| code == light_source).map(|&(_, temp)| temp)
+  }
+
+  // How much of the first illuminant's matrices to use for a white point, interpolating
+  // linearly in inverse temperature between the two illuminants and clamping outside of them
+  // as Adobe does. If the illuminants aren't known the second set is used as it's usually D65.
+  fn weight(&self, temperature: f32) -> f32 {
+    let temps = (self.calibration_illuminant1.and_then(Self::illuminant_temperature),
+                 self.calibration_illuminant2.and_then(Self::illuminant_temperature));
+    match temps {
+      (Some(t1), Some(t2)) if t1 != t2 => {
+        let (inv, inv1, inv2) = (1.0 / temperature, 1.0 / t1, 1.0 / t2);
+        ((inv - inv2) / (inv1 - inv2)).clamp(0.0, 1.0)
+      },
+      _ => 0.0,
+    }
+  }
+
+  fn blend<M, F>(&self, temperature: f32, get: F) -> Option<M>
+    where M: Blend, F: Fn(usize) -> Option<M> {
+    match (get(1), get(2)) {
+      (Some(m1), Some(m2)) => Some(m1.blend(&m2, self.weight(temperature))),
+      (Some(m), None) | (None, Some(m)) => Some(m),
+      (None, None) => None,
+    }
+  }
+
+  /// XYZ to camera matrix for a white point of a given color temperature, interpolating
+  /// between the two calibration illuminants and including the camera calibration and analog
+  /// balance as in the DNG color model
+  pub fn xyz_to_cam(&self, temperature: f32) -> Option<[[f32;3];4]> {
+    self.blend(temperature, |i| {
+      let (cm, cc) = if i == 1 { (self.color_matrix1, self.camera_calibration1) }
+                     else { (self.color_matrix2, self.camera_calibration2) };
+      let cm = cm?;
+      let mut out = [[0.0;3];4];
+      for (row, out) in out.iter_mut().enumerate() {
+        for (col, v) in out.iter_mut().enumerate() {
+          *v = match cc {
+            Some(cc) => (0..4).map(|k| cc[row][k] * cm[k][col]).sum(),
+            None => cm[row][col],
+          };
+          *v *= self.analog_balance.map(|ab| ab[row]).unwrap_or(1.0);
+        }
+      }
+      Some(out)
+    })
+  }
+
+  /// Forward matrix for a white point of a given color temperature, interpolating between the
+  /// two calibration illuminants
+  pub fn forward_matrix(&self, temperature: f32) -> Option<[[f32;4];3]> {
+    self.blend(temperature, |i| if i == 1 { self.forward_matrix1 } else { self.forward_matrix2 })
+  }
+}
+
+trait Blend {
+  fn blend(&self, other: &Self, weight: f32) -> Self;
+}
+
+impl<const R: usize, const C: usize> Blend for [[f32;C];R] {
+  fn blend(&self, other: &Self, weight: f32) -> Self {
+    let mut out = *self;
+    for (orow, row) in out.iter_mut().zip(other.iter()) {
+      for (o, v) in orow.iter_mut().zip(row.iter()) {
+        *o = *o * weight + v * (1.0 - weight);
+      }
+    }
+    out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn filled<const R: usize, const C: usize>(v: f32) -> [[f32;C];R] {
+    [[v;C];R]
+  }
+
+  // Standard light A and D65, the pair most DNGs are calibrated for
+  fn dual(m1: f32, m2: f32) -> ColorMatrices {
+    ColorMatrices {
+      calibration_illuminant1: Some(17),
+      calibration_illuminant2: Some(21),
+      color_matrix1: Some(filled(m1)),
+      color_matrix2: Some(filled(m2)),
+      ..Default::default()
+    }
+  }
+
+  fn assert_all(m: [[f32;3];4], v: f32) {
+    assert!(m.iter().flatten().all(|&x| (x - v).abs() < 1e-4), "{:?} isn't all {}", m, v);
+  }
+
+  #[test]
+  fn illuminant_temperatures() {
+    assert_eq!(ColorMatrices::illuminant_temperature(17), Some(2856.0));
+    assert_eq!(ColorMatrices::illuminant_temperature(21), Some(6504.0));
+    assert_eq!(ColorMatrices::illuminant_temperature(0), None);
+    assert_eq!(ColorMatrices::illuminant_temperature(255), None);
+  }
+
+  #[test]
+  fn interpolates_in_inverse_temperature() {
+    let matrices = dual(1.0, 3.0);
+    assert_all(matrices.xyz_to_cam(2856.0).unwrap(), 1.0);
+    assert_all(matrices.xyz_to_cam(6504.0).unwrap(), 3.0);
+    // Halfway in mired gets half of each matrix
+    let mid = 2.0 / (1.0 / 2856.0 + 1.0 / 6504.0);
+    assert_all(matrices.xyz_to_cam(mid).unwrap(), 2.0);
+    // while halfway in Kelvin is closer to D65
+    assert_all(matrices.xyz_to_cam(4680.0).unwrap(), 2.38974);
+  }
+
+  #[test]
+  fn clamps_outside_the_illuminants() {
+    let matrices = dual(1.0, 3.0);
+    assert_all(matrices.xyz_to_cam(2000.0).unwrap(), 1.0);
+    assert_all(matrices.xyz_to_cam(10000.0).unwrap(), 3.0);
+    // The order of the illuminants doesn't matter
+    let swapped = ColorMatrices {
+      calibration_illuminant1: Some(21),
+      calibration_illuminant2: Some(17),
+      ..dual(3.0, 1.0)
+    };
+    for &temp in &[2000.0, 2856.0, 4000.0, 6504.0, 10000.0] {
+      assert_eq!(swapped.xyz_to_cam(temp), matrices.xyz_to_cam(temp));
+    }
+  }
+
+  #[test]
+  fn single_and_unknown_illuminants() {
+    let single = ColorMatrices::from_single(filled(2.0));
+    assert_eq!(single.calibration_illuminant1, Some(21));
+    assert_all(single.xyz_to_cam(2856.0).unwrap(), 2.0);
+    assert_eq!(ColorMatrices::from_single(filled(0.0)), ColorMatrices::default());
+    assert_eq!(ColorMatrices::default().xyz_to_cam(5000.0), None);
+
+    // Without known temperatures the second matrix is used, it's usually D65
+    let unknown = ColorMatrices { calibration_illuminant1: Some(255), ..dual(1.0, 3.0) };
+    assert_all(unknown.xyz_to_cam(2856.0).unwrap(), 3.0);
+    let same = ColorMatrices { calibration_illuminant1: Some(21), ..dual(1.0, 3.0) };
+    assert_all(same.xyz_to_cam(2856.0).unwrap(), 3.0);
+  }
+
+  #[test]
+  fn calibration_and_analog_balance() {
+    let mut calibration = [[0.0;4];4];
+    for (i, row) in calibration.iter_mut().enumerate() {
+      row[i] = 1.0;
+    }
+    calibration[0][0] = 2.0;
+    let matrices = ColorMatrices {
+      camera_calibration1: Some(calibration),
+      camera_calibration2: Some(calibration),
+      analog_balance: Some([1.0, 0.5, 1.0, 1.0]),
+      ..dual(1.0, 1.0)
+    };
+    let m = matrices.xyz_to_cam(5000.0).unwrap();
+    assert_eq!(m[0], [2.0; 3]);
+    assert_eq!(m[1], [0.5; 3]);
+    assert_eq!(m[2], [1.0; 3]);
+  }
+
+  #[test]
+  fn forward_matrices() {
+    let matrices = ColorMatrices {
+      forward_matrix1: Some(filled(1.0)),
+      forward_matrix2: Some(filled(3.0)),
+      ..dual(1.0, 1.0)
+    };
+    let mid = 2.0 / (1.0 / 2856.0 + 1.0 / 6504.0);
+    let m = matrices.forward_matrix(mid).unwrap();
+    assert!(m.iter().flatten().all(|&v| (v - 2.0).abs() < 1e-4));
+    let only = ColorMatrices { forward_matrix1: None, ..matrices };
+    assert_eq!(only.forward_matrix(2856.0), Some(filled(3.0)));
+  }
+}
@@ -0,0 +1,125 @@
+// Conversions between CIE 1960 uv chromaticities and correlated color temperature plus tint,
+// using Krystek's approximation of the Planckian locus
+//
+// Tint follows the convention of raw developers, it's the correction that is applied so a
+// greenish illuminant above the locus gives a positive (magenta) tint, with 1 unit being
+// 1/3000 of a uv distance.
+
+const TINT_SCALE: f64 = 3000.0;
+const MIN_TEMP: f64 = 1000.0;
+const MAX_TEMP: f64 = 15000.0;
+
+fn planck_uv(temp: f64) -> (f64, f64) {
+  let t = temp;
+  let u = (0.860117757 + 1.54118254e-4*t + 1.28641212e-7*t*t) / (1.0 + 8.42420235e-4*t + 7.08145163e-7*t*t);
+  let v = (0.317398726 + 4.22806245e-5*t + 4.20481691e-8*t*t) / (1.0 - 2.89741816e-5*t + 1.61456053e-7*t*t);
+  (u, v)
+}
+
+// Unit vector perpendicular to the locus at a temperature, pointing to the green side
+fn planck_normal(temp: f64) -> (f64, f64) {
+  let (u1, v1) = planck_uv(temp - 1.0);
+  let (u2, v2) = planck_uv(temp + 1.0);
+  let (du, dv) = (u2 - u1, v2 - v1);
+  let len = (du*du + dv*dv).sqrt();
+  (dv / len, -du / len)
+}
+
+pub(crate) fn xyz_to_uv(xyz: [f64;3]) -> Option<(f64, f64)> {
+  let denom = xyz[0] + 15.0*xyz[1] + 3.0*xyz[2];
+  if !denom.is_finite() || denom <= 0.0 {
+    return None
+  }
+  Some((4.0*xyz[0] / denom, 6.0*xyz[1] / denom))
+}
+
+pub(crate) fn uv_to_xyz(u: f64, v: f64) -> [f64;3] {
+  let denom = 2.0*u - 8.0*v + 4.0;
+  let (x, y) = (3.0*u / denom, 2.0*v / denom);
+  [x / y, 1.0, (1.0 - x - y) / y]
+}
+
+// Find the closest point of the locus, searching in mired as it's closer to perceptually
+// uniform, first coarsely and then refining around the best match
+pub(crate) fn uv_to_temperature(u: f64, v: f64) -> (f64, f64) {
+  let dist = |temp: f64| {
+    let (pu, pv) = planck_uv(temp);
+    (u - pu).powi(2) + (v - pv).powi(2)
+  };
+  let (min_mired, max_mired) = (1e6 / MAX_TEMP, 1e6 / MIN_TEMP);
+
+  let mut best = min_mired;
+  let mut mired = min_mired;
+  while mired <= max_mired {
+    if dist(1e6 / mired) < dist(1e6 / best) {
+      best = mired;
+    }
+    mired += 1.0;
+  }
+  let (mut lo, mut hi) = ((best - 1.0).max(min_mired), (best + 1.0).min(max_mired));
+  for _ in 0..50 {
+    let m1 = lo + (hi - lo) / 3.0;
+    let m2 = hi - (hi - lo) / 3.0;
+    if dist(1e6 / m1) < dist(1e6 / m2) { hi = m2 } else { lo = m1 }
+  }
+
+  let temp = 1e6 / ((lo + hi) / 2.0);
+  let (pu, pv) = planck_uv(temp);
+  let (nu, nv) = planck_normal(temp);
+  let duv = (u - pu)*nu + (v - pv)*nv;
+  (temp, duv * TINT_SCALE)
+}
+
+pub(crate) fn temperature_to_uv(temp: f64, tint: f64) -> (f64, f64) {
+  let temp = temp.clamp(MIN_TEMP, MAX_TEMP);
+  let (pu, pv) = planck_uv(temp);
+  let (nu, nv) = planck_normal(temp);
+  let duv = tint / TINT_SCALE;
+  (pu + duv*nu, pv + duv*nv)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn d65_temperature() {
+    // D65 is at 6504K and 0.0032 above the locus, on the green side
+    let (x, y) = (0.31271, 0.32902);
+    let (u, v) = xyz_to_uv([x / y, 1.0, (1.0 - x - y) / y]).unwrap();
+    let (temp, tint) = uv_to_temperature(u, v);
+    assert!((temp - 6504.0).abs() < 20.0, "{}", temp);
+    assert!((tint - 0.0032*TINT_SCALE).abs() < 1.0, "{}", tint);
+  }
+
+  #[test]
+  fn temperature_roundtrips() {
+    for &temp in &[2000.0, 2856.0, 4000.0, 5500.0, 6504.0, 9000.0, 14000.0] {
+      for &tint in &[-40.0, -5.0, 0.0, 12.0, 40.0] {
+        let (u, v) = temperature_to_uv(temp, tint);
+        let (t, n) = uv_to_temperature(u, v);
+        assert!((t - temp).abs() < temp * 1e-3, "{} {} gave {}", temp, tint, t);
+        assert!((n - tint).abs() < 0.1, "{} {} gave {}", temp, tint, n);
+      }
+    }
+  }
+
+  #[test]
+  fn temperatures_outside_the_range_are_clamped() {
+    assert_eq!(temperature_to_uv(100.0, 0.0), temperature_to_uv(MIN_TEMP, 0.0));
+    assert_eq!(temperature_to_uv(50000.0, 0.0), temperature_to_uv(MAX_TEMP, 0.0));
+    let (u, v) = temperature_to_uv(MAX_TEMP, 0.0);
+    assert!((uv_to_temperature(u, v).0 - MAX_TEMP).abs() < 20.0);
+  }
+
+  #[test]
+  fn uv_xyz_roundtrips() {
+    for &xyz in &[[0.9505, 1.0, 1.089], [1.0985, 1.0, 0.3558], [0.3, 1.0, 2.0]] {
+      let (u, v) = xyz_to_uv(xyz).unwrap();
+      let back = uv_to_xyz(u, v);
+      assert!(back.iter().zip(xyz.iter()).all(|(b, c)| (b - c).abs() < 1e-9), "{:?} gave {:?}", xyz, back);
+    }
+    assert_eq!(xyz_to_uv([0.0, 0.0, 0.0]), None);
+    assert_eq!(xyz_to_uv([f64::NAN, 1.0, 1.0]), None);
+  }
+}
@@ -0,0 +1,137 @@
+use crate::decoders::*;
+use crate::decoders::opcodes::*;
+use crate::decoders::makernotes::Makernote;
+
+// Number of points the polynomial models are sampled at
+const SAMPLES: usize = 16;
+
+/// Where a set of lens corrections came from
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CorrectionSource {
+  /// the WarpRectilinear and FixVignetteRadial opcodes of a DNG
+  Dng,
+  /// the correction tables in the Fujifilm makernotes
+  Fujifilm,
+  /// the DistortionInfo tag of Panasonic RW2 files
+  Panasonic,
+  /// the ImageProcessing section of the Olympus makernotes
+  Olympus,
+}
+
+/// Lens corrections recorded by the camera, normalized to radial tables so they can be used
+/// without knowing the format they came from. Radii go from 0.0 at the optical center to 1.0
+/// at the farthest corner of the image.
+///
+/// Panasonic and Olympus don't document the model behind their parameters so for those the
+/// tables stay empty and the parameters are in `vendor_params` as found in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LensCorrections {
+  /// where the corrections came from
+  pub source: CorrectionSource,
+  /// distortion as (radius, scale), a point at a given radius of the corrected image comes
+  /// from radius*scale in the raw image
+  pub distortion: Vec<(f32,f32)>,
+  /// lateral chromatic aberration as (radius, red scale, blue scale), applied on top of the
+  /// distortion to the red and blue channels
+  pub chromatic_aberration: Vec<(f32,f32,f32)>,
+  /// vignetting as (radius, gain) to multiply the image with
+  pub vignetting: Vec<(f32,f32)>,
+  /// optical center relative to the image, (0.5, 0.5) being the center of the image
+  pub center: (f32, f32),
+  /// if the camera applied the corrections to its own JPEGs
+  pub applied_in_camera: Option<bool>,
+  /// parameters in a vendor specific model that can't be turned into tables. For Panasonic
+  /// the five `PanasonicDistortion::params` followed by its scale, for Olympus the four
+  /// distortion parameters followed by the six chromatic aberration ones if present
+  pub vendor_params: Vec<f32>,
+}
+
+impl LensCorrections {
+  fn new(source: CorrectionSource) -> LensCorrections {
+    LensCorrections {
+      source,
+      distortion: Vec::new(),
+      chromatic_aberration: Vec::new(),
+      vignetting: Vec::new(),
+      center: (0.5, 0.5),
+      applied_in_camera: None,
+      vendor_params: Vec::new(),
+    }
+  }
+
+  // DNGs are self describing so their opcodes take precedence over the makernotes
+  pub(crate) fn from_image(image: &RawImage) -> Option<LensCorrections> {
+    Self::from_opcodes(&image.opcodes, image.cpp)
+      .or_else(|| image.exif.makernote.as_ref().and_then(Self::from_makernote))
+  }
+
+  fn from_opcodes(opcodes: &DngOpcodes, cpp: usize) -> Option<LensCorrections> {
+    let mut corr = LensCorrections::new(CorrectionSource::Dng);
+    let all = opcodes.list1.iter().chain(opcodes.list2.iter()).chain(opcodes.list3.iter());
+    for opcode in all {
+      match opcode.operation {
+        Operation::WarpRectilinear(ref warp) if !warp.coefficients.is_empty() => {
+          let scale = |plane: usize, r: f64| {
+            let k = warp.coefficients[plane.min(warp.coefficients.len()-1)];
+            let r2 = r*r;
+            k[0] + r2*(k[1] + r2*(k[2] + r2*k[3]))
+          };
+          // On RGB data the green plane has the distortion and the others add the CA
+          let green = if cpp == 3 && warp.coefficients.len() == 3 { 1 } else { 0 };
+          corr.distortion = samples().map(|r| (r as f32, scale(green, r) as f32)).collect();
+          if warp.coefficients.len() == 3 {
+            corr.chromatic_aberration = samples().map(|r| {
+              let g = scale(1, r);
+              (r as f32, (scale(0, r) / g) as f32, (scale(2, r) / g) as f32)
+            }).collect();
+          }
+          corr.center = (warp.center.0 as f32, warp.center.1 as f32);
+        },
+        Operation::FixVignetteRadial(ref vignette) => {
+          let k = vignette.coefficients;
+          corr.vignetting = samples().map(|r| {
+            let r2 = r*r;
+            (r as f32, (1.0 + r2*(k[0] + r2*(k[1] + r2*(k[2] + r2*(k[3] + r2*k[4]))))) as f32)
+          }).collect();
+        },
+        _ => {},
+      }
+    }
+    if corr.distortion.is_empty() && corr.vignetting.is_empty() { None } else { Some(corr) }
+  }
+
+  fn from_makernote(makernote: &Makernote) -> Option<LensCorrections> {
+    let corr = match makernote {
+      Makernote::Fujifilm(mn) => {
+        let mut corr = LensCorrections::new(CorrectionSource::Fujifilm);
+        corr.distortion = mn.distortion.iter().map(|&(r, d)| (r, 1.0 + d / 100.0)).collect();
+        corr.chromatic_aberration = mn.chromatic_aberration.iter().map(|&(r, red, blue)| (r, 1.0 + red, 1.0 + blue)).collect();
+        corr.vignetting = mn.vignetting.iter().filter(|&&(_, v)| v > 0.0).map(|&(r, v)| (r, 100.0 / v)).collect();
+        corr
+      },
+      Makernote::Panasonic(mn) => {
+        let dist = mn.distortion?;
+        let mut corr = LensCorrections::new(CorrectionSource::Panasonic);
+        corr.applied_in_camera = Some(dist.enabled);
+        corr.vendor_params = dist.params.to_vec();
+        corr.vendor_params.push(dist.scale);
+        corr
+      },
+      Makernote::Olympus(mn) => {
+        let dist = mn.distortion_params?;
+        let mut corr = LensCorrections::new(CorrectionSource::Olympus);
+        let params = dist.iter().chain(mn.ca_params.iter().flatten());
+        corr.vendor_params = params.map(|&v| v as f32).collect();
+        corr
+      },
+      _ => return None,
+    };
+    let empty = corr.distortion.is_empty() && corr.chromatic_aberration.is_empty() &&
+                corr.vignetting.is_empty() && corr.vendor_params.is_empty();
+    if empty { None } else { Some(corr) }
+  }
+}
+
+fn samples() -> impl Iterator<Item=f64> {
+  (0..SAMPLES).map(|i| i as f64 / (SAMPLES - 1) as f64)
+}
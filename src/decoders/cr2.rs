@@ -4,6 +4,7 @@ use crate::decoders::*;
 use crate::decoders::tiff::*;
 use crate::decoders::basics::*;
 use crate::decoders::ljpeg::*;
+use crate::decoders::dualpixel::DualPixelImage;
 
 #[derive(Debug, Clone)]
 pub struct Cr2Decoder<'a> {
@@ -23,7 +24,11 @@ impl<'a> Cr2Decoder<'a> {
 }
 
 impl<'a> Decoder for Cr2Decoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "CR2"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let camera = self.rawloader.check_supported(&self.tiff)?;
     let (raw, offset) = {
       if let Some(raw) = self.tiff.find_first_ifd(Tag::Cr2Id) {
@@ -33,133 +38,181 @@ impl<'a> Decoder for Cr2Decoder<'a> {
       } else if let Some(off) = self.tiff.find_entry(Tag::Cr2OldOffset) {
         (&self.tiff, off.get_usize(0))
       } else {
-        return Err("CR2: Couldn't find raw info".to_string())
+        return Err(RawLoaderError::Corrupt("CR2: Couldn't find raw info".to_string()))
       }
     };
-    let src = &self.buffer[offset..];
-
-    let (width, height, cpp, image) = {
-      let decompressor = LjpegDecompressor::new(src)?;
-      let ljpegwidth = decompressor.width();
-      let mut width = ljpegwidth;
-      let mut height = decompressor.height();
-      let cpp = if decompressor.super_h() == 2 {3} else {1};
-      let mut ljpegout = alloc_image_plain!(width, height, dummy);
-
-      decompressor.decode(&mut ljpegout, 0, width, width, height, dummy)?;
-
-      // Linearize the output (applies only to D2000 as far as I can tell)
-      if camera.find_hint("linearization") {
-        let table = {
-          let linearization = fetch_tag!(self.tiff, Tag::GrayResponse);
-          let mut t = [0 as u16;4096];
-          for i in 0..t.len() {
-            t[i] = linearization.get_u32(i) as u16;
-          }
-          LookupTable::new(&t)
-        };
+    let (width, height, cpp, image) = self.decode_frame(&camera, raw, offset, dummy)?;
+    self.build_image(camera, width, height, cpp, image, dummy)
+  }
+
+  fn dual_pixel(&self, dummy: bool) -> Result<DualPixelImage, RawLoaderError> {
+    let camera = self.rawloader.check_supported(&self.tiff)?;
+    // Dual Pixel RAWs have a second raw IFD after the normal one with the frame read out of
+    // only the A photodiodes
+    let frames = self.tiff.find_ifds_with_tag(Tag::Cr2StripeWidths);
+    if frames.len() < 2 {
+      return Err(RawLoaderError::Corrupt("CR2: No dual pixel frames in this file".to_string()))
+    }
+    let (full, a) = (frames[0], frames[1]);
+    let (width, height, cpp, fullimg) = self.decode_frame(&camera, full, fetch_tag!(full, Tag::StripOffsets).get_usize(0), dummy)?;
+    let (awidth, aheight, acpp, aimg) = self.decode_frame(&camera, a, fetch_tag!(a, Tag::StripOffsets).get_usize(0), dummy)?;
+    if cpp != 1 || (awidth, aheight, acpp) != (width, height, cpp) {
+      return Err(RawLoaderError::Corrupt("CR2: Dual pixel frame doesn't match the raw image".to_string()))
+    }
+
+    Ok(DualPixelImage::new(
+      self.build_image(camera.clone(), width, height, cpp, fullimg, dummy)?,
+      self.build_image(camera, width, height, cpp, aimg, dummy)?,
+    ))
+  }
+
+  fn image_count(&self) -> usize {
+    self.tiff.find_ifds_with_tag(Tag::Cr2StripeWidths).len().clamp(1, 2)
+  }
+
+  fn image_at(&self, index: usize, dummy: bool) -> Result<RawImage, RawLoaderError> {
+    match index {
+      0 => self.image(dummy),
+      1 if self.image_count() == 2 => self.dual_pixel(dummy).map(|img| img.a),
+      _ => Err(RawLoaderError::NoSuchImage { index, count: self.image_count() }),
+    }
+  }
+}
+
+impl<'a> Cr2Decoder<'a> {
+  // Decodes the LJPEG of a raw IFD putting the vertical slices in place and converting
+  // sRAWs to RGB, returning the size in samples
+  fn decode_frame(&self, cam: &Camera, raw: &TiffIFD, offset: usize, dummy: bool) -> Result<(usize,usize,usize,Vec<u16>), RawLoaderError> {
+    if offset >= self.buffer.len() {
+      return Err(RawLoaderError::Corrupt("CR2: raw data offset is out of bounds".to_string()))
+    }
+    let src = rest(self.buffer, offset);
+
+    let decompressor = LjpegDecompressor::new(src)?;
+    let ljpegwidth = decompressor.width();
+    let mut width = ljpegwidth;
+    let mut height = decompressor.height();
+    let cpp = if decompressor.super_h() == 2 {3} else {1};
+    let mut ljpegout = alloc_image_plain!(width, height, dummy);
+
+    decompressor.decode(&mut ljpegout, 0, width, width, height, dummy)?;
 
-        let mut random = ljpegout[0] as u32;
-        for o in ljpegout.chunks_exact_mut(1) {
-          o[0] = table.dither(o[0], &mut random);
+    // Linearize the output (applies only to D2000 as far as I can tell)
+    if cam.find_hint("linearization") {
+      let table = {
+        let linearization = fetch_tag!(self.tiff, Tag::GrayResponse);
+        let mut t = [0 as u16;4096];
+        for i in 0..t.len() {
+          t[i] = linearization.get_u32(i) as u16;
         }
+        LookupTable::new(&t)
+      };
+
+      let mut random = ljpegout[0] as u32;
+      for o in ljpegout.chunks_exact_mut(1) {
+        o[0] = table.dither(o[0], &mut random);
       }
+    }
 
-      // Convert the YUV in sRAWs to RGB
-      if cpp == 3 {
-        self.convert_to_rgb(&camera, &mut ljpegout, dummy)?;
-        if raw.has_entry(Tag::ImageWidth) {
-          width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0) * cpp;
-          height = fetch_tag!(raw, Tag::ImageLength).get_usize(0) ;
-        } else if width/cpp < height {
-          let temp = width/cpp;
-          width = height*cpp;
-          height = temp;
-        }
-      } else if camera.find_hint("double_line") {
-        width /= 2;
-        height *= 2;
+    // Convert the YUV in sRAWs to RGB
+    if cpp == 3 {
+      self.convert_to_rgb(cam, &mut ljpegout, dummy)?;
+      if raw.has_entry(Tag::ImageWidth) {
+        width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0) * cpp;
+        height = fetch_tag!(raw, Tag::ImageLength).get_usize(0) ;
+      } else if width/cpp < height {
+        let temp = width/cpp;
+        width = height*cpp;
+        height = temp;
       }
+    } else if cam.find_hint("double_line") {
+      width /= 2;
+      height *= 2;
+    }
 
-      // Take each of the vertical fields and put them into the right location
-      // FIXME: Doing this at the decode would reduce about 5% in runtime but I haven't
-      //        been able to do it without hairy code
-      if let Some(canoncol) = raw.find_entry(Tag::Cr2StripeWidths) {
-        if canoncol.get_usize(0) == 0 {
-          (width, height, cpp, ljpegout)
-        } else {
-          let mut out = alloc_image_plain!(width, height, dummy);
-          if !dummy {
-            let mut fieldwidths = Vec::new();
-            for _ in 0..canoncol.get_usize(0) {
-              fieldwidths.push(canoncol.get_usize(1));
-            }
-            fieldwidths.push(canoncol.get_usize(2));
-
-            if decompressor.super_v() == 2 {
-              // We've decoded 2 lines at a time so we also need to copy two strips at a time
-              let nfields = fieldwidths.len();
-              let fieldwidth = fieldwidths[0];
-              let mut fieldstart = 0;
-              let mut inpos = 0;
-              for _ in 0..nfields {
-                for row in (0..height).step_by(2) {
-                  for col in (0..fieldwidth).step_by(3) {
-                    let outpos = row*width+fieldstart+col;
-                    out[outpos..outpos+3].copy_from_slice(&ljpegout[inpos..inpos+3]);
-                    let outpos = (row+1)*width+fieldstart+col;
-                    let inpos2 = inpos+ljpegwidth;
-                    out[outpos..outpos+3].copy_from_slice(&ljpegout[inpos2..inpos2+3]);
-                    inpos += 3;
-                    if inpos % ljpegwidth == 0 {
-                      // we've used a full input line and we're reading 2 by 2 so skip one
-                      inpos += ljpegwidth;
-                    }
+    // Take each of the vertical fields and put them into the right location
+    // FIXME: Doing this at the decode would reduce about 5% in runtime but I haven't
+    //        been able to do it without hairy code
+    if let Some(canoncol) = raw.find_entry(Tag::Cr2StripeWidths) {
+      if canoncol.get_usize(0) == 0 {
+        Ok((width, height, cpp, ljpegout))
+      } else {
+        let mut out = alloc_image_plain!(width, height, dummy);
+        if !dummy {
+          let mut fieldwidths = Vec::new();
+          for _ in 0..canoncol.get_usize(0) {
+            fieldwidths.push(canoncol.get_usize(1));
+          }
+          fieldwidths.push(canoncol.get_usize(2));
+
+          if decompressor.super_v() == 2 {
+            // We've decoded 2 lines at a time so we also need to copy two strips at a time
+            let nfields = fieldwidths.len();
+            let fieldwidth = fieldwidths[0];
+            let mut fieldstart = 0;
+            let mut inpos = 0;
+            for _ in 0..nfields {
+              for row in (0..height).step_by(2) {
+                check_abort()?;
+                for col in (0..fieldwidth).step_by(3) {
+                  let outpos = row*width+fieldstart+col;
+                  out[outpos..outpos+3].copy_from_slice(&ljpegout[inpos..inpos+3]);
+                  let outpos = (row+1)*width+fieldstart+col;
+                  let inpos2 = inpos+ljpegwidth;
+                  out[outpos..outpos+3].copy_from_slice(&ljpegout[inpos2..inpos2+3]);
+                  inpos += 3;
+                  if inpos % ljpegwidth == 0 {
+                    // we've used a full input line and we're reading 2 by 2 so skip one
+                    inpos += ljpegwidth;
                   }
                 }
-                fieldstart += fieldwidth;
               }
-            } else {
-              let sh = decompressor.super_h();
-              let mut fieldstart = 0;
-              let mut fieldpos = 0;
-              for fieldwidth in fieldwidths {
-                let fieldwidth = fieldwidth/sh*cpp;
-                for row in 0..height {
-                  let outpos = row*width+fieldstart;
-                  let inpos = fieldpos+row*fieldwidth;
-                  let outb = &mut out[outpos..outpos+fieldwidth];
-                  let inb = &ljpegout[inpos..inpos+fieldwidth];
-                  outb.copy_from_slice(inb);
-                }
-                fieldstart += fieldwidth;
-                fieldpos += fieldwidth*height;
+              fieldstart += fieldwidth;
+            }
+          } else {
+            let sh = decompressor.super_h();
+            let mut fieldstart = 0;
+            let mut fieldpos = 0;
+            for fieldwidth in fieldwidths {
+              let fieldwidth = fieldwidth/sh*cpp;
+              for row in 0..height {
+                check_abort()?;
+                let outpos = row*width+fieldstart;
+                let inpos = fieldpos+row*fieldwidth;
+                let outb = &mut out[outpos..outpos+fieldwidth];
+                let inb = &ljpegout[inpos..inpos+fieldwidth];
+                outb.copy_from_slice(inb);
               }
+              fieldstart += fieldwidth;
+              fieldpos += fieldwidth*height;
             }
           }
-
-          (width, height, cpp, out)
         }
-      } else {
-        (width, height, cpp, ljpegout)
+
+        Ok((width, height, cpp, out))
       }
-    };
+    } else {
+      Ok((width, height, cpp, ljpegout))
+    }
+  }
 
+  fn build_image(&self, camera: Camera, width: usize, height: usize, cpp: usize, image: Vec<u16>, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let wb = self.get_wb(&camera)?;
     let mut img = RawImage::new(camera, width, height, wb, image, dummy);
     if cpp == 3 {
       img.cpp = 3;
       img.width /= 3;
+      img.cfa = cfa::CFA::new("");
       img.crops = [0,0,0,0];
+      img.default_crop = None;
+      img.display_crop = None;
       img.blacklevels = [0,0,0,0];
       img.whitelevels = [65535,65535,65535,65535];
     }
     Ok(img)
   }
-}
 
-impl<'a> Cr2Decoder<'a> {
-  fn get_wb(&self, cam: &Camera) -> Result<[f32;4], String> {
+  fn get_wb(&self, cam: &Camera) -> Result<[f32;4], RawLoaderError> {
     if let Some(levels) = self.tiff.find_entry(Tag::Cr2ColorData) {
       let offset = if cam.wb_offset != 0 {cam.wb_offset} else {63};
       Ok([levels.get_force_u16(offset) as f32, levels.get_force_u16(offset+1) as f32,
@@ -175,7 +228,7 @@ impl<'a> Cr2Decoder<'a> {
     }
   }
 
-  fn convert_to_rgb(&self, cam: &Camera, image: &mut [u16], dummy: bool) -> Result<(),String>{
+  fn convert_to_rgb(&self, cam: &Camera, image: &mut [u16], dummy: bool) -> Result<(), RawLoaderError>{
     let coeffs = self.get_wb(cam)?;
     if dummy {
       return Ok(())
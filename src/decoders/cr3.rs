@@ -81,17 +81,17 @@ impl<'a> Cr3Decoder<'a> {
         }
     }
 
-    fn read_box(cursor: &mut Cursor<&[u8]>) -> Result<Box, String> {
+    fn read_box(cursor: &mut Cursor<&[u8]>) -> Result<Box, RawLoaderError> {
         let offset = cursor.position();
         let mut size_bytes = [0u8; 4];
         let mut type_bytes = [0u8; 4];
 
         // Read size and type
         if cursor.read_exact(&mut size_bytes).is_err() {
-            return Err("Failed to read box size".to_string());
+            return Err(RawLoaderError::Corrupt("Failed to read box size".to_string()));
         }
         if cursor.read_exact(&mut type_bytes).is_err() {
-            return Err("Failed to read box type".to_string());
+            return Err(RawLoaderError::Corrupt("Failed to read box type".to_string()));
         }
 
         let mut size = u32::from_be_bytes(size_bytes);
@@ -100,27 +100,27 @@ impl<'a> Cr3Decoder<'a> {
         // Handle large boxes (size == 1)
         if size == 1 {
             // Skip large boxes for now as they're unlikely to contain CRAW
-            return Err("Large boxes not supported yet".to_string());
+            return Err(RawLoaderError::Unimplemented("Large boxes not supported yet".to_string()));
         }
 
         // Handle UUID boxes
         if type_bytes == *b"uuid" {
             let mut uuid = [0u8; 16];
             if cursor.read_exact(&mut uuid).is_err() {
-                return Err("Failed to read UUID".to_string());
+                return Err(RawLoaderError::Corrupt("Failed to read UUID".to_string()));
             }
             data_offset += 16;
-            eprintln!("Found UUID box: {:02x?}", uuid);
+            debug!("Found UUID box: {:02x?}", uuid);
         }
 
         // Basic size validation
         if size < 8 {
-            return Err(format!("Invalid box size {} at offset {}", size, offset));
+            return Err(RawLoaderError::Corrupt(format!("Invalid box size {} at offset {}", size, offset)));
         }
 
         // Check if we're still within the file
         if offset + size as u64 > cursor.get_ref().len() as u64 {
-            return Err(format!("Box extends beyond file end: size {} at offset {}", size, offset));
+            return Err(RawLoaderError::Corrupt(format!("Box extends beyond file end: size {} at offset {}", size, offset)));
         }
 
         Ok(Box {
@@ -131,10 +131,10 @@ impl<'a> Cr3Decoder<'a> {
         })
     }
 
-    fn parse_craw_header(&self, cursor: &mut Cursor<&[u8]>) -> Result<CrawHeader, String> {
+    fn parse_craw_header(&self, cursor: &mut Cursor<&[u8]>) -> Result<CrawHeader, RawLoaderError> {
         let mut header = [0u8; 28];
         if cursor.read_exact(&mut header).is_err() {
-            return Err("Failed to read CRAW header".to_string());
+            return Err(RawLoaderError::Corrupt("Failed to read CRAW header".to_string()));
         }
 
         let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
@@ -145,16 +145,16 @@ impl<'a> Cr3Decoder<'a> {
 
         // Validate header values
         if width == 0 || height == 0 {
-            return Err("Invalid CRAW dimensions".to_string());
+            return Err(RawLoaderError::Corrupt("Invalid CRAW dimensions".to_string()));
         }
         if bit_depth == 0 || bit_depth > 32 {
-            return Err("Invalid CRAW bit depth".to_string());
+            return Err(RawLoaderError::Corrupt("Invalid CRAW bit depth".to_string()));
         }
         if components == 0 {
-            return Err("Invalid CRAW components".to_string());
+            return Err(RawLoaderError::Corrupt("Invalid CRAW components".to_string()));
         }
 
-        eprintln!("CRAW header: {}x{}, {} bit, {} components", width, height, bit_depth, components);
+        debug!("CRAW header: {}x{}, {} bit, {} components", width, height, bit_depth, components);
 
         Ok(CrawHeader {
             width,
@@ -165,7 +165,7 @@ impl<'a> Cr3Decoder<'a> {
         })
     }
 
-    fn extract_basic_metadata(&self, camera: &mut Camera) -> Result<(), String> {
+    fn extract_basic_metadata(&self, camera: &mut Camera) -> Result<(), RawLoaderError> {
         if let Some(ref tiff) = self.tiff {
             // Extract Make
             if let Some(make) = tiff.find_entry(Tag::Make) {
@@ -191,7 +191,7 @@ impl<'a> Cr3Decoder<'a> {
         Ok(())
     }
 
-    fn decode_raw_image(&self, cursor: &mut Cursor<&[u8]>, header: &CrawHeader, dummy: bool) -> Result<Vec<u16>, String> {
+    fn decode_raw_image(&self, cursor: &mut Cursor<&[u8]>, header: &CrawHeader, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
         let width = header.width as usize;
         let height = header.height as usize;
         let mut image = alloc_image_plain!(width, height, dummy);
@@ -201,7 +201,7 @@ impl<'a> Cr3Decoder<'a> {
 
         // Save current position for error reporting
         let data_start = cursor.position();
-        eprintln!("Starting raw data decode at offset {}", data_start);
+        debug!("Starting raw data decode at offset {}", data_start);
 
         // For now we're implementing basic raw data reading
         // In a full implementation, we'd need to:
@@ -212,10 +212,11 @@ impl<'a> Cr3Decoder<'a> {
         let row_size = width * bytes_per_pixel;
 
         for row in 0..height {
+            check_abort()?;
             let mut row_data = vec![0u8; row_size];
             if let Err(e) = cursor.read_exact(&mut row_data) {
-                return Err(format!("Failed to read raw data at offset {} (row {}): {}",
-                    cursor.position(), row, e));
+                return Err(RawLoaderError::Corrupt(format!("Failed to read raw data at offset {} (row {}): {}",
+                    cursor.position(), row, e)));
             }
 
             for col in 0..width {
@@ -229,7 +230,7 @@ impl<'a> Cr3Decoder<'a> {
                         row_data[pixel_offset],
                         row_data[pixel_offset + 1]
                     ]),
-                    _ => return Err("Unsupported bit depth".to_string()),
+                    _ => return Err(RawLoaderError::Unimplemented("Unsupported bit depth".to_string())),
                 }
 
                 image[row * width + col] = pixel_value;
@@ -241,7 +242,11 @@ impl<'a> Cr3Decoder<'a> {
 }
 
 impl<'a> Decoder for Cr3Decoder<'a> {
-    fn image(&self, dummy: bool) -> Result<RawImage, String> {
+    fn format(&self) -> &'static str {
+      "CR3"
+    }
+
+    fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
         let mut camera = if let Some(ref tiff) = self.tiff {
             self.rawloader.check_supported(tiff)?
         } else {
@@ -261,7 +266,7 @@ impl<'a> Decoder for Cr3Decoder<'a> {
         let mut craw_header = None;
 
         // Parse boxes recursively until we find CRAW
-        fn find_craw_box(cursor: &mut Cursor<&[u8]>, decoder: &Cr3Decoder, end_pos: Option<u64>) -> Result<Option<CrawHeader>, String> {
+        fn find_craw_box(cursor: &mut Cursor<&[u8]>, decoder: &Cr3Decoder, end_pos: Option<u64>) -> Result<Option<CrawHeader>, RawLoaderError> {
             while let Ok(box_header) = Cr3Decoder::read_box(cursor) {
                 let box_end = box_header.offset + box_header.size as u64;
                 
@@ -274,28 +279,28 @@ impl<'a> Decoder for Cr3Decoder<'a> {
                 
                 // Convert box type to string for logging
                 let box_type = String::from_utf8_lossy(&box_header.box_type);
-                eprintln!("Found box: {} at offset {} (size: {}, data offset: {})",
+                debug!("Found box: {} at offset {} (size: {}, data offset: {})",
                     box_type, box_header.offset, box_header.size, box_header.data_offset);
 
                 if box_header.box_type == BOX_TYPE_CRAW {
-                    eprintln!("Found CRAW box at offset {}", box_header.offset);
+                    debug!("Found CRAW box at offset {}", box_header.offset);
                     // Move to the data portion of the CRAW box
                     if let Err(e) = cursor.seek(SeekFrom::Start(box_header.data_offset)) {
-                        return Err(format!("Failed to seek to CRAW data: {}", e));
+                        return Err(RawLoaderError::Corrupt(format!("Failed to seek to CRAW data: {}", e)));
                     }
                     let header = decoder.parse_craw_header(cursor)?;
-                    eprintln!("Successfully parsed CRAW header: {}x{} @ {} bit",
+                    debug!("Successfully parsed CRAW header: {}x{} @ {} bit",
                         header.width, header.height, header.bit_depth);
                     return Ok(Some(header));
                 }
                 
                 // Only recurse into container boxes
                 if box_header.size > 8 && is_container_box(&box_header.box_type) {
-                    eprintln!("Entering container box: {} at offset {}", String::from_utf8_lossy(&box_header.box_type), box_header.offset);
+                    debug!("Entering container box: {} at offset {}", String::from_utf8_lossy(&box_header.box_type), box_header.offset);
                     
                     // Move to the data portion of the box
                     if let Err(e) = cursor.seek(SeekFrom::Start(box_header.data_offset)) {
-                        return Err(format!("Failed to seek in file: {}", e));
+                        return Err(RawLoaderError::Corrupt(format!("Failed to seek in file: {}", e)));
                     }
                     
                     // Recursively check this container box
@@ -303,12 +308,12 @@ impl<'a> Decoder for Cr3Decoder<'a> {
                         return Ok(Some(header));
                     }
                     
-                    eprintln!("Exiting container box: {} at offset {}", String::from_utf8_lossy(&box_header.box_type), box_header.offset);
+                    debug!("Exiting container box: {} at offset {}", String::from_utf8_lossy(&box_header.box_type), box_header.offset);
                 }
                 
                 // Skip to next box
                 if let Err(e) = cursor.seek(SeekFrom::Start(box_end)) {
-                    return Err(format!("Failed to seek in file: {}", e));
+                    return Err(RawLoaderError::Corrupt(format!("Failed to seek in file: {}", e)));
                 }
             }
             Ok(None)
@@ -316,7 +321,7 @@ impl<'a> Decoder for Cr3Decoder<'a> {
 
         craw_header = find_craw_box(&mut cursor, self, None)?;
 
-        let header = craw_header.ok_or("Could not find CRAW box")?;
+        let header = craw_header.ok_or_else(|| RawLoaderError::Corrupt("Could not find CRAW box".to_string()))?;
         let width = header.width as usize;
         let height = header.height as usize;
 
@@ -80,15 +80,19 @@ impl<'a> CrwDecoder<'a> {
 }
 
 impl<'a> Decoder for CrwDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "CRW"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let makemodel = fetch_tag!(self.ciff, CiffTag::MakeModel).get_strings();
     if makemodel.len() < 2 {
-      return Err("CRW: MakeModel tag needs to have 2 strings".to_string())
+      return Err(RawLoaderError::Corrupt("CRW: MakeModel tag needs to have 2 strings".to_string()))
     }
     let camera = self.rawloader.check_supported_with_everything(&makemodel[0], &makemodel[1], "")?;
 
     let (width, height, image) = if camera.model == "Canon PowerShot Pro70" {
-      (1552,1024,decode_10le_lsb16(&self.buffer[26..], 1552, 1024, dummy))
+      (1552,1024,decode_10le_lsb16(&self.buffer[26..], 1552, 1024, dummy)?)
     } else {
       let sensorinfo = fetch_tag!(self.ciff, CiffTag::SensorInfo);
       let width = sensorinfo.get_usize(1);
@@ -97,12 +101,14 @@ impl<'a> Decoder for CrwDecoder<'a> {
     };
 
     let wb = self.get_wb(&camera)?;
-    ok_image(camera, width, height, wb, image)
+    let mut img = RawImage::new(camera, width, height, wb, image, false);
+    img.exif = exif::Exif::from_ciff(&self.ciff);
+    Ok(img)
   }
 }
 
 impl<'a> CrwDecoder<'a> {
-  fn get_wb(&self, cam: &Camera) -> Result<[f32;4], String> {
+  fn get_wb(&self, cam: &Camera) -> Result<[f32;4], RawLoaderError> {
     if let Some(levels) = self.ciff.find_entry(CiffTag::WhiteBalance) {
       let offset = cam.wb_offset;
       return Ok([levels.get_f32(offset+0), levels.get_f32(offset+1), levels.get_f32(offset+3), NAN])
@@ -154,17 +160,17 @@ impl<'a> CrwDecoder<'a> {
     htable
   }
 
-  fn decode_compressed(&self, cam: &Camera, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>,String> {
+  fn decode_compressed(&self, cam: &Camera, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     let lowbits = !cam.find_hint("nolowbits");
     let dectable = fetch_tag!(self.ciff, CiffTag::DecoderTable).get_usize(0);
     if dectable > 2 {
-      return Err(format!("CRW: Unknown decoder table {}", dectable).to_string())
+      return Err(RawLoaderError::Unimplemented(format!("CRW: Unknown decoder table {}", dectable)))
     }
-    Ok(Self::do_decode(&self.buffer, lowbits, dectable, width, height, dummy))
+    Self::do_decode(&self.buffer, lowbits, dectable, width, height, dummy)
   }
 
-  pub(crate) fn do_decode(buffer: &[u8], lowbits: bool, dectable: usize, width: usize, height: usize, dummy: bool) -> Vec<u16> {
-    let mut out = alloc_image!(width, height, dummy);
+  pub(crate) fn do_decode(buffer: &[u8], lowbits: bool, dectable: usize, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+    let mut out = alloc_image_ok!(width, height, dummy);
 
     let htables = Self::create_hufftables(dectable);
     let offset = 540 + (lowbits as usize)*height*width/4;
@@ -227,6 +233,6 @@ impl<'a> CrwDecoder<'a> {
         }
       }
     }
-    out
+    Ok(out)
   }
 }
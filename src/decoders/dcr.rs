@@ -23,13 +23,17 @@ impl<'a> DcrDecoder<'a> {
 }
 
 impl<'a> Decoder for DcrDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "DCR"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let camera = self.rawloader.check_supported(&self.tiff)?;
     let raw = fetch_ifd!(&self.tiff, Tag::CFAPattern);
     let width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0);
     let height = fetch_tag!(raw, Tag::ImageLength).get_usize(0);
     let offset = fetch_tag!(raw, Tag::StripOffsets).get_usize(0);
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
 
     let linearization = fetch_tag!(self.tiff, Tag::DcrLinearization);
     let curve = {
@@ -40,14 +44,14 @@ impl<'a> Decoder for DcrDecoder<'a> {
       LookupTable::new(&points)
     };
 
-    let image = DcrDecoder::decode_kodak65000(src, &curve, width, height, dummy);
+    let image = DcrDecoder::decode_kodak65000(src, &curve, width, height, dummy)?;
 
     ok_image(camera, width, height, self.get_wb()?, image)
   }
 }
 
 impl<'a> DcrDecoder<'a> {
-  fn get_wb(&self) -> Result<[f32;4], String> {
+  fn get_wb(&self) -> Result<[f32;4], RawLoaderError> {
     let dcrwb = fetch_tag!(self.tiff, Tag::DcrWB);
     if dcrwb.count() >= 46 {
       let levels = dcrwb.get_data();
@@ -60,26 +64,27 @@ impl<'a> DcrDecoder<'a> {
     }
   }
 
-  pub(crate) fn decode_kodak65000(buf: &[u8], curve: &LookupTable, width: usize, height: usize, dummy: bool) -> Vec<u16> {
-    let mut out: Vec<u16> = alloc_image!(width, height, dummy);
+  pub(crate) fn decode_kodak65000(buf: &[u8], curve: &LookupTable, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+    let mut out: Vec<u16> = alloc_image_ok!(width, height, dummy);
     let mut input = ByteStream::new(buf, LITTLE_ENDIAN);
 
     let mut random: u32 = 0;
     for row in 0..height {
+      check_abort()?;
       for col in (0..width).step_by(256) {
         let mut pred: [i32;2] = [0;2];
         let buf = DcrDecoder::decode_segment(&mut input, cmp::min(256, width-col));
         for (i,val) in buf.iter().enumerate() {
           pred[i & 1] += *val;
           if pred[i & 1] < 0 {
-            panic!("Found a negative pixel!");
+            return Err(RawLoaderError::Corrupt("DCR: Found a negative pixel!".to_string()))
           }
           out[row*width+col+i] = curve.dither(pred[i & 1] as u16, &mut random);
         }
       }
     }
 
-    out
+    Ok(out)
   }
 
   fn decode_segment(input: &mut ByteStream, size: usize) -> Vec<i32> {
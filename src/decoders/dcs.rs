@@ -22,7 +22,11 @@ impl<'a> DcsDecoder<'a> {
 }
 
 impl<'a> Decoder for DcsDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "DCS"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let camera = self.rawloader.check_supported(&self.tiff)?;
     let data = self.tiff.find_ifds_with_tag(Tag::StripOffsets);
     let raw = data.iter().find(|&&ifd| {
@@ -31,7 +35,7 @@ impl<'a> Decoder for DcsDecoder<'a> {
     let width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0);
     let height = fetch_tag!(raw, Tag::ImageLength).get_usize(0);
     let offset = fetch_tag!(raw, Tag::StripOffsets).get_usize(0);
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
     let linearization = fetch_tag!(self.tiff, Tag::GrayResponse);
     let table = {
       let mut t: [u16;256] = [0;256];
@@ -41,7 +45,7 @@ impl<'a> Decoder for DcsDecoder<'a> {
       LookupTable::new(&t)
     };
 
-    let image = decode_8bit_wtable(src, &table, width, height, dummy);
+    let image = decode_8bit_wtable(src, &table, width, height, dummy)?;
     ok_image(camera, width, height, [NAN,NAN,NAN,NAN], image)
   }
 }
@@ -0,0 +1,156 @@
+// Bilinear demosaic of CFA images into interleaved RGB, as a quick way to a full color image
+
+use rayon::prelude::*;
+
+use crate::decoders::*;
+use crate::decoders::cfa::{CFA, CFAColor};
+
+pub(crate) fn bilinear(image: &RawImage) -> Result<RawImage, RawLoaderError> {
+  if image.cpp == 3 {
+    return Ok(image.clone())
+  }
+  if image.cpp != 1 || image.is_monochrome() || image.cfa.colors() != [CFAColor::Red, CFAColor::Green, CFAColor::Blue] {
+    return Err(RawLoaderError::Unimplemented(format!("demosaicing a {} image into RGB",
+      if image.cpp == 1 { image.cfa.to_string() } else { format!("{} components per pixel", image.cpp) })))
+  }
+  if image.width == 0 || image.height == 0 || image.data_len() != image.width * image.height {
+    return Err(RawLoaderError::Corrupt("image has no data to demosaic".to_string()))
+  }
+
+  let data = match image.data {
+    RawImageData::Integer(ref data) => RawImageData::Integer(interpolate(image, data, |v| v.round() as u16)?),
+    RawImageData::Float(ref data) => RawImageData::Float(interpolate(image, data, |v| v)?),
+  };
+  let mut out = image.clone();
+  out.cpp = 3;
+  out.data = data;
+  out.cfa = CFA::new("");
+  Ok(out)
+}
+
+// Each color a pixel doesn't have is the average of that color in its 3x3 neighborhood, or the
+// 5x5 one for patterns like X-Trans that can leave it out, without mixing the pixels inside
+// and outside of crops
+fn interpolate<T, F>(image: &RawImage, data: &[T], convert: F) -> Result<Vec<T>, RawLoaderError>
+  where T: Copy + Default + Send + Sync + Into<f64>, F: Fn(f32) -> T + Sync {
+  let (width, height) = (image.width, image.height);
+  let full = Rect::new(0, 0, width, height);
+  let active = image.areas().active_area;
+  let inside = |area: &Rect, row: usize, col: usize| {
+    row >= area.y && row < area.y + area.height && col >= area.x && col < area.x + area.width
+  };
+
+  let mut out = Vec::new();
+  out.try_reserve_exact(width * height * 3).map_err(|_| RawLoaderError::LimitExceeded(
+    format!("Couldn't allocate {} values for the image", width * height * 3)))?;
+  out.resize(width * height * 3, T::default());
+  out.par_chunks_exact_mut(width * 3).enumerate().for_each(|(row, line)| {
+    for (col, pix) in line.chunks_exact_mut(3).enumerate() {
+      let area = if inside(&active, row, col) { &active } else { &full };
+      let own = image.cfa.color_at(row, col);
+      for (color, val) in pix.iter_mut().enumerate() {
+        if color == own {
+          *val = data[row * width + col];
+          continue
+        }
+        for radius in 1..=2 {
+          let (mut sum, mut count) = (0.0f64, 0);
+          for r in row.saturating_sub(radius).max(area.y)..(row + radius + 1).min(area.y + area.height) {
+            for c in col.saturating_sub(radius).max(area.x)..(col + radius + 1).min(area.x + area.width) {
+              if image.cfa.color_at(r, c) == color {
+                sum += data[r * width + c].into();
+                count += 1;
+              }
+            }
+          }
+          if count > 0 {
+            *val = convert((sum / count as f64) as f32);
+            break
+          }
+        }
+      }
+    }
+  });
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // A mosaic of `pattern` sampled from a full color image given by `value`
+  fn mosaic(pattern: &str, width: usize, height: usize, value: impl Fn(usize, usize, usize) -> u16) -> RawImage {
+    let cfa = CFA::new(pattern);
+    let data = (0..width*height).map(|i| value(i / width, i % width, cfa.color_at(i / width, i % width))).collect();
+    let mut image = RawImage::from_data("Make", "Model", width, height, 1, RawImageData::Integer(data));
+    image.cfa = cfa;
+    image
+  }
+
+  fn integers(image: &RawImage) -> &[u16] {
+    match image.data {
+      RawImageData::Integer(ref data) => data,
+      RawImageData::Float(_) => unreachable!(),
+    }
+  }
+
+  #[test]
+  fn flat_colors_stay_flat() {
+    for pattern in ["RGGB", "GBRG", "GGRGGBGGBGGRBRGRBGGGBGGRGGRGGBRBGBRG"] {
+      let image = mosaic(pattern, 13, 11, |_, _, color| 1000 * (color as u16 + 1));
+      let rgb = bilinear(&image).unwrap();
+      assert_eq!((rgb.cpp, rgb.width, rgb.height), (3, 13, 11));
+      assert_eq!(rgb.cfa.width, 0);
+      for pix in integers(&rgb).chunks_exact(3) {
+        assert_eq!(pix, [1000, 2000, 3000], "{}", pattern);
+      }
+    }
+  }
+
+  #[test]
+  fn ramps_are_interpolated() {
+    let ramp = |row: usize, col: usize, color: usize| (100 * row + 10 * col + 1000 * color) as u16;
+    let (width, height) = (12, 10);
+    let rgb = bilinear(&mosaic("RGGB", width, height, ramp)).unwrap();
+    let data = integers(&rgb);
+    // Away from the edges the neighbors of each color are symmetric around the pixel
+    for row in 1..height-1 {
+      for col in 1..width-1 {
+        for color in 0..3 {
+          assert_eq!(data[(row*width + col)*3 + color], ramp(row, col, color), "pixel at {},{}", row, col);
+        }
+      }
+    }
+    // The corner only has neighbors on one side, the blue of the red pixel is the one at 1,1
+    assert_eq!(&data[0..3], [ramp(0, 0, 0), ramp(0, 0, 1) + 55, ramp(1, 1, 2)]);
+  }
+
+  #[test]
+  fn crops_are_interpolated_on_their_own() {
+    let mut image = mosaic("RGGB", 12, 10, |_, _, color| 1000 * (color as u16 + 1));
+    image.crops = [2, 3, 1, 1];
+    if let RawImageData::Integer(ref mut data) = image.data {
+      for (i, value) in data.iter_mut().enumerate() {
+        let (row, col) = (i / 12, i % 12);
+        if row < 2 || row >= 9 || col < 1 || col >= 9 {
+          *value = 60000;
+        }
+      }
+    }
+    let rgb = bilinear(&image).unwrap();
+    let data = integers(&rgb);
+    for row in 2..9 {
+      for col in 1..9 {
+        assert_eq!(&data[(row*12 + col)*3..][..3], [1000, 2000, 3000], "pixel at {},{}", row, col);
+      }
+    }
+  }
+
+  #[test]
+  fn only_rgb_mosaics_are_demosaiced() {
+    let rgb = RawImage::from_data("Make", "Model", 4, 2, 3, RawImageData::Integer((0..24).collect()));
+    assert_eq!(integers(&bilinear(&rgb).unwrap()), integers(&rgb));
+    let cygm = mosaic("CYGM", 4, 4, |_, _, color| color as u16);
+    assert!(matches!(bilinear(&cygm), Err(RawLoaderError::Unimplemented(_))));
+  }
+}
@@ -0,0 +1,161 @@
+// The simple development behind to_dynamic_image(), write_tiff() and write_exr(), giving
+// data with the crop of crops applied
+#[cfg(feature = "image")] use ::image::{DynamicImage, ImageBuffer};
+#[cfg(feature = "image")] use ::image::metadata::Orientation as ImageOrientation;
+use rayon::prelude::*;
+
+use crate::decoders::*;
+
+// sRGB D65
+const RGB_TO_XYZ: [[f32;3];3] = [
+  [ 0.412453, 0.357580, 0.180423 ],
+  [ 0.212671, 0.715160, 0.072169 ],
+  [ 0.019334, 0.119193, 0.950227 ],
+];
+
+// The area of the image to convert, if it has data
+fn area(image: &RawImage) -> Result<Rect, RawLoaderError> {
+  let area = image.areas().active_area;
+  if area.is_empty() || image.data_len() != image.width * image.height * image.cpp {
+    return Err(RawLoaderError::Corrupt("image has no data to convert".to_string()))
+  }
+  Ok(area)
+}
+
+#[cfg(feature = "image")]
+fn buffer<P: ::image::Pixel<Subpixel=u16>>(area: &Rect, data: Vec<u16>) -> Result<ImageBuffer<P, Vec<u16>>, RawLoaderError> {
+  ImageBuffer::from_raw(area.width as u32, area.height as u32, data)
+    .ok_or_else(|| RawLoaderError::LimitExceeded(format!("{}x{} image is too big", area.width, area.height)))
+}
+
+// The undemosaiced data, with floating point values rounded
+pub(crate) fn cfa_data(image: &RawImage) -> Result<(Rect, Vec<u16>), RawLoaderError> {
+  if image.cpp != 1 {
+    return Err(RawLoaderError::Unimplemented(format!("CFA output of an image with {} components per pixel", image.cpp)))
+  }
+  let area = area(image)?;
+  let mut out = alloc_vec(area.width * area.height, 0)?;
+  for (row, line) in out.chunks_exact_mut(area.width).enumerate() {
+    let start = (area.y + row) * image.width + area.x;
+    match image.data {
+      RawImageData::Integer(ref data) => line.copy_from_slice(&data[start..start+area.width]),
+      RawImageData::Float(ref data) => for (o, v) in line.iter_mut().zip(&data[start..start+area.width]) {
+        *o = v.round().clamp(0.0, 65535.0) as u16;
+      },
+    }
+  }
+  Ok((area, out))
+}
+
+#[cfg(feature = "image")]
+pub(crate) fn cfa(image: &RawImage) -> Result<DynamicImage, RawLoaderError> {
+  let (area, out) = cfa_data(image)?;
+  Ok(DynamicImage::ImageLuma16(buffer(&area, out)?))
+}
+
+// Matrix from white balanced camera colors to linear sRGB, with RGB images without a color
+// matrix taken to already be sRGB
+fn rgb_cam(image: &RawImage) -> [[f32;4];3] {
+  if image.cpp == 3 && !image.has_color_matrix() {
+    return [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]]
+  }
+  let xyz_to_cam = if image.has_color_matrix() { image.xyz_to_cam } else { image.cfa.ideal_xyz_to_cam() };
+  let mut cam_rgb = [[0.0;3];4];
+  for i in 0..4 {
+    for j in 0..3 {
+      for k in 0..3 {
+        cam_rgb[i][j] += xyz_to_cam[i][k] * RGB_TO_XYZ[k][j];
+      }
+    }
+  }
+  RawImage::normalized_pseudoinverse(cam_rgb)
+}
+
+fn gamma(v: f32) -> u16 {
+  let v = v.clamp(0.0, 1.0);
+  let v = if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0/2.4) - 0.055 };
+  (v * 65535.0).round() as u16
+}
+
+// Gamma encoded sRGB, without the orientation applied
+pub(crate) fn rgb_data(image: &RawImage) -> Result<(Rect, Vec<u16>), RawLoaderError> {
+  develop(image, gamma)
+}
+
+// Linear sRGB with white at 1.0 and the highlights going over it, without the orientation
+// applied
+#[cfg(feature = "exr")]
+pub(crate) fn rgb_linear(image: &RawImage) -> Result<(Rect, Vec<f32>), RawLoaderError> {
+  develop(image, |v| v)
+}
+
+fn develop<T, F>(image: &RawImage, encode: F) -> Result<(Rect, Vec<T>), RawLoaderError>
+  where T: Copy + Default + Send, F: Fn(f32) -> T + Sync {
+  let mono = image.is_monochrome();
+  if image.cpp != 1 && image.cpp != 3 {
+    return Err(RawLoaderError::Unimplemented(format!("developing an image with {} components per pixel", image.cpp)))
+  }
+
+  let wb = if image.wb_coeffs.iter().take(3).all(|c| c.is_finite() && *c > 0.0) { image.wb_coeffs } else { image.neutralwb() };
+  let mut scale = [0.0f32;4];
+  for c in 0..4 {
+    let range = image.whitelevels[c] as f32 - image.blacklevels[c] as f32;
+    let coeff = if wb[c].is_finite() && wb[c] > 0.0 { wb[c] / wb[1] } else { 1.0 };
+    scale[c] = if range > 0.0 && !mono { coeff / range } else if range > 0.0 { 1.0 / range } else { 0.0 };
+  }
+  let value = |idx: usize, color: usize| {
+    (image.data.get_f32(idx) - image.blacklevels[color] as f32).max(0.0) * scale[color]
+  };
+  let rgb_cam = rgb_cam(image);
+
+  let area = area(image)?;
+  let mut out = alloc_vec(area.width * area.height * 3, T::default())?;
+  out.par_chunks_exact_mut(area.width * 3).enumerate().for_each(|(row, line)| {
+    let row = area.y + row;
+    for (col, pix) in line.chunks_exact_mut(3).enumerate() {
+      let col = area.x + col;
+      let idx = row * image.width + col;
+      let mut cam = [0.0f32;4];
+      if image.cpp == 3 {
+        for (c, val) in cam.iter_mut().take(3).enumerate() {
+          *val = value(idx*3 + c, c);
+        }
+      } else if mono {
+        let v = value(idx, 0);
+        pix.copy_from_slice(&[encode(v); 3]);
+        continue
+      } else {
+        // Average each color over the 3x3 neighborhood, the pixel's own color is its value
+        let mut count = [0u32;4];
+        for r in row.saturating_sub(1).max(area.y)..(row+2).min(area.y+area.height) {
+          for c in col.saturating_sub(1).max(area.x)..(col+2).min(area.x+area.width) {
+            let color = image.cfa.color_at(r, c);
+            cam[color] += value(r * image.width + c, color);
+            count[color] += 1;
+          }
+        }
+        for c in 0..4 {
+          if count[c] > 0 {
+            cam[c] /= count[c] as f32;
+          }
+        }
+        let own = image.cfa.color_at(row, col);
+        cam[own] = value(idx, own);
+      }
+      for (i, p) in pix.iter_mut().enumerate() {
+        *p = encode((0..4).map(|c| rgb_cam[i][c] * cam[c]).sum());
+      }
+    }
+  });
+  Ok((area, out))
+}
+
+#[cfg(feature = "image")]
+pub(crate) fn rgb(image: &RawImage) -> Result<DynamicImage, RawLoaderError> {
+  let (area, out) = rgb_data(image)?;
+  let mut developed = DynamicImage::ImageRgb16(buffer(&area, out)?);
+  if let Some(orientation) = ImageOrientation::from_exif(image.orientation.to_u16() as u8) {
+    developed.apply_orientation(orientation);
+  }
+  Ok(developed)
+}
@@ -1,11 +1,15 @@
-use std::f32::NAN;
 use std::cmp;
+use rayon::prelude::*;
 
 use crate::decoders::*;
 use crate::decoders::tiff::*;
 use crate::decoders::basics::*;
 use crate::decoders::ljpeg::*;
 use crate::decoders::cfa::*;
+use crate::decoders::opcodes::*;
+use crate::decoders::color::ColorMatrices;
+use crate::decoders::noise::NoiseModel;
+use crate::decoders::masks::SemanticMask;
 
 #[derive(Debug, Clone)]
 pub struct DngDecoder<'a> {
@@ -25,25 +29,40 @@ impl<'a> DngDecoder<'a> {
 }
 
 impl<'a> Decoder for DngDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
-    let ifds = self.tiff.find_ifds_with_tag(Tag::Compression).into_iter().filter(|ifd| {
-      let compression = (**ifd).find_entry(Tag::Compression).unwrap().get_u32(0);
-      let subsampled = match (**ifd).find_entry(Tag::NewSubFileType) {
-        Some(e) => e.get_u32(0) & 1 != 0,
-        None => false,
-      };
-      !subsampled && (compression == 7 || compression == 1 || compression == 0x884c)
-    }).collect::<Vec<&TiffIFD>>();
-    let raw = ifds[0];
+  fn format(&self) -> &'static str {
+    "DNG"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
+    self.image_at(0, dummy)
+  }
+
+  fn image_count(&self) -> usize {
+    self.raw_ifds().len().max(1)
+  }
+
+  fn image_at(&self, index: usize, dummy: bool) -> Result<RawImage, RawLoaderError> {
+    let ifds = self.raw_ifds();
+    let raw = match ifds.get(index) {
+      Some(raw) => *raw,
+      None if index == 0 => return Err(RawLoaderError::Corrupt("DNG: couldn't find the raw image".to_string())),
+      None => return Err(RawLoaderError::NoSuchImage { index, count: ifds.len() }),
+    };
     let width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0);
     let height = fetch_tag!(raw, Tag::ImageLength).get_usize(0);
     let cpp = fetch_tag!(raw, Tag::SamplesPerPixel).get_usize(0);
-    let linear = fetch_tag!(raw, Tag::PhotometricInt).get_usize(0) == 34892;
+    let photometric = fetch_tag!(raw, Tag::PhotometricInt).get_usize(0);
+    let linear = photometric == 34892;
+    // Single component images that aren't CFA data come from monochrome sensors
+    let monochrome = cpp == 1 && (linear || photometric == 1);
 
+    let float = raw.find_entry(Tag::SampleFormat).is_some_and(|e| e.get_u32(0) == 3);
     let image = match fetch_tag!(raw, Tag::Compression).get_u32(0) {
-      1 => self.decode_uncompressed(raw, width*cpp, height, dummy)?,
-      7 => self.decode_compressed(raw, width*cpp, height, cpp, dummy)?,
-      c => return Err(format!("Don't know how to read DNGs with compression {}", c).to_string()),
+      1 if float => RawImageData::Float(self.decode_uncompressed_float(raw, width*cpp, height, dummy)?),
+      1 => RawImageData::Integer(self.decode_uncompressed(raw, width*cpp, height, dummy)?),
+      7 => RawImageData::Integer(self.decode_compressed(raw, width*cpp, height, cpp, dummy)?),
+      8 if float => RawImageData::Float(self.decode_deflate_float(raw, width, height, cpp, dummy)?),
+      c => return Err(RawLoaderError::Unimplemented(format!("Don't know how to read DNGs with compression {}", c))),
     };
 
     let (make, model, clean_make, clean_model, orientation) = {
@@ -62,6 +81,20 @@ impl<'a> Decoder for DngDecoder<'a> {
       }
     };
 
+    let cfa = if monochrome {
+      CFA::new_monochrome()
+    } else if linear {
+      CFA::new("")
+    } else {
+      self.get_cfa(raw)?
+    };
+    let planes = self.get_plane_indexes(raw, &cfa);
+    let blacklevels = self.get_blacklevels(raw, &cfa)?;
+    let whitelevels = self.get_whitelevels(raw)?;
+    let crops = self.get_crops(raw, width, height)?;
+    let (default_crop, display_crop) = self.get_default_crop(raw, &crops);
+    let opcodes = DngOpcodes::from_tiff(raw);
+
     Ok(RawImage {
       make: make,
       model: model,
@@ -70,30 +103,157 @@ impl<'a> Decoder for DngDecoder<'a> {
       width: width,
       height: height,
       cpp: cpp,
-      wb_coeffs: self.get_wb()?,
-      data: RawImageData::Integer(image),
-      blacklevels: self.get_blacklevels(raw)?,
-      whitelevels: self.get_whitelevels(raw)?,
-      xyz_to_cam: self.get_color_matrix()?,
-      cfa: if linear {CFA::new("")} else {self.get_cfa(raw)?},
-      crops: self.get_crops(raw, width, height)?,
+      wb_coeffs: self.get_wb(&planes)?,
+      data: image,
+      blacklevels,
+      whitelevels,
+      camera_blacklevels: blacklevels,
+      camera_whitelevels: whitelevels,
+      measured_blacklevels: None,
+      xyz_to_cam: self.get_color_matrix(&planes)?,
+      color_matrices: ColorMatrices::from_tiff(&self.tiff, &planes),
+      rendering_hints: RenderingHints::from_tiff(&self.tiff),
+      noise_profile: NoiseModel::from_tiff(raw, &planes, if cpp == 1 { cfa.colors().len() } else { cpp }),
+      masks: if dummy { Vec::new() } else { self.get_masks() },
+      calibration: None,
+      image_count: 1,
+      cfa,
+      crops,
+      default_crop,
+      display_crop,
+      geometry: SensorGeometry::Rectangular,
       blackareas: self.get_masked_areas(raw),
+      bad_pixels: opcodes.bad_pixels(&crops, width, height),
+      opcodes,
       orientation: orientation,
+      exif: exif::Exif::from_tiff(&self.tiff),
+      partially_decoded: false,
+      failure_offset: None,
+      warnings: Vec::new(),
     })
   }
 }
 
+// The bits of a floating point DNG, which can have 16, 24 or 32 bit values
+fn float_bps(raw: &TiffIFD) -> Result<usize, RawLoaderError> {
+  match fetch_tag!(raw, Tag::BitsPerSample).get_usize(0) {
+    bps @ (16 | 24 | 32) => Ok(bps),
+    bps => Err(RawLoaderError::Unimplemented(format!("DNG: Don't know about {} bps floating point images", bps))),
+  }
+}
+
+fn read_bits(buf: &[u8], pos: usize, bps: usize, endian: Endian) -> u32 {
+  match bps {
+    16 => endian.ru16(buf, pos) as u32,
+    24 if endian.little() => buf[pos] as u32 | (buf[pos+1] as u32) << 8 | (buf[pos+2] as u32) << 16,
+    24 => (buf[pos] as u32) << 16 | (buf[pos+1] as u32) << 8 | buf[pos+2] as u32,
+    _ => endian.ru32(buf, pos),
+  }
+}
+
+// The value of a half float, the 24 bit floats DNG added or a plain f32 from their bits
+fn float_from_bits(bits: u32, bps: usize) -> f32 {
+  let (exp_bits, mant_bits) = match bps {
+    16 => (5, 10),
+    24 => (7, 16),
+    _ => return f32::from_bits(bits),
+  };
+  let sign = (bits >> (exp_bits + mant_bits)) & 1;
+  let exp = ((bits >> mant_bits) & ((1 << exp_bits) - 1)) as i32;
+  let mant = bits & ((1 << mant_bits) - 1);
+  let bias = (1 << (exp_bits - 1)) - 1;
+  let val = if exp == 0 {
+    // Subnormal, which are still normal as f32
+    mant as f32 * 2f32.powi(1 - bias - mant_bits)
+  } else if exp == (1 << exp_bits) - 1 {
+    if mant == 0 { f32::INFINITY } else { f32::NAN }
+  } else {
+    f32::from_bits((((exp - bias + 127) as u32) << 23) | (mant << (23 - mant_bits)))
+  };
+  if sign == 1 { -val } else { val }
+}
+
+fn lcm(a: usize, b: usize) -> usize {
+  let (mut x, mut y) = (a, b);
+  while y != 0 {
+    let rem = x % y;
+    x = y;
+    y = rem;
+  }
+  a / x * b
+}
+
 impl<'a> DngDecoder<'a> {
-  fn get_wb(&self) -> Result<[f32;4], String> {
+  // The IFDs with raw data in file order, skipping previews as well as transparency masks,
+  // depth maps and semantic masks
+  fn raw_ifds(&self) -> Vec<&TiffIFD<'_>> {
+    self.tiff.find_ifds_with_tag(Tag::Compression).into_iter().filter(|ifd| {
+      let compression = (**ifd).find_entry(Tag::Compression).unwrap().get_u32(0);
+      let auxiliary = match (**ifd).find_entry(Tag::NewSubFileType) {
+        Some(e) => e.get_u32(0) & 0x0d != 0,
+        None => false,
+      } || (**ifd).has_entry(Tag::SemanticName);
+      !auxiliary && (compression == 7 || compression == 1 || compression == 8 || compression == 0x884c)
+    }).collect()
+  }
+
+  // The per-color DNG tags (AsShotNeutral, ColorMatrix*) are in the order given by CFAPlaneColor
+  // so work out which of our CFA color indexes each of those planes ends up in
+  fn get_plane_indexes(&self, raw: &TiffIFD, cfa: &CFA) -> Vec<usize> {
+    match raw.find_entry(Tag::CFAPlaneColor) {
+      Some(planes) if cfa.is_valid() => {
+        (0..planes.count()).map(|i| {
+          match CFAColor::from_tiff(planes.get_u32(i)).and_then(|c| cfa.color_index(c)) {
+            Some(idx) => idx,
+            None => i,
+          }
+        }).collect()
+      },
+      _ => vec![0,1,2,3],
+    }
+  }
+
+  fn get_wb(&self, planes: &[usize]) -> Result<[f32;4], RawLoaderError> {
+    let mut wb = [f32::NAN; 4];
     if let Some(levels) = self.tiff.find_entry(Tag::AsShotNeutral) {
-      Ok([1.0/levels.get_f32(0),1.0/levels.get_f32(1),1.0/levels.get_f32(2),NAN])
-    } else {
-      Ok([NAN,NAN,NAN,NAN])
+      for (i, &idx) in planes.iter().enumerate().take(levels.count()) {
+        if idx < 4 {
+          wb[idx] = 1.0/levels.get_f32(i);
+        }
+      }
     }
+    Ok(wb)
   }
 
-  fn get_blacklevels(&self, raw: &TiffIFD) -> Result<[u16;4], String> {
+  fn get_blacklevels(&self, raw: &TiffIFD, cfa: &CFA) -> Result<[u16;4], RawLoaderError> {
     if let Some(levels) = raw.find_entry(Tag::BlackLevels) {
+      if let Some(dim) = raw.find_entry(Tag::BlackRepeatDim) {
+        // The levels are given per position in the repeat pattern, which for a CFA needs to
+        // be turned into per color levels so a white or yellow channel gets its own value.
+        // Positions of the same color (the two greens of a Bayer pattern) are averaged, over
+        // the area where both patterns repeat, and the indexes no color uses get the
+        // average of all of them
+        let (rows, cols) = (dim.get_usize(0), dim.get_usize(1));
+        if cfa.is_valid() && rows*cols > 1 && levels.count() >= rows*cols {
+          let (lrows, lcols) = (lcm(rows, cfa.height), lcm(cols, cfa.width));
+          let mut sums = [0u64;4];
+          let mut counts = [0u64;4];
+          for row in 0..lrows {
+            for col in 0..lcols {
+              let color = cfa.color_at(row, col);
+              sums[color] += levels.get_f32((row%rows)*cols + col%cols) as u16 as u64;
+              counts[color] += 1;
+            }
+          }
+          let total = (lrows*lcols) as u64;
+          let all = (sums.iter().sum::<u64>() + total/2) / total;
+          let mut blacks = [0;4];
+          for (i, black) in blacks.iter_mut().enumerate() {
+            *black = (sums[i] + counts[i]/2).checked_div(counts[i]).unwrap_or(all) as u16;
+          }
+          return Ok(blacks)
+        }
+      }
       if levels.count() < 4 {
         let black = levels.get_f32(0) as u16;
         Ok([black, black, black, black])
@@ -102,21 +262,30 @@ impl<'a> DngDecoder<'a> {
             levels.get_f32(2) as u16,levels.get_f32(3) as u16])
       }
     } else {
+      warn(DecodeWarning::GuessedBlackLevel);
       Ok([0,0,0,0])
     }
   }
 
-  fn get_whitelevels(&self, raw: &TiffIFD) -> Result<[u16;4], String> {
-    let level = fetch_tag!(raw, Tag::WhiteLevel).get_u32(0) as u16;
+  fn get_whitelevels(&self, raw: &TiffIFD) -> Result<[u16;4], RawLoaderError> {
+    // Floating point data is in the 0 to 1 range when it doesn't say otherwise
+    let level = match raw.find_entry(Tag::WhiteLevel) {
+      None if raw.find_entry(Tag::SampleFormat).is_some_and(|e| e.get_u32(0) == 3) => 1,
+      _ => fetch_tag!(raw, Tag::WhiteLevel).get_u32(0) as u16,
+    };
     Ok([level,level,level,level])
   }
 
-  fn get_cfa(&self, raw: &TiffIFD) -> Result<CFA,String> {
+  fn get_cfa(&self, raw: &TiffIFD) -> Result<CFA, RawLoaderError> {
     let pattern = fetch_tag!(raw, Tag::CFAPattern);
-    Ok(CFA::new_from_tag(pattern))
+    match raw.find_entry(Tag::CFARepeatDim) {
+      // The dimensions are given as rows first and then columns
+      Some(dim) => CFA::new_from_tag_with_size(pattern, dim.get_usize(1), dim.get_usize(0)),
+      None => CFA::new_from_tag(pattern),
+    }
   }
 
-  fn get_crops(&self, raw: &TiffIFD, width: usize, height: usize) -> Result<[usize;4],String> {
+  fn get_crops(&self, raw: &TiffIFD, width: usize, height: usize) -> Result<[usize;4], RawLoaderError> {
     if let Some(crops) = raw.find_entry(Tag::ActiveArea) {
       Ok([crops.get_usize(0), width - crops.get_usize(3),
           height - crops.get_usize(2), crops.get_usize(1)])
@@ -126,16 +295,42 @@ impl<'a> DngDecoder<'a> {
     }
   }
 
+  // The default crop is relative to the ActiveArea and the user crop is in fractions of the
+  // default crop
+  fn get_default_crop(&self, raw: &TiffIFD, crops: &[usize;4]) -> (Option<Rect>, Option<Rect>) {
+    let (origin, size) = match (raw.find_entry(Tag::DefaultCropOrig), raw.find_entry(Tag::DefaultCropSize)) {
+      (Some(o), Some(s)) if o.count() >= 2 && s.count() >= 2 => (o, s),
+      _ => return (None, None),
+    };
+    let default = Rect::new(crops[3] + origin.get_f32(0) as usize, crops[0] + origin.get_f32(1) as usize,
+                            size.get_f32(0) as usize, size.get_f32(1) as usize);
+
+    let user = raw.find_entry(Tag::DefaultUserCrop).filter(|e| e.count() >= 4).and_then(|e| {
+      let (top, left, bottom, right) = (e.get_f32(0), e.get_f32(1), e.get_f32(2), e.get_f32(3));
+      if !(0.0 <= top && top < bottom && bottom <= 1.0 && 0.0 <= left && left < right && right <= 1.0) {
+        return None
+      }
+      if top == 0.0 && left == 0.0 && bottom == 1.0 && right == 1.0 {
+        return None
+      }
+      let (w, h) = (default.width as f32, default.height as f32);
+      Some(Rect::new(default.x + (left * w) as usize, default.y + (top * h) as usize,
+                     ((right - left) * w) as usize, ((bottom - top) * h) as usize))
+    });
+    (Some(default), user)
+  }
+
   fn get_masked_areas(&self, raw: &TiffIFD) -> Vec<(u64, u64, u64, u64)> {
     let mut areas = Vec::new();
 
+    // DNG stores the areas as top, left, bottom, right
     if let Some(masked_area) = raw.find_entry(Tag::MaskedAreas) {
       for x in (0..masked_area.count()).step_by(4) {
         areas.push((
           masked_area.get_u32(x).into(),
-          masked_area.get_u32(x + 1).into(),
+          masked_area.get_u32(x + 3).into(),
           masked_area.get_u32(x + 2).into(),
-          masked_area.get_u32(x + 3).into()
+          masked_area.get_u32(x + 1).into()
         ));
       }
     }
@@ -143,7 +338,7 @@ impl<'a> DngDecoder<'a> {
     areas
   }
 
-  fn get_color_matrix(&self) -> Result<[[f32;3];4],String> {
+  fn get_color_matrix(&self, planes: &[usize]) -> Result<[[f32;3];4], RawLoaderError> {
     let mut matrix: [[f32;3];4] = [[0.0;3];4];
     let cmatrix = {
       if let Some(c) = self.tiff.find_entry(Tag::ColorMatrix2) {
@@ -161,23 +356,90 @@ impl<'a> DngDecoder<'a> {
       }
     };
     if cmatrix.count() > 12 {
-      Err(format!("color matrix supposedly has {} components",cmatrix.count()).to_string())
+      Err(RawLoaderError::Corrupt(format!("color matrix supposedly has {} components",cmatrix.count())))
     } else {
       for i in 0..cmatrix.count() {
-        matrix[i/3][i%3] = cmatrix.get_f32(i);
+        let row = planes.get(i/3).cloned().unwrap_or(i/3);
+        if row < 4 {
+          matrix[row][i%3] = cmatrix.get_f32(i);
+        }
       }
       Ok(matrix)
     }
   }
 
-  pub fn decode_uncompressed(&self, raw: &TiffIFD, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>,String> {
+  // Masks that can't be read are left out instead of failing the whole image
+  fn get_masks(&self) -> Vec<SemanticMask> {
+    self.tiff.find_ifds_with_tag(Tag::NewSubFileType).into_iter().filter_map(|ifd| {
+      let name = match ifd.find_entry(Tag::SemanticName) {
+        Some(name) => name.get_str().to_string(),
+        None if ifd.find_entry(Tag::NewSubFileType).is_some_and(|e| e.get_u32(0) & 0x08 != 0) => "Depth".to_string(),
+        None => return None,
+      };
+      let (width, height, whitelevel, data) = self.decode_mask(ifd).ok()?;
+      let rational = |tag| ifd.find_entry(tag).filter(|e| e.count() > 0).map(|e| e.get_f32(0));
+      Some(SemanticMask {
+        name,
+        instance_id: ifd.find_entry(Tag::SemanticInstID).map(|e| e.get_str().to_string()),
+        width,
+        height,
+        sub_area: ifd.find_entry(Tag::MaskSubArea).filter(|e| e.count() == 4)
+          .map(|e| [e.get_u32(0), e.get_u32(1), e.get_u32(2), e.get_u32(3)]),
+        depth_range: rational(Tag::DepthNear).zip(rational(Tag::DepthFar)),
+        whitelevel,
+        data,
+      })
+    }).collect()
+  }
+
+  fn decode_mask(&self, ifd: &TiffIFD) -> Result<(usize, usize, u16, Vec<u16>), RawLoaderError> {
+    let width = fetch_tag!(ifd, Tag::ImageWidth).get_usize(0);
+    let height = fetch_tag!(ifd, Tag::ImageLength).get_usize(0);
+    let bps = ifd.find_entry(Tag::BitsPerSample).map(|e| e.get_u32(0)).unwrap_or(8);
+    if bps == 0 || bps > 16 || ifd.find_entry(Tag::SamplesPerPixel).map_or(1, |e| e.get_u32(0)) != 1 {
+      return Err(RawLoaderError::Corrupt("DNG: masks need to be a single plane of up to 16 bits".to_string()))
+    }
+    let whitelevel = ((1u32 << bps) - 1) as u16;
+
+    let data = match fetch_tag!(ifd, Tag::Compression).get_u32(0) {
+      1 => {
+        // Put the strips together as they can be split in several
+        let offsets = fetch_tag!(ifd, Tag::StripOffsets);
+        let counts = fetch_tag!(ifd, Tag::StripByteCounts);
+        let mut src = Vec::new();
+        for i in 0..offsets.count().min(counts.count()) {
+          let start = offsets.get_usize(i);
+          match self.buffer.get(start..start+counts.get_usize(i)) {
+            Some(strip) => src.extend_from_slice(strip),
+            None => return Err(RawLoaderError::Corrupt("DNG: mask strip is out of bounds".to_string())),
+          }
+        }
+        match bps {
+          8 if src.len() >= width*height => src[..width*height].iter().map(|&v| v as u16).collect(),
+          16 if src.len() >= width*height*2 => {
+            if self.tiff.little_endian() {
+              decode_16le(&src, width, height, false)?
+            } else {
+              decode_16be(&src, width, height, false)?
+            }
+          },
+          _ => return Err(RawLoaderError::Unimplemented(format!("DNG: can't read {} bps masks", bps))),
+        }
+      },
+      7 if ifd.has_entry(Tag::StripOffsets) => self.decode_compressed(ifd, width, height, 1, false)?,
+      c => return Err(RawLoaderError::Unimplemented(format!("DNG: can't read masks with compression {}", c))),
+    };
+    Ok((width, height, whitelevel, data))
+  }
+
+  pub fn decode_uncompressed(&self, raw: &TiffIFD, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     let offset = fetch_tag!(raw, Tag::StripOffsets).get_usize(0);
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
 
     match fetch_tag!(raw, Tag::BitsPerSample).get_u32(0) {
-      16  => Ok(decode_16le(src, width, height, dummy)),
-      12  => Ok(decode_12be(src, width, height, dummy)),
-      10  => Ok(decode_10le(src, width, height, dummy)),
+      16  => decode_16le(src, width, height, dummy),
+      12  => decode_12be(src, width, height, dummy),
+      10  => decode_10le(src, width, height, dummy),
       8   => {
         // It's 8 bit so there will be linearization involved surely!
         let linearization = fetch_tag!(self.tiff, Tag::Linearization);
@@ -188,19 +450,107 @@ impl<'a> DngDecoder<'a> {
           }
           LookupTable::new(&points)
         };
-        Ok(decode_8bit_wtable(src, &curve, width, height, dummy))
+        decode_8bit_wtable(src, &curve, width, height, dummy)
       },
-      bps => Err(format!("DNG: Don't know about {} bps images", bps).to_string()),
+      bps => Err(RawLoaderError::Unimplemented(format!("DNG: Don't know about {} bps images", bps))),
+    }
+  }
+
+  pub fn decode_uncompressed_float(&self, raw: &TiffIFD, width: usize, height: usize, dummy: bool) -> Result<Vec<f32>, RawLoaderError> {
+    let offset = fetch_tag!(raw, Tag::StripOffsets).get_usize(0);
+    let src = rest(self.buffer, offset);
+    let bps = float_bps(raw)?;
+    check_size(width, height)?;
+    if dummy {
+      return Ok(vec![0.0])
+    }
+    if src.len() < width*height*bps/8 {
+      return Err(RawLoaderError::Corrupt("DNG: image data ends before the image does".to_string()))
+    }
+    let endian = raw.get_endian();
+    let mut out = alloc_vec(width*height, 0.0)?;
+    for (i, val) in out.iter_mut().enumerate() {
+      *val = float_from_bits(read_bits(src, i*bps/8, bps, endian), bps);
+    }
+    Ok(out)
+  }
+
+  // Deflate compressed floating point data in tiles or strips, with or without the floating
+  // point predictor that puts the bytes of each row in planes and codes their differences
+  pub fn decode_deflate_float(&self, raw: &TiffIFD, width: usize, height: usize, cpp: usize, dummy: bool) -> Result<Vec<f32>, RawLoaderError> {
+    let bps = float_bps(raw)?;
+    let predictor = raw.find_entry(Tag::Predictor).map_or(1, |e| e.get_u32(0));
+    if predictor != 1 && predictor != 3 {
+      return Err(RawLoaderError::Unimplemented(format!("DNG: can't read floating point data with predictor {}", predictor)))
+    }
+    let (offsets, counts, twidth, tlength) = match raw.find_entry(Tag::TileOffsets) {
+      Some(offsets) => (offsets, fetch_tag!(raw, Tag::TileByteCounts),
+                        fetch_tag!(raw, Tag::TileWidth).get_usize(0), fetch_tag!(raw, Tag::TileLength).get_usize(0)),
+      None => (fetch_tag!(raw, Tag::StripOffsets), fetch_tag!(raw, Tag::StripByteCounts), width,
+               raw.find_entry(Tag::RowsPerStrip).map_or(height, |e| e.get_usize(0))),
+    };
+    if twidth == 0 || tlength == 0 {
+      return Err(RawLoaderError::Corrupt("DNG: tiles without a size".to_string()))
+    }
+    let coltiles = (width-1)/twidth + 1;
+    let rowtiles = (height-1)/tlength + 1;
+    if coltiles*rowtiles != offsets.count() || offsets.count() != counts.count() {
+      return Err(RawLoaderError::Corrupt(format!("DNG: trying to decode {} tiles from {} offsets",
+                         coltiles*rowtiles, offsets.count())))
+    }
+    check_size(width*cpp, height)?;
+    if dummy {
+      return Ok(vec![0.0])
+    }
+
+    let endian = raw.get_endian();
+    let bytes = bps / 8;
+    let stride = twidth * cpp;
+    let tiles = (0..offsets.count()).into_par_iter().map(|tile| {
+      let src = rest(self.buffer, offsets.get_usize(tile));
+      let src = &src[..cmp::min(src.len(), counts.get_usize(tile))];
+      let mut data = miniz_oxide::inflate::decompress_to_vec_zlib_with_limit(src, stride*tlength*bytes)
+        .map_err(|_| RawLoaderError::Corrupt("DNG: couldn't inflate a tile".to_string()))?;
+      let rows = data.len() / (stride*bytes);
+      let mut out = Vec::with_capacity(rows*stride);
+      for line in data.chunks_exact_mut(stride*bytes) {
+        if predictor == 3 {
+          for i in cpp..line.len() {
+            line[i] = line[i].wrapping_add(line[i-cpp]);
+          }
+          for i in 0..stride {
+            let bits = (0..bytes).fold(0, |acc, byte| (acc << 8) | line[byte*stride + i] as u32);
+            out.push(float_from_bits(bits, bps));
+          }
+        } else {
+          for i in 0..stride {
+            out.push(float_from_bits(read_bits(line, i*bytes, bps, endian), bps));
+          }
+        }
+      }
+      Ok(out)
+    }).collect::<Result<Vec<Vec<f32>>, RawLoaderError>>()?;
+
+    let mut out = alloc_vec(width*height*cpp, 0.0)?;
+    for (tile, values) in tiles.iter().enumerate() {
+      let (top, left) = ((tile / coltiles) * tlength, (tile % coltiles) * twidth);
+      let bwidth = cmp::min(width, left + twidth) - left;
+      let blength = cmp::min(height, top + tlength) - top;
+      for (row, line) in values.chunks_exact(stride).take(blength).enumerate() {
+        let start = ((top + row) * width + left) * cpp;
+        out[start..start + bwidth*cpp].copy_from_slice(&line[..bwidth*cpp]);
+      }
     }
+    Ok(out)
   }
 
-  pub fn decode_compressed(&self, raw: &TiffIFD, width: usize, height: usize, cpp: usize, dummy: bool) -> Result<Vec<u16>,String> {
+  pub fn decode_compressed(&self, raw: &TiffIFD, width: usize, height: usize, cpp: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     if let Some(offsets) = raw.find_entry(Tag::StripOffsets) { // We're in a normal offset situation
       if offsets.count() != 1 {
-        return Err("DNG: files with more than one slice not supported yet".to_string())
+        return Err(RawLoaderError::Unimplemented("DNG: files with more than one slice not supported yet".to_string()))
       }
       let offset = offsets.get_usize(0);
-      let src = &self.buffer[offset..];
+      let src = rest(self.buffer, offset);
       let mut out = alloc_image_ok!(width, height, dummy);
       let decompressor = LjpegDecompressor::new(src)?;
       decompressor.decode(&mut out, 0, width, width, height, dummy)?;
@@ -212,24 +562,129 @@ impl<'a> DngDecoder<'a> {
       let coltiles = (width-1)/twidth + 1;
       let rowtiles = (height-1)/tlength + 1;
       if coltiles*rowtiles != offsets.count() {
-        return Err(format!("DNG: trying to decode {} tiles from {} offsets",
-                           coltiles*rowtiles, offsets.count()).to_string())
+        return Err(RawLoaderError::Corrupt(format!("DNG: trying to decode {} tiles from {} offsets",
+                           coltiles*rowtiles, offsets.count())))
       }
 
-      Ok(decode_threaded_multiline(width, height, tlength, dummy, &(|strip: &mut [u16], row| {
+      decode_threaded_multiline(width, height, tlength, dummy, &(|strip: &mut [u16], row| {
         let row = row / tlength;
         for col in 0..coltiles {
           let offset = offsets.get_usize(row*coltiles+col);
-          let src = &self.buffer[offset..];
+          let src = rest(self.buffer, offset);
           let decompressor = LjpegDecompressor::new(src).unwrap();
           let bwidth = cmp::min(width, (col+1)*twidth) - col*twidth;
           let blength = cmp::min(height, (row+1)*tlength) - row*tlength;
           // FIXME: instead of unwrap() we need to propagate the error
           decompressor.decode(strip, col*twidth, width, bwidth, blength, dummy).unwrap();
         }
-      })))
+      }))
     } else {
-      Err("DNG: didn't find tiles or strips".to_string())
+      Err(RawLoaderError::Corrupt("DNG: didn't find tiles or strips".to_string()))
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn blacklevels_per_color() {
+    let mut image = RawImage::from_data("Make", "Model", 4, 4, 1, RawImageData::Integer(vec![100; 16]));
+    image.cfa = CFA::new("RGGB");
+    image.blacklevels = [10, 20, 30, 40];
+    image.whitelevels = [4095; 4];
+    let mut dng = Vec::new();
+    crate::dng::write(&image, &mut dng).unwrap();
+    let decoded = crate::decode_slice(&dng).unwrap();
+    // The fourth index isn't used by RGGB and gets the average of the pattern
+    assert_eq!(decoded.blacklevels, [10, 20, 30, 20]);
+  }
+
+  // The value of a float of any size straight from its definition
+  fn float_value(bits: u32, exp_bits: u32, mant_bits: u32) -> f64 {
+    let sign = if (bits >> (exp_bits + mant_bits)) & 1 == 1 { -1.0 } else { 1.0 };
+    let exp = ((bits >> mant_bits) & ((1 << exp_bits) - 1)) as i32;
+    let mant = (bits & ((1 << mant_bits) - 1)) as f64 / (1u64 << mant_bits) as f64;
+    let bias = (1 << (exp_bits - 1)) - 1;
+    if exp == (1 << exp_bits) - 1 {
+      if mant == 0.0 { sign * f64::INFINITY } else { f64::NAN }
+    } else if exp == 0 {
+      sign * mant * 2f64.powi(1 - bias)
+    } else {
+      sign * (1.0 + mant) * 2f64.powi(exp - bias)
+    }
+  }
+
+  #[test]
+  fn floats_from_bits() {
+    for (bps, exp_bits, mant_bits) in [(16, 5, 10), (24, 7, 16)] {
+      for bits in 0..(1u32 << bps) {
+        let expected = float_value(bits, exp_bits, mant_bits);
+        let val = float_from_bits(bits, bps);
+        if expected.is_nan() {
+          assert!(val.is_nan(), "{} bit value {:x}", bps, bits);
+        } else {
+          assert_eq!(val, expected as f32, "{} bit value {:x}", bps, bits);
+        }
+      }
+    }
+    assert_eq!(float_from_bits(0.3f32.to_bits(), 32), 0.3);
+  }
+
+  fn float_roundtrip(data: Vec<f32>, width: usize, height: usize, cpp: usize, options: crate::dng::WriteOptions) {
+    let mut image = RawImage::from_data("Make", "Model", width, height, cpp, RawImageData::Float(data.clone()));
+    if cpp == 1 {
+      image.cfa = CFA::new("RGGB");
+    }
+    image.whitelevels = [1; 4];
+    let mut dng = Vec::new();
+    crate::dng::write_with_options(&image, &options, &mut dng).unwrap();
+    let decoded = crate::decode_slice(&dng).unwrap();
+    assert_eq!((decoded.width, decoded.height, decoded.cpp), (width, height, cpp));
+    match decoded.data {
+      RawImageData::Float(ref values) => {
+        assert_eq!(values.len(), data.len());
+        for (i, (a, b)) in values.iter().zip(data.iter()).enumerate() {
+          assert!(a == b || (a.is_nan() && b.is_nan()), "value {} is {} instead of {}", i, a, b);
+        }
+      },
+      RawImageData::Integer(_) => panic!("float DNG decoded as integers"),
+    }
+  }
+
+  #[test]
+  fn half_floats_roundtrip() {
+    // Every half float, in a 256x256 image that's a single tile
+    let data: Vec<f32> = (0..1u32 << 16).map(|bits| float_value(bits, 5, 10) as f32).collect();
+    for compression in [crate::dng::Compression::Uncompressed, crate::dng::Compression::Deflate] {
+      let options = crate::dng::WriteOptions { compression, half_float: true };
+      float_roundtrip(data.clone(), 256, 256, 1, options);
+    }
+  }
+
+  #[test]
+  fn float_predictor_roundtrip() {
+    // Sizes that aren't a multiple of the tiles, with values that fit in half floats so
+    // both sizes can be checked exactly
+    let values = |len: usize| -> Vec<f32> {
+      (0..len).map(|i| ((i * 7919) % 1000) as f32 / 8.0 - 50.0).collect()
+    };
+    for half_float in [false, true] {
+      let options = crate::dng::WriteOptions { compression: crate::dng::Compression::Deflate, half_float };
+      float_roundtrip(values(300*20), 300, 20, 1, options);
+      float_roundtrip(values(270*5*3), 270, 5, 3, options);
+    }
+    let options = crate::dng::WriteOptions { compression: crate::dng::Compression::Deflate, half_float: false };
+    let data: Vec<f32> = (0..300*3).map(|i| (i as f32 * 0.37).sin() * 1e-3).collect();
+    float_roundtrip(data, 300, 3, 1, options);
+  }
+
+  #[test]
+  fn pattern_lcm() {
+    assert_eq!(lcm(2, 2), 2);
+    assert_eq!(lcm(2, 6), 6);
+    assert_eq!(lcm(4, 6), 12);
+    assert_eq!(lcm(1, 7), 7);
+  }
+}
@@ -0,0 +1,169 @@
+use crate::decoders::*;
+
+// Largest shift between the two halves searched for, in pixels of the binned image
+const MAX_SHIFT: isize = 4;
+// Blocks with less average gradient than this fraction of their brightness are too flat to
+// match reliably
+const MIN_TEXTURE: f32 = 0.02;
+
+/// The frames of a Canon Dual Pixel RAW, where each photosite is split into a left (A) and a
+/// right (B) photodiode. The normal image is the sum of both, the file additionally stores
+/// the A one on its own and B is recovered as the difference. As the two halves see the scene
+/// through opposite sides of the lens they are shifted horizontally in proportion to the
+/// defocus, which `disparity()` measures.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DualPixelImage {
+  /// the normal image, with both photodiodes combined
+  pub full: RawImage,
+  /// the image of the A photodiodes only
+  pub a: RawImage,
+  /// the image of the B photodiodes only
+  pub b: RawImage,
+}
+
+/// Horizontal shift between the A and B halves of a Dual Pixel image, measured over a grid of
+/// blocks. Values are in pixels, positive when the B image is shifted to the right of the A
+/// one. In focus areas are around 0.0 with the sign telling if something is in front of or
+/// behind the focus plane.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DisparityMap {
+  /// area of the image the map covers
+  pub area: Rect,
+  /// size in pixels of the square blocks
+  pub block_size: usize,
+  /// number of blocks in each row
+  pub width: usize,
+  /// number of rows of blocks
+  pub height: usize,
+  /// shift for each block in row major order, NaN for blocks without enough texture to
+  /// measure it
+  pub values: Vec<f32>,
+}
+
+impl DisparityMap {
+  /// Shift of the block at a given column and row of the grid
+  pub fn get(&self, col: usize, row: usize) -> Option<f32> {
+    if col < self.width && row < self.height {
+      Some(self.values[row*self.width + col])
+    } else {
+      None
+    }
+  }
+
+  /// Shift at a pixel of the image, from the block it's in
+  pub fn at_pixel(&self, x: usize, y: usize) -> Option<f32> {
+    if x < self.area.x || y < self.area.y {
+      return None
+    }
+    self.get((x - self.area.x) / self.block_size, (y - self.area.y) / self.block_size)
+  }
+}
+
+impl DualPixelImage {
+  #[doc(hidden)] pub fn new(full: RawImage, a: RawImage) -> DualPixelImage {
+    let mut b = a.clone();
+    if let (RawImageData::Integer(ref fulldata), RawImageData::Integer(ref adata)) = (&full.data, &a.data) {
+      if fulldata.len() == adata.len() {
+        // Both frames carry the black level so it needs to be added back once
+        let bdata = fulldata.iter().zip(adata.iter()).enumerate().map(|(i, (&f, &v))| {
+          let black = full.blacklevels[full.color_for_index(i)];
+          (f as i32 - v as i32 + black as i32).clamp(0, 65535) as u16
+        }).collect();
+        b.data = RawImageData::Integer(bdata);
+      }
+    }
+    DualPixelImage { full, a, b }
+  }
+
+  /// Measure the shift between the A and B images over blocks of `block_size` pixels of the
+  /// image's active area. The images are binned 2x2 to remove the CFA so shifts can be found
+  /// up to 8 pixels either way, to a fraction of a pixel.
+  pub fn disparity(&self, block_size: usize) -> Result<DisparityMap, RawLoaderError> {
+    let (a, b) = (&self.a, &self.b);
+    if a.width != b.width || a.height != b.height || a.cpp != 1 || b.cpp != 1 ||
+       a.data_len() != a.width*a.height || b.data_len() != a.data_len() {
+      return Err(RawLoaderError::Corrupt("Dual pixel frames don't match".to_string()))
+    }
+    let block_size = block_size.max(8) & !1;
+    let area = a.areas().active_area;
+
+    // Sum each 2x2 CFA cell into a single value without the black level
+    let (bwidth, bheight) = (area.width / 2, area.height / 2);
+    let bin = |img: &RawImage| -> Vec<f32> {
+      let mut out = vec![0.0f32; bwidth*bheight];
+      for (i, o) in out.iter_mut().enumerate() {
+        let (row, col) = (area.y + (i / bwidth)*2, area.x + (i % bwidth)*2);
+        for (dy, dx) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+          let idx = (row+dy)*img.width + col + dx;
+          *o += img.data.get_f32(idx) - img.blacklevels[img.color_for_index(idx)] as f32;
+        }
+      }
+      out
+    };
+    let (abin, bbin) = (bin(a), bin(b));
+
+    let half = block_size / 2;
+    let (width, height) = (bwidth / half, bheight / half);
+    let mut values = Vec::with_capacity(width*height);
+    for brow in 0..height {
+      for bcol in 0..width {
+        let (y0, x0) = (brow*half, bcol*half);
+        values.push(Self::block_shift(&abin, &bbin, bwidth, x0, y0, half) * 2.0);
+      }
+    }
+
+    Ok(DisparityMap {
+      area: Rect::new(area.x, area.y, width*block_size, height*block_size),
+      block_size,
+      width,
+      height,
+      values,
+    })
+  }
+
+  // Find the shift of B that best matches A within a block by the mean absolute difference,
+  // refining the minimum with a parabola through its neighbours
+  fn block_shift(a: &[f32], b: &[f32], width: usize, x0: usize, y0: usize, size: usize) -> f32 {
+    let (mut sum, mut texture) = (0.0f32, 0.0f32);
+    for row in y0..y0+size {
+      for col in x0..x0+size-1 {
+        sum += a[row*width+col];
+        texture += (a[row*width+col+1] - a[row*width+col]).abs();
+      }
+    }
+    if sum <= 0.0 || texture < sum * MIN_TEXTURE {
+      return f32::NAN
+    }
+
+    let cost = |shift: isize| -> Option<f32> {
+      let start = x0 as isize + shift;
+      if start < 0 || start as usize + size > width {
+        return None
+      }
+      let mut total = 0.0f32;
+      for row in y0..y0+size {
+        for col in x0..x0+size {
+          total += (a[row*width+col] - b[row*width + (col as isize + shift) as usize]).abs();
+        }
+      }
+      Some(total)
+    };
+    let costs: Vec<(isize, f32)> = (-MAX_SHIFT..=MAX_SHIFT).filter_map(|s| cost(s).map(|c| (s, c))).collect();
+    let best = match costs.iter().cloned().min_by(|x, y| x.1.partial_cmp(&y.1).unwrap()) {
+      Some(best) => best,
+      None => return f32::NAN,
+    };
+
+    let neighbour = |s: isize| costs.iter().find(|c| c.0 == s).map(|c| c.1);
+    match (neighbour(best.0 - 1), neighbour(best.0 + 1)) {
+      (Some(left), Some(right)) => {
+        let den = left - 2.0*best.1 + right;
+        let offset = if den > 0.0 { (0.5 * (left - right) / den).clamp(-0.5, 0.5) } else { 0.0 };
+        best.0 as f32 + offset
+      },
+      _ => best.0 as f32,
+    }
+  }
+}
@@ -0,0 +1,129 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+use crate::decoders::SAMPLE;
+
+/// Error type for any reason for the decode to fail
+#[derive(Debug)]
+pub enum RawLoaderError {
+  /// the file was recognized but the camera that made it isn't supported, the model is empty
+  /// when there's no decoder for the make at all and both are when the format wasn't recognized
+  Unsupported {
+    /// make of the camera as found in the file
+    make: String,
+    /// model of the camera as found in the file
+    model: String,
+  },
+  /// the file is truncated or has values that don't make sense for its format
+  Corrupt(String),
+  /// reading the file failed
+  Io(io::Error),
+  /// the file uses a variant of its format (compression, bit depth, etc) that isn't
+  /// implemented yet
+  Unimplemented(String),
+  /// the file or the image is bigger than the limits set with `RawLoader::builder()`
+  LimitExceeded(String),
+  /// `RawDecodeParams` asked for an image the file doesn't have
+  NoSuchImage {
+    /// the index that was asked for
+    index: usize,
+    /// how many raw images the file has
+    count: usize,
+  },
+  /// the buffer given to `RawLoader::decode_into()` isn't the size of the image
+  OutputSize {
+    /// number of values the image has
+    expected: usize,
+    /// number of values the buffer has
+    got: usize,
+  },
+  /// the decode was cancelled through `DecodeOptions::cancel`
+  Cancelled,
+  /// the decode took longer than `DecodeOptions::timeout`, which is given
+  TimedOut(Duration),
+}
+
+impl RawLoaderError {
+  pub(crate) fn unsupported(make: &str, model: &str) -> Self {
+    RawLoaderError::Unsupported { make: make.to_string(), model: model.to_string() }
+  }
+}
+
+impl fmt::Display for RawLoaderError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RawLoaderError::Unsupported { make, model } if make.is_empty() =>
+        write!(f, "Couldn't find a decoder for this file.{}", SAMPLE),
+      RawLoaderError::Unsupported { make, model } if model.is_empty() =>
+        write!(f, "Couldn't find a decoder for make \"{}\".{}", make, SAMPLE),
+      RawLoaderError::Unsupported { make, model } =>
+        write!(f, "Couldn't find camera \"{}\" \"{}\".{}", make, model, SAMPLE),
+      RawLoaderError::Corrupt(msg) => write!(f, "{}", msg),
+      RawLoaderError::Io(err) => write!(f, "IOError: {}", err),
+      RawLoaderError::Unimplemented(msg) => write!(f, "{}", msg),
+      RawLoaderError::LimitExceeded(msg) => write!(f, "{}", msg),
+      RawLoaderError::NoSuchImage { index, count } =>
+        write!(f, "Asked for raw image {} of a file that has {}", index, count),
+      RawLoaderError::OutputSize { expected, got } =>
+      write!(f, "Output buffer has {} values but the image has {}", got, expected),
+      RawLoaderError::Cancelled => write!(f, "Decode was cancelled"),
+      RawLoaderError::TimedOut(timeout) => write!(f, "Decode took longer than {:?}", timeout),
+    }
+  }
+}
+
+impl Error for RawLoaderError {
+  // Implement description so that older versions of rust still work
+  fn description(&self) -> &str {
+    "description() is deprecated; use Display"
+  }
+
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      RawLoaderError::Io(err) => Some(err),
+      _ => None,
+    }
+  }
+}
+
+/// A problem a decode worked around instead of failing, see `RawImage::warnings`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DecodeWarning {
+  /// the file doesn't have white balance coefficients so `wb_coeffs` are NaN
+  MissingWhiteBalance,
+  /// the file doesn't say what its black level is so the default of the format was used
+  GuessedBlackLevel,
+  /// an opcode of the DNG opcode lists wasn't applied as it isn't implemented, with its ID
+  IgnoredOpcode(u32),
+  /// the `sub_image()` asked for couldn't be decoded so the main image was, with why
+  MainImageInstead(String),
+  /// the file was only recognized by its size
+  RecognizedBySize,
+  /// the file ends at this offset before the image data does, see `RawImage::partially_decoded`
+  Truncated(usize),
+  /// a problem in the structure of the file that a lenient decode worked around
+  Tolerated(String),
+}
+
+impl fmt::Display for DecodeWarning {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      DecodeWarning::MissingWhiteBalance => write!(f, "file has no white balance"),
+      DecodeWarning::GuessedBlackLevel => write!(f, "file has no black level, using the default"),
+      DecodeWarning::IgnoredOpcode(id) => write!(f, "DNG opcode {} isn't implemented, ignored it", id),
+      DecodeWarning::MainImageInstead(why) => write!(f, "{}, decoded the main image instead", why),
+      DecodeWarning::RecognizedBySize => write!(f, "file was only recognized by its size"),
+      DecodeWarning::Truncated(offset) => write!(f, "file ends at {} before the image data does, decoded what was there", offset),
+      DecodeWarning::Tolerated(problem) => write!(f, "{}", problem),
+    }
+  }
+}
+
+impl From<io::Error> for RawLoaderError {
+  fn from(err: io::Error) -> Self {
+    RawLoaderError::Io(err)
+  }
+}
@@ -0,0 +1,693 @@
+use std::fmt;
+
+use crate::decoders::tiff::*;
+use crate::decoders::basics::*;
+use crate::decoders::lens::LensInfo;
+use crate::decoders::xmp::Xmp;
+use crate::decoders::writers::ifd::{BlobEntry, ifd_size, write_ifd};
+use crate::decoders::ciff::{CiffIFD, CiffTag};
+use crate::decoders::makernotes::{Makernote, CanonMakernote, AfPoint, Bracketing, CameraLevel, DriveMode, WbIlluminant, WbPreset};
+
+/// Basic shooting information found in the EXIF data of the file
+///
+/// All the fields are optional as not all formats and cameras record them.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Exif {
+  /// ISO sensitivity
+  pub iso: Option<u32>,
+  /// shutter time in seconds
+  pub exposure_time: Option<f32>,
+  /// aperture as an f-number
+  pub aperture: Option<f32>,
+  /// exposure compensation in EV
+  pub exposure_bias: Option<f32>,
+  /// focal length in mm
+  pub focal_length: Option<f32>,
+  /// how the camera metered the scene
+  pub metering_mode: Option<MeteringMode>,
+  /// how the camera set the exposure
+  pub exposure_program: Option<ExposureProgram>,
+  /// what the flash did
+  pub flash: Option<Flash>,
+  /// when the image was taken
+  pub capture_time: Option<Timestamp>,
+  /// serial number of the camera body
+  pub serial: Option<String>,
+  /// name of the photographer as set in the camera
+  pub artist: Option<String>,
+  /// copyright notice, with the photographer's and the editor's separated by "; " when the
+  /// file has both
+  pub copyright: Option<String>,
+  /// comment from the EXIF UserComment tag, decoded from the character code it names
+  pub user_comment: Option<String>,
+  /// number of shutter actuations when the image was taken
+  pub shutter_count: Option<u32>,
+  /// temperature of the camera or sensor in degrees Celsius
+  pub temperature: Option<f32>,
+  /// location where the image was taken
+  pub gps: Option<GpsInfo>,
+  /// lens used to take the image
+  pub lens: Option<LensInfo>,
+  /// star rating, usually 0 to 5 with -1 for rejected, from the XMP when it has one and the
+  /// EXIF Rating tag otherwise
+  pub rating: Option<i32>,
+  /// color label from the XMP
+  pub label: Option<String>,
+  /// XMP packet embedded in the file, or read from a sidecar when requested
+  pub xmp: Option<Xmp>,
+  /// the EXIF data re-serialized as a standalone TIFF structure (starting with the "II"/"MM"
+  /// header) that can be embedded as is in the APP1 segment of a JPEG after "Exif\0\0". It
+  /// has the descriptive IFD0 tags, the full EXIF IFD except for the makernote, whose internal
+  /// offsets can't be kept valid, and the GPS IFD.
+  pub raw_exif: Option<Vec<u8>>,
+  /// ICC profile as stored in the file, either in the TIFF structure or in the APP2 segments
+  /// of an embedded JPEG preview
+  pub icc_profile: Option<Vec<u8>>,
+  /// brand specific information from the makernotes
+  pub makernote: Option<Makernote>,
+  /// white balance presets stored by the camera
+  pub wb_presets: Vec<WbPreset>,
+  /// autofocus points the camera recorded
+  pub af_points: Vec<AfPoint>,
+  /// drive mode the camera was set to
+  pub drive_mode: Option<DriveMode>,
+  /// bracketing sequence the image is part of
+  pub bracketing: Option<Bracketing>,
+  /// roll and pitch of the camera from its electronic level
+  pub level: Option<CameraLevel>,
+}
+
+/// A date and time as recorded by the camera
+///
+/// The time is in the camera's local time, `utc_offset` says how that relates to UTC when the
+/// camera recorded it.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timestamp {
+  /// year (e.g., 2024)
+  pub year: u16,
+  /// month from 1 to 12
+  pub month: u8,
+  /// day of the month from 1 to 31
+  pub day: u8,
+  /// hour from 0 to 23
+  pub hour: u8,
+  /// minute from 0 to 59
+  pub minute: u8,
+  /// second from 0 to 60
+  pub second: u8,
+  /// fraction of the second in nanoseconds, when the camera records it
+  pub nanosecond: Option<u32>,
+  /// offset of the local time from UTC in minutes, when the camera records it
+  pub utc_offset: Option<i16>,
+}
+
+impl Timestamp {
+  /// Parse an EXIF date and time in the "YYYY:MM:DD HH:MM:SS" format
+  pub fn parse(val: &str) -> Option<Timestamp> {
+    let val = val.trim();
+    if val.len() < 19 || !val.is_char_boundary(19) {
+      return None
+    }
+    let bytes = val.as_bytes();
+    let num = |start: usize, len: usize| -> Option<u32> {
+      let part = &val[start..start+len];
+      if part.bytes().all(|b| b.is_ascii_digit()) { part.parse().ok() } else { None }
+    };
+    // Some cameras write dashes as the date separator and a T between date and time
+    if !matches!(bytes[4], b':' | b'-') || !matches!(bytes[7], b':' | b'-') || !matches!(bytes[10], b' ' | b'T') {
+      return None
+    }
+
+    let ts = Timestamp {
+      year: num(0, 4)? as u16,
+      month: num(5, 2)? as u8,
+      day: num(8, 2)? as u8,
+      hour: num(11, 2)? as u8,
+      minute: num(14, 2)? as u8,
+      second: num(17, 2)? as u8,
+      nanosecond: None,
+      utc_offset: None,
+    };
+    if ts.year == 0 || ts.month == 0 || ts.month > 12 || ts.day == 0 || ts.day > 31
+       || ts.hour > 23 || ts.minute > 59 || ts.second > 60 {
+      return None
+    }
+    Some(ts)
+  }
+
+  /// Build a timestamp from seconds since 1970-01-01 00:00:00
+  pub fn from_unix(secs: i64) -> Timestamp {
+    let days = secs.div_euclid(86400);
+    let time = secs.rem_euclid(86400);
+
+    // Convert the day count to a civil date (Howard Hinnant's days_from_civil inverted)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let day = doy - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    Timestamp {
+      year: year as u16,
+      month: month as u8,
+      day: day as u8,
+      hour: (time / 3600) as u8,
+      minute: (time / 60 % 60) as u8,
+      second: (time % 60) as u8,
+      nanosecond: None,
+      utc_offset: None,
+    }
+  }
+
+  // Add the digits of a SubSecTime tag, they are the decimal digits of the fraction
+  fn set_subsec(&mut self, val: &str) {
+    let digits: String = val.trim().chars().take_while(|c| c.is_ascii_digit()).take(9).collect();
+    if !digits.is_empty() {
+      let frac: u32 = digits.parse().unwrap_or(0);
+      self.nanosecond = Some(frac * 10u32.pow(9 - digits.len() as u32));
+    }
+  }
+
+  // Add an OffsetTime tag in the "+HH:MM" format
+  fn set_offset(&mut self, val: &str) {
+    let val = val.trim();
+    if val.len() != 6 || !val.is_char_boundary(1) || &val[3..4] != ":" {
+      return
+    }
+    let sign = match &val[0..1] { "+" => 1, "-" => -1, _ => return };
+    if let (Ok(h), Ok(m)) = (val[1..3].parse::<i16>(), val[4..6].parse::<i16>()) {
+      if h < 24 && m < 60 {
+        self.utc_offset = Some(sign * (h*60 + m));
+      }
+    }
+  }
+}
+
+impl fmt::Display for Timestamp {
+  /// Formats as ISO 8601, e.g. "2024-05-01T12:34:56.120+09:00"
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", self.year, self.month, self.day, self.hour, self.minute, self.second)?;
+    if let Some(ns) = self.nanosecond {
+      let frac = format!("{:09}", ns);
+      let frac = frac.trim_end_matches('0');
+      write!(f, ".{}", if frac.is_empty() { "0" } else { frac })?;
+    }
+    if let Some(offset) = self.utc_offset {
+      let sign = if offset < 0 { '-' } else { '+' };
+      write!(f, "{}{:02}:{:02}", sign, offset.abs() / 60, offset.abs() % 60)?;
+    }
+    Ok(())
+  }
+}
+
+/// Location information from the GPS IFD
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GpsInfo {
+  /// latitude in degrees, positive to the north
+  pub latitude: Option<f64>,
+  /// longitude in degrees, positive to the east
+  pub longitude: Option<f64>,
+  /// altitude in meters, negative below sea level
+  pub altitude: Option<f64>,
+  /// UTC time of the fix as "YYYY-MM-DDTHH:MM:SSZ", with just the time when there's no date
+  pub timestamp: Option<String>,
+  /// direction the camera was pointing at in degrees
+  pub direction: Option<f32>,
+  /// if `direction` is relative to true north (as opposed to magnetic north)
+  pub direction_true_north: bool,
+}
+
+impl GpsInfo {
+  fn from_ifd(ifd: &TiffIFD) -> Option<GpsInfo> {
+    let string = |tag: u16| -> Option<String> {
+      ifd.get_entry_raw(tag).filter(|e| e.typ() == 2).and_then(|e| entry_string(e))
+    };
+    let rationals = |tag: u16, count: usize| -> Option<Vec<f64>> {
+      match ifd.get_entry_raw(tag) {
+        Some(e) if (e.typ() == 5 || e.typ() == 10) && e.count() >= count => {
+          let vals: Vec<f64> = (0..count).map(|i| e.get_f32(i) as f64).collect();
+          if vals.iter().all(|v| v.is_finite()) { Some(vals) } else { None }
+        },
+        _ => None,
+      }
+    };
+    let degrees = |v: Vec<f64>| v[0] + v[1]/60.0 + v[2]/3600.0;
+
+    let latitude = rationals(2, 3).map(|v| {
+      let deg = degrees(v);
+      if string(1).as_deref() == Some("S") { -deg } else { deg }
+    });
+    let longitude = rationals(4, 3).map(|v| {
+      let deg = degrees(v);
+      if string(3).as_deref() == Some("W") { -deg } else { deg }
+    });
+    let altitude = rationals(6, 1).map(|v| {
+      let below = ifd.get_entry_raw(5).map(|e| e.count() > 0 && e.get_data()[0] == 1).unwrap_or(false);
+      if below { -v[0] } else { v[0] }
+    });
+    let timestamp = rationals(7, 3).map(|v| {
+      let secs = if v[2].fract() == 0.0 { format!("{:02}", v[2]) } else { format!("{:06.3}", v[2]) };
+      let time = format!("{:02}:{:02}:{}Z", v[0], v[1], secs);
+      match string(0x1D) {
+        Some(ref date) if date.len() == 10 => format!("{}T{}", date.replace(":", "-"), time),
+        _ => time,
+      }
+    });
+    let direction = rationals(0x11, 1).map(|v| v[0] as f32);
+    let direction_true_north = string(0x10).as_deref() == Some("T");
+
+    if latitude.is_none() && longitude.is_none() && altitude.is_none() && timestamp.is_none() {
+      return None
+    }
+
+    Some(GpsInfo {
+      latitude,
+      longitude,
+      altitude,
+      timestamp,
+      direction,
+      direction_true_north,
+    })
+  }
+}
+
+/// Metering modes as defined by the EXIF MeteringMode (0x9207) tag
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MeteringMode {
+  Unknown,
+  Average,
+  CenterWeighted,
+  Spot,
+  MultiSpot,
+  Pattern,
+  Partial,
+  Other,
+}
+
+impl MeteringMode {
+  /// Convert a u16 from the EXIF MeteringMode tag into its corresponding enum value
+  pub fn from_u16(mode: u16) -> MeteringMode {
+    match mode {
+      1 => MeteringMode::Average,
+      2 => MeteringMode::CenterWeighted,
+      3 => MeteringMode::Spot,
+      4 => MeteringMode::MultiSpot,
+      5 => MeteringMode::Pattern,
+      6 => MeteringMode::Partial,
+      255 => MeteringMode::Other,
+      _ => MeteringMode::Unknown,
+    }
+  }
+
+  /// Convert the metering mode to the EXIF MeteringMode value
+  pub fn to_u16(&self) -> u16 {
+    match *self {
+      MeteringMode::Unknown => 0,
+      MeteringMode::Average => 1,
+      MeteringMode::CenterWeighted => 2,
+      MeteringMode::Spot => 3,
+      MeteringMode::MultiSpot => 4,
+      MeteringMode::Pattern => 5,
+      MeteringMode::Partial => 6,
+      MeteringMode::Other => 255,
+    }
+  }
+}
+
+/// Exposure programs as defined by the EXIF ExposureProgram (0x8822) tag
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExposureProgram {
+  Unknown,
+  Manual,
+  Program,
+  AperturePriority,
+  ShutterPriority,
+  Creative,
+  Action,
+  Portrait,
+  Landscape,
+}
+
+impl ExposureProgram {
+  /// Convert a u16 from the EXIF ExposureProgram tag into its corresponding enum value
+  pub fn from_u16(program: u16) -> ExposureProgram {
+    match program {
+      1 => ExposureProgram::Manual,
+      2 => ExposureProgram::Program,
+      3 => ExposureProgram::AperturePriority,
+      4 => ExposureProgram::ShutterPriority,
+      5 => ExposureProgram::Creative,
+      6 => ExposureProgram::Action,
+      7 => ExposureProgram::Portrait,
+      8 => ExposureProgram::Landscape,
+      _ => ExposureProgram::Unknown,
+    }
+  }
+
+  /// Convert the exposure program to the EXIF ExposureProgram value
+  pub fn to_u16(&self) -> u16 {
+    match *self {
+      ExposureProgram::Unknown => 0,
+      ExposureProgram::Manual => 1,
+      ExposureProgram::Program => 2,
+      ExposureProgram::AperturePriority => 3,
+      ExposureProgram::ShutterPriority => 4,
+      ExposureProgram::Creative => 5,
+      ExposureProgram::Action => 6,
+      ExposureProgram::Portrait => 7,
+      ExposureProgram::Landscape => 8,
+    }
+  }
+}
+
+/// Flash modes as encoded in the EXIF Flash (0x9209) tag
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FlashMode {
+  Unknown,
+  On,
+  Off,
+  Auto,
+}
+
+/// Flash information from the EXIF Flash (0x9209) tag
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Flash {
+  /// if the flash fired
+  pub fired: bool,
+  /// mode the flash was set to
+  pub mode: FlashMode,
+  /// if the flash return light was detected, when the camera can tell
+  pub return_detected: Option<bool>,
+  /// if red-eye reduction was used
+  pub red_eye_reduction: bool,
+  /// if the camera has a flash function at all
+  pub present: bool,
+}
+
+impl Flash {
+  /// Decode the bits of the EXIF Flash tag
+  pub fn from_u16(val: u16) -> Flash {
+    Flash {
+      fired: val & 0x01 != 0,
+      mode: match (val >> 3) & 0x03 {
+        1 => FlashMode::On,
+        2 => FlashMode::Off,
+        3 => FlashMode::Auto,
+        _ => FlashMode::Unknown,
+      },
+      return_detected: match (val >> 1) & 0x03 {
+        2 => Some(false),
+        3 => Some(true),
+        _ => None,
+      },
+      red_eye_reduction: val & 0x40 != 0,
+      present: val & 0x20 == 0,
+    }
+  }
+}
+
+impl Exif {
+  /// Extract the EXIF fields from a TiffIFD, missing or unreadable ones are left as None
+  pub(crate) fn from_tiff(tiff: &TiffIFD) -> Exif {
+    let makernote = Makernote::from_tiff(tiff);
+    let xmp = Xmp::from_tiff(tiff);
+    Exif {
+      iso: get_f32(tiff, Tag::ISOSpeed).map(|v| v as u32),
+      exposure_time: get_f32(tiff, Tag::ExposureTime),
+      aperture: get_f32(tiff, Tag::FNumber),
+      exposure_bias: get_f32(tiff, Tag::ExposureBias),
+      focal_length: get_f32(tiff, Tag::FocalLength),
+      metering_mode: get_f32(tiff, Tag::MeteringMode).map(|v| MeteringMode::from_u16(v as u16)),
+      exposure_program: get_f32(tiff, Tag::ExposureProgram).map(|v| ExposureProgram::from_u16(v as u16)),
+      flash: get_f32(tiff, Tag::Flash).map(|v| Flash::from_u16(v as u16)),
+      capture_time: Self::capture_time(tiff),
+      artist: get_string(tiff, Tag::Artist),
+      copyright: tiff.find_entry(Tag::Copyright).filter(|e| e.typ() == 2).and_then(copyright),
+      user_comment: tiff.find_entry(Tag::UserComment).and_then(user_comment),
+      serial: get_string(tiff, Tag::BodySerial).or_else(|| get_string(tiff, Tag::CameraSerial))
+        .or_else(|| makernote.as_ref().and_then(|mn| mn.serial().map(|s| s.to_string()))),
+      shutter_count: makernote.as_ref().and_then(|mn| mn.shutter_count()),
+      temperature: makernote.as_ref().and_then(|mn| mn.temperature()),
+      gps: tiff.find_gps_ifd().and_then(GpsInfo::from_ifd),
+      lens: LensInfo::from_tiff(tiff, makernote.as_ref()),
+      rating: xmp.as_ref().and_then(|x| x.rating)
+        .or_else(|| get_f32(tiff, Tag::Rating).map(|v| v as u16 as i16 as i32)),
+      label: xmp.as_ref().and_then(|x| x.label.clone()),
+      xmp,
+      raw_exif: serialize(tiff),
+      icc_profile: tiff.find_entry(Tag::IccProfile).map(|e| e.get_data().to_vec()).filter(|d| !d.is_empty()),
+      wb_presets: makernote.as_ref().map(|mn| mn.wb_presets().to_vec()).unwrap_or_default(),
+      af_points: makernote.as_ref().map(|mn| mn.af_points().to_vec()).unwrap_or_default(),
+      drive_mode: makernote.as_ref().and_then(|mn| mn.drive_mode()),
+      bracketing: makernote.as_ref().and_then(|mn| mn.bracketing()),
+      level: makernote.as_ref().and_then(|mn| mn.level()),
+      makernote,
+    }
+  }
+
+  /// CRW files have no EXIF so fill in what we can from the Canon makernote fields in the CIFF
+  pub(crate) fn from_ciff(ciff: &CiffIFD) -> Exif {
+    let canon = match CanonMakernote::from_ciff(ciff) {
+      Some(canon) => canon,
+      None => return Exif::default(),
+    };
+    // The capture time is in seconds since the epoch in local time, with the timezone only
+    // valid when the top bit of the third value is set
+    let capture_time = ciff.find_entry(CiffTag::CapturedTime).filter(|e| e.count >= 3).map(|e| {
+      let mut ts = Timestamp::from_unix(e.get_u32(0) as i64);
+      if e.get_u32(2) & 0x80000000 != 0 {
+        ts.utc_offset = Some((e.get_u32(1) as i32 / 60) as i16);
+      }
+      ts
+    });
+
+    let makernote = Makernote::Canon(canon.clone());
+    let owner = ciff.find_entry(CiffTag::OwnerName).and_then(|e| e.get_strings().into_iter().next())
+      .map(|s| s.trim().to_string()).filter(|s| !s.is_empty());
+    Exif {
+      artist: owner,
+      iso: canon.base_iso.map(|v| v.round() as u32),
+      capture_time,
+      serial: canon.serial.clone(),
+      exposure_time: canon.exposure_time,
+      aperture: canon.fnumber,
+      // Canon's exposure modes are easy, program, Tv, Av, manual and A-DEP
+      exposure_program: canon.exposure_mode.map(|mode| match mode {
+        1 => ExposureProgram::Program,
+        2 => ExposureProgram::ShutterPriority,
+        3 => ExposureProgram::AperturePriority,
+        4 => ExposureProgram::Manual,
+        _ => ExposureProgram::Unknown,
+      }),
+      lens: LensInfo::from_canon(&canon),
+      wb_presets: canon.wb_presets,
+      drive_mode: makernote.drive_mode(),
+      temperature: makernote.temperature(),
+      bracketing: canon.bracketing,
+      makernote: Some(makernote),
+      ..Default::default()
+    }
+  }
+
+  /// Find the white balance preset the camera stored for an illuminant
+  pub fn wb_preset(&self, illuminant: WbIlluminant) -> Option<&WbPreset> {
+    self.wb_presets.iter().find(|p| p.illuminant == illuminant)
+  }
+
+  /// Add an XMP packet on top of the one from the file, as when reading a sidecar, updating
+  /// the rating and label from it
+  pub(crate) fn merge_xmp(&mut self, other: Xmp) {
+    if other.rating.is_some() { self.rating = other.rating; }
+    if other.label.is_some() { self.label = other.label.clone(); }
+    match self.xmp {
+      Some(ref mut xmp) => xmp.merge(&other),
+      None => self.xmp = Some(other),
+    }
+  }
+
+  fn capture_time(tiff: &TiffIFD) -> Option<Timestamp> {
+    let mut ts = Timestamp::parse(&get_string(tiff, Tag::DateTimeOriginal)?)?;
+    if let Some(subsec) = get_string(tiff, Tag::SubSecTimeOrig) {
+      ts.set_subsec(&subsec);
+    }
+    if let Some(offset) = get_string(tiff, Tag::OffsetTimeOrig) {
+      ts.set_offset(&offset);
+    }
+    Some(ts)
+  }
+}
+
+// Read a numeric tag making sure it has a type we can read and a sane value
+pub(crate) fn get_f32(tiff: &TiffIFD, tag: Tag) -> Option<f32> {
+  match tiff.find_entry(tag) {
+    Some(entry) if entry.count() > 0 => {
+      match entry.typ() {
+        1 | 3 | 4 | 5 | 8 | 9 | 10 => {
+          let val = entry.get_f32(0);
+          if val.is_finite() { Some(val) } else { None }
+        },
+        _ => None,
+      }
+    },
+    _ => None,
+  }
+}
+
+// Read a string tag without failing on bad encodings or empty values
+pub(crate) fn get_string(tiff: &TiffIFD, tag: Tag) -> Option<String> {
+  match tiff.find_entry(tag) {
+    Some(entry) if entry.typ() == 2 => entry_string(entry),
+    _ => None,
+  }
+}
+
+// The photographer's and the editor's copyright are separate NUL terminated strings, with
+// a space standing in for the photographer's when only the editor's is set
+fn copyright(entry: &TiffEntry) -> Option<String> {
+  let parts: Vec<String> = entry.get_data().split(|&b| b == 0).take(2)
+    .map(|part| String::from_utf8_lossy(part).trim().to_string())
+    .filter(|part| !part.is_empty()).collect();
+  if parts.is_empty() { None } else { Some(parts.join("; ")) }
+}
+
+// UserComment starts with 8 bytes naming the character code. UNICODE is UCS-2 in the byte
+// order of the file and undefined is in practice ASCII or UTF-8. For JIS we don't carry the
+// JIS X 0208 tables so only the ASCII and half-width katakana characters are decoded, with
+// the double byte ones replaced.
+fn user_comment(entry: &TiffEntry) -> Option<String> {
+  let data = entry.get_data();
+  if data.len() <= 8 {
+    return None
+  }
+  let (code, text) = data.split_at(8);
+  let val = match code {
+    b"UNICODE\0" => {
+      let little = entry.get_endian().little();
+      let units: Vec<u16> = text.chunks_exact(2).map(|c| if little { LEu16(c, 0) } else { BEu16(c, 0) }).collect();
+      String::from_utf16_lossy(&units)
+    },
+    b"JIS\0\0\0\0\0" => decode_jis(text),
+    _ => String::from_utf8_lossy(text).into_owned(),
+  };
+  let val = val.split('\0').next().unwrap_or("").trim().to_string();
+  if val.is_empty() { None } else { Some(val) }
+}
+
+// Handles both the 7 bit ISO-2022-JP escapes and 8 bit Shift-JIS, which is what most
+// cameras actually write
+fn decode_jis(data: &[u8]) -> String {
+  let mut out = String::new();
+  let mut double = false;
+  let mut pos = 0;
+  while pos < data.len() {
+    let b = data[pos];
+    match b {
+      0x1b if pos + 2 < data.len() => {
+        double = data[pos+1] == b'$';
+        pos += 3;
+        continue
+      },
+      0x00 => break,
+      0xa1..=0xdf => out.push(char::from_u32(0xff61 + (b - 0xa1) as u32).unwrap()),
+      0x81..=0x9f | 0xe0..=0xfc => {
+        out.push('\u{fffd}');
+        pos += 1;
+      },
+      _ if double && b >= 0x21 => {
+        out.push('\u{fffd}');
+        pos += 1;
+      },
+      _ if b < 0x80 => out.push(b as char),
+      _ => out.push('\u{fffd}'),
+    }
+    pos += 1;
+  }
+  out
+}
+
+pub(crate) fn entry_string(entry: &TiffEntry) -> Option<String> {
+  let data = entry.get_data().split(|&b| b == 0).next().unwrap_or(&[]);
+  let val = String::from_utf8_lossy(data).trim().to_string();
+  if val.is_empty() { None } else { Some(val) }
+}
+
+// The IFD0 tags that describe the image rather than the raw data layout
+const IFD0_TAGS: [Tag;9] = [
+  Tag::ImageDescription, Tag::Make, Tag::Model, Tag::Orientation, Tag::Software, Tag::DateTime,
+  Tag::Artist, Tag::Copyright, Tag::Rating,
+];
+
+// Tags in the EXIF IFD that point to data we can't carry over
+const EXIF_SKIP: [u16;2] = [0x927C, 0xA005]; // Makernote and InteropIFD
+
+// Write IFD0, the EXIF IFD and the GPS IFD as a minimal TIFF file
+fn serialize(tiff: &TiffIFD) -> Option<Vec<u8>> {
+  let exif = tiff.find_private_ifd(Tag::ExifIFDPointer)?;
+  let e = exif.get_endian();
+
+  let mut ifd0: Vec<BlobEntry> = IFD0_TAGS.iter().filter_map(|&tag| tiff.find_entry(tag)).map(|entry| blob_entry(entry, e)).collect();
+  let exif_entries: Vec<BlobEntry> = exif.get_entries().into_iter()
+    .filter(|entry| !EXIF_SKIP.contains(&entry.tag()))
+    .map(|entry| blob_entry(entry, e)).collect();
+  let gps_entries: Vec<BlobEntry> = tiff.find_gps_ifd().map(|gps| {
+    gps.get_entries().into_iter().map(|entry| blob_entry(entry, e)).collect()
+  }).unwrap_or_default();
+
+  // The pointers are always there so the sizes are known before their values
+  ifd0.push((Tag::ExifIFDPointer as u16, 4, 1, vec![0;4]));
+  if !gps_entries.is_empty() {
+    ifd0.push((Tag::GPSInfo as u16, 4, 1, vec![0;4]));
+  }
+  ifd0.sort_by_key(|entry| entry.0);
+  let exif_offset = 8 + ifd_size(&ifd0);
+  let gps_offset = exif_offset + ifd_size(&exif_entries);
+  for entry in ifd0.iter_mut() {
+    if entry.0 == Tag::ExifIFDPointer as u16 {
+      entry.3 = e.wu32(exif_offset as u32).to_vec();
+    } else if entry.0 == Tag::GPSInfo as u16 {
+      entry.3 = e.wu32(gps_offset as u32).to_vec();
+    }
+  }
+
+  let mut out = Vec::with_capacity(gps_offset + ifd_size(&gps_entries));
+  out.extend_from_slice(if e.little() { b"II" } else { b"MM" });
+  out.extend_from_slice(&e.wu16(42));
+  out.extend_from_slice(&e.wu32(8));
+  write_ifd(&mut out, &ifd0, 0, e);
+  write_ifd(&mut out, &exif_entries, 0, e);
+  if !gps_entries.is_empty() {
+    write_ifd(&mut out, &gps_entries, 0, e);
+  }
+  Some(out)
+}
+
+// Copy an entry converting its data to the output endianness if needed
+pub(crate) fn blob_entry(entry: &TiffEntry, e: Endian) -> BlobEntry {
+  let mut data = entry.get_data().to_vec();
+  if entry.get_endian().little() != e.little() {
+    let size = match entry.typ() {
+      3 | 8 => 2,
+      4 | 5 | 9 | 10 | 11 | 13 => 4,
+      12 => 8,
+      _ => 1,
+    };
+    for chunk in data.chunks_mut(size) {
+      chunk.reverse();
+    }
+  }
+  (entry.tag(), entry.typ(), entry.count() as u32, data)
+}
@@ -0,0 +1,50 @@
+use crate::decoders::basics::*;
+
+static ICC_MARKER: &[u8] = b"ICC_PROFILE\0";
+
+// Find the ICC profile of the first JPEG in the buffer that has one. JPEGs store the profile
+// split over APP2 segments, each with the ICC_PROFILE marker followed by the number of the
+// chunk and the total number of chunks.
+pub(crate) fn from_jpegs(buf: &[u8]) -> Option<Vec<u8>> {
+  let mut pos = 0;
+  while pos + 4 <= buf.len() {
+    if buf[pos] == 0xff && buf[pos+1] == 0xd8 && buf[pos+2] == 0xff {
+      if let Some(profile) = from_jpeg(&buf[pos..]) {
+        return Some(profile)
+      }
+    }
+    pos += 1;
+  }
+  None
+}
+
+fn from_jpeg(jpeg: &[u8]) -> Option<Vec<u8>> {
+  let mut chunks: Vec<(u8, &[u8])> = Vec::new();
+  let mut total = 0;
+
+  // Walk the segments after SOI until the image data starts
+  let mut pos = 2;
+  while pos + 4 <= jpeg.len() && jpeg[pos] == 0xff {
+    let marker = jpeg[pos+1];
+    if marker == 0xda || marker == 0xd9 {
+      break
+    }
+    let len = BEu16(jpeg, pos+2) as usize;
+    if len < 2 || pos + 2 + len > jpeg.len() {
+      break
+    }
+    let data = &jpeg[pos+4..pos+2+len];
+    if marker == 0xe2 && data.len() > ICC_MARKER.len() + 2 && data.starts_with(ICC_MARKER) {
+      let seq = data[ICC_MARKER.len()];
+      total = data[ICC_MARKER.len()+1];
+      chunks.push((seq, &data[ICC_MARKER.len()+2..]));
+    }
+    pos += 2 + len;
+  }
+
+  if chunks.is_empty() || chunks.len() != total as usize {
+    return None
+  }
+  chunks.sort_by_key(|&(seq, _)| seq);
+  Some(chunks.iter().flat_map(|&(_, data)| data.iter().cloned()).collect())
+}
@@ -3,6 +3,7 @@ use std::f32::NAN;
 use crate::decoders::*;
 use crate::decoders::tiff::*;
 use crate::decoders::basics::*;
+use crate::decoders::calibration::*;
 
 #[derive(Debug, Clone)]
 pub struct IiqDecoder<'a> {
@@ -22,7 +23,11 @@ impl<'a> IiqDecoder<'a> {
 }
 
 impl<'a> Decoder for IiqDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "IIQ"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let camera = self.rawloader.check_supported(&self.tiff)?;
 
     let off = LEu32(self.buffer, 16) as usize + 8;
@@ -35,8 +40,15 @@ impl<'a> Decoder for IiqDecoder<'a> {
     let mut data_offset: usize = 0;
     let mut strip_offset: usize = 0;
     let mut black: u16 = 0;
+    let mut defects: (usize, usize) = (0, 0);
+    let mut format: u32 = 0;
+    let mut meta: usize = 0;
+    let mut sensor_temp: f32 = 0.0;
+    let mut splits: (usize, usize) = (0, 0);
+    let mut black_tables: (usize, usize) = (0, 0);
     for _ in 0..entries {
       let tag = LEu32(self.buffer, off+pos);
+      let len = LEu32(self.buffer, off+pos+8) as usize;
       let data = LEu32(self.buffer, off+pos+12) as usize;
       pos += 16;
       match tag {
@@ -45,29 +57,199 @@ impl<'a> Decoder for IiqDecoder<'a> {
         0x109 => height = data,
         0x10f => data_offset = data+8,
         0x21c => strip_offset = data+8,
+        0x10e => format = data as u32,
+        0x110 => meta = data+8,
+        0x210 => sensor_temp = f32::from_bits(data as u32),
         0x21d => black = (data>>2) as u16,
+        0x222 => splits.0 = data,
+        0x223 => black_tables.0 = data+8,
+        0x224 => splits.1 = data,
+        0x225 => black_tables.1 = data+8,
+        0x400 => defects = (data+8, len),
         _ => {},
       }
     }
 
     if width <= 0 || height <= 0 {
-      return Err("IIQ: couldn't find width and height".to_string())
+      return Err(RawLoaderError::Corrupt("IIQ: couldn't find width and height".to_string()))
     }
 
-    let image = Self::decode_compressed(self.buffer, data_offset, strip_offset, width, height, dummy);
+    let image = Self::decode_compressed(self.buffer, data_offset, strip_offset, width, height, dummy)?;
+
+    let mut img = ok_image_with_blacklevels(camera, width, height, self.get_wb(wb_offset)?, [black, black, black, black], image)?;
+    img.bad_pixels = self.get_bad_pixels(defects.0, defects.1, width, height);
 
-    ok_image_with_blacklevels(camera, width, height, self.get_wb(wb_offset)?, [black, black, black, black], image)
+    // Besides the 8 bit format the data is stored without its two lowest bits
+    let mut calib = SensorCalibration {
+      scale: if format == 8 { 1 } else { 4 },
+      black: black << 2,
+      split_col: Some(splits.0).filter(|&c| c > 0 && c < width),
+      split_row: Some(splits.1).filter(|&r| r > 0 && r < height),
+      row_blacks: self.get_black_table(black_tables.0, height),
+      col_blacks: self.get_black_table(black_tables.1, width),
+      ..Default::default()
+    };
+    self.parse_calibration(&mut calib, meta, sensor_temp, width, height);
+    for defect in &calib.defects {
+      if defect.column {
+        img.bad_pixels.extend((0..height as u32).map(|row| (row, defect.col)));
+      } else {
+        img.bad_pixels.push((defect.row, defect.col));
+      }
+    }
+    img.bad_pixels.sort_unstable();
+    img.bad_pixels.dedup();
+    if calib != SensorCalibration::default() {
+      img.calibration = Some(calib);
+    }
+    Ok(img)
   }
 }
 
 impl<'a> IiqDecoder<'a> {
-  fn get_wb(&self, wb_offset: usize) -> Result<[f32;4], String> {
+  // The sensor defects are a list of 8 byte entries of column, row and type
+  fn get_bad_pixels(&self, offset: usize, len: usize, width: usize, height: usize) -> Vec<(u32,u32)> {
+    let mut pixels = Vec::new();
+    if offset == 0 || offset + len > self.buffer.len() {
+      return pixels
+    }
+
+    for pos in (offset..offset+len).step_by(8).take_while(|pos| pos+8 <= offset+len) {
+      let col = LEu16(self.buffer, pos) as usize;
+      let row = LEu16(self.buffer, pos+2) as usize;
+      let typ = LEu16(self.buffer, pos+4);
+      if col >= width {
+        continue
+      }
+      match typ {
+        129 if row < height => pixels.push((row as u32, col as u32)),
+        131 | 137 => {
+          for row in 0..height {
+            pixels.push((row as u32, col as u32));
+          }
+        },
+        _ => {},
+      }
+    }
+    pixels
+  }
+
+  // Pairs of signed shorts for each row or column, for each side of the split
+  fn get_black_table(&self, offset: usize, len: usize) -> Vec<[i16;2]> {
+    if offset == 0 || offset + len*4 > self.buffer.len() {
+      return Vec::new()
+    }
+    (0..len).map(|i| {
+      let pos = offset + i*4;
+      [LEu16(self.buffer, pos) as i16, LEu16(self.buffer, pos+2) as i16]
+    }).collect()
+  }
+
+  // The sensor calibration is a separate directory with its offsets relative to its start and
+  // entries of tag, length and offset
+  fn parse_calibration(&self, calib: &mut SensorCalibration, meta: usize, sensor_temp: f32, width: usize, height: usize) {
+    if meta == 0 || meta + 12 > self.buffer.len() {
+      return
+    }
+    let dir = meta + LEu32(self.buffer, meta+8) as usize;
+    if dir + 8 > self.buffer.len() {
+      return
+    }
+    let entries = LEu32(self.buffer, dir) as usize;
+    for i in 0..entries {
+      let pos = dir + 8 + i*12;
+      if pos + 12 > self.buffer.len() {
+        break
+      }
+      let tag = LEu32(self.buffer, pos);
+      let len = LEu32(self.buffer, pos+4) as usize;
+      let start = meta + LEu32(self.buffer, pos+8) as usize;
+      let data = match self.buffer.get(start..start+len) {
+        Some(data) => data,
+        None => continue,
+      };
+      match tag {
+        0x400 => {
+          for entry in data.chunks_exact(8) {
+            let (col, row, typ) = (LEu16(entry, 0) as u32, LEu16(entry, 2) as u32, LEu16(entry, 4));
+            let column = typ == 131 || typ == 137;
+            if (col as usize) < width && (column || (typ == 129 && (row as usize) < height)) {
+              calib.defects.push(SensorDefect { col, row, column });
+            }
+          }
+        },
+        // Quadratic curve for the right half that depends on the sensor temperature
+        0x419 if len >= 36 => {
+          let mut poly: Vec<f32> = (0..8).map(|i| LEf32(data, 4 + i*4)).collect();
+          poly[3] += (sensor_temp - poly[7]) * poly[6] + 1.0;
+          calib.curves.push(CalibrationCurve {
+            first_col: calib.split_col.unwrap_or(0),
+            table: Self::curve(|x| (poly[5]*x + poly[3])*x + poly[1]),
+          });
+        },
+        // Cubic correction added to the value over the whole sensor
+        0x41a if len >= 16 => {
+          let poly: Vec<f32> = (0..4).map(|i| LEf32(data, i*4)).collect();
+          calib.curves.push(CalibrationCurve {
+            first_col: 0,
+            table: Self::curve(|x| ((poly[3]*x + poly[2])*x + poly[1])*x + poly[0] + x),
+          });
+        },
+        0x401 => calib.flat_fields.extend(Self::flat_field(data, true, false)),
+        0x410 | 0x416 => calib.flat_fields.extend(Self::flat_field(data, false, false)),
+        0x40b => calib.flat_fields.extend(Self::flat_field(data, false, true)),
+        _ => calib.other.push((tag, data.to_vec())),
+      }
+    }
+  }
+
+  fn curve<F: Fn(f32) -> f32>(f: F) -> Vec<u16> {
+    (0..65536).map(|i| f(i as f32).clamp(0.0, 65535.0) as u16).collect()
+  }
+
+  // A header of the area and the cell size followed by the nodes as floats or as shorts
+  // in units of 1/32768
+  fn flat_field(data: &[u8], float: bool, red_blue: bool) -> Option<FlatField> {
+    if data.len() < 16 {
+      return None
+    }
+    let head: Vec<usize> = (0..8).map(|i| LEu16(data, i*2) as usize).collect();
+    if head[2] == 0 || head[3] == 0 || head[4] == 0 || head[5] == 0 {
+      return None
+    }
+    let columns = head[2].div_ceil(head[4]);
+    let rows = head[3].div_ceil(head[5]);
+    let planes = if red_blue { 2 } else { 1 };
+    let size = if float { 4 } else { 2 };
+    let count = columns*rows*planes;
+    if data.len() < 16 + count*size {
+      return None
+    }
+    let gains = (0..count).map(|i| {
+      let pos = 16 + i*size;
+      if float { LEf32(data, pos) } else { LEu16(data, pos) as f32 / 32768.0 }
+    }).collect();
+    Some(FlatField {
+      x: head[0],
+      y: head[1],
+      width: head[2],
+      height: head[3],
+      cell_width: head[4],
+      cell_height: head[5],
+      columns,
+      rows,
+      red_blue,
+      gains,
+    })
+  }
+
+  fn get_wb(&self, wb_offset: usize) -> Result<[f32;4], RawLoaderError> {
     Ok([LEf32(self.buffer, wb_offset),
         LEf32(self.buffer, wb_offset+4),
         LEf32(self.buffer, wb_offset+8), NAN])
   }
 
-  pub(crate) fn decode_compressed(buffer: &[u8], data_offset: usize, strip_offset: usize, width: usize, height: usize, dummy: bool) -> Vec<u16>{
+  pub(crate) fn decode_compressed(buffer: &[u8], data_offset: usize, strip_offset: usize, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     let lens: [u32; 10] = [8,7,6,9,11,10,5,12,14,13];
 
     decode_threaded(width, height, dummy, &(|out: &mut [u16], row| {
@@ -1,9 +1,25 @@
+use std::collections::HashSet;
+use std::io::Write;
+#[cfg(feature = "exr")] use std::io::Seek;
+
 use crate::decoders::*;
 use crate::decoders::cfa::*;
+use crate::decoders::exif::*;
+use crate::decoders::opcodes::DngOpcodes;
+use crate::decoders::corrections::LensCorrections;
+use crate::decoders::color::ColorMatrices;
+use crate::decoders::noise::NoiseModel;
+use crate::decoders::masks::SemanticMask;
+use crate::decoders::calibration::SensorCalibration;
+
+// How far to look for pixels of the same color, and how many there can be that far out
+const MAX_RADIUS: usize = 3;
+const MAX_NEIGHBORS: usize = (2*MAX_RADIUS+1)*(2*MAX_RADIUS+1) - 1;
 
 /// All the data needed to process this raw image, including the image data itself as well
 /// as all the needed metadata
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RawImage {
   /// camera make as encoded in the file
   pub make: String,
@@ -19,31 +35,356 @@ pub struct RawImage {
   pub height: usize,
   /// number of components per pixel (1 for bayer, 3 for RGB images)
   pub cpp: usize,
-  /// whitebalance coefficients encoded in the file in RGBE order
+  /// whitebalance coefficients encoded in the file in the order of the CFA color indexes
+  /// (RGBE for the usual patterns, see `CFA::colors()`)
+  #[cfg_attr(feature = "serde", serde(deserialize_with = "nan_from_null"))]
   pub wb_coeffs: [f32;4],
-  /// image whitelevels in RGBE order
+  /// image whitelevels in the order of the CFA color indexes
   pub whitelevels: [u16;4],
-  /// image blacklevels in RGBE order
+  /// image blacklevels in the order of the CFA color indexes
   pub blacklevels: [u16;4],
-  /// matrix to convert XYZ to camera RGBE
+  /// whitelevels from the camera database (or the file itself when it is self describing)
+  /// before any adjustment by the decoder
+  pub camera_whitelevels: [u16;4],
+  /// blacklevels from the camera database (or the file itself when it is self describing)
+  /// before any adjustment by the decoder
+  pub camera_blacklevels: [u16;4],
+  /// blacklevels measured from the masked areas of the sensor when the decoder did so, in
+  /// which case they are also what `blacklevels` is set to
+  pub measured_blacklevels: Option<[f32;4]>,
+  /// matrix to convert XYZ to camera colors, one row per CFA color index
   pub xyz_to_cam: [[f32;3];4],
-  /// color filter array
+  /// the color matrices for each calibration illuminant, a single D65 one for formats that
+  /// aren't DNG
+  pub color_matrices: ColorMatrices,
+  /// how DNGs ask to be rendered, empty for other formats
+  pub rendering_hints: RenderingHints,
+  /// noise model of each color from the DNG NoiseProfile tag, in the order of the CFA color
+  /// indexes. Empty when the file doesn't have one, see `estimate_noise()`
+  pub noise_profile: Vec<NoiseModel>,
+  /// semantic masks and depth maps stored with the image, only found in DNGs
+  pub masks: Vec<SemanticMask>,
+  /// per unit calibration of medium format backs (Phase One and Hasselblad)
+  pub calibration: Option<SensorCalibration>,
+  /// number of raw images in the file (frames of a burst or pixel shift, the halves of a
+  /// Dual Pixel raw, several resolutions of a DNG), see `RawDecodeParams` to decode the others
+  pub image_count: usize,
+  /// color filter array as seen from the top-left pixel of the full image. Use
+  /// `cropped_cfa()` or `cfa_for_crop()` to get the pattern for a cropped area. The decoders
+  /// set it for the data they return, after any rotation or reorientation, and leave it empty
+  /// when they return RGB, so it never needs to be shifted by hand
   pub cfa: CFA,
-  /// how much to crop the image to get all the usable area, order is top, right, bottom, left
+  /// how much to crop the image to get all the usable area, order is top, right, bottom, left.
+  /// See `areas()` for this together with the other crops as rectangles
   pub crops: [usize;4],
+  /// crop the camera maker recommends within the usable area, in full image coordinates,
+  /// when it's not all of it
+  pub default_crop: Option<Rect>,
+  /// crop the image was framed with, e.g. an in-camera aspect ratio, in full image
+  /// coordinates
+  pub display_crop: Option<Rect>,
+  /// physical layout of the sensor pixels relative to the stored image data
+  pub geometry: SensorGeometry,
 
   /// Areas of the sensor that is masked to prevent it from receiving light. Used to calculate
   /// black levels and noise. Each tuple represents a masked rectangle's top, right, bottom, left
   pub blackareas: Vec<(u64,u64,u64,u64)>,
 
+  /// defective pixels listed in the file's metadata as (row, column) in the full image. Only
+  /// the lists of DNG FixBadPixelsList opcodes and Phase One defects are read, the ones
+  /// in Olympus and Panasonic makernotes aren't.
+  pub bad_pixels: Vec<(u32,u32)>,
+
+  /// DNG opcode lists, the processing steps the file asks to apply to the image, for
+  /// example lens corrections. Empty for other formats
+  pub opcodes: DngOpcodes,
+
   /// orientation of the image as indicated by the image metadata
   pub orientation: Orientation,
-  /// image data itself, has `width`\*`height`\*`cpp` elements
+  /// shooting information from the file's EXIF data
+  pub exif: Exif,
+  /// the file ended before the strips or tiles of the image data did, so only the rows that
+  /// were there are decoded and the rest are left as zeros. Only lenient decodes of TIFF based
+  /// formats with the image in rows return such images, strict ones fail instead.
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub partially_decoded: bool,
+  /// for partially decoded images where in the file the data ran out, in bytes
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub failure_offset: Option<usize>,
+  /// the problems the decode worked around, like missing metadata that was filled in with
+  /// defaults
+  #[cfg_attr(feature = "serde", serde(default))]
+  pub warnings: Vec<DecodeWarning>,
+  /// image data itself, has `width`\*`height`\*`cpp` elements. Serialized data without it
+  /// (see `RawMetadata` to leave it out) deserializes with it empty.
+  /// `pixels()` gives it together with its layout.
+  #[cfg_attr(feature = "serde", serde(default))]
   pub data: RawImageData,
 }
 
+/// The two frames of a sensor with dual gain readout, where the same exposure is read out
+/// twice at different gains. The high gain frame has less noise in the shadows while the low
+/// gain one keeps the highlights that clip in the other.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DualGainImage {
+  /// the brighter frame
+  pub high_gain: RawImage,
+  /// the darker frame
+  pub low_gain: RawImage,
+  /// how much brighter the high gain frame is, estimated from the image data
+  pub gain_ratio: f32,
+}
+
+impl DualGainImage {
+  #[doc(hidden)] pub fn new(high_gain: RawImage, low_gain: RawImage) -> DualGainImage {
+    let gain_ratio = Self::estimate_gain_ratio(&high_gain, &low_gain);
+    DualGainImage {
+      high_gain,
+      low_gain,
+      gain_ratio,
+    }
+  }
+
+  fn same_shape(high: &RawImage, low: &RawImage) -> bool {
+    high.width == low.width && high.height == low.height && high.cpp == low.cpp &&
+      high.data_len() == high.width*high.height*high.cpp && low.data_len() == high.data_len()
+  }
+
+  fn estimate_gain_ratio(high: &RawImage, low: &RawImage) -> f32 {
+    if !Self::same_shape(high, low) {
+      return 1.0
+    }
+
+    // Compare the pixels that are well exposed but not close to clipping in the bright frame
+    let mut sumhigh = 0.0f64;
+    let mut sumlow = 0.0f64;
+    for i in 0..high.data_len() {
+      let color = high.color_for_index(i);
+      let range = high.whitelevels[color] as f32 - high.blacklevels[color] as f32;
+      let h = high.data.get_f32(i) - high.blacklevels[color] as f32;
+      let l = low.data.get_f32(i) - low.blacklevels[color] as f32;
+      if h > range * 0.1 && h < range * 0.8 && l > 0.0 {
+        sumhigh += h as f64;
+        sumlow += l as f64;
+      }
+    }
+
+    if sumlow > 0.0 { (sumhigh / sumlow) as f32 } else { 1.0 }
+  }
+
+  /// Merge both frames into a single extended range float image. The output has its black
+  /// levels at 0 and its white levels at the range of the high gain frame, with highlights
+  /// recovered from the low gain frame going above that.
+  pub fn merge(&self) -> RawImage {
+    let high = &self.high_gain;
+    let low = &self.low_gain;
+    if !Self::same_shape(high, low) {
+      return high.clone()
+    }
+
+    let mut whites = [0u16;4];
+    for (white, (w, b)) in whites.iter_mut().zip(high.whitelevels.iter().zip(high.blacklevels.iter())) {
+      *white = w.saturating_sub(*b);
+    }
+
+    let mut out = vec![0.0f32; high.data_len()];
+    for (i, o) in out.iter_mut().enumerate() {
+      let color = high.color_for_index(i);
+      let range = whites[color] as f32;
+      let h = high.data.get_f32(i) - high.blacklevels[color] as f32;
+      let l = (low.data.get_f32(i) - low.blacklevels[color] as f32) * self.gain_ratio;
+      // Fade into the low gain data as the high gain one gets close to clipping
+      let t = if range > 0.0 { ((h / range - 0.8) / 0.15).clamp(0.0, 1.0) } else { 1.0 };
+      *o = h * (1.0 - t) + l * t;
+    }
+
+    let mut img = high.clone();
+    img.data = RawImageData::Float(out);
+    img.blacklevels = [0,0,0,0];
+    img.whitelevels = whites;
+    img.blackareas = Vec::new();
+    img
+  }
+}
+
+/// Everything needed to catalog a raw image without its data, as returned by
+/// `rawloader::probe()`, which skips decoding the image data for all formats
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RawMetadata {
+  /// the format of the file, None for the ones of decoders from outside the library
+  #[cfg_attr(feature = "serde", serde(with = "capabilities::format_name"))]
+  pub format: Option<&'static capabilities::FormatCapabilities>,
+  /// camera make as encoded in the file
+  pub make: String,
+  /// camera model as encoded in the file
+  pub model: String,
+  /// make cleaned up to be consistent and short
+  pub clean_make: String,
+  /// model cleaned up to be consistent and short
+  pub clean_model: String,
+  /// width of the full image
+  pub width: usize,
+  /// height of the full image
+  pub height: usize,
+  /// number of components per pixel (1 for bayer, 3 for RGB images)
+  pub cpp: usize,
+  /// whitebalance coefficients encoded in the file in the order of the CFA color indexes
+  #[cfg_attr(feature = "serde", serde(deserialize_with = "nan_from_null"))]
+  pub wb_coeffs: [f32;4],
+  /// image whitelevels in the order of the CFA color indexes
+  pub whitelevels: [u16;4],
+  /// image blacklevels in the order of the CFA color indexes
+  pub blacklevels: [u16;4],
+  /// matrix to convert XYZ to camera colors, one row per CFA color index
+  pub xyz_to_cam: [[f32;3];4],
+  /// color filter array as seen from the top-left pixel of the full image
+  pub cfa: CFA,
+  /// how much to crop the image to get all the usable area, order is top, right, bottom, left
+  pub crops: [usize;4],
+  /// crop the camera maker recommends within the usable area, in full image coordinates
+  pub default_crop: Option<Rect>,
+  /// crop the image was framed with, in full image coordinates
+  pub display_crop: Option<Rect>,
+  /// orientation of the image as indicated by the image metadata
+  pub orientation: Orientation,
+  /// number of raw images in the file
+  pub image_count: usize,
+  /// shooting information from the file's EXIF data
+  pub exif: Exif,
+}
+
+impl RawMetadata {
+  pub(crate) fn new(format: &'static str, image: RawImage) -> RawMetadata {
+    RawMetadata {
+      format: capabilities::for_format(format),
+      make: image.make,
+      model: image.model,
+      clean_make: image.clean_make,
+      clean_model: image.clean_model,
+      width: image.width,
+      height: image.height,
+      cpp: image.cpp,
+      wb_coeffs: image.wb_coeffs,
+      whitelevels: image.whitelevels,
+      blacklevels: image.blacklevels,
+      xyz_to_cam: image.xyz_to_cam,
+      cfa: image.cfa,
+      crops: image.crops,
+      default_crop: image.default_crop,
+      display_crop: image.display_crop,
+      orientation: image.orientation,
+      image_count: image.image_count,
+      exif: image.exif,
+    }
+  }
+}
+
+/// A rectangular area of the image in pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rect {
+  /// column of the left edge
+  pub x: usize,
+  /// row of the top edge
+  pub y: usize,
+  /// width in pixels
+  pub width: usize,
+  /// height in pixels
+  pub height: usize,
+}
+
+impl Rect {
+  /// Creates a rectangle from its top left corner and size
+  pub fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+    Rect { x, y, width, height }
+  }
+
+  /// The part of this rectangle that is also inside another one
+  ///
+  /// ```
+  /// use rawloader::Rect;
+  /// let rect = Rect::new(0, 0, 100, 100);
+  /// assert_eq!(rect.intersect(&Rect::new(50, 80, 100, 100)), Rect::new(50, 80, 50, 20));
+  /// assert!(rect.intersect(&Rect::new(200, 0, 10, 10)).is_empty());
+  /// ```
+  pub fn intersect(&self, other: &Rect) -> Rect {
+    let (x, y) = (self.x.max(other.x), self.y.max(other.y));
+    let right = (self.x + self.width).min(other.x + other.width);
+    let bottom = (self.y + self.height).min(other.y + other.height);
+    Rect { x, y, width: right.saturating_sub(x), height: bottom.saturating_sub(y) }
+  }
+
+  /// Checks if the rectangle has no area
+  pub fn is_empty(&self) -> bool {
+    self.width == 0 || self.height == 0
+  }
+}
+
+/// The nested areas of the image, from the full stored data to the part that was framed by
+/// the photographer. Each one is contained in the previous one and all are in full image
+/// coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageAreas {
+  /// width of the full image data including masked and garbage areas
+  pub sensor_width: usize,
+  /// height of the full image data including masked and garbage areas
+  pub sensor_height: usize,
+  /// the area with valid image data
+  pub active_area: Rect,
+  /// the area the camera maker recommends using as the image
+  pub default_crop: Rect,
+  /// the area to show by default, for example with the aspect ratio the camera was set to
+  pub display_crop: Rect,
+}
+
+/// Hints DNGs give on how the image should be rendered
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RenderingHints {
+  /// exposure compensation in EV to apply so the image renders at the intended brightness,
+  /// phone DNGs often rely on it being applied
+  pub baseline_exposure: Option<f32>,
+  /// noise level of the camera at its base ISO relative to a reference camera
+  pub baseline_noise: Option<f32>,
+  /// amount of sharpening needed relative to a reference camera
+  pub baseline_sharpness: Option<f32>,
+  /// fraction of the white level up to which the sensor response is linear
+  pub linear_response_limit: Option<f32>,
+}
+
+impl RenderingHints {
+  pub(crate) fn from_tiff(tiff: &TiffIFD) -> RenderingHints {
+    let get = |tag| tiff.find_entry(tag).filter(|e| e.count() > 0).map(|e| e.get_f32(0)).filter(|v| v.is_finite());
+    RenderingHints {
+      baseline_exposure: get(Tag::BaselineExposure),
+      baseline_noise: get(Tag::BaselineNoise),
+      baseline_sharpness: get(Tag::BaselineSharp),
+      linear_response_limit: get(Tag::LinearRespLimit),
+    }
+  }
+}
+
+/// How the sensor pixels are physically laid out in relation to the stored image data
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SensorGeometry {
+  /// The usual regular rectangular grid of pixels
+  Rectangular,
+  /// Fuji SuperCCD sensors have their pixels on a lattice rotated by 45 degrees. The data is
+  /// stored so that the lattice becomes a regular grid and thus the CFA can be used directly,
+  /// but the scene ends up tilted and surrounded by empty corners. `RawImage::rotate_upright()`
+  /// resamples it back into an upright image.
+  Rotated45 {
+    /// row of the stored image where the left corner of the tilted scene sits
+    fuji_width: usize,
+  },
+}
+
 /// The actual image data, after decoding
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum RawImageData {
   /// The most usual u16 output of almost all formats
   Integer(Vec<u16>),
@@ -51,33 +392,124 @@ pub enum RawImageData {
   Float(Vec<f32>),
 }
 
+/// The samples of a `RawPixels`, in the type they were decoded as
+#[derive(Debug, Copy, Clone)]
+pub enum RawSamples<'a> {
+  /// values of `RawImageData::Integer` data
+  Integer(&'a [u16]),
+  /// values of `RawImageData::Float` data
+  Float(&'a [f32]),
+}
+
+impl<'a> RawSamples<'a> {
+  /// Number of values
+  pub fn len(&self) -> usize {
+    match self {
+      RawSamples::Integer(data) => data.len(),
+      RawSamples::Float(data) => data.len(),
+    }
+  }
+
+  /// Checks if there are no values
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+/// The image data borrowed from a `RawImage` together with how to read it, as returned by
+/// `RawImage::pixels()`
+#[derive(Debug, Copy, Clone)]
+pub struct RawPixels<'a> {
+  /// the values, row after row with the components of each pixel next to each other
+  pub samples: RawSamples<'a>,
+  /// pixels per row
+  pub width: usize,
+  /// number of rows
+  pub height: usize,
+  /// components per pixel
+  pub cpp: usize,
+  /// distance in values from the start of a row to the start of the next
+  pub stride: usize,
+  /// bits of each integer value that can be set, from the highest white level, or 32 for
+  /// floating point data
+  pub bits: u32,
+}
+
+impl<'a> RawPixels<'a> {
+  /// The values of a row, or None if it's outside of the image
+  pub fn row(&self, row: usize) -> Option<RawSamples<'a>> {
+    if row >= self.height {
+      return None
+    }
+    let range = row*self.stride..row*self.stride + self.width*self.cpp;
+    match self.samples {
+      RawSamples::Integer(data) => data.get(range).map(RawSamples::Integer),
+      RawSamples::Float(data) => data.get(range).map(RawSamples::Float),
+    }
+  }
+
+  /// Value of a component of the pixel at a row and column as a float, or None if it's
+  /// outside of the image
+  pub fn get(&self, row: usize, col: usize, component: usize) -> Option<f32> {
+    if col >= self.width || component >= self.cpp {
+      return None
+    }
+    let idx = col*self.cpp + component;
+    match self.row(row)? {
+      RawSamples::Integer(data) => Some(data[idx] as f32),
+      RawSamples::Float(data) => Some(data[idx]),
+    }
+  }
+}
+
+// JSON has no NaN so serde_json writes the ones of unknown coefficients as null, which are
+// read back as NaN here
+#[cfg(feature = "serde")]
+pub(crate) fn nan_from_null<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<[f32;4], D::Error> {
+  let values = <[Option<f32>;4] as serde::Deserialize>::deserialize(deserializer)?;
+  Ok(values.map(|v| v.unwrap_or(f32::NAN)))
+}
+
+impl Default for RawImageData {
+  fn default() -> Self {
+    RawImageData::Integer(Vec::new())
+  }
+}
+
+impl RawImageData {
+  pub(crate) fn get_f32(&self, idx: usize) -> f32 {
+    match *self {
+      RawImageData::Integer(ref data) => data[idx] as f32,
+      RawImageData::Float(ref data) => data[idx],
+    }
+  }
+
+  pub(crate) fn set_f32(&mut self, idx: usize, val: f32) {
+    match *self {
+      RawImageData::Integer(ref mut data) => data[idx] = val.round().clamp(0.0, 65535.0) as u16,
+      RawImageData::Float(ref mut data) => data[idx] = val,
+    }
+  }
+}
+
 impl RawImage {
-  #[doc(hidden)] pub fn new(camera: Camera, width: usize, height: usize, wb_coeffs: [f32;4], image: Vec<u16>, dummy: bool) -> RawImage {
-    let blacks = if !dummy && (camera.blackareah.1 != 0 || camera.blackareav.1 != 0) {
-      let mut avg = [0 as f32; 4];
-      let mut count = [0 as f32; 4];
-      for row in camera.blackareah.0 .. camera.blackareah.0+camera.blackareah.1 {
-        for col in 0..width {
-          let color = camera.cfa.color_at(row,col);
-          avg[color] += image[row*width+col] as f32;
-          count[color] += 1.0;
-        }
-      }
-      for row in 0..height {
-        for col in camera.blackareav.0 .. camera.blackareav.0+camera.blackareav.1 {
-          let color = camera.cfa.color_at(row,col);
-          avg[color] += image[row*width+col] as f32;
-          count[color] += 1.0;
-        }
-      }
-      [(avg[0]/count[0]) as u16,
-       (avg[1]/count[1]) as u16,
-       (avg[2]/count[2]) as u16,
-       (avg[3]/count[3]) as u16]
-    } else {
-      camera.blacklevels
-    };
+  /// Creates an image from its data with everything else unset, for decoders from outside
+  /// the library. The white level is the largest integer value and the black level 0,
+  /// everything else needs to be filled in as far as the format allows.
+  pub fn from_data(make: &str, model: &str, width: usize, height: usize, cpp: usize, data: RawImageData) -> RawImage {
+    let mut camera = Camera::new();
+    camera.make = make.to_string();
+    camera.model = model.to_string();
+    camera.clean_make = make.to_string();
+    camera.clean_model = model.to_string();
+    camera.whitelevels = [65535; 4];
+    let mut img = RawImage::new(camera, width, height, [f32::NAN; 4], Vec::new(), true);
+    img.cpp = cpp;
+    img.data = data;
+    img
+  }
 
+  #[doc(hidden)] pub fn new(camera: Camera, width: usize, height: usize, wb_coeffs: [f32;4], image: Vec<u16>, dummy: bool) -> RawImage {
     // tuple format is top, right, bottom left
     let mut blackareas: Vec<(u64,u64,u64,u64)> = Vec::new();
 
@@ -91,7 +523,14 @@ impl RawImage {
                         height as u64, camera.blackareav.0 as u64))
     }
 
-    RawImage {
+    // Complementary color sensors are old enough that we usually don't have a measured matrix
+    let xyz_to_cam = if camera.cfa.is_complementary() && camera.xyz_to_cam == [[0.0;3];4] {
+      camera.cfa.ideal_xyz_to_cam()
+    } else {
+      camera.xyz_to_cam
+    };
+
+    let mut img = RawImage {
       make: camera.make.clone(),
       model: camera.model.clone(),
       clean_make: camera.clean_make.clone(),
@@ -101,14 +540,104 @@ impl RawImage {
       cpp: 1,
       wb_coeffs: wb_coeffs,
       data: RawImageData::Integer(image),
-      blacklevels: blacks,
+      blacklevels: camera.blacklevels,
       whitelevels: camera.whitelevels,
-      xyz_to_cam: camera.xyz_to_cam,
+      camera_blacklevels: camera.blacklevels,
+      camera_whitelevels: camera.whitelevels,
+      measured_blacklevels: None,
+      xyz_to_cam,
+      color_matrices: ColorMatrices::from_single(xyz_to_cam),
+      rendering_hints: RenderingHints::default(),
+      noise_profile: Vec::new(),
+      masks: Vec::new(),
+      calibration: None,
+      image_count: 1,
       cfa: camera.cfa.clone(),
       crops: camera.crops,
+      default_crop: None,
+      display_crop: None,
+      geometry: SensorGeometry::Rectangular,
       blackareas: blackareas,
+      bad_pixels: Vec::new(),
+      opcodes: DngOpcodes::default(),
       orientation: camera.orientation,
+      exif: camera.exif,
+      partially_decoded: false,
+      failure_offset: None,
+      warnings: Vec::new(),
+    };
+
+    if !dummy && !img.blackareas.is_empty() {
+      if let Some(blacks) = img.measure_blacklevels() {
+        img.blacklevels = [blacks[0] as u16, blacks[1] as u16, blacks[2] as u16, blacks[3] as u16];
+        img.measured_blacklevels = Some(blacks);
+      }
     }
+
+    img
+  }
+
+  /// Measure the per-color blacklevels by averaging the pixels in the masked areas of the
+  /// sensor. Colors that don't show up in those areas are returned as NaN. Returns None if
+  /// there are no masked areas or no image data to measure.
+  pub fn measure_blacklevels(&self) -> Option<[f32;4]> {
+    if self.blackareas.is_empty() || self.data_len() != self.width*self.height*self.cpp {
+      return None
+    }
+
+    let mut avg = [0 as f32; 4];
+    let mut count = [0 as f32; 4];
+    for &(top, right, bottom, left) in &self.blackareas {
+      for row in (top as usize)..(bottom as usize).min(self.height) {
+        for col in (left as usize)..(right as usize).min(self.width) {
+          for cpp in 0..self.cpp {
+            let idx = (row*self.width+col)*self.cpp + cpp;
+            let color = self.color_for_index(idx);
+            avg[color] += self.data.get_f32(idx);
+            count[color] += 1.0;
+          }
+        }
+      }
+    }
+
+    Some([avg[0]/count[0], avg[1]/count[1], avg[2]/count[2], avg[3]/count[3]])
+  }
+
+  /// Measure the blacklevel of each row of the image from the masked pixels at its sides, to be
+  /// able to remove horizontal banding. Rows with no masked pixels get the average of the
+  /// ones that have them. Returns None if no rows have masked pixels.
+  pub fn row_blacklevels(&self) -> Option<Vec<f32>> {
+    if self.blackareas.is_empty() || self.data_len() != self.width*self.height*self.cpp {
+      return None
+    }
+
+    let mut rows: Vec<Option<f32>> = vec![None; self.height];
+    for (row, out) in rows.iter_mut().enumerate() {
+      let mut sum = 0.0;
+      let mut count = 0;
+      for &(top, right, bottom, left) in &self.blackareas {
+        // Areas spanning the full width are above or below the image and not at its sides
+        if row < top as usize || row >= bottom as usize || (left == 0 && right as usize >= self.width) {
+          continue
+        }
+        for col in (left as usize)..(right as usize).min(self.width) {
+          for cpp in 0..self.cpp {
+            sum += self.data.get_f32((row*self.width+col)*self.cpp + cpp);
+            count += 1;
+          }
+        }
+      }
+      if count > 0 {
+        *out = Some(sum / count as f32);
+      }
+    }
+
+    let measured: Vec<f32> = rows.iter().filter_map(|&v| v).collect();
+    if measured.is_empty() {
+      return None
+    }
+    let avg = measured.iter().sum::<f32>() / measured.len() as f32;
+    Some(rows.iter().map(|v| v.unwrap_or(avg)).collect())
   }
 
   /// Outputs the inverted matrix that converts pixels in the camera colorspace into
@@ -159,20 +688,95 @@ impl RawImage {
      neutralwb[3]/neutralwb[1]]
   }
 
+  /// Converts white balance multipliers into a correlated color temperature in Kelvin and a
+  /// tint, positive towards magenta and negative towards green, on the scale raw developers
+  /// use. Returns None if the camera has no color matrix or the multipliers aren't valid.
+  ///
+  /// Use `as_shot_temperature()` for the multipliers of the image itself.
+  pub fn wb_to_temperature(&self, coeffs: [f32;4]) -> Option<(f32, f32)> {
+    if coeffs[0..3].iter().any(|&c| !c.is_finite() || c <= 0.0) || !self.has_color_matrix() {
+      return None
+    }
+    // The multipliers make the illuminant neutral so its camera response is their inverse
+    let mut cam = [0.0f64; 4];
+    for (c, &coeff) in cam.iter_mut().zip(coeffs.iter()) {
+      *c = if coeff.is_finite() && coeff > 0.0 { 1.0 / coeff as f64 } else { 0.0 };
+    }
+
+    // The matrix depends on the white point we're looking for so iterate until they agree
+    let mut matrix = self.xyz_to_cam;
+    let mut result = None;
+    for _ in 0..20 {
+      let cam_to_xyz = Self::pseudoinverse(matrix);
+      let mut xyz = [0.0f64; 3];
+      for i in 0..3 {
+        for j in 0..4 {
+          xyz[i] += cam_to_xyz[i][j] as f64 * cam[j];
+        }
+      }
+      let (u, v) = colortemp::xyz_to_uv(xyz)?;
+      let (temp, tint) = colortemp::uv_to_temperature(u, v);
+      let done = result.is_some_and(|(prev, _): (f64, f64)| (prev - temp).abs() < 1.0);
+      result = Some((temp, tint));
+      if done {
+        break
+      }
+      matrix = self.xyz_to_cam_at(temp as f32);
+    }
+    result.map(|(temp, tint)| (temp as f32, tint as f32))
+  }
+
+  /// XYZ to camera matrix for a white point of the given color temperature, interpolating
+  /// between the matrices of the two calibration illuminants as in the DNG color model.
+  /// Falls back to `xyz_to_cam` when there's only a single matrix.
+  pub fn xyz_to_cam_at(&self, temperature: f32) -> [[f32;3];4] {
+    self.color_matrices.xyz_to_cam(temperature).unwrap_or(self.xyz_to_cam)
+  }
+
+  /// Correlated color temperature in Kelvin and tint of the as shot white balance
+  pub fn as_shot_temperature(&self) -> Option<(f32, f32)> {
+    self.wb_to_temperature(self.wb_coeffs)
+  }
+
+  /// Converts a color temperature in Kelvin and a tint, as returned by `wb_to_temperature()`,
+  /// into white balance multipliers normalized to green. Returns None if the camera has no
+  /// color matrix.
+  pub fn temperature_to_wb(&self, temperature: f32, tint: f32) -> Option<[f32;4]> {
+    if !temperature.is_finite() || !tint.is_finite() || !self.has_color_matrix() {
+      return None
+    }
+    let (u, v) = colortemp::temperature_to_uv(temperature as f64, tint as f64);
+    let xyz = colortemp::uv_to_xyz(u, v);
+
+    let mut coeffs = [f32::NAN; 4];
+    for (coeff, row) in coeffs.iter_mut().zip(self.xyz_to_cam_at(temperature).iter()) {
+      let cam: f64 = row.iter().zip(xyz.iter()).map(|(&m, &c)| m as f64 * c).sum();
+      if cam > 0.0 {
+        *coeff = (1.0 / cam) as f32;
+      }
+    }
+    if !coeffs[1].is_finite() {
+      return None
+    }
+    let green = coeffs[1];
+    Some([coeffs[0]/green, 1.0, coeffs[2]/green, coeffs[3]/green])
+  }
+
+  pub(crate) fn has_color_matrix(&self) -> bool {
+    self.xyz_to_cam.iter().any(|row| row.iter().any(|&v| v != 0.0))
+  }
+
   /// Normalize matrix so that inm * (1,1,1) is (1,1,1,1) and then invert
   pub fn normalized_pseudoinverse(inm: [[f32;3];4]) -> [[f32;4];3] {
     let mut xyz_to_cam = inm;
     // Normalize xyz_to_cam so that xyz_to_cam * (1,1,1) is (1,1,1,1)
-    for i in 0..4 {
-      let mut num = 0.0;
-      for j in 0..3 {
-        num += xyz_to_cam[i][j];
-      }
-      for j in 0..3 {
-        xyz_to_cam[i][j] = if num == 0.0 {
+    for row in xyz_to_cam.iter_mut() {
+      let num: f32 = row.iter().sum();
+      for val in row.iter_mut() {
+        *val = if num == 0.0 {
           0.0
         }  else {
-          xyz_to_cam[i][j] / num
+          *val / num
         };
       }
     }
@@ -223,14 +827,818 @@ impl RawImage {
     out
   }
 
+  /// The areas of the image as rectangles, from the sensor size down to the display crop
+  pub fn areas(&self) -> ImageAreas {
+    let sensor = Rect::new(0, 0, self.width, self.height);
+    let [top, right, bottom, left] = self.crops;
+    let active = Rect::new(left, top, self.width.saturating_sub(left+right), self.height.saturating_sub(top+bottom))
+      .intersect(&sensor);
+    let nested = |crop: Option<Rect>, outer: Rect| {
+      crop.map(|c| c.intersect(&outer)).filter(|c| !c.is_empty()).unwrap_or(outer)
+    };
+    let default_crop = nested(self.default_crop, active);
+    ImageAreas {
+      sensor_width: self.width,
+      sensor_height: self.height,
+      active_area: active,
+      default_crop,
+      display_crop: nested(self.display_crop, default_crop),
+    }
+  }
+
   /// Returns the CFA pattern after the crop has been applied (and thus the pattern
   /// potentially shifted)
   pub fn cropped_cfa(&self) -> CFA {
-    self.cfa.shift(self.crops[3], self.crops[0])
+    self.cfa_for_crop(self.crops[3], self.crops[0])
+  }
+
+  /// Returns the CFA pattern as seen from an arbitrary crop of the image starting at
+  /// column `x` and row `y`. Useful when a consumer applies its own crop instead of `crops`
+  /// and still needs to know which color each pixel is. `x` and `y` are in the coordinates of
+  /// the full image, so `cfa_for_crop(0, 0)` is `cfa` itself.
+  pub fn cfa_for_crop(&self, x: usize, y: usize) -> CFA {
+    self.cfa.shift(x, y)
+  }
+
+  /// Resamples an image from a sensor with `SensorGeometry::Rotated45` into an upright
+  /// rectangular mosaic with the same CFA pattern. Each output pixel is interpolated from the
+  /// nearby stored pixels of the same color. Images with a rectangular geometry are returned
+  /// unchanged. Fails with `RawLoaderError::LimitExceeded` if there isn't enough memory for
+  /// the resampled image.
+  pub fn rotate_upright(&self) -> Result<RawImage, RawLoaderError> {
+    let fuji_width = match self.geometry {
+      SensorGeometry::Rotated45{fuji_width} if self.cpp == 1 => fuji_width,
+      _ => return Ok(self.clone()),
+    };
+
+    let step = 0.5f32.sqrt();
+    let wide = (fuji_width as f32 / step) as usize;
+    let high = (self.height.saturating_sub(fuji_width) as f32 / step) as usize;
+    let dummy = self.data_len() != self.width*self.height;
+
+    let data = match self.data {
+      RawImageData::Integer(ref data) => {
+        let out = self.resample_45(fuji_width, wide, high, dummy, |i| data[i] as f32)?;
+        RawImageData::Integer(out.iter().map(|v| v.round() as u16).collect())
+      },
+      RawImageData::Float(ref data) => {
+        RawImageData::Float(self.resample_45(fuji_width, wide, high, dummy, |i| data[i])?)
+      },
+    };
+
+    let mut img = self.clone();
+    img.width = wide;
+    img.height = high;
+    img.data = data;
+    img.crops = [0,0,0,0];
+    img.default_crop = None;
+    img.display_crop = None;
+    img.blackareas = Vec::new();
+    img.geometry = SensorGeometry::Rectangular;
+    Ok(img)
+  }
+
+  /// Demosaics the CFA with bilinear interpolation into an image of interleaved RGB with the
+  /// same size, sample type (u16 for integer data and f32 for floating point) and metadata,
+  /// which can then be developed or written like any RGB image. Fast and simple, so meant
+  /// for previews rather than final output. Only CFAs of red, green and blue, like Bayer
+  /// and X-Trans, can be demosaiced, and images that are already RGB are returned unchanged.
+  ///
+  /// ```
+  /// use rawloader::{RawImage, RawImageData, CFA};
+  /// let data = RawImageData::Integer(vec![10, 20, 10, 20, 30, 40, 30, 40]);
+  /// let mut image = RawImage::from_data("Make", "Model", 4, 2, 1, data);
+  /// image.cfa = CFA::new("RGGB");
+  /// let rgb = image.demosaic_bilinear().unwrap();
+  /// assert_eq!(rgb.cpp, 3);
+  /// assert!(matches!(rgb.data, RawImageData::Integer(ref data) if data[..3] == [10, 25, 40]));
+  /// ```
+  pub fn demosaic_bilinear(&self) -> Result<RawImage, RawLoaderError> {
+    demosaic::bilinear(self)
+  }
+
+  /// Borrows the image data with its size, stride and bit depth, so it can be read without
+  /// having to know how `data` is laid out
+  ///
+  /// ```
+  /// use rawloader::{RawImage, RawImageData, RawSamples};
+  /// let data = RawImageData::Integer(vec![1, 2, 3, 4, 5, 6]);
+  /// let mut image = RawImage::from_data("Make", "Model", 3, 2, 1, data);
+  /// image.whitelevels = [4095; 4];
+  /// let pixels = image.pixels();
+  /// assert_eq!((pixels.stride, pixels.bits), (3, 12));
+  /// assert_eq!(pixels.get(1, 2, 0), Some(6.0));
+  /// assert!(matches!(pixels.row(1), Some(RawSamples::Integer(&[4, 5, 6]))));
+  /// ```
+  pub fn pixels(&self) -> RawPixels<'_> {
+    let (samples, bits) = match self.data {
+      RawImageData::Integer(ref data) => {
+        let white = self.whitelevels.iter().max().copied().unwrap_or(u16::MAX);
+        (RawSamples::Integer(data), 16 - white.leading_zeros())
+      },
+      RawImageData::Float(ref data) => (RawSamples::Float(data), 32),
+    };
+    RawPixels {
+      samples,
+      width: self.width,
+      height: self.height,
+      cpp: self.cpp,
+      stride: self.width*self.cpp,
+      bits,
+    }
+  }
+
+  /// Width and height of the image once `orientation` is applied, swapped from `width` and
+  /// `height` for the orientations that turn the image sideways
+  pub fn display_size(&self) -> (usize, usize) {
+    if self.orientation.to_flips().0 { (self.height, self.width) } else { (self.width, self.height) }
+  }
+
+  /// Where the pixel at a row and column of the stored image ends up once `orientation` is
+  /// applied, or None if it's outside of the image
+  ///
+  /// ```
+  /// use rawloader::{RawImage, RawImageData, Orientation};
+  /// let mut image = RawImage::from_data("Make", "Model", 300, 200, 1, RawImageData::Integer(Vec::new()));
+  /// image.orientation = Orientation::Rotate90;
+  /// assert_eq!(image.display_size(), (200, 300));
+  /// assert_eq!(image.to_display(0, 0), Some((0, 199)));
+  /// assert_eq!(image.to_sensor(0, 199), Some((0, 0)));
+  /// assert_eq!(image.to_display(200, 0), None);
+  /// ```
+  pub fn to_display(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+    if row >= self.height || col >= self.width {
+      return None
+    }
+    Some(self.orientation.oriented(row, col, self.width, self.height))
+  }
+
+  /// Where the pixel at a row and column of the image with `orientation` applied is in the
+  /// stored image, or None if it's outside of the image. The opposite of `to_display()`.
+  pub fn to_sensor(&self, row: usize, col: usize) -> Option<(usize, usize)> {
+    let (width, height) = self.display_size();
+    if row >= height || col >= width {
+      return None
+    }
+    Some(self.orientation.unoriented(row, col, self.width, self.height))
+  }
+
+  /// The area of the image with `orientation` applied that a rectangle of the stored image
+  /// covers, for example to show `areas()` on screen. The parts outside of the image are
+  /// left out.
+  pub fn rect_to_display(&self, rect: Rect) -> Rect {
+    let rect = rect.intersect(&Rect::new(0, 0, self.width, self.height));
+    if rect.is_empty() {
+      return Rect::default()
+    }
+    // A rectangle given by its first and last pixel ends up with its corners swapped around
+    let (r1, c1) = self.orientation.oriented(rect.y, rect.x, self.width, self.height);
+    let (r2, c2) = self.orientation.oriented(rect.y+rect.height-1, rect.x+rect.width-1, self.width, self.height);
+    Rect::new(c1.min(c2), r1.min(r2), c1.abs_diff(c2)+1, r1.abs_diff(r2)+1)
+  }
+
+  /// Rotates and flips the image data to the way up the camera intended, as given by
+  /// `orientation`, which is then set to `Orientation::Normal`. The CFA, the crops, the masked
+  /// areas and the bad pixels are moved along with the data so the image can still be
+  /// processed as a mosaic. Images with `SensorGeometry::Rotated45` are first resampled with
+  /// `rotate_upright()`. The DNG opcodes, the lens corrections and the semantic masks stay in
+  /// the original orientation, so apply them before this. Fails with
+  /// `RawLoaderError::LimitExceeded` if there isn't enough memory for the moved data.
+  ///
+  /// ```
+  /// use rawloader::{RawImage, RawImageData, Orientation};
+  /// let data = RawImageData::Integer(vec![1, 2, 3, 4, 5, 6]);
+  /// let mut image = RawImage::from_data("Make", "Model", 3, 2, 1, data);
+  /// image.orientation = Orientation::Rotate90;
+  /// image.apply_orientation().unwrap();
+  /// assert_eq!((image.width, image.height), (2, 3));
+  /// if let RawImageData::Integer(data) = image.data {
+  ///   assert_eq!(data, vec![4, 1, 5, 2, 6, 3]);
+  /// }
+  /// ```
+  pub fn apply_orientation(&mut self) -> Result<(), RawLoaderError> {
+    if self.geometry != SensorGeometry::Rectangular {
+      *self = self.rotate_upright()?;
+    }
+    let orientation = self.orientation;
+    if orientation.to_flips() == (false, false, false) || self.width == 0 || self.height == 0 {
+      return Ok(())
+    }
+    let (width, height) = (self.width, self.height);
+    let (owidth, oheight) = self.display_size();
+
+    if self.data_len() == width*height*self.cpp {
+      let cpp = self.cpp;
+      self.data = match self.data {
+        RawImageData::Integer(ref data) => RawImageData::Integer(orient_data(data, orientation, width, height, owidth, cpp)?),
+        RawImageData::Float(ref data) => RawImageData::Float(orient_data(data, orientation, width, height, owidth, cpp)?),
+      };
+    }
+
+    let active = self.rect_to_display(self.areas().active_area);
+    self.crops = [active.y, owidth - active.x - active.width, oheight - active.y - active.height, active.x];
+    self.default_crop = self.default_crop.map(|r| self.rect_to_display(r));
+    self.display_crop = self.display_crop.map(|r| self.rect_to_display(r));
+    self.blackareas = self.blackareas.iter().map(|&(top, right, bottom, left)| {
+      let rect = Rect::new(left as usize, top as usize, right.saturating_sub(left) as usize, bottom.saturating_sub(top) as usize);
+      let rect = self.rect_to_display(rect);
+      (rect.y as u64, (rect.x+rect.width) as u64, (rect.y+rect.height) as u64, rect.x as u64)
+    }).collect();
+    self.bad_pixels = self.bad_pixels.iter().filter_map(|&(row, col)| {
+      self.to_display(row as usize, col as usize).map(|(row, col)| (row as u32, col as u32))
+    }).collect();
+    self.cfa = self.cfa.orient(orientation, width, height);
+    self.width = owidth;
+    self.height = oheight;
+    self.orientation = Orientation::Normal;
+    Ok(())
+  }
+
+  /// Replace the pixels listed in `bad_pixels` with the average of their closest neighbors of
+  /// the same color that are not themselves bad
+  pub fn fix_bad_pixels(&mut self) {
+    let bad = self.bad_pixels.clone();
+    self.interpolate_pixels(&bad);
+  }
+
+  /// Apply the DNG opcodes we know how to to the image data: GainMap and FixVignetteRadial
+  /// vignetting corrections, FixBadPixelsList and FixBadPixelsConstant and WarpRectilinear
+  /// distortion and chromatic aberration correction. The ones that were applied are removed
+  /// from `opcodes` so the rest can still be handled by the caller.
+  pub fn apply_opcodes(&mut self) {
+    opcodes::apply(self);
+  }
+
+  /// Apply the per unit sensor calibration in `calibration`: the row and column black
+  /// offsets of sensors read out in halves, the linearization curves and the flat fields,
+  /// followed by interpolating over the defective pixels and columns. The ones that were
+  /// applied are removed so only the entries whose layout isn't known are left.
+  pub fn apply_calibration(&mut self) {
+    calibration::apply(self);
+  }
+
+  /// Estimate a noise model for each color from the image itself, for files without a
+  /// `noise_profile`. The read noise is measured in the masked areas when there are any and
+  /// the shot noise from the differences between neighboring pixels of the same color, so
+  /// images with a lot of fine detail will overestimate it. Returns None if there isn't
+  /// enough data for an estimate.
+  pub fn estimate_noise(&self) -> Option<Vec<NoiseModel>> {
+    noise::estimate(self)
+  }
+
+  /// Look for stuck pixels (hot or dead) by comparing each pixel to the median of its neighbors
+  /// of the same color. A pixel is considered stuck when it is far brighter than all of its
+  /// surroundings or sits at the black level while they are clearly exposed. The result is in
+  /// the same format as `bad_pixels`.
+  pub fn find_stuck_pixels(&self) -> Vec<(u32,u32)> {
+    if self.data_len() != self.width*self.height*self.cpp {
+      return Vec::new()
+    }
+
+    (0..self.height).into_par_iter().flat_map_iter(|row| {
+      let mut stuck = Vec::new();
+      let mut neighbors = [0usize; MAX_NEIGHBORS];
+      let mut values = [0f32; MAX_NEIGHBORS];
+      for col in 0..self.width {
+        for cpp in 0..self.cpp {
+          let idx = (row*self.width+col)*self.cpp + cpp;
+          let color = self.color_for_index(idx);
+          let black = self.blacklevels[color] as f32;
+          let range = (self.whitelevels[color] as f32 - black).max(1.0);
+          let count = self.same_color_neighbors(row, col, cpp, &mut neighbors);
+          if count < 4 {
+            continue
+          }
+          let values = &mut values[..count];
+          for (val, &i) in values.iter_mut().zip(&neighbors[..count]) {
+            *val = self.data.get_f32(i) - black;
+          }
+          let (min, max) = values.iter().fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), &v| (min.min(v), max.max(v)));
+          let median = *values.select_nth_unstable_by(count/2, |a, b| a.total_cmp(b)).1;
+          let val = self.data.get_f32(idx) - black;
+
+          let hot = val - max > range * 0.25 && val > median.max(0.0) * 4.0;
+          let dead = val <= 0.0 && min > range * 0.1;
+          if hot || dead {
+            stuck.push((row as u32, col as u32));
+            break
+          }
+        }
+      }
+      stuck
+    }).collect()
+  }
+
+  // Fills in the indexes of the closest pixels of the same color, returning how many there are
+  fn same_color_neighbors(&self, row: usize, col: usize, cpp: usize, out: &mut [usize; MAX_NEIGHBORS]) -> usize {
+    let color_at = |row, col| if self.cpp == 1 { self.cfa.color_at(row, col) } else { cpp };
+    let color = color_at(row, col);
+    // Bayer has the same color two pixels away, sparser patterns may need a wider search
+    for radius in 1..=MAX_RADIUS {
+      let mut count = 0;
+      for nrow in row.saturating_sub(radius)..(row+radius+1).min(self.height) {
+        for ncol in col.saturating_sub(radius)..(col+radius+1).min(self.width) {
+          if nrow == row && ncol == col {
+            continue
+          }
+          if color_at(nrow, ncol) == color {
+            out[count] = (nrow*self.width+ncol)*self.cpp + cpp;
+            count += 1;
+          }
+        }
+      }
+      if count >= 4 {
+        return count
+      }
+    }
+    0
+  }
+
+  pub(crate) fn interpolate_pixels(&mut self, pixels: &[(u32,u32)]) {
+    if pixels.is_empty() || self.data_len() != self.width*self.height*self.cpp {
+      return
+    }
+
+    let bad: HashSet<usize> = pixels.iter()
+      .filter(|&&(row, col)| (row as usize) < self.height && (col as usize) < self.width)
+      .map(|&(row, col)| row as usize * self.width + col as usize)
+      .collect();
+
+    let mut neighbors = [0usize; MAX_NEIGHBORS];
+    for &pos in &bad {
+      let (row, col) = (pos / self.width, pos % self.width);
+      for cpp in 0..self.cpp {
+        let count = self.same_color_neighbors(row, col, cpp, &mut neighbors);
+        let good: Vec<f32> = neighbors[..count].iter()
+          .filter(|&&i| !bad.contains(&(i / self.cpp)))
+          .map(|&i| self.data.get_f32(i))
+          .collect();
+        if !good.is_empty() {
+          let avg = good.iter().sum::<f32>() / good.len() as f32;
+          self.data.set_f32(pos*self.cpp + cpp, avg);
+        }
+      }
+    }
+  }
+
+  // Which of the per-color values applies to a given index into the data
+  pub(crate) fn color_for_index(&self, idx: usize) -> usize {
+    if self.cpp == 1 {
+      let (row, col) = (idx / self.width, idx % self.width);
+      self.cfa.color_at(row, col)
+    } else {
+      idx % self.cpp
+    }
+  }
+
+  pub(crate) fn data_len(&self) -> usize {
+    match self.data {
+      RawImageData::Integer(ref data) => data.len(),
+      RawImageData::Float(ref data) => data.len(),
+    }
+  }
+
+  fn resample_45<F>(&self, fuji_width: usize, wide: usize, high: usize, dummy: bool, get: F) -> Result<Vec<f32>, RawLoaderError>
+    where F: Fn(usize) -> f32 {
+    if dummy {
+      return Ok(vec![0.0])
+    }
+
+    let step = 0.5f32.sqrt();
+    let mut out = alloc_vec(wide.saturating_mul(high), 0.0)?;
+    for row in 0..high {
+      for col in 0..wide {
+        let r = fuji_width as f32 + (row as f32 - col as f32) * step;
+        let c = (row + col) as f32 * step;
+        if r < 0.0 || c < 0.0 || r > self.height.saturating_sub(2) as f32 || c > self.width.saturating_sub(2) as f32 {
+          continue
+        }
+
+        // Weigh the same color pixels in a 4x4 neighborhood by their distance so that colors
+        // don't get mixed up in the mosaic
+        let color = self.cfa.color_at(row, col);
+        let (ur, uc) = (r as usize, c as usize);
+        let mut sum = 0.0;
+        let mut weight = 0.0;
+        for srow in ur.saturating_sub(1)..(ur+3).min(self.height) {
+          for scol in uc.saturating_sub(1)..(uc+3).min(self.width) {
+            if self.cfa.color_at(srow, scol) != color {
+              continue
+            }
+            let w = (2.0 - (srow as f32 - r).abs()).max(0.0) * (2.0 - (scol as f32 - c).abs()).max(0.0);
+            sum += get(srow*self.width+scol) * w;
+            weight += w;
+          }
+        }
+        if weight > 0.0 {
+          out[row*wide+col] = sum / weight;
+        }
+      }
+    }
+    Ok(out)
   }
 
-  /// Checks if the image is monochrome
+  /// Get the areas of the sensor that are masked from light, as top, right, bottom, left
+  /// rectangles. These are usually outside of `crops` and can be used to estimate black levels
+  /// and row noise.
+  pub fn masked_areas(&self) -> &[(u64,u64,u64,u64)] {
+    &self.blackareas
+  }
+
+  /// Get the pixel data of a rectangle of the image given as top, right, bottom, left, usually
+  /// one of the `masked_areas()`. The data is returned row by row with all the components of
+  /// each pixel and is clipped to the size of the image.
+  pub fn masked_area_data(&self, area: (u64,u64,u64,u64)) -> RawImageData {
+    let (top, right, bottom, left) = area;
+    let right = (right as usize).min(self.width);
+    let bottom = (bottom as usize).min(self.height);
+    let top = (top as usize).min(bottom);
+    let left = (left as usize).min(right);
+    let complete = self.data_len() == self.width*self.height*self.cpp;
+
+    let rowrange = |row: usize| (row*self.width+left)*self.cpp..(row*self.width+right)*self.cpp;
+    match self.data {
+      RawImageData::Integer(ref data) => {
+        let mut out = Vec::with_capacity((bottom-top)*(right-left)*self.cpp);
+        if complete {
+          for row in top..bottom {
+            out.extend_from_slice(&data[rowrange(row)]);
+          }
+        }
+        RawImageData::Integer(out)
+      },
+      RawImageData::Float(ref data) => {
+        let mut out = Vec::with_capacity((bottom-top)*(right-left)*self.cpp);
+        if complete {
+          for row in top..bottom {
+            out.extend_from_slice(&data[rowrange(row)]);
+          }
+        }
+        RawImageData::Float(out)
+      },
+    }
+  }
+
+  /// Lens corrections the camera recorded for the image, from the DNG opcodes or the
+  /// makernotes, normalized so they don't need any knowledge of the file format to be used
+  pub fn lens_corrections(&self) -> Option<LensCorrections> {
+    LensCorrections::from_image(self)
+  }
+
+  /// Serialize the metadata of the image (camera, exposure, lens, GPS, rating, crop and
+  /// orientation) as an XMP packet, as used in sidecar files. The crop is written as the
+  /// Camera Raw crop fields in fractions of the full image before orientation is applied.
+  pub fn to_xmp(&self) -> String {
+    xmp::generate(self)
+  }
+
+  /// Dump the metadata of the image (camera, exposure, levels, white balance, color matrices,
+  /// crops, CFA and the EXIF fields) as pretty printed JSON, for debugging and for use from
+  /// other languages. Needs the `serde` feature.
+  #[cfg(feature = "serde")]
+  pub fn metadata_json(&self) -> String {
+    json::metadata(self)
+  }
+
+  /// Develops the image into 16 bit sRGB for the `image` crate, with a deliberately simple
+  /// pipeline: the black and white levels and the white balance (or `neutralwb()` when the
+  /// file has none) are applied, the CFA is demosaiced by averaging each color over the 3x3
+  /// neighborhood of each pixel, the color matrix converts to sRGB and the gamma curve and
+  /// the orientation are applied. The crop of `crops` is applied, not the default crop.
+  /// Good enough for previews and tests, not a replacement for a raw developer. Needs the
+  /// `image` feature.
+  ///
+  /// # Example
+  /// ```rust,ignore
+  /// let image = rawloader::decode_file(path)?.to_dynamic_image()?;
+  /// image.resize(1024, 1024, image::imageops::FilterType::Triangle).save("preview.png")?;
+  /// ```
+  #[cfg(feature = "image")]
+  pub fn to_dynamic_image(&self) -> Result<::image::DynamicImage, RawLoaderError> {
+    develop::rgb(self)
+  }
+
+  /// Gives the undemosaiced data, with the crop of `crops` applied, as a 16 bit gray image
+  /// for the `image` crate, for tools that do their own processing. The colors of the pixels
+  /// are those of `cropped_cfa()`. Floating point data is rounded to integers. Needs the
+  /// `image` feature.
+  #[cfg(feature = "image")]
+  pub fn to_dynamic_image_cfa(&self) -> Result<::image::DynamicImage, RawLoaderError> {
+    develop::cfa(self)
+  }
+
+  /// Writes the image developed as by `to_dynamic_image()` as a 16 bit RGB TIFF, with its
+  /// orientation in the Orientation tag instead of applied to the data. Doesn't need the
+  /// `image` feature.
+  ///
+  /// # Example
+  /// ```rust,no_run
+  /// let image = rawloader::decode_file("path/to/your/file.NEF").unwrap();
+  /// let mut out = std::io::BufWriter::new(std::fs::File::create("file.tif").unwrap());
+  /// image.write_tiff(&mut out).unwrap();
+  /// ```
+  pub fn write_tiff(&self, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+    writers::tiff::rgb(self, out)
+  }
+
+  /// Writes the undemosaiced data, with the crop of `crops` applied, as a 16 bit grayscale
+  /// TIFF with the raw values, for looking at the sensor data as is. The colors of the
+  /// pixels are those of `cropped_cfa()`. Floating point data is rounded to integers.
+  ///
+  /// ```
+  /// use rawloader::{RawImage, RawImageData};
+  /// let image = RawImage::from_data("Make", "Model", 4, 2, 1, RawImageData::Integer(vec![1, 2, 3, 4, 5, 6, 7, 8]));
+  /// let mut tiff = Vec::new();
+  /// image.write_tiff_cfa(&mut tiff).unwrap();
+  /// assert_eq!(&tiff[..4], b"II*\0");
+  /// ```
+  pub fn write_tiff_cfa(&self, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+    writers::tiff::cfa(self, out)
+  }
+
+  /// Writes the undemosaiced data inside `crops` as FITS for astrophotography tools, with the
+  /// raw values as 16 bit integers (32 bit floats for floating point data) from the top row
+  /// down, the Bayer pattern in BAYERPAT and the exposure time, ISO, sensor temperature and
+  /// capture time from the EXIF data. RGB images are written as a cube of three planes.
+  ///
+  /// ```
+  /// use rawloader::{RawImage, RawImageData, CFA};
+  /// let mut image = RawImage::from_data("Make", "Model", 4, 2, 1, RawImageData::Integer(vec![1, 2, 3, 4, 5, 6, 7, 8]));
+  /// image.cfa = CFA::new("RGGB");
+  /// let mut fits = Vec::new();
+  /// image.write_fits(&mut fits).unwrap();
+  /// assert_eq!(fits.len(), 2 * 2880);
+  /// assert!(fits.starts_with(b"SIMPLE  =                    T"));
+  /// ```
+  pub fn write_fits(&self, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+    writers::fits::cfa(self, out)
+  }
+
+  /// Same as `write_fits()` with each position of a 2x2 CFA split into a plane of half the
+  /// width and height, written as a cube with the color of each plane in CHANNEL1 to
+  /// CHANNEL4, for stacking each color separately. Other CFAs can't be split this way.
+  pub fn write_fits_channels(&self, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+    writers::fits::channels(self, out)
+  }
+
+  /// Writes the image developed as by `to_dynamic_image()` but kept linear, as 32 bit float
+  /// OpenEXR in linear sRGB with white at 1.0 and the highlights the white balance pushes
+  /// over it kept, and with the orientation applied. Needs the `exr` feature.
+  ///
+  /// # Example
+  /// ```rust,ignore
+  /// let image = rawloader::decode_file(path)?;
+  /// image.write_exr(&mut std::io::BufWriter::new(std::fs::File::create("file.exr")?))?;
+  /// ```
+  #[cfg(feature = "exr")]
+  pub fn write_exr<W: Write + Seek>(&self, out: &mut W) -> Result<(), RawLoaderError> {
+    writers::exr::rgb(self, out)
+  }
+
+  /// Writes the undemosaiced data inside `crops` as 32 bit float OpenEXR with the values as
+  /// they are, in a Y channel for CFA and monochrome images and R, G and B channels for RGB
+  /// ones, for float raws like HDR merges. Needs the `exr` feature.
+  #[cfg(feature = "exr")]
+  pub fn write_exr_raw<W: Write + Seek>(&self, out: &mut W) -> Result<(), RawLoaderError> {
+    writers::exr::raw(self, out)
+  }
+
+  /// Views the data inside `crops` as an `ndarray` of shape (rows, columns, `cpp`) without
+  /// copying it, keeping the integer or floating point type it was decoded as. Needs the
+  /// `ndarray` feature.
+  #[cfg(feature = "ndarray")]
+  pub fn array_view(&self) -> Result<arrays::RawArrayView<'_>, RawLoaderError> {
+    arrays::array(self)
+  }
+
+  /// Views the data inside `crops` of a single component integer image as an `ndarray` of
+  /// rows and columns without copying it. The colors of the pixels are those of
+  /// `cropped_cfa()`. Floating point data needs `array_view()` instead. Needs the `ndarray`
+  /// feature.
+  #[cfg(feature = "ndarray")]
+  pub fn cfa_array_view(&self) -> Result<::ndarray::ArrayView2<'_, u16>, RawLoaderError> {
+    arrays::cfa(self)
+  }
+
+  /// Checks if the image is monochrome, a single channel of gray data that should not be
+  /// demosaiced. Decoders that know they have a monochrome sensor set a `CFA::new_monochrome()`
+  /// but images with a single component and no valid CFA are also treated as monochrome.
   pub fn is_monochrome(&self) -> bool {
-    self.cpp == 1 && !self.cfa.is_valid()
+    self.cpp == 1 && (self.cfa.is_monochrome() || !self.cfa.is_valid())
+  }
+}
+
+// Data of an image of width by height moved to where it goes with the orientation applied
+pub(crate) fn orient_data<T: Copy + Default + Send + Sync>(data: &[T], orientation: Orientation, width: usize, height: usize, owidth: usize, cpp: usize) -> Result<Vec<T>, RawLoaderError> {
+  let mut out = alloc_vec(data.len(), T::default())?;
+  out.par_chunks_exact_mut(owidth*cpp).enumerate().for_each(|(row, line)| {
+    for (col, pix) in line.chunks_exact_mut(cpp).enumerate() {
+      let (srow, scol) = orientation.unoriented(row, col, width, height);
+      let idx = (srow*width + scol)*cpp;
+      pix.copy_from_slice(&data[idx..idx+cpp]);
+    }
+  });
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::decoders::writers::dng;
+
+  const XTRANS: &str = "GGRGGBGGBGGRBRGRBGGGBGGRGGRGGBRBGBRG";
+
+  // The value of the pixels of each color, to check from the data which color a pixel is
+  fn code(color: usize) -> u16 {
+    1000 * (color as u16 + 1)
+  }
+
+  fn color_coded(cfa: &CFA, width: usize, height: usize) -> Vec<u16> {
+    (0..width*height).map(|i| code(cfa.color_at(i / width, i % width))).collect()
+  }
+
+  fn mosaic(pattern: &str, width: usize, height: usize) -> RawImage {
+    let cfa = CFA::new(pattern);
+    let data = RawImageData::Integer(color_coded(&cfa, width, height));
+    let mut image = RawImage::from_data("Make", "Model", width, height, 1, data);
+    image.cfa = cfa;
+    image
+  }
+
+  // Checks that each pixel is the color the CFA says it is, pixels left as 0 aside, and that
+  // the pattern of a crop is the one of the full image from where the crop starts
+  fn assert_cfa_matches(image: &RawImage) {
+    let data = match image.data {
+      RawImageData::Integer(ref data) => data,
+      RawImageData::Float(_) => unreachable!(),
+    };
+    assert_eq!(data.len(), image.width*image.height);
+    for row in 0..image.height {
+      for col in 0..image.width {
+        let value = data[row*image.width + col];
+        assert!(value == 0 || value == code(image.cfa.color_at(row, col)), "pixel at {},{}", row, col);
+      }
+    }
+    for (x, y) in [(1, 0), (0, 1), (3, 5), (image.crops[3], image.crops[0])] {
+      let crop = image.cfa_for_crop(x, y);
+      for row in 0..12 {
+        for col in 0..12 {
+          assert_eq!(crop.color_at(row, col), image.cfa.color_at(row+y, col+x));
+        }
+      }
+    }
+    let cropped = image.cropped_cfa();
+    let (top, left) = (image.crops[0], image.crops[3]);
+    for row in 0..image.height - top - image.crops[2] {
+      for col in 0..image.width - left - image.crops[1] {
+        assert_eq!(cropped.color_at(row, col), image.cfa.color_at(row+top, col+left));
+      }
+    }
+  }
+
+  #[test]
+  fn cfa_of_camera_definitions() {
+    let loader = RawLoader::new().with_camera_defs(r#"
+      make = "Make"
+      model = "Model"
+      clean_make = "Make"
+      clean_model = "Model"
+      whitepoint = 16383
+      color_pattern = "GBRG"
+      crops = [1,2,3,5]
+    "#).unwrap();
+    let camera = loader.check_supported_with_everything("Make", "Model", "").unwrap();
+    let data = color_coded(&camera.cfa, 15, 11);
+    let image = RawImage::new(camera, 15, 11, [f32::NAN; 4], data, false);
+    assert_eq!(image.cfa.to_string(), "GBRG");
+    assert_eq!(image.cropped_cfa().to_string(), "GRBG");
+    assert_cfa_matches(&image);
+  }
+
+  #[test]
+  fn cfa_of_dng_roundtrips() {
+    for pattern in ["RGGB", "GBRG", XTRANS] {
+      for compression in [dng::Compression::Uncompressed, dng::Compression::LosslessJpeg] {
+        let mut image = mosaic(pattern, 20, 14);
+        image.whitelevels = [65535; 4];
+        image.crops = [1, 2, 3, 1];
+        let options = dng::WriteOptions { compression, ..Default::default() };
+        let mut file = Vec::new();
+        dng::write_with_options(&image, &options, &mut file).unwrap();
+
+        let decoded = crate::decode_slice(&file).unwrap();
+        assert_eq!(decoded.cfa.to_string(), pattern);
+        assert_eq!(decoded.crops, image.crops);
+        assert_cfa_matches(&decoded);
+      }
+    }
+  }
+
+  #[test]
+  fn rgb_has_no_cfa() {
+    let data = RawImageData::Integer(vec![1; 6*4*3]);
+    let mut image = RawImage::from_data("Make", "Model", 6, 4, 3, data);
+    image.whitelevels = [65535; 4];
+    assert!(!image.cfa.is_valid());
+    assert!(!image.is_monochrome());
+
+    let mut file = Vec::new();
+    dng::write(&image, &mut file).unwrap();
+    let mut decoded = crate::decode_slice(&file).unwrap();
+    assert_eq!(decoded.cpp, 3);
+    assert!(!decoded.cfa.is_valid());
+
+    decoded.orientation = Orientation::Rotate90;
+    decoded.apply_orientation().unwrap();
+    assert!(!decoded.cfa.is_valid());
+  }
+
+  #[test]
+  fn cfa_after_apply_orientation() {
+    let orientations = [Orientation::HorizontalFlip, Orientation::Rotate180, Orientation::VerticalFlip,
+      Orientation::Transpose, Orientation::Rotate90, Orientation::Transverse, Orientation::Rotate270];
+    for pattern in ["RGGB", "GBRG", XTRANS] {
+      for &orientation in &orientations {
+        let mut image = mosaic(pattern, 13, 8);
+        image.crops = [1, 2, 3, 4];
+        image.orientation = orientation;
+        image.apply_orientation().unwrap();
+        assert_eq!(image.orientation, Orientation::Normal);
+        assert_cfa_matches(&image);
+      }
+    }
+  }
+
+  #[test]
+  fn cfa_after_rotate_upright() {
+    for pattern in ["RGGB", "GBRG"] {
+      let mut image = mosaic(pattern, 40, 60);
+      image.geometry = SensorGeometry::Rotated45 { fuji_width: 20 };
+      let upright = image.rotate_upright().unwrap();
+      assert_eq!(upright.geometry, SensorGeometry::Rectangular);
+      assert_eq!(upright.cfa.to_string(), pattern);
+      assert_cfa_matches(&upright);
+      // Most of the output is inside the rotated image and gets a value
+      let data = match upright.data {
+        RawImageData::Integer(ref data) => data,
+        RawImageData::Float(_) => unreachable!(),
+      };
+      assert!(data.iter().filter(|&&v| v != 0).count() > data.len() / 2);
+    }
+  }
+
+  #[test]
+  fn wb_temperature_roundtrips() {
+    let mut image = mosaic("RGGB", 4, 4);
+    assert_eq!(image.temperature_to_wb(5000.0, 0.0), None);
+    assert_eq!(image.wb_to_temperature([2.0, 1.0, 1.5, f32::NAN]), None);
+
+    // A camera that sees linear sRGB
+    image.xyz_to_cam = [
+      [ 3.240479, -1.53715,  -0.498535 ],
+      [-0.969256,  1.875992,  0.041556 ],
+      [ 0.055648, -0.204043,  1.057311 ],
+      [ 0.0,       0.0,       0.0      ],
+    ];
+    // Under D65 the white point of sRGB needs no white balance
+    let (temp, tint) = image.wb_to_temperature([1.0, 1.0, 1.0, f32::NAN]).unwrap();
+    assert!((temp - 6504.0).abs() < 20.0, "{}", temp);
+    assert!((tint - 9.6).abs() < 1.0, "{}", tint);
+    // Warmer light needs more blue
+    let wb = image.temperature_to_wb(3000.0, 0.0).unwrap();
+    assert_eq!(wb[1], 1.0);
+    assert!(wb[2] > wb[0]);
+
+    for &(temp, tint) in &[(2800.0, 0.0), (5200.0, 8.0), (7500.0, -15.0)] {
+      let wb = image.temperature_to_wb(temp, tint).unwrap();
+      let (t, n) = image.wb_to_temperature(wb).unwrap();
+      assert!((t - temp).abs() < temp * 2e-3, "{} {} gave {}", temp, tint, t);
+      assert!((n - tint).abs() < 0.2, "{} {} gave {}", temp, tint, n);
+    }
+  }
+
+  #[test]
+  fn dual_illuminant_temperature_roundtrips() {
+    let mut image = mosaic("RGGB", 4, 4);
+    // Made up matrices for standard light A and D65 that differ enough to matter
+    let a = [[1.2, -0.3, -0.2], [-0.4, 1.3, 0.1], [0.0, -0.1, 0.6], [0.0, 0.0, 0.0]];
+    let d65 = [[0.8, -0.2, -0.1], [-0.5, 1.4, 0.1], [-0.1, 0.2, 0.7], [0.0, 0.0, 0.0]];
+    image.xyz_to_cam = d65;
+    image.color_matrices = ColorMatrices {
+      calibration_illuminant1: Some(17),
+      calibration_illuminant2: Some(21),
+      color_matrix1: Some(a),
+      color_matrix2: Some(d65),
+      ..Default::default()
+    };
+    assert_eq!(image.xyz_to_cam_at(2000.0), a);
+    assert_eq!(image.xyz_to_cam_at(6504.0), d65);
+
+    for &(temp, tint) in &[(2856.0, 0.0), (3500.0, 5.0), (4500.0, -10.0), (6504.0, 0.0)] {
+      let wb = image.temperature_to_wb(temp, tint).unwrap();
+      let (t, n) = image.wb_to_temperature(wb).unwrap();
+      assert!((t - temp).abs() < temp * 5e-3, "{} {} gave {}", temp, tint, t);
+      assert!((n - tint).abs() < 0.5, "{} {} gave {}", temp, tint, n);
+    }
   }
 }
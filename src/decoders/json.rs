@@ -0,0 +1,181 @@
+use serde_json::{json, Value};
+
+use crate::decoders::*;
+use crate::decoders::exif::Exif;
+use crate::decoders::makernotes::Makernote;
+
+fn rect(r: &Rect) -> Value {
+  json!({ "x": r.x, "y": r.y, "width": r.width, "height": r.height })
+}
+
+// Enums are written with their Rust names as that's what FFI users will see documented
+fn debug<T: fmt::Debug>(v: &T) -> String {
+  format!("{:?}", v)
+}
+
+fn exif(exif: &Exif) -> Value {
+  json!({
+    "iso": exif.iso,
+    "exposure_time": exif.exposure_time,
+    "aperture": exif.aperture,
+    "exposure_bias": exif.exposure_bias,
+    "focal_length": exif.focal_length,
+    "metering_mode": exif.metering_mode.as_ref().map(debug),
+    "exposure_program": exif.exposure_program.as_ref().map(debug),
+    "flash": exif.flash.as_ref().map(|f| json!({
+      "fired": f.fired,
+      "mode": debug(&f.mode),
+      "return_detected": f.return_detected,
+      "red_eye_reduction": f.red_eye_reduction,
+      "present": f.present,
+    })),
+    "capture_time": exif.capture_time.map(|t| t.to_string()),
+    "serial": exif.serial,
+    "artist": exif.artist,
+    "copyright": exif.copyright,
+    "user_comment": exif.user_comment,
+    "shutter_count": exif.shutter_count,
+    "temperature": exif.temperature,
+    "gps": exif.gps.as_ref().map(|gps| json!({
+      "latitude": gps.latitude,
+      "longitude": gps.longitude,
+      "altitude": gps.altitude,
+      "timestamp": gps.timestamp,
+      "direction": gps.direction,
+      "direction_true_north": gps.direction_true_north,
+    })),
+    "lens": exif.lens.as_ref().map(|lens| json!({
+      "make": lens.make,
+      "model": lens.model,
+      "id": lens.id,
+      "min_focal_length": lens.min_focal_length,
+      "max_focal_length": lens.max_focal_length,
+      "max_aperture_at_min_focal": lens.max_aperture_at_min_focal,
+      "max_aperture_at_max_focal": lens.max_aperture_at_max_focal,
+    })),
+    "rating": exif.rating,
+    "label": exif.label,
+    "wb_presets": exif.wb_presets.iter().map(|p| json!({
+      "illuminant": debug(&p.illuminant),
+      "coeffs": p.coeffs,
+      "temperature": p.temperature,
+    })).collect::<Vec<Value>>(),
+    "af_points": exif.af_points.iter().map(|p| json!({
+      "x": p.x,
+      "y": p.y,
+      "width": p.width,
+      "height": p.height,
+      "selected": p.selected,
+      "in_focus": p.in_focus,
+    })).collect::<Vec<Value>>(),
+    "drive_mode": exif.drive_mode.as_ref().map(debug),
+    "bracketing": exif.bracketing.map(|b| json!({
+      "step": b.step,
+      "frames": b.frames,
+      "position": b.position,
+      "exposure_offset": b.exposure_offset,
+    })),
+    "level": exif.level.map(|l| json!({ "roll": l.roll, "pitch": l.pitch })),
+    "makernote": exif.makernote.as_ref().map(|mn| match mn {
+      Makernote::Canon(_) => "Canon",
+      Makernote::Nikon(_) => "Nikon",
+      Makernote::Sony(_) => "Sony",
+      Makernote::Olympus(_) => "Olympus",
+      Makernote::Panasonic(_) => "Panasonic",
+      Makernote::Fujifilm(_) => "Fujifilm",
+      Makernote::Pentax(_) => "Pentax",
+    }),
+  })
+}
+
+// Everything but the image data and the binary blobs (raw EXIF, ICC profile, mask data), with
+// NaN values written as null
+pub(crate) fn metadata(image: &RawImage) -> String {
+  let areas = image.areas();
+  let cm = &image.color_matrices;
+  let value = json!({
+    "camera": {
+      "make": image.make,
+      "model": image.model,
+      "clean_make": image.clean_make,
+      "clean_model": image.clean_model,
+    },
+    "image": {
+      "width": image.width,
+      "height": image.height,
+      "cpp": image.cpp,
+      "orientation": debug(&image.orientation),
+      "geometry": debug(&image.geometry),
+      "monochrome": image.is_monochrome(),
+      "image_count": image.image_count,
+    },
+    "levels": {
+      "black": image.blacklevels,
+      "white": image.whitelevels,
+      "camera_black": image.camera_blacklevels,
+      "camera_white": image.camera_whitelevels,
+      "measured_black": image.measured_blacklevels,
+    },
+    "white_balance": {
+      "coeffs": image.wb_coeffs,
+      "neutral": image.neutralwb(),
+      "as_shot_temperature": image.as_shot_temperature(),
+    },
+    "color": {
+      "xyz_to_cam": image.xyz_to_cam,
+      "cam_to_xyz": image.cam_to_xyz(),
+      "calibration_illuminant1": cm.calibration_illuminant1,
+      "calibration_illuminant2": cm.calibration_illuminant2,
+      "color_matrix1": cm.color_matrix1,
+      "color_matrix2": cm.color_matrix2,
+      "forward_matrix1": cm.forward_matrix1,
+      "forward_matrix2": cm.forward_matrix2,
+      "camera_calibration1": cm.camera_calibration1,
+      "camera_calibration2": cm.camera_calibration2,
+      "analog_balance": cm.analog_balance,
+    },
+    "crops": {
+      "crops": image.crops,
+      "sensor_width": areas.sensor_width,
+      "sensor_height": areas.sensor_height,
+      "active_area": rect(&areas.active_area),
+      "default_crop": rect(&areas.default_crop),
+      "display_crop": rect(&areas.display_crop),
+      "masked_areas": image.blackareas,
+    },
+    "cfa": {
+      "name": image.cfa.name,
+      "width": image.cfa.width,
+      "height": image.cfa.height,
+      "pattern": image.cfa.to_string(),
+    },
+    "rendering_hints": {
+      "baseline_exposure": image.rendering_hints.baseline_exposure,
+      "baseline_noise": image.rendering_hints.baseline_noise,
+      "baseline_sharpness": image.rendering_hints.baseline_sharpness,
+      "linear_response_limit": image.rendering_hints.linear_response_limit,
+    },
+    "noise_profile": image.noise_profile.iter().map(|n| json!({ "scale": n.scale, "offset": n.offset })).collect::<Vec<Value>>(),
+    "bad_pixels": image.bad_pixels.len(),
+    "partially_decoded": image.partially_decoded,
+    "failure_offset": image.failure_offset,
+    "warnings": image.warnings.iter().map(|w| w.to_string()).collect::<Vec<String>>(),
+    "masks": image.masks.iter().map(|m| json!({
+      "name": m.name,
+      "instance_id": m.instance_id,
+      "width": m.width,
+      "height": m.height,
+    })).collect::<Vec<Value>>(),
+    "calibration": image.calibration.as_ref().map(|c| json!({
+      "scale": c.scale,
+      "black": c.black,
+      "defects": c.defects.len(),
+      "curves": c.curves.len(),
+      "flat_fields": c.flat_fields.len(),
+      "split_col": c.split_col,
+      "split_row": c.split_row,
+    })),
+    "exif": exif(&image.exif),
+  });
+  serde_json::to_string_pretty(&value).unwrap_or_default()
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use lazy_static::lazy_static;
+
+use crate::decoders::tiff::*;
+use crate::decoders::exif::{get_f32, get_string};
+use crate::decoders::makernotes::{Makernote, CanonMakernote, NikonMakernote, OlympusMakernote};
+use crate::decoders::makernotes::{PanasonicMakernote, FujiMakernote};
+
+lazy_static! {
+  static ref LENSES: RwLock<HashMap<(String,u32),String>> = RwLock::new(HashMap::new());
+}
+
+/// Add a lens to the table used to turn makernote lens IDs into names, or replace the name of
+/// an existing one. `make` is the short brand name ("Canon", "Nikon", "Olympus", "Pentax", "Sony") and
+/// the IDs are the ones found in `LensInfo::id`.
+///
+/// No lenses come with the crate, the table starts empty, so applications that want names for
+/// the lenses that files only record by ID have to register them, for example from ExifTool's
+/// lens tables. Nikon's ID is the LensIDNumber byte of LensData, which isn't unique across
+/// lenses on its own.
+pub fn register_lens(make: &str, id: u32, name: &str) {
+  let mut lenses = LENSES.write().unwrap_or_else(|e| e.into_inner());
+  lenses.insert((make.to_string(), id), name.to_string());
+}
+
+/// Look up the name of a lens by brand and makernote lens ID
+pub fn lens_name(make: &str, id: u32) -> Option<String> {
+  let lenses = LENSES.read().unwrap_or_else(|e| e.into_inner());
+  lenses.get(&(make.to_string(), id)).cloned()
+}
+
+/// Information about the lens used to take the image
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LensInfo {
+  /// lens manufacturer when recorded in the file
+  pub make: Option<String>,
+  /// lens model, either as recorded in the file or looked up from `id` in the lenses added with
+  /// `register_lens()`
+  pub model: Option<String>,
+  /// brand specific lens ID found in the makernotes
+  pub id: Option<u32>,
+  /// shortest focal length in mm
+  pub min_focal_length: Option<f32>,
+  /// longest focal length in mm
+  pub max_focal_length: Option<f32>,
+  /// maximum aperture (as an f-number) at the shortest focal length
+  pub max_aperture_at_min_focal: Option<f32>,
+  /// maximum aperture (as an f-number) at the longest focal length
+  pub max_aperture_at_max_focal: Option<f32>,
+}
+
+impl LensInfo {
+  pub(crate) fn from_tiff(tiff: &TiffIFD, makernote: Option<&Makernote>) -> Option<LensInfo> {
+    let make = get_string(tiff, Tag::Make).unwrap_or_default();
+    let brand = match make.as_ref() {
+      "Canon" => "Canon",
+      "NIKON CORPORATION" | "NIKON" => "Nikon",
+      "PENTAX Corporation" | "RICOH IMAGING COMPANY, LTD." | "PENTAX" => "Pentax",
+      "SONY" => "Sony",
+      "Panasonic" => "Panasonic",
+      "FUJIFILM" => "Fujifilm",
+      "OLYMPUS IMAGING CORP." | "OLYMPUS CORPORATION" | "OLYMPUS OPTICAL CO.,LTD" | "OM Digital Solutions" => "Olympus",
+      _ => "",
+    };
+
+    let mut lens = LensInfo {
+      make: get_string(tiff, Tag::LensMake),
+      model: get_string(tiff, Tag::LensModel),
+      ..Default::default()
+    };
+
+    // Both the EXIF and DNG versions of LensInfo are min/max focal and the apertures at those
+    if let Some(info) = [Tag::LensInfo, Tag::DNGLensInfo].iter()
+      .filter_map(|&tag| tiff.find_entry(tag)).find(|e| e.count() >= 4 && (e.typ() == 5 || e.typ() == 10)) {
+      lens.set_ranges(&[info.get_f32(0), info.get_f32(1), info.get_f32(2), info.get_f32(3)]);
+    }
+
+    match brand {
+      "Canon" => {
+        if let Some(Makernote::Canon(canon)) = makernote {
+          lens.merge_canon(canon);
+        }
+      },
+      "Nikon" => {
+        if let Some(info) = tiff.find_entry(Tag::NefLens).filter(|e| e.typ() == 5 && e.count() >= 4) {
+          if lens.min_focal_length.is_none() {
+            lens.set_ranges(&[info.get_f32(0), info.get_f32(1), info.get_f32(2), info.get_f32(3)]);
+          }
+        }
+        if let Some(Makernote::Nikon(nikon)) = makernote {
+          lens.merge_nikon(nikon);
+        }
+      },
+      "Pentax" => {
+        if let Some(id) = tiff.find_entry(Tag::PefLensType).filter(|e| e.typ() == 1 && e.count() >= 2) {
+          lens.id = Some(((id.get_u16(0) as u32) << 8) | id.get_u16(1) as u32);
+        }
+        if let Some(Makernote::Pentax(pentax)) = makernote {
+          if lens.id.is_none() {
+            lens.id = pentax.lens_id;
+          }
+        }
+      },
+      "Sony" => {
+        if let Some(id) = get_f32(tiff, Tag::SonyLensType) {
+          lens.id = Some(id as u32);
+        }
+      },
+      "Olympus" => {
+        if let Some(Makernote::Olympus(olympus)) = makernote {
+          lens.merge_olympus(olympus);
+        }
+      },
+      "Panasonic" => {
+        if let Some(Makernote::Panasonic(panasonic)) = makernote {
+          lens.merge_panasonic(panasonic);
+        }
+      },
+      "Fujifilm" => {
+        if let Some(Makernote::Fujifilm(fuji)) = makernote {
+          lens.merge_fuji(fuji);
+        }
+      },
+      _ => {},
+    }
+
+    if lens.model.is_none() {
+      if let Some(id) = lens.id {
+        lens.model = lens_name(brand, id);
+      }
+    }
+
+    if lens == LensInfo::default() { None } else { Some(lens) }
+  }
+
+  pub(crate) fn from_canon(canon: &CanonMakernote) -> Option<LensInfo> {
+    let mut lens = LensInfo::default();
+    lens.merge_canon(canon);
+    if lens.model.is_none() {
+      if let Some(id) = lens.id {
+        lens.model = lens_name("Canon", id);
+      }
+    }
+    if lens == LensInfo::default() { None } else { Some(lens) }
+  }
+
+  fn merge_canon(&mut self, canon: &CanonMakernote) {
+    self.id = canon.lens_id.map(|id| id as u32);
+    if self.min_focal_length.is_none() {
+      self.min_focal_length = canon.min_focal_length;
+      self.max_focal_length = canon.max_focal_length;
+    }
+    if self.model.is_none() {
+      self.model = canon.lens_model.clone();
+    }
+  }
+
+  fn merge_nikon(&mut self, nikon: &NikonMakernote) {
+    self.id = nikon.lens_id_number.map(|id| id as u32);
+    if self.min_focal_length.is_none() {
+      self.min_focal_length = nikon.min_focal_length;
+      self.max_focal_length = nikon.max_focal_length;
+      self.max_aperture_at_min_focal = nikon.max_aperture_at_min_focal;
+      self.max_aperture_at_max_focal = nikon.max_aperture_at_max_focal;
+    }
+  }
+
+  fn merge_olympus(&mut self, olympus: &OlympusMakernote) {
+    self.id = olympus.lens_id;
+    if self.model.is_none() {
+      self.model = olympus.lens_model.clone();
+    }
+    if self.min_focal_length.is_none() {
+      self.min_focal_length = olympus.min_focal_length;
+      self.max_focal_length = olympus.max_focal_length;
+      self.max_aperture_at_min_focal = olympus.max_aperture_at_min_focal;
+      self.max_aperture_at_max_focal = olympus.max_aperture_at_max_focal;
+    }
+  }
+
+  fn merge_panasonic(&mut self, panasonic: &PanasonicMakernote) {
+    if self.model.is_none() {
+      self.model = panasonic.lens_model.clone();
+    }
+  }
+
+  fn merge_fuji(&mut self, fuji: &FujiMakernote) {
+    if self.min_focal_length.is_none() {
+      self.min_focal_length = fuji.min_focal_length;
+      self.max_focal_length = fuji.max_focal_length;
+      self.max_aperture_at_min_focal = fuji.max_aperture_at_min_focal;
+      self.max_aperture_at_max_focal = fuji.max_aperture_at_max_focal;
+    }
+  }
+
+  fn set_ranges(&mut self, vals: &[f32;4]) {
+    let valid = |v: f32| if v.is_finite() && v > 0.0 { Some(v) } else { None };
+    self.min_focal_length = valid(vals[0]);
+    self.max_focal_length = valid(vals[1]);
+    self.max_aperture_at_min_focal = valid(vals[2]);
+    self.max_aperture_at_max_focal = valid(vals[3]);
+  }
+}
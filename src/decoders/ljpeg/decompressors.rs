@@ -1,12 +1,13 @@
 use crate::decoders::basics::*;
+use crate::decoders::{RawLoaderError, check_abort};
 use crate::decoders::ljpeg::LjpegDecompressor;
 use crate::decoders::ljpeg::huffman::*;
 
-pub fn decode_ljpeg_2components(ljpeg: &LjpegDecompressor, out: &mut [u16], x: usize, stripwidth:usize, width: usize, height: usize) -> Result<(),String> {
+pub fn decode_ljpeg_2components(ljpeg: &LjpegDecompressor, out: &mut [u16], x: usize, stripwidth:usize, width: usize, height: usize) -> Result<(), RawLoaderError> {
   if ljpeg.sof.width*2 < width || ljpeg.sof.height < height {
-    return Err(format!("ljpeg: trying to decode {}x{} into {}x{}",
+    return Err(RawLoaderError::Corrupt(format!("ljpeg: trying to decode {}x{} into {}x{}",
                        ljpeg.sof.width*2, ljpeg.sof.height,
-                       width, height).to_string())
+                       width, height)))
   }
   let ref htable1 = ljpeg.dhts[ljpeg.sof.components[0].dc_tbl_num];
   let ref htable2 = ljpeg.dhts[ljpeg.sof.components[1].dc_tbl_num];
@@ -14,10 +15,14 @@ pub fn decode_ljpeg_2components(ljpeg: &LjpegDecompressor, out: &mut [u16], x: u
 
   let base_prediction = 1 << (ljpeg.sof.precision - ljpeg.point_transform -1);
   out[x]   = (base_prediction + htable1.huff_decode(&mut pump)?) as u16;
-  out[x+1] = (base_prediction + htable2.huff_decode(&mut pump)?) as u16;
-  let skip_x = ljpeg.sof.width - width/2;
+  let second = (base_prediction + htable2.huff_decode(&mut pump)?) as u16;
+  if width > 1 {
+    out[x+1] = second;
+  }
+  let skip_x = ljpeg.sof.width - width.div_ceil(2);
 
   for row in 0..height {
+    check_abort()?;
     let startcol = if row == 0 {x+2} else {x};
     for col in (startcol..(width+x)).step_by(2) {
       let (p1,p2) = if col == x {
@@ -31,7 +36,10 @@ pub fn decode_ljpeg_2components(ljpeg: &LjpegDecompressor, out: &mut [u16], x: u
       let diff1 = htable1.huff_decode(&mut pump)?;
       let diff2 = htable2.huff_decode(&mut pump)?;
       out[row*stripwidth+col] = ((p1 as i32) + diff1) as u16;
-      out[row*stripwidth+col+1] = ((p2 as i32) + diff2) as u16;
+      // With an odd width the second component of the last pixel is padding
+      if col+1 < width+x {
+        out[row*stripwidth+col+1] = ((p2 as i32) + diff2) as u16;
+      }
     }
     // Skip extra encoded differences if the ljpeg frame is wider than the output
     for _ in 0..skip_x {
@@ -43,11 +51,11 @@ pub fn decode_ljpeg_2components(ljpeg: &LjpegDecompressor, out: &mut [u16], x: u
   Ok(())
 }
 
-pub fn decode_ljpeg_3components(ljpeg: &LjpegDecompressor, out: &mut [u16], x: usize, stripwidth:usize, width: usize, height: usize) -> Result<(),String> {
+pub fn decode_ljpeg_3components(ljpeg: &LjpegDecompressor, out: &mut [u16], x: usize, stripwidth:usize, width: usize, height: usize) -> Result<(), RawLoaderError> {
   if ljpeg.sof.width*3 < width || ljpeg.sof.height < height {
-    return Err(format!("ljpeg: trying to decode {}x{} into {}x{}",
+    return Err(RawLoaderError::Corrupt(format!("ljpeg: trying to decode {}x{} into {}x{}",
                        ljpeg.sof.width*3, ljpeg.sof.height,
-                       width, height).to_string())
+                       width, height)))
   }
 
   let ref htable1 = ljpeg.dhts[ljpeg.sof.components[0].dc_tbl_num];
@@ -62,6 +70,7 @@ pub fn decode_ljpeg_3components(ljpeg: &LjpegDecompressor, out: &mut [u16], x: u
   let skip_x = ljpeg.sof.width - width/3;
 
   for row in 0..height {
+    check_abort()?;
     let startcol = if row == 0 {x+3} else {x};
     for col in (startcol..(width+x)).step_by(3) {
       let pos = if col == x {
@@ -91,11 +100,11 @@ pub fn decode_ljpeg_3components(ljpeg: &LjpegDecompressor, out: &mut [u16], x: u
   Ok(())
 }
 
-pub fn decode_ljpeg_4components(ljpeg: &LjpegDecompressor, out: &mut [u16], width: usize, height: usize) -> Result<(),String> {
+pub fn decode_ljpeg_4components(ljpeg: &LjpegDecompressor, out: &mut [u16], width: usize, height: usize) -> Result<(), RawLoaderError> {
   if ljpeg.sof.width*4 < width || ljpeg.sof.height < height {
-    return Err(format!("ljpeg: trying to decode {}x{} into {}x{}",
+    return Err(RawLoaderError::Corrupt(format!("ljpeg: trying to decode {}x{} into {}x{}",
                        ljpeg.sof.width*4, ljpeg.sof.height,
-                       width, height).to_string())
+                       width, height)))
   }
   let ref htable1 = ljpeg.dhts[ljpeg.sof.components[0].dc_tbl_num];
   let ref htable2 = ljpeg.dhts[ljpeg.sof.components[1].dc_tbl_num];
@@ -111,6 +120,7 @@ pub fn decode_ljpeg_4components(ljpeg: &LjpegDecompressor, out: &mut [u16], widt
   let skip_x = ljpeg.sof.width - width/4;
 
   for row in 0..height {
+    check_abort()?;
     let startcol = if row == 0 {4} else {0};
     for col in (startcol..width).step_by(4) {
       let pos = if col == 0 {
@@ -164,11 +174,11 @@ fn set_yuv_420(out: &mut [u16], row: usize, col: usize, width: usize, y1: i32, y
   out[pix4+2] = cr as u16;
 }
 
-pub fn decode_ljpeg_420(ljpeg: &LjpegDecompressor, out: &mut [u16], width: usize, height: usize) -> Result<(),String> {
+pub fn decode_ljpeg_420(ljpeg: &LjpegDecompressor, out: &mut [u16], width: usize, height: usize) -> Result<(), RawLoaderError> {
   if ljpeg.sof.width*3 != width || ljpeg.sof.height != height {
-    return Err(format!("ljpeg: trying to decode {}x{} into {}x{}",
+    return Err(RawLoaderError::Corrupt(format!("ljpeg: trying to decode {}x{} into {}x{}",
                        ljpeg.sof.width*3, ljpeg.sof.height,
-                       width, height).to_string())
+                       width, height)))
   }
 
   let ref htable1 = ljpeg.dhts[ljpeg.sof.components[0].dc_tbl_num];
@@ -222,11 +232,11 @@ fn set_yuv_422(out: &mut [u16], row: usize, col: usize, width: usize, y1: i32, y
   out[pix2+2] = cr as u16;
 }
 
-pub fn decode_ljpeg_422(ljpeg: &LjpegDecompressor, out: &mut [u16], width: usize, height: usize) -> Result<(),String> {
+pub fn decode_ljpeg_422(ljpeg: &LjpegDecompressor, out: &mut [u16], width: usize, height: usize) -> Result<(), RawLoaderError> {
   if ljpeg.sof.width*3 != width || ljpeg.sof.height != height {
-    return Err(format!("ljpeg: trying to decode {}x{} into {}x{}",
+    return Err(RawLoaderError::Corrupt(format!("ljpeg: trying to decode {}x{} into {}x{}",
                        ljpeg.sof.width*3, ljpeg.sof.height,
-                       width, height).to_string())
+                       width, height)))
   }
   let ref htable1 = ljpeg.dhts[ljpeg.sof.components[0].dc_tbl_num];
   let ref htable2 = ljpeg.dhts[ljpeg.sof.components[1].dc_tbl_num];
@@ -241,6 +251,7 @@ pub fn decode_ljpeg_422(ljpeg: &LjpegDecompressor, out: &mut [u16], width: usize
   set_yuv_422(out, 0, 0, width, y1, y2, cb, cr);
 
   for row in 0..height {
+    check_abort()?;
     let startcol = if row == 0 {6} else {0};
     for col in (startcol..width).step_by(6) {
       let pos = if col == 0 {
@@ -263,7 +274,7 @@ pub fn decode_ljpeg_422(ljpeg: &LjpegDecompressor, out: &mut [u16], width: usize
   Ok(())
 }
 
-pub fn decode_hasselblad(ljpeg: &LjpegDecompressor, out: &mut [u16], width: usize) -> Result<(),String> {
+pub fn decode_hasselblad(ljpeg: &LjpegDecompressor, out: &mut [u16], width: usize) -> Result<(), RawLoaderError> {
   // Pixels are packed two at a time, not like LJPEG:
   // [p1_length_as_huffman][p2_length_as_huffman][p0_diff_with_length][p1_diff_with_length]|NEXT PIXELS
   let mut pump = BitPumpMSB32::new(ljpeg.buffer);
@@ -285,7 +296,7 @@ pub fn decode_hasselblad(ljpeg: &LjpegDecompressor, out: &mut [u16], width: usiz
   Ok(())
 }
 
-pub fn decode_leaf_strip(src: &[u8], out: &mut [u16], width: usize, height: usize, htable1: &HuffTable, htable2: &HuffTable, bpred: i32) -> Result<(),String> {
+pub fn decode_leaf_strip(src: &[u8], out: &mut [u16], width: usize, height: usize, htable1: &HuffTable, htable2: &HuffTable, bpred: i32) -> Result<(), RawLoaderError> {
   let mut pump = BitPumpJPEG::new(src);
   out[0] = (bpred + htable1.huff_decode(&mut pump)?) as u16;
   out[1] = (bpred + htable2.huff_decode(&mut pump)?) as u16;
@@ -1,5 +1,6 @@
 use std::fmt;
 use crate::decoders::basics::*;
+use crate::decoders::RawLoaderError;
 
 const DECODE_CACHE_BITS: u32 = 13;
 
@@ -84,7 +85,7 @@ impl HuffTable {
     }
   }
 
-  pub fn new(bits: [u32;17], huffval: [u32;256], dng_bug: bool) -> Result<HuffTable,String> {
+  pub fn new(bits: [u32;17], huffval: [u32;256], dng_bug: bool) -> Result<HuffTable, RawLoaderError> {
     let mut tbl = HuffTable {
       bits: bits,
       huffval: huffval,
@@ -101,7 +102,7 @@ impl HuffTable {
     Ok(tbl)
   }
 
-  pub fn initialize(&mut self) -> Result<(), String> {
+  pub fn initialize(&mut self) -> Result<(), RawLoaderError> {
     // Find out the max code length and allocate a table with that size
     self.nbits = 16;
     for i in 0..16 {
@@ -148,7 +149,7 @@ impl HuffTable {
   }
 
   #[inline(always)]
-  pub fn huff_decode(&self, pump: &mut dyn BitPump) -> Result<i32,String> {
+  pub fn huff_decode(&self, pump: &mut dyn BitPump) -> Result<i32, RawLoaderError> {
     let code = pump.peek_bits(DECODE_CACHE_BITS) as usize;
     if let Some((bits,decode)) = self.decodecache[code] {
       pump.consume_bits(bits as u32);
@@ -162,7 +163,9 @@ impl HuffTable {
   #[inline(always)]
   pub fn huff_decode_slow(&self, pump: &mut dyn BitPump) -> (u8,i32) {
     let len = self.huff_len(pump);
-    (len.0+len.1, self.huff_diff(pump, len))
+    // The -32768 difference has no bits after its code unless the stream has the DNG bug
+    let extra = if len.1 == 16 && !self.dng_bug { 0 } else { len.1 };
+    (len.0+extra, self.huff_diff(pump, len))
   }
 
   #[inline(always)]
@@ -1,10 +1,85 @@
+//! Decoder for lossless JPEG (ITU T.81 process 14, also known as LJ92), the compression of
+//! CR2s, DNGs and several other formats, for use on streams from anywhere else, like MLV
+//! files or DNG tiles read by other tools.
+//!
+//! # Example
+//! ```rust,no_run
+//! use rawloader::ljpeg::LjpegDecompressor;
+//!
+//! let stream = std::fs::read("path/to/tile.ljpeg").unwrap();
+//! let ljpeg = LjpegDecompressor::new(&stream).unwrap();
+//! let pixels = ljpeg.decode_image().unwrap();
+//! println!("{}x{} samples, {} bits", ljpeg.width(), ljpeg.height(), ljpeg.precision());
+//! ```
+
+use std::error::Error;
+use std::fmt;
+use std::panic;
+
 use crate::decoders::basics::*;
+use crate::decoders::RawLoaderError;
 use crate::decoders::ljpeg::huffman::*;
 use crate::decoders::ljpeg::decompressors::*;
 
-pub mod huffman;
+pub(crate) mod huffman;
 mod decompressors;
 
+/// Error type for any reason for a lossless JPEG stream to fail to decode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LjpegError {
+  /// the stream is truncated or isn't a valid lossless JPEG
+  Corrupt(String),
+  /// the stream uses a variant (predictor, number of components, etc) that isn't implemented
+  Unsupported(String),
+  /// the image is bigger than the pixel limit
+  LimitExceeded(String),
+}
+
+impl fmt::Display for LjpegError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      LjpegError::Corrupt(msg) | LjpegError::Unsupported(msg) | LjpegError::LimitExceeded(msg) => write!(f, "{}", msg),
+    }
+  }
+}
+
+impl Error for LjpegError {}
+
+impl From<RawLoaderError> for LjpegError {
+  fn from(err: RawLoaderError) -> Self {
+    match err {
+      RawLoaderError::Corrupt(msg) => LjpegError::Corrupt(msg),
+      RawLoaderError::Unimplemented(msg) => LjpegError::Unsupported(msg),
+      RawLoaderError::LimitExceeded(msg) => LjpegError::LimitExceeded(msg),
+      err => LjpegError::Corrupt(err.to_string()),
+    }
+  }
+}
+
+impl From<LjpegError> for RawLoaderError {
+  fn from(err: LjpegError) -> Self {
+    match err {
+      LjpegError::Corrupt(msg) => RawLoaderError::Corrupt(msg),
+      LjpegError::Unsupported(msg) => RawLoaderError::Unimplemented(msg),
+      LjpegError::LimitExceeded(msg) => RawLoaderError::LimitExceeded(msg),
+    }
+  }
+}
+
+// Streams that are cut short make the decoding panic, which is returned as an error instead.
+// Cancellations and timeouts keep unwinding to the RawLoader running the decode.
+fn guard<T, F>(decode: F) -> Result<T, LjpegError>
+  where F: FnOnce() -> Result<T, RawLoaderError> {
+  panic::catch_unwind(panic::AssertUnwindSafe(decode))
+    .unwrap_or_else(|payload| {
+      if let Some(RawLoaderError::Cancelled) | Some(RawLoaderError::TimedOut(_)) = payload.downcast_ref::<RawLoaderError>() {
+        panic::resume_unwind(payload)
+      }
+      Err(crate::decoders::panic_error(payload))
+    })
+    .map_err(LjpegError::from)
+}
+
 enum Marker {
   Stuff        = 0x00,
   SOF3         = 0xc3, // lossless
@@ -55,7 +130,7 @@ impl SOFInfo {
     }
   }
 
-  fn parse_sof(&mut self, input: &mut ByteStream) -> Result<(), String> {
+  fn parse_sof(&mut self, input: &mut ByteStream) -> Result<(), RawLoaderError> {
     let header_length = input.get_u16() as usize;
     self.precision = input.get_u8() as usize;
     self.height = input.get_u16() as usize;
@@ -63,13 +138,13 @@ impl SOFInfo {
     self.cps = input.get_u8() as usize;
 
     if self.precision > 16 {
-      return Err("ljpeg: More than 16 bits per channel is not supported.".to_string())
+      return Err(RawLoaderError::Unimplemented("ljpeg: More than 16 bits per channel is not supported.".to_string()))
     }
     if self.cps > 4 || self.cps < 1 {
-      return Err("ljpeg: Only from 1 to 4 components are supported.".to_string())
+      return Err(RawLoaderError::Unimplemented("ljpeg: Only from 1 to 4 components are supported.".to_string()))
     }
     if header_length != 8 + self.cps*3 {
-      return Err("ljpeg: Header size mismatch.".to_string())
+      return Err(RawLoaderError::Corrupt("ljpeg: Header size mismatch.".to_string()))
     }
 
     for _ in 0..self.cps {
@@ -88,14 +163,14 @@ impl SOFInfo {
     Ok(())
   }
 
-  fn parse_sos(&mut self, input: &mut ByteStream) -> Result<(usize, usize), String> {
+  fn parse_sos(&mut self, input: &mut ByteStream) -> Result<(usize, usize), RawLoaderError> {
     if self.width == 0 {
-      return Err("ljpeg: Trying to parse SOS before SOF".to_string())
+      return Err(RawLoaderError::Corrupt("ljpeg: Trying to parse SOS before SOF".to_string()))
     }
     input.get_u16(); //skip header length
     let soscps = input.get_u8() as usize;
     if self.cps != soscps {
-      return Err("ljpeg: component number mismatch in SOS".to_string())
+      return Err(RawLoaderError::Corrupt("ljpeg: component number mismatch in SOS".to_string()))
     }
     for cs in 0..self.cps {
       // At least some MOS cameras have this broken
@@ -103,11 +178,11 @@ impl SOFInfo {
       let cs = if self.csfix {cs} else {readcs};
       let component = match self.components.iter_mut().find(|&&mut c| c.id == cs) {
         Some(val) => val,
-        None => return Err(format!("ljpeg: invalid component selector {}", cs).to_string())
+        None => return Err(RawLoaderError::Corrupt(format!("ljpeg: invalid component selector {}", cs)))
       };
       let td = (input.get_u8() as usize) >> 4;
       if td > 3 {
-        return Err("ljpeg: Invalid Huffman table selection".to_string())
+        return Err(RawLoaderError::Corrupt("ljpeg: Invalid Huffman table selection".to_string()))
       }
       component.dc_tbl_num = td;
     }
@@ -118,6 +193,7 @@ impl SOFInfo {
   }
 }
 
+/// A lossless JPEG stream with its headers parsed, ready to be decoded
 #[derive(Debug)]
 pub struct LjpegDecompressor<'a> {
   buffer: &'a [u8],
@@ -128,14 +204,22 @@ pub struct LjpegDecompressor<'a> {
 }
 
 impl<'a> LjpegDecompressor<'a> {
-  pub fn new(src: &'a [u8]) -> Result<LjpegDecompressor, String> {
+  /// Parses the headers of a stream, which has to start with the SOI marker
+  pub fn new(src: &'a [u8]) -> Result<LjpegDecompressor<'a>, LjpegError> {
     LjpegDecompressor::new_full(src, false, false)
   }
 
-  pub fn new_full(src: &'a [u8], dng_bug: bool, csfix: bool) -> Result<LjpegDecompressor, String> {
+  /// Same as `new()` with workarounds for broken encoders: `dng_bug` for streams that store
+  /// 16 extra bits after 16 bit differences, and `csfix` for the component selectors some
+  /// Leaf backs write wrong
+  pub fn new_full(src: &'a [u8], dng_bug: bool, csfix: bool) -> Result<LjpegDecompressor<'a>, LjpegError> {
+    guard(|| LjpegDecompressor::parse(src, dng_bug, csfix))
+  }
+
+  fn parse(src: &'a [u8], dng_bug: bool, csfix: bool) -> Result<LjpegDecompressor<'a>, RawLoaderError> {
     let mut input = ByteStream::new(src, BIG_ENDIAN);
     if LjpegDecompressor::get_next_marker(&mut input, false)? != m(Marker::SOI) {
-      return Err("ljpeg: Image did not start with SOI. Probably not LJPEG".to_string())
+      return Err(RawLoaderError::Corrupt("ljpeg: Image did not start with SOI. Probably not LJPEG".to_string()))
     }
 
     let mut sof = SOFInfo::empty(csfix);
@@ -150,7 +234,7 @@ impl<'a> LjpegDecompressor<'a> {
         // Start of the frame, giving us the basic info
         sof.parse_sof(&mut input)?;
         if sof.precision > 16 || sof.precision < 12 {
-          return Err(format!("ljpeg: sof.precision {}", sof.precision).to_string())
+          return Err(RawLoaderError::Corrupt(format!("ljpeg: sof.precision {}", sof.precision)))
         }
       } else if marker == m(Marker::DHT) {
         // Huffman table settings
@@ -162,9 +246,9 @@ impl<'a> LjpegDecompressor<'a> {
         break;
       } else if marker == m(Marker::EOI) {
         // Should never be reached as we stop at SOS
-        return Err("ljpeg: reached EOI before SOS".to_string())
+        return Err(RawLoaderError::Corrupt("ljpeg: reached EOI before SOS".to_string()))
       } else if marker == m(Marker::DQT) {
-        return Err("ljpeg: not a valid raw file, found DQT".to_string())
+        return Err(RawLoaderError::Corrupt("ljpeg: not a valid raw file, found DQT".to_string()))
       }
     }
 
@@ -187,14 +271,14 @@ impl<'a> LjpegDecompressor<'a> {
     })
   }
 
-  fn get_next_marker(input: &mut ByteStream, allowskip:bool) -> Result<u8,String> {
+  fn get_next_marker(input: &mut ByteStream, allowskip:bool) -> Result<u8, RawLoaderError> {
     if !allowskip {
       if input.get_u8() != 0xff {
-        return Err("ljpeg: (noskip) expected marker not found".to_string())
+        return Err(RawLoaderError::Corrupt("ljpeg: (noskip) expected marker not found".to_string()))
       }
       let mark = input.get_u8();
       if mark == m(Marker::Stuff) || mark == m(Marker::Fill) {
-        return Err("ljpeg: (noskip) expected marker but found stuff or fill".to_string())
+        return Err(RawLoaderError::Corrupt("ljpeg: (noskip) expected marker but found stuff or fill".to_string()))
       }
       return Ok(mark)
     }
@@ -203,7 +287,7 @@ impl<'a> LjpegDecompressor<'a> {
     Ok(input.get_u8())
   }
 
-  fn parse_dht(input: &mut ByteStream, init: &mut [bool;4], bits: &mut [[u32;17];4], huffval: &mut [[u32;256];4]) -> Result<(), String> {
+  fn parse_dht(input: &mut ByteStream, init: &mut [bool;4], bits: &mut [[u32;17];4], huffval: &mut [[u32;256];4]) -> Result<(), RawLoaderError> {
     let mut length = (input.get_u16() as usize) - 2;
 
     while length > 0 {
@@ -212,10 +296,10 @@ impl<'a> LjpegDecompressor<'a> {
       let th = b & 0xf;
 
       if tc != 0 {
-        return Err("ljpeg: unsuported table class in DHT".to_string())
+        return Err(RawLoaderError::Unimplemented("ljpeg: unsuported table class in DHT".to_string()))
       }
       if th > 3 {
-        return Err(format!("ljpeg: unsuported table id {}", th).to_string())
+        return Err(RawLoaderError::Unimplemented(format!("ljpeg: unsuported table id {}", th)))
       }
 
       let mut acc: usize = 0;
@@ -226,11 +310,11 @@ impl<'a> LjpegDecompressor<'a> {
       bits[th][0] = 0;
 
       if acc > 256 {
-        return Err("ljpeg: invalid DHT table".to_string())
+        return Err(RawLoaderError::Corrupt("ljpeg: invalid DHT table".to_string()))
       }
 
       if length < 1+16+acc {
-        return Err("ljpeg: invalid DHT table length".to_string())
+        return Err(RawLoaderError::Corrupt("ljpeg: invalid DHT table length".to_string()))
       }
 
       for i in 0..acc {
@@ -244,7 +328,24 @@ impl<'a> LjpegDecompressor<'a> {
     Ok(())
   }
 
-  pub fn decode(&self, out: &mut [u16], x: usize, stripwidth: usize, width: usize, height: usize, dummy: bool) -> Result<(),String> {
+  /// Decodes the stream into `out`, as a block `width` values wide and `height` rows high
+  /// starting at column `x` of rows `stripwidth` values wide, which is how tiles are put into
+  /// a larger image. With `dummy` set nothing is decoded.
+  pub fn decode(&self, out: &mut [u16], x: usize, stripwidth: usize, width: usize, height: usize, dummy: bool) -> Result<(), LjpegError> {
+    guard(|| self.decode_into(out, x, stripwidth, width, height, dummy))
+  }
+
+  /// Decodes the whole stream, `width()`\*`height()` values in row major order
+  pub fn decode_image(&self) -> Result<Vec<u16>, LjpegError> {
+    let (width, height) = (self.width(), self.height());
+    guard(|| {
+      let mut out = alloc_image_ok!(width, height, false);
+      self.decode_into(&mut out, 0, width, width, height, false)?;
+      Ok(out)
+    })
+  }
+
+  fn decode_into(&self, out: &mut [u16], x: usize, stripwidth: usize, width: usize, height: usize, dummy: bool) -> Result<(), RawLoaderError> {
     if dummy {
       return Ok(());
     }
@@ -261,15 +362,15 @@ impl<'a> LjpegDecompressor<'a> {
           2 => decode_ljpeg_2components(self, out, x, stripwidth, width, height),
           3 => decode_ljpeg_3components(self, out, x, stripwidth, width, height),
           4 => decode_ljpeg_4components(self, out, width, height),
-          c => return Err(format!("ljpeg: {} component files not supported", c).to_string()),
+          c => return Err(RawLoaderError::Unimplemented(format!("ljpeg: {} component files not supported", c))),
         }
       },
       8 => decode_hasselblad(self, out, width),
-      p => return Err(format!("ljpeg: predictor {} not supported", p).to_string()),
+      p => return Err(RawLoaderError::Unimplemented(format!("ljpeg: predictor {} not supported", p))),
     }
   }
 
-  pub fn decode_leaf(&self, width: usize, height: usize) -> Result<Vec<u16>,String> {
+  pub(crate) fn decode_leaf(&self, width: usize, height: usize) -> Result<Vec<u16>, RawLoaderError> {
     let mut offsets = vec![0 as usize; 1];
     let mut input = ByteStream::new(self.buffer, BIG_ENDIAN);
     loop {
@@ -285,22 +386,32 @@ impl<'a> LjpegDecompressor<'a> {
     }
     let nstrips = (height-1)/8 + 1;
     if offsets.len() != nstrips {
-      return Err(format!("MOS: expecting {} strips found {}", nstrips, offsets.len()).to_string())
+      return Err(RawLoaderError::Corrupt(format!("MOS: expecting {} strips found {}", nstrips, offsets.len())))
     }
 
     let ref htable1 = self.dhts[self.sof.components[0].dc_tbl_num];
     let ref htable2 = self.dhts[self.sof.components[1].dc_tbl_num];
     let bpred = 1 << (self.sof.precision - self.point_transform -1);
-    Ok(decode_threaded_multiline(width, height, 8, false, &(|strip: &mut [u16], block| {
+    decode_threaded_multiline(width, height, 8, false, &(|strip: &mut [u16], block| {
       let block = block / 8;
       let offset = offsets[block];
       let nlines = strip.len()/width;
       decode_leaf_strip(&self.buffer[offset..], strip, width, nlines, htable1, htable2, bpred).unwrap();
-    })))
+    }))
   }
 
+  /// Width of the decoded image in values, the width of the frame times its components
   pub fn width(&self) -> usize { self.sof.width * self.sof.cps }
+  /// Height of the decoded image in rows
   pub fn height(&self) -> usize { self.sof.height }
+  /// Number of components of the frame
+  pub fn components(&self) -> usize { self.sof.cps }
+  /// Bits per sample, from 12 to 16
+  pub fn precision(&self) -> usize { self.sof.precision }
+  /// Predictor of the scan, from 1 to 7 as in the specification
+  pub fn predictor(&self) -> usize { self.predictor }
+  /// Vertical subsampling of the first component, 2 for YCbCr 4:2:0
   pub fn super_v(&self) -> usize { self.sof.components[0].super_v }
+  /// Horizontal subsampling of the first component, 2 for YCbCr 4:2:2 and 4:2:0
   pub fn super_h(&self) -> usize { self.sof.components[0].super_h }
 }
@@ -0,0 +1,344 @@
+use crate::decoders::tiff::*;
+use crate::decoders::ciff::*;
+use crate::decoders::exif::get_string;
+use crate::decoders::makernotes::{AfPoint, Bracketing, CameraLevel, WbIlluminant, WbPreset};
+
+// ColorData versions by the length of the tag, with the offset of the as shot WB levels.
+// Each set of levels is RGGB followed by the color temperature.
+const COLOR_DATA: [(&[usize], u16, usize);8] = [
+  (&[582], 1, 0x19),
+  (&[653], 2, 0x22),
+  (&[796], 3, 0x3f),
+  (&[674, 692, 702, 1227, 1250, 1251, 1337, 1338, 1346], 4, 0x3f),
+  (&[5120], 5, 0x47),
+  (&[1273, 1275], 6, 0x3f),
+  (&[1312, 1313, 1316, 1506], 7, 0x3f),
+  (&[1560, 1592, 1353, 1602], 8, 0x3f),
+];
+
+// The presets that follow the as shot levels in the versions where their layout is known
+const PRESETS_V1: [WbIlluminant;7] = [
+  WbIlluminant::Auto, WbIlluminant::Daylight, WbIlluminant::Shade, WbIlluminant::Cloudy,
+  WbIlluminant::Tungsten, WbIlluminant::Fluorescent, WbIlluminant::Flash,
+];
+const PRESETS_V3: [WbIlluminant;9] = [
+  WbIlluminant::Auto, WbIlluminant::Measured, WbIlluminant::Daylight, WbIlluminant::Shade,
+  WbIlluminant::Cloudy, WbIlluminant::Tungsten, WbIlluminant::Fluorescent, WbIlluminant::Kelvin,
+  WbIlluminant::Flash,
+];
+// Unknown marks the slots whose use isn't known, those are skipped
+const PRESETS_V4: [WbIlluminant;10] = [
+  WbIlluminant::Auto, WbIlluminant::Measured, WbIlluminant::Unknown, WbIlluminant::Daylight,
+  WbIlluminant::Shade, WbIlluminant::Cloudy, WbIlluminant::Tungsten, WbIlluminant::Fluorescent,
+  WbIlluminant::Kelvin, WbIlluminant::Flash,
+];
+const PRESETS_V6: [WbIlluminant;14] = [
+  WbIlluminant::Auto, WbIlluminant::Measured, WbIlluminant::Unknown, WbIlluminant::Unknown,
+  WbIlluminant::Unknown, WbIlluminant::Unknown, WbIlluminant::Unknown, WbIlluminant::Daylight,
+  WbIlluminant::Shade, WbIlluminant::Cloudy, WbIlluminant::Tungsten, WbIlluminant::Fluorescent,
+  WbIlluminant::Kelvin, WbIlluminant::Flash,
+];
+
+/// Information from the Canon makernotes (CameraSettings, ShotInfo, ColorData and LensInfo)
+///
+/// Codes that Canon documents as enumerations are kept as the raw values found in the file.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CanonMakernote {
+  /// macro mode code (1 = macro, 2 = normal)
+  pub macro_mode: Option<u16>,
+  /// self timer delay in seconds
+  pub self_timer: Option<f32>,
+  /// quality code (4 = RAW, 5 = RAW+JPEG, ...)
+  pub quality: Option<u16>,
+  /// flash mode code
+  pub flash_mode: Option<u16>,
+  /// drive mode code (0 = single, 1 = continuous, ...)
+  pub continuous_drive: Option<u16>,
+  /// focus mode code (0 = one shot AF, 1 = AI servo, 3 = manual, ...)
+  pub focus_mode: Option<u16>,
+  /// exposure mode code (1 = program, 2 = Tv, 3 = Av, 4 = manual, ...)
+  pub exposure_mode: Option<u16>,
+  /// lens type ID as used by `lens_name()`
+  pub lens_id: Option<u16>,
+  /// shortest focal length of the lens in mm
+  pub min_focal_length: Option<f32>,
+  /// longest focal length of the lens in mm
+  pub max_focal_length: Option<f32>,
+  /// widest aperture of the lens as an f-number
+  pub max_aperture: Option<f32>,
+  /// narrowest aperture of the lens as an f-number
+  pub min_aperture: Option<f32>,
+  /// image stabilization code
+  pub image_stabilization: Option<u16>,
+  /// ISO the sensor was read out at
+  pub base_iso: Option<f32>,
+  /// exposure value the camera measured
+  pub measured_ev: Option<f32>,
+  /// aperture as an f-number
+  pub fnumber: Option<f32>,
+  /// shutter time in seconds
+  pub exposure_time: Option<f32>,
+  /// white balance the camera was set to
+  pub white_balance: Option<WbIlluminant>,
+  /// position of the image in a burst
+  pub sequence_number: Option<u16>,
+  /// camera temperature in degrees Celsius
+  pub camera_temperature: Option<i16>,
+  /// upper bound of the focus distance in meters
+  pub focus_distance_upper: Option<f32>,
+  /// lower bound of the focus distance in meters
+  pub focus_distance_lower: Option<f32>,
+  /// ColorData version, 0 when unknown
+  pub color_data_version: u16,
+  /// as shot white balance multipliers
+  pub wb_as_shot: Option<[f32;4]>,
+  /// as shot color temperature in Kelvin
+  pub color_temp_as_shot: Option<u32>,
+  /// white balance presets stored in ColorData
+  pub wb_presets: Vec<WbPreset>,
+  /// lens model as a string
+  pub lens_model: Option<String>,
+  /// lens serial number
+  pub lens_serial: Option<String>,
+  /// camera serial number
+  pub serial: Option<String>,
+  /// autofocus points from AFInfo2
+  pub af_points: Vec<AfPoint>,
+  /// auto exposure bracketing the image is part of
+  pub bracketing: Option<Bracketing>,
+  /// aspect ratio code from AspectInfo (0 = 3:2, 1 = 1:1, 2 = 4:3, 7 = 16:9, 8 = 4:5, ...)
+  pub aspect_ratio: Option<u32>,
+  /// area of the aspect ratio as left, top, width and height within the sensor's image area
+  pub aspect_crop: Option<[u32;4]>,
+  /// roll and pitch from LevelInfo
+  pub level: Option<CameraLevel>,
+  /// clockwise rotation of the image in degrees from the CIFF ImageInfo
+  pub rotation: Option<i32>,
+}
+
+impl CanonMakernote {
+  pub(crate) fn from_tiff(tiff: &TiffIFD) -> Option<CanonMakernote> {
+    let shorts = |tag: Tag| -> Vec<u16> {
+      match tiff.find_entry(tag) {
+        Some(e) if e.typ() == 3 || e.typ() == 8 => (0..e.count()).map(|i| e.get_force_u16(i)).collect(),
+        _ => Vec::new(),
+      }
+    };
+    let lens_info = tiff.find_entry(Tag::Cr2LensInfo).map(|e| e.get_data()).unwrap_or(&[]);
+    let mut mn = Self::parse(&shorts(Tag::Cr2CamSettings), &shorts(Tag::Cr2ShotInfo),
+                             &shorts(Tag::Cr2ColorData), lens_info, get_string(tiff, Tag::Cr2LensModel));
+    if let Some(makernote) = tiff.find_private_ifd(Tag::Makernote) {
+      let serial = makernote.get_entry_raw(0x000c).filter(|e| e.typ() == 4 && e.count() > 0).map(|e| e.get_u32(0));
+      mn.serial = Self::serial(serial, &get_string(tiff, Tag::Model).unwrap_or_default());
+      if let Some(af) = makernote.get_entry_raw(0x0026).filter(|e| e.typ() == 3 || e.typ() == 8) {
+        let af: Vec<u16> = (0..af.count()).map(|i| af.get_force_u16(i)).collect();
+        mn.af_points = Self::parse_af_info(&af);
+      }
+      if let Some(aspect) = makernote.get_entry_raw(0x009a).filter(|e| e.typ() == 4 && e.count() >= 5) {
+        mn.aspect_ratio = Some(aspect.get_u32(0));
+        let (width, height) = (aspect.get_u32(1), aspect.get_u32(2));
+        if width > 0 && height > 0 {
+          mn.aspect_crop = Some([aspect.get_u32(3), aspect.get_u32(4), width, height]);
+        }
+      }
+      // Angles are in tenths of a degree from 0 to 360, with the roll counterclockwise
+      if let Some(level) = makernote.get_entry_raw(0x4059).filter(|e| (e.typ() == 4 || e.typ() == 9) && e.count() >= 6) {
+        let angle = |v: u32| {
+          let v = v as i32;
+          (if v > 1800 { v - 3600 } else { v }) as f32 / 10.0
+        };
+        mn.level = Some(CameraLevel { roll: -angle(level.get_u32(4)), pitch: angle(level.get_u32(5)) });
+      }
+    }
+    if mn == CanonMakernote::default() { None } else { Some(mn) }
+  }
+
+  pub(crate) fn from_ciff(ciff: &CiffIFD) -> Option<CanonMakernote> {
+    let shorts = |tag: CiffTag| -> Vec<u16> {
+      match ciff.find_entry(tag) {
+        Some(e) if e.typ == 0x1000 => (0..e.count).map(|i| e.get_force_u16(i)).collect(),
+        _ => Vec::new(),
+      }
+    };
+    let mut mn = Self::parse(&shorts(CiffTag::CameraSettings), &shorts(CiffTag::ShotInfo), &[], &[], None);
+    let serial = ciff.find_entry(CiffTag::SerialNumber).filter(|e| e.count > 0).map(|e| e.get_u32(0));
+    let model = ciff.find_entry(CiffTag::MakeModel).and_then(|e| e.get_strings().get(1).cloned()).unwrap_or_default();
+    mn.serial = Self::serial(serial, &model);
+    mn.rotation = ciff.find_entry(CiffTag::ImageInfo).filter(|e| e.count >= 4).map(|e| e.get_u32(3) as i32);
+    if mn == CanonMakernote::default() { None } else { Some(mn) }
+  }
+
+  fn parse(settings: &[u16], shot: &[u16], colordata: &[u16], lens_info: &[u8], lens_model: Option<String>) -> CanonMakernote {
+    let setting = |idx: usize| settings.get(idx).cloned();
+    let info = |idx: usize| shot.get(idx).map(|&v| v as i16);
+    let nonzero = |v: Option<u16>| v.filter(|&v| v != 0 && v != 0xffff);
+
+    let units = setting(25).filter(|&u| u > 0).unwrap_or(1) as f32;
+    let focal = |idx: usize| nonzero(setting(idx)).map(|v| v as f32 / units);
+    let aperture = |v: i16| (canon_ev(v) * std::f32::consts::LN_2 / 2.0).exp();
+
+    let (color_data_version, wb_as_shot, color_temp_as_shot, wb_presets) = Self::parse_colordata(colordata);
+
+    CanonMakernote {
+      macro_mode: setting(1),
+      self_timer: setting(2).map(|v| (v & 0xfff) as f32 / 10.0),
+      quality: setting(3),
+      flash_mode: setting(4),
+      continuous_drive: setting(5),
+      focus_mode: setting(7),
+      exposure_mode: setting(20),
+      lens_id: nonzero(setting(22)),
+      max_focal_length: focal(23),
+      min_focal_length: focal(24),
+      max_aperture: nonzero(setting(26)).map(|v| aperture(v as i16)),
+      min_aperture: nonzero(setting(27)).map(|v| aperture(v as i16)),
+      image_stabilization: setting(34),
+      base_iso: info(2).filter(|&v| v != 0).map(|v| (v as f32 / 32.0 * std::f32::consts::LN_2).exp() * 100.0 / 32.0),
+      measured_ev: info(3).map(|v| v as f32 / 32.0 + 5.0),
+      fnumber: info(21).filter(|&v| v != 0).map(aperture),
+      exposure_time: info(22).filter(|&v| v != 0).map(|v| (-canon_ev(v) * std::f32::consts::LN_2).exp()),
+      white_balance: info(7).map(|v| Self::illuminant(v as u16)),
+      sequence_number: info(9).map(|v| v as u16),
+      camera_temperature: info(12).filter(|&v| v != 0).map(|v| v - 128),
+      focus_distance_upper: info(19).filter(|&v| v != 0).map(|v| v as u16 as f32 / 100.0),
+      focus_distance_lower: info(20).filter(|&v| v != 0).map(|v| v as u16 as f32 / 100.0),
+      color_data_version,
+      wb_as_shot,
+      color_temp_as_shot,
+      wb_presets,
+      lens_model,
+      lens_serial: if lens_info.len() >= 5 && lens_info[0..5].iter().any(|&b| b != 0) {
+        Some(lens_info[0..5].iter().map(|b| format!("{:02x}", b)).collect())
+      } else {
+        None
+      },
+      serial: None,
+      af_points: Vec::new(),
+      // The shot number is 1 to 3 in the sequence or -1 when it's unknown
+      bracketing: match info(16) {
+        Some(shot) if shot != 0 => Some(Bracketing {
+          step: info(17).map(|v| canon_ev(v).abs()).filter(|&v| v > 0.0),
+          frames: Some(3),
+          position: if shot > 0 { Some(shot as u32) } else { None },
+          exposure_offset: None,
+        }),
+        _ => None,
+      },
+      aspect_ratio: None,
+      aspect_crop: None,
+      level: None,
+      rotation: None,
+    }
+  }
+
+  // AFInfo2 has a header with the number of points and the size of the image they refer to,
+  // followed by the sizes and positions of all the points relative to the center of the image
+  // and bitmasks of the ones in focus and selected
+  fn parse_af_info(data: &[u16]) -> Vec<AfPoint> {
+    if data.len() < 8 {
+      return Vec::new()
+    }
+    let num = data[2] as usize;
+    let (width, height) = if data[6] > 0 && data[7] > 0 { (data[6], data[7]) } else { (data[4], data[5]) };
+    let words = num.div_ceil(16);
+    if num == 0 || width == 0 || height == 0 || data.len() < 8 + num*4 + words {
+      return Vec::new()
+    }
+    let (width, height) = (width as f32, height as f32);
+    let bit = |start: usize, i: usize| data[start + i/16] >> (i%16) & 1 == 1;
+    let has_selected = data.len() >= 8 + num*4 + words*2;
+
+    (0..num).filter_map(|i| {
+      let w = data[8 + i] as i16 as f32;
+      let h = data[8 + num + i] as i16 as f32;
+      let x = data[8 + num*2 + i] as i16 as f32;
+      let y = data[8 + num*3 + i] as i16 as f32;
+      if w <= 0.0 || h <= 0.0 {
+        return None
+      }
+      let in_focus = bit(8 + num*4, i);
+      Some(AfPoint {
+        x: 0.5 + x / width,
+        y: 0.5 - y / height,
+        width: w / width,
+        height: h / height,
+        selected: if has_selected { bit(8 + num*4 + words, i) } else { in_focus },
+        in_focus,
+      })
+    }).collect()
+  }
+
+  // The 1D bodies use 6 digit serials, the others 10
+  fn serial(serial: Option<u32>, model: &str) -> Option<String> {
+    match serial {
+      Some(0) | None => None,
+      Some(v) if model.contains("EOS-1D") => Some(format!("{:06}", v)),
+      Some(v) => Some(format!("{:010}", v)),
+    }
+  }
+
+  fn parse_colordata(data: &[u16]) -> (u16, Option<[f32;4]>, Option<u32>, Vec<WbPreset>) {
+    let (version, offset) = match COLOR_DATA.iter().find(|(counts, _, _)| counts.contains(&data.len())) {
+      Some(&(_, version, offset)) => (version, offset),
+      None => return (0, None, None, Vec::new()),
+    };
+
+    let levels = |pos: usize| -> Option<([f32;4], Option<u32>)> {
+      let vals = data.get(pos..pos+5)?;
+      if vals[0] == 0 || vals[1] == 0 || vals[3] == 0 {
+        return None
+      }
+      let temp = if vals[4] > 0 { Some(vals[4] as u32) } else { None };
+      Some(([vals[0] as f32, vals[1] as f32, vals[3] as f32, f32::NAN], temp))
+    };
+
+    let (wb_as_shot, color_temp_as_shot) = match levels(offset) {
+      Some((coeffs, temp)) => (Some(coeffs), temp),
+      None => (None, None),
+    };
+
+    let order: &[WbIlluminant] = match version {
+      1 => &PRESETS_V1,
+      3 => &PRESETS_V3,
+      4 => &PRESETS_V4,
+      6 => &PRESETS_V6,
+      _ => &[],
+    };
+    let presets = order.iter().enumerate()
+      .filter(|&(_, &illuminant)| illuminant != WbIlluminant::Unknown)
+      .filter_map(|(i, &illuminant)| {
+        levels(offset + (i+1)*5).map(|(coeffs, temperature)| WbPreset { illuminant, coeffs, temperature })
+      }).collect();
+
+    (version, wb_as_shot, color_temp_as_shot, presets)
+  }
+
+  fn illuminant(code: u16) -> WbIlluminant {
+    match code {
+      0 => WbIlluminant::Auto,
+      1 => WbIlluminant::Daylight,
+      2 => WbIlluminant::Cloudy,
+      3 => WbIlluminant::Tungsten,
+      4 => WbIlluminant::Fluorescent,
+      5 => WbIlluminant::Flash,
+      6 | 15 | 16 | 18 | 19 | 20 => WbIlluminant::Custom,
+      8 => WbIlluminant::Shade,
+      9 => WbIlluminant::Kelvin,
+      _ => WbIlluminant::Unknown,
+    }
+  }
+}
+
+// Canon stores EVs in 1/32 steps but with 1/3 stops rounded to 0x0c and 0x14
+fn canon_ev(val: i16) -> f32 {
+  let sign = if val < 0 { -1.0 } else { 1.0 };
+  let val = (val as i32).abs();
+  let frac = val & 0x1f;
+  let frac = match frac {
+    0x0c => 32.0 / 3.0,
+    0x14 => 64.0 / 3.0,
+    _ => frac as f32,
+  };
+  sign * ((val & !0x1f) as f32 + frac) / 32.0
+}
@@ -0,0 +1,172 @@
+use crate::decoders::tiff::*;
+use crate::decoders::exif::entry_string;
+use crate::decoders::makernotes::{Bracketing, WbIlluminant, WbPreset};
+
+// The WB presets in the RAF header directory, stored as GRGB
+const RAF_PRESETS: [(Tag, WbIlluminant);5] = [
+  (Tag::RafWBAuto, WbIlluminant::Auto),
+  (Tag::RafWBDaylight, WbIlluminant::Daylight),
+  (Tag::RafWBCloudy, WbIlluminant::Cloudy),
+  (Tag::RafWBFluorescent, WbIlluminant::Fluorescent),
+  (Tag::RafWBTungsten, WbIlluminant::Tungsten),
+];
+
+/// Information from the Fujifilm makernotes and the RAF header on how the camera intended the
+/// image to be rendered
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FujiMakernote {
+  /// camera serial number
+  pub serial: Option<String>,
+  /// number of images the camera has taken
+  pub image_count: Option<u32>,
+  /// film simulation name (e.g., "Provia", "Velvia", "Classic Chrome", "Acros")
+  pub film_simulation: Option<String>,
+  /// dynamic range mode code (1 = standard, 3 = wide)
+  pub dynamic_range: Option<u16>,
+  /// dynamic range setting code (0 = auto, 1 = manual, 0x100 = DR100, 0x200 = DR200, ...)
+  pub dynamic_range_setting: Option<u16>,
+  /// dynamic range used for development as a percentage (100, 200 or 400)
+  pub development_dynamic_range: Option<u16>,
+  /// white balance mode code (0 = auto, 0x100 = daylight, 0x200 = cloudy, 0xff0 = Kelvin, ...)
+  pub white_balance: Option<u16>,
+  /// color temperature in Kelvin when set manually
+  pub color_temperature: Option<u32>,
+  /// white balance fine tune shifts for red and blue
+  pub wb_fine_tune: Option<(i32,i32)>,
+  /// shortest focal length of the lens in mm
+  pub min_focal_length: Option<f32>,
+  /// longest focal length of the lens in mm
+  pub max_focal_length: Option<f32>,
+  /// maximum aperture (as an f-number) at the shortest focal length
+  pub max_aperture_at_min_focal: Option<f32>,
+  /// maximum aperture (as an f-number) at the longest focal length
+  pub max_aperture_at_max_focal: Option<f32>,
+  /// drive mode code (0 = single, 1 = continuous low, 2 = continuous high)
+  pub drive_mode: Option<u8>,
+  /// auto bracketing the image is part of
+  pub bracketing: Option<Bracketing>,
+  /// white balance presets from the RAF header
+  pub wb_presets: Vec<WbPreset>,
+  /// distortion correction as (radius, distortion in percent), with the radius relative to
+  /// the distance from the center to the corners
+  pub distortion: Vec<(f32,f32)>,
+  /// lateral chromatic aberration correction as (radius, red, blue) scale offsets
+  pub chromatic_aberration: Vec<(f32,f32,f32)>,
+  /// vignetting as (radius, brightness in percent of the center)
+  pub vignetting: Vec<(f32,f32)>,
+}
+
+impl FujiMakernote {
+  pub(crate) fn from_tiff(tiff: &TiffIFD) -> Option<FujiMakernote> {
+    let mut mn = FujiMakernote::default();
+
+    if let Some(ifd) = tiff.find_private_ifd(Tag::Makernote) {
+      let short = |tag: u16| ifd.get_entry_raw(tag).filter(|e| e.typ() == 3 && e.count() > 0).map(|e| e.get_u16(0));
+      let rational = |tag: u16| -> Option<f32> {
+        ifd.get_entry_raw(tag).filter(|e| e.typ() == 5 && e.count() > 0).map(|e| e.get_f32(0))
+          .filter(|v| v.is_finite() && *v > 0.0)
+      };
+
+      mn.serial = ifd.get_entry_raw(0x0010).filter(|e| e.typ() == 2).and_then(|e| entry_string(e));
+      mn.image_count = short(0x1438).map(|v| (v & 0x7fff) as u32).filter(|&v| v > 0);
+      mn.white_balance = short(0x1002);
+      mn.color_temperature = short(0x1005).filter(|&v| v > 0).map(|v| v as u32);
+      if let Some(tune) = ifd.get_entry_raw(0x100a).filter(|e| e.typ() == 9 && e.count() >= 2) {
+        mn.wb_fine_tune = Some((tune.get_force_u32(0) as i32, tune.get_force_u32(1) as i32));
+      }
+      mn.film_simulation = match short(0x1003).and_then(Self::monochrome_name) {
+        Some(name) => Some(name.to_string()),
+        None => short(0x1401).and_then(Self::film_mode_name).map(|n| n.to_string()),
+      };
+      mn.drive_mode = ifd.get_entry_raw(0x1103).filter(|e| e.typ() == 4 && e.count() > 0).map(|e| e.get_u32(0) as u8);
+      if short(0x1100).unwrap_or(0) != 0 {
+        mn.bracketing = Some(Bracketing {
+          position: short(0x1101).filter(|&v| v > 0).map(|v| v as u32),
+          ..Default::default()
+        });
+      }
+      mn.dynamic_range = short(0x1400);
+      mn.dynamic_range_setting = short(0x1402);
+      mn.development_dynamic_range = short(0x1403).filter(|&v| v > 0);
+      mn.min_focal_length = rational(0x1404);
+      mn.max_focal_length = rational(0x1405);
+      mn.max_aperture_at_min_focal = rational(0x1406);
+      mn.max_aperture_at_max_focal = rational(0x1407);
+
+      // The correction tables start with a value we don't know the meaning of followed by the
+      // radii of the knots and then one set of values per knot for each table
+      let table = |tag: u16, sets: usize| -> Option<(Vec<f32>, Vec<Vec<f32>>)> {
+        let e = ifd.get_entry_raw(tag).filter(|e| e.typ() == 10 && e.count() > 1)?;
+        let knots = (e.count() - 1) / (sets + 1);
+        if knots == 0 || 1 + knots*(sets+1) != e.count() {
+          return None
+        }
+        let radii = (0..knots).map(|i| e.get_f32(1+i)).collect();
+        let values = (0..sets).map(|s| (0..knots).map(|i| e.get_f32(1+knots*(s+1)+i)).collect()).collect();
+        Some((radii, values))
+      };
+      if let Some((radii, values)) = table(0x9650, 1) {
+        mn.distortion = radii.into_iter().zip(values[0].iter().cloned()).collect();
+      }
+      if let Some((radii, values)) = table(0x9651, 2) {
+        mn.chromatic_aberration = radii.iter().enumerate().map(|(i, &r)| (r, values[0][i], values[1][i])).collect();
+      }
+      if let Some((radii, values)) = table(0x9652, 1) {
+        mn.vignetting = radii.into_iter().zip(values[0].iter().cloned()).collect();
+      }
+    }
+
+    for &(tag, illuminant) in RAF_PRESETS.iter() {
+      if let Some(levels) = tiff.find_entry(tag) {
+        let (g, r, b) = (levels.get_u16(0), levels.get_u16(1), levels.get_u16(3));
+        if g > 0 && r > 0 && b > 0 {
+          mn.wb_presets.push(WbPreset {
+            illuminant,
+            coeffs: [r as f32, g as f32, b as f32, f32::NAN],
+            temperature: None,
+          });
+        }
+      }
+    }
+
+    if mn == FujiMakernote::default() { None } else { Some(mn) }
+  }
+
+  fn film_mode_name(mode: u16) -> Option<&'static str> {
+    Some(match mode {
+      0x000 => "Provia",
+      0x100 => "Studio Portrait",
+      0x110 => "Studio Portrait Enhanced Saturation",
+      0x120 => "Astia",
+      0x130 => "Studio Portrait Increased Sharpness",
+      0x200 | 0x400 => "Velvia",
+      0x300 => "Studio Portrait Ex",
+      0x500 => "Pro Neg. Std",
+      0x501 => "Pro Neg. Hi",
+      0x600 => "Classic Chrome",
+      0x700 => "Eterna",
+      0x800 => "Classic Negative",
+      0x900 => "Bleach Bypass",
+      0xa00 => "Nostalgic Neg",
+      0xb00 => "Reala Ace",
+      _ => return None,
+    })
+  }
+
+  // The monochrome simulations are recorded in the Saturation tag instead
+  fn monochrome_name(saturation: u16) -> Option<&'static str> {
+    Some(match saturation {
+      0x300 => "Monochrome",
+      0x301 => "Monochrome+R",
+      0x302 => "Monochrome+Ye",
+      0x303 => "Monochrome+G",
+      0x310 => "Sepia",
+      0x500 => "Acros",
+      0x501 => "Acros+R",
+      0x502 => "Acros+Ye",
+      0x503 => "Acros+G",
+      _ => return None,
+    })
+  }
+}
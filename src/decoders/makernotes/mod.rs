@@ -0,0 +1,337 @@
+use crate::decoders::tiff::*;
+use crate::decoders::exif::get_string;
+use crate::decoders::{ImageAreas, Orientation, Rect};
+
+pub mod canon;
+pub mod fuji;
+pub mod nikon;
+pub mod olympus;
+pub mod panasonic;
+pub mod pentax;
+pub mod sony;
+pub use self::canon::CanonMakernote;
+pub use self::fuji::FujiMakernote;
+pub use self::nikon::NikonMakernote;
+pub use self::olympus::OlympusMakernote;
+pub use self::panasonic::{PanasonicMakernote, PanasonicDistortion};
+pub use self::pentax::PentaxMakernote;
+pub use self::sony::SonyMakernote;
+
+/// Lighting a white balance preset is meant for
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum WbIlluminant {
+  Auto,
+  Measured,
+  Daylight,
+  Shade,
+  Cloudy,
+  Tungsten,
+  Fluorescent,
+  Flash,
+  Kelvin,
+  Custom,
+  Unknown,
+}
+
+impl WbIlluminant {
+  // Convert an EXIF LightSource value
+  pub(crate) fn from_light_source(val: u16) -> WbIlluminant {
+    match val {
+      0 => WbIlluminant::Auto,
+      1 | 9 | 20 ..= 23 => WbIlluminant::Daylight,
+      2 | 12 ..= 16 => WbIlluminant::Fluorescent,
+      3 | 24 => WbIlluminant::Tungsten,
+      4 => WbIlluminant::Flash,
+      10 => WbIlluminant::Cloudy,
+      11 => WbIlluminant::Shade,
+      255 => WbIlluminant::Custom,
+      _ => WbIlluminant::Unknown,
+    }
+  }
+}
+
+/// A white balance preset as stored by the camera
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WbPreset {
+  /// the lighting the preset is meant for
+  pub illuminant: WbIlluminant,
+  /// multipliers in the same order as `RawImage::wb_coeffs`
+  #[cfg_attr(feature = "serde", serde(deserialize_with = "crate::decoders::nan_from_null"))]
+  pub coeffs: [f32;4],
+  /// color temperature in Kelvin the camera associates with the preset
+  pub temperature: Option<u32>,
+}
+
+/// An autofocus point or area as recorded by the camera
+///
+/// Positions and sizes are fractions of the full image before any rotation from the
+/// orientation is applied, with (0.0, 0.0) the top left corner.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AfPoint {
+  /// horizontal position of the center of the point
+  pub x: f32,
+  /// vertical position of the center of the point
+  pub y: f32,
+  /// width of the point, 0.0 when the camera only records its position
+  pub width: f32,
+  /// height of the point, 0.0 when the camera only records its position
+  pub height: f32,
+  /// if the point was selected for focusing
+  pub selected: bool,
+  /// if the camera reported focus was achieved at the point
+  pub in_focus: bool,
+}
+
+/// How the camera was set to release the shutter
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[allow(missing_docs)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DriveMode {
+  Single,
+  Continuous,
+  SelfTimer,
+}
+
+/// Position of the image in an automatic bracketing sequence
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Bracketing {
+  /// exposure difference between the frames in EV
+  pub step: Option<f32>,
+  /// number of frames in the sequence
+  pub frames: Option<u32>,
+  /// position of this frame in the sequence, starting at 1
+  pub position: Option<u32>,
+  /// exposure offset of this frame in EV
+  pub exposure_offset: Option<f32>,
+}
+
+/// Orientation of the camera as measured by its electronic level, in degrees
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CameraLevel {
+  /// rotation around the lens axis, positive when the camera is turned clockwise as seen
+  /// from behind
+  pub roll: f32,
+  /// tilt of the lens axis, positive when pointing up
+  pub pitch: f32,
+}
+
+/// Brand specific information parsed from the makernotes
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Makernote {
+  /// Canon CR2, CR3 and CRW files
+  Canon(CanonMakernote),
+  /// Nikon NEF and NRW files
+  Nikon(NikonMakernote),
+  /// Sony ARW files
+  Sony(SonyMakernote),
+  /// Olympus and OM System ORF files
+  Olympus(OlympusMakernote),
+  /// Panasonic RW2 files
+  Panasonic(PanasonicMakernote),
+  /// Fujifilm RAF files
+  Fujifilm(FujiMakernote),
+  /// Pentax PEF and DNG files
+  Pentax(PentaxMakernote),
+}
+
+impl Makernote {
+  pub(crate) fn from_tiff(tiff: &TiffIFD) -> Option<Makernote> {
+    match get_string(tiff, Tag::Make).as_deref() {
+      Some("Canon") => CanonMakernote::from_tiff(tiff).map(Makernote::Canon),
+      Some("NIKON CORPORATION") | Some("NIKON") => NikonMakernote::from_tiff(tiff).map(Makernote::Nikon),
+      Some("SONY") => Some(Makernote::Sony(SonyMakernote::from_tiff(tiff))),
+      Some("Panasonic") => PanasonicMakernote::from_tiff(tiff).map(Makernote::Panasonic),
+      Some("FUJIFILM") => FujiMakernote::from_tiff(tiff).map(Makernote::Fujifilm),
+      Some("PENTAX Corporation") | Some("RICOH IMAGING COMPANY, LTD.") | Some("PENTAX") => {
+        PentaxMakernote::from_tiff(tiff).map(Makernote::Pentax)
+      },
+      Some(make) if make.starts_with("OLYMPUS") || make == "OM Digital Solutions" => {
+        OlympusMakernote::from_tiff(tiff).map(Makernote::Olympus)
+      },
+      _ => None,
+    }
+  }
+
+  /// Camera serial number found in the makernotes
+  pub fn serial(&self) -> Option<&str> {
+    match self {
+      Makernote::Canon(mn) => mn.serial.as_deref(),
+      Makernote::Nikon(mn) => mn.serial.as_deref(),
+      Makernote::Sony(_) => None,
+      Makernote::Olympus(mn) => mn.serial.as_deref(),
+      Makernote::Panasonic(mn) => mn.serial.as_deref(),
+      Makernote::Fujifilm(mn) => mn.serial.as_deref(),
+      Makernote::Pentax(mn) => mn.serial.as_deref(),
+    }
+  }
+
+  /// Number of shutter actuations found in the makernotes, for the brands that record it
+  pub fn shutter_count(&self) -> Option<u32> {
+    match self {
+      Makernote::Nikon(mn) => mn.shutter_count,
+      Makernote::Sony(mn) => mn.shutter_count,
+      Makernote::Fujifilm(mn) => mn.image_count,
+      Makernote::Pentax(mn) => mn.shutter_count,
+      Makernote::Canon(_) | Makernote::Olympus(_) | Makernote::Panasonic(_) => None,
+    }
+  }
+
+  /// Autofocus points found in the makernotes, for the brands where we know how to read them
+  pub fn af_points(&self) -> &[AfPoint] {
+    match self {
+      Makernote::Canon(mn) => &mn.af_points,
+      Makernote::Nikon(mn) => &mn.af_points,
+      Makernote::Sony(mn) => &mn.af_points,
+      _ => &[],
+    }
+  }
+
+  /// Drive mode the camera was set to
+  pub fn drive_mode(&self) -> Option<DriveMode> {
+    match self {
+      Makernote::Canon(mn) => match (mn.self_timer, mn.continuous_drive) {
+        (Some(t), _) if t > 0.0 => Some(DriveMode::SelfTimer),
+        (_, Some(0)) | (_, Some(6)) | (_, Some(9)) => Some(DriveMode::Single),
+        (_, Some(1)) | (_, Some(3..=5)) | (_, Some(8)) | (_, Some(10)) => Some(DriveMode::Continuous),
+        _ => None,
+      },
+      Makernote::Nikon(mn) => mn.shooting_mode.map(|mode| {
+        if mode & 0x08 != 0 {
+          DriveMode::SelfTimer
+        } else if mode & 0x01 != 0 {
+          DriveMode::Continuous
+        } else {
+          DriveMode::Single
+        }
+      }),
+      Makernote::Olympus(mn) => match mn.drive_mode {
+        Some(0) => Some(DriveMode::Single),
+        Some(1) | Some(2) => Some(DriveMode::Continuous),
+        _ => None,
+      },
+      Makernote::Fujifilm(mn) => match mn.drive_mode {
+        Some(0) => Some(DriveMode::Single),
+        Some(1) | Some(2) => Some(DriveMode::Continuous),
+        _ => None,
+      },
+      Makernote::Pentax(mn) => match mn.drive_mode {
+        Some([_, 1..=2, _, _]) => Some(DriveMode::SelfTimer),
+        Some([0, _, _, _]) => Some(DriveMode::Single),
+        Some([1..=3, _, _, _]) => Some(DriveMode::Continuous),
+        _ => None,
+      },
+      Makernote::Sony(_) | Makernote::Panasonic(_) => None,
+    }
+  }
+
+  /// Bracketing sequence the image is part of, if any
+  pub fn bracketing(&self) -> Option<Bracketing> {
+    match self {
+      Makernote::Canon(mn) => mn.bracketing,
+      Makernote::Nikon(mn) => mn.bracketing,
+      Makernote::Olympus(mn) => mn.bracketing,
+      Makernote::Fujifilm(mn) => mn.bracketing,
+      Makernote::Pentax(mn) => mn.bracketing,
+      Makernote::Sony(_) | Makernote::Panasonic(_) => None,
+    }
+  }
+
+  /// Temperature of the camera or sensor in degrees Celsius, whichever the brand records
+  pub fn temperature(&self) -> Option<f32> {
+    match self {
+      Makernote::Canon(mn) => mn.camera_temperature.map(|t| t as f32),
+      Makernote::Sony(mn) => mn.ambient_temperature.map(|t| t as f32),
+      Makernote::Olympus(mn) => mn.sensor_temperature.map(|t| t as f32),
+      Makernote::Pentax(mn) => mn.camera_temperature.map(|t| t as f32),
+      Makernote::Nikon(_) | Makernote::Panasonic(_) | Makernote::Fujifilm(_) => None,
+    }
+  }
+
+  /// Orientation of the image for the brands that record it in the makernotes, to use
+  /// when the file doesn't have the TIFF Orientation tag
+  pub fn orientation(&self) -> Option<Orientation> {
+    let orientation = match self {
+      Makernote::Canon(mn) => match mn.rotation?.rem_euclid(360) {
+        0 => Orientation::Normal,
+        90 => Orientation::Rotate90,
+        180 => Orientation::Rotate180,
+        270 => Orientation::Rotate270,
+        _ => return None,
+      },
+      Makernote::Pentax(mn) => match mn.level_orientation? & 0x07 {
+        1 => Orientation::Normal,
+        2 => Orientation::Rotate180,
+        3 => Orientation::Rotate90,
+        4 => Orientation::Rotate270,
+        _ => return None,
+      },
+      Makernote::Panasonic(mn) => Orientation::from_u16(mn.rotation?),
+      Makernote::Nikon(_) | Makernote::Sony(_) | Makernote::Olympus(_) | Makernote::Fujifilm(_) => return None,
+    };
+    if orientation == Orientation::Unknown { None } else { Some(orientation) }
+  }
+
+  /// Roll and pitch the camera's electronic level measured when the image was taken
+  pub fn level(&self) -> Option<CameraLevel> {
+    match self {
+      Makernote::Canon(mn) => mn.level,
+      Makernote::Olympus(mn) => mn.level,
+      Makernote::Pentax(mn) => mn.level,
+      Makernote::Nikon(_) | Makernote::Sony(_) | Makernote::Panasonic(_) | Makernote::Fujifilm(_) => None,
+    }
+  }
+
+  /// Area of the image the camera was framing when set to a crop mode or aspect ratio other
+  /// than the native one while still storing the full sensor, in full image coordinates
+  pub fn aspect_crop(&self, areas: &ImageAreas) -> Option<Rect> {
+    let active = areas.active_area;
+    let crop = match self {
+      // Relative to the area of the sensor the camera uses for its own images
+      Makernote::Canon(mn) => match (mn.aspect_ratio, mn.aspect_crop) {
+        (Some(ratio), Some([x, y, w, h])) if ratio != 0 => {
+          Rect::new(active.x + x as usize, active.y + y as usize, w as usize, h as usize)
+        },
+        _ => return None,
+      },
+      // Modes that make the camera store only the cropped area have a different sensor size
+      Makernote::Nikon(mn) => match (mn.crop_mode, mn.crop_sensor_size, mn.crop) {
+        (Some(mode), Some([sw, sh]), Some([x, y, w, h]))
+          if mode != 0 && sw as usize == areas.sensor_width && sh as usize == areas.sensor_height => {
+          Rect::new(x as usize, y as usize, w as usize, h as usize)
+        },
+        _ => return None,
+      },
+      // Relative to the ImageProcessing crop, with the right and bottom edges included
+      Makernote::Olympus(mn) => match (mn.aspect_ratio, mn.aspect_frame) {
+        (Some(ratio), Some([left, top, right, bottom])) if ratio != (1, 1) && right > left && bottom > top => {
+          let (x, y) = mn.crop.map(|c| (c[0] as usize, c[1] as usize)).unwrap_or((active.x, active.y));
+          Rect::new(x + left as usize, y + top as usize, (right - left) as usize + 1, (bottom - top) as usize + 1)
+        },
+        _ => return None,
+      },
+      _ => return None,
+    };
+    let crop = crop.intersect(&Rect::new(0, 0, areas.sensor_width, areas.sensor_height));
+    if crop.is_empty() || crop == areas.default_crop { None } else { Some(crop) }
+  }
+
+  /// White balance presets found in the makernotes
+  pub fn wb_presets(&self) -> &[WbPreset] {
+    match self {
+      Makernote::Canon(mn) => &mn.wb_presets,
+      Makernote::Nikon(_) => &[],
+      Makernote::Sony(mn) => &mn.wb_presets,
+      Makernote::Olympus(mn) => &mn.wb_presets,
+      Makernote::Panasonic(mn) => &mn.wb_presets,
+      Makernote::Fujifilm(mn) => &mn.wb_presets,
+      Makernote::Pentax(mn) => &mn.wb_presets,
+    }
+  }
+}
@@ -0,0 +1,292 @@
+use crate::decoders::tiff::*;
+use crate::decoders::basics::*;
+use crate::decoders::exif::get_string;
+use crate::decoders::makernotes::{AfPoint, Bracketing};
+
+// Nikon "encrypts" ColorBalance and LensData with these tables, from dcraw
+const WB_SERIALMAP: [u8;256] = [
+  0xc1,0xbf,0x6d,0x0d,0x59,0xc5,0x13,0x9d,0x83,0x61,0x6b,0x4f,0xc7,0x7f,0x3d,0x3d,
+  0x53,0x59,0xe3,0xc7,0xe9,0x2f,0x95,0xa7,0x95,0x1f,0xdf,0x7f,0x2b,0x29,0xc7,0x0d,
+  0xdf,0x07,0xef,0x71,0x89,0x3d,0x13,0x3d,0x3b,0x13,0xfb,0x0d,0x89,0xc1,0x65,0x1f,
+  0xb3,0x0d,0x6b,0x29,0xe3,0xfb,0xef,0xa3,0x6b,0x47,0x7f,0x95,0x35,0xa7,0x47,0x4f,
+  0xc7,0xf1,0x59,0x95,0x35,0x11,0x29,0x61,0xf1,0x3d,0xb3,0x2b,0x0d,0x43,0x89,0xc1,
+  0x9d,0x9d,0x89,0x65,0xf1,0xe9,0xdf,0xbf,0x3d,0x7f,0x53,0x97,0xe5,0xe9,0x95,0x17,
+  0x1d,0x3d,0x8b,0xfb,0xc7,0xe3,0x67,0xa7,0x07,0xf1,0x71,0xa7,0x53,0xb5,0x29,0x89,
+  0xe5,0x2b,0xa7,0x17,0x29,0xe9,0x4f,0xc5,0x65,0x6d,0x6b,0xef,0x0d,0x89,0x49,0x2f,
+  0xb3,0x43,0x53,0x65,0x1d,0x49,0xa3,0x13,0x89,0x59,0xef,0x6b,0xef,0x65,0x1d,0x0b,
+  0x59,0x13,0xe3,0x4f,0x9d,0xb3,0x29,0x43,0x2b,0x07,0x1d,0x95,0x59,0x59,0x47,0xfb,
+  0xe5,0xe9,0x61,0x47,0x2f,0x35,0x7f,0x17,0x7f,0xef,0x7f,0x95,0x95,0x71,0xd3,0xa3,
+  0x0b,0x71,0xa3,0xad,0x0b,0x3b,0xb5,0xfb,0xa3,0xbf,0x4f,0x83,0x1d,0xad,0xe9,0x2f,
+  0x71,0x65,0xa3,0xe5,0x07,0x35,0x3d,0x0d,0xb5,0xe9,0xe5,0x47,0x3b,0x9d,0xef,0x35,
+  0xa3,0xbf,0xb3,0xdf,0x53,0xd3,0x97,0x53,0x49,0x71,0x07,0x35,0x61,0x71,0x2f,0x43,
+  0x2f,0x11,0xdf,0x17,0x97,0xfb,0x95,0x3b,0x7f,0x6b,0xd3,0x25,0xbf,0xad,0xc7,0xc5,
+  0xc5,0xb5,0x8b,0xef,0x2f,0xd3,0x07,0x6b,0x25,0x49,0x95,0x25,0x49,0x6d,0x71,0xc7
+];
+
+const WB_KEYMAP: [u8;256] = [
+  0xa7,0xbc,0xc9,0xad,0x91,0xdf,0x85,0xe5,0xd4,0x78,0xd5,0x17,0x46,0x7c,0x29,0x4c,
+  0x4d,0x03,0xe9,0x25,0x68,0x11,0x86,0xb3,0xbd,0xf7,0x6f,0x61,0x22,0xa2,0x26,0x34,
+  0x2a,0xbe,0x1e,0x46,0x14,0x68,0x9d,0x44,0x18,0xc2,0x40,0xf4,0x7e,0x5f,0x1b,0xad,
+  0x0b,0x94,0xb6,0x67,0xb4,0x0b,0xe1,0xea,0x95,0x9c,0x66,0xdc,0xe7,0x5d,0x6c,0x05,
+  0xda,0xd5,0xdf,0x7a,0xef,0xf6,0xdb,0x1f,0x82,0x4c,0xc0,0x68,0x47,0xa1,0xbd,0xee,
+  0x39,0x50,0x56,0x4a,0xdd,0xdf,0xa5,0xf8,0xc6,0xda,0xca,0x90,0xca,0x01,0x42,0x9d,
+  0x8b,0x0c,0x73,0x43,0x75,0x05,0x94,0xde,0x24,0xb3,0x80,0x34,0xe5,0x2c,0xdc,0x9b,
+  0x3f,0xca,0x33,0x45,0xd0,0xdb,0x5f,0xf5,0x52,0xc3,0x21,0xda,0xe2,0x22,0x72,0x6b,
+  0x3e,0xd0,0x5b,0xa8,0x87,0x8c,0x06,0x5d,0x0f,0xdd,0x09,0x19,0x93,0xd0,0xb9,0xfc,
+  0x8b,0x0f,0x84,0x60,0x33,0x1c,0x9b,0x45,0xf1,0xf0,0xa3,0x94,0x3a,0x12,0x77,0x33,
+  0x4d,0x44,0x78,0x28,0x3c,0x9e,0xfd,0x65,0x57,0x16,0x94,0x6b,0xfb,0x59,0xd0,0xc8,
+  0x22,0x36,0xdb,0xd2,0x63,0x98,0x43,0xa1,0x04,0x87,0x86,0xf7,0xa6,0x26,0xbb,0xd6,
+  0x59,0x4d,0xbf,0x6a,0x2e,0xaa,0x2b,0xef,0xe6,0x78,0xb6,0x4e,0xe0,0x2f,0xdc,0x7c,
+  0xbe,0x57,0x19,0x32,0x7e,0x2a,0xd0,0xb8,0xba,0x29,0x00,0x3c,0x52,0x7d,0xa8,0x49,
+  0x3b,0x2d,0xeb,0x25,0x49,0xfa,0xa3,0xaa,0x39,0xa7,0xc5,0xa7,0x50,0x11,0x36,0xfb,
+  0xc6,0x67,0x4a,0xf5,0xa5,0x12,0x65,0x7e,0xb0,0xdf,0xaf,0x4e,0xb3,0x61,0x7f,0x2f
+];
+
+/// Information from the Nikon makernotes, including the encrypted ColorBalance and LensData
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NikonMakernote {
+  /// camera serial number as a string
+  pub serial: Option<String>,
+  /// number of shutter actuations when the image was taken
+  pub shutter_count: Option<u32>,
+  /// version of the ColorBalance data (e.g., 0x0204 for "0204")
+  pub color_balance_version: Option<u16>,
+  /// as shot white balance multipliers
+  pub wb_as_shot: Option<[f32;4]>,
+  /// version of the LensData (e.g., 0x0204 for "0204")
+  pub lens_data_version: Option<u16>,
+  /// Nikon lens ID number
+  pub lens_id_number: Option<u8>,
+  /// number of f-stops the lens can close down
+  pub lens_fstops: Option<f32>,
+  /// shortest focal length of the lens in mm
+  pub min_focal_length: Option<f32>,
+  /// longest focal length of the lens in mm
+  pub max_focal_length: Option<f32>,
+  /// maximum aperture (as an f-number) at the shortest focal length
+  pub max_aperture_at_min_focal: Option<f32>,
+  /// maximum aperture (as an f-number) at the longest focal length
+  pub max_aperture_at_max_focal: Option<f32>,
+  /// lens microcontroller version
+  pub mcu_version: Option<u8>,
+  /// focal length the image was taken at in mm
+  pub focal_length: Option<f32>,
+  /// distance the lens was focused at in meters
+  pub focus_distance: Option<f32>,
+  /// aperture the lens was set to for autofocus as an f-number
+  pub af_aperture: Option<f32>,
+  /// effective maximum aperture of the lens at the focal length used as an f-number
+  pub effective_max_aperture: Option<f32>,
+  /// autofocus area from AFInfo2, when the camera records its position
+  pub af_points: Vec<AfPoint>,
+  /// shooting mode bits (continuous, delay, PC control, self-timer, exposure bracketing, ...)
+  pub shooting_mode: Option<u16>,
+  /// exposure bracketing the image is part of
+  pub bracketing: Option<Bracketing>,
+  /// image area code from CropHiSpeed (0 = off, 1 = 1.3x, 2 = DX, 3 = 5:4, 6 = 16:9, 17 = 1:1, ...)
+  pub crop_mode: Option<u16>,
+  /// size of the full sensor image the crop is from, as width and height
+  pub crop_sensor_size: Option<[u32;2]>,
+  /// area of the crop as left, top, width and height
+  pub crop: Option<[u32;4]>,
+}
+
+// The layouts of the LensData versions as offsets of ExitPupilPosition, AFAperture,
+// FocusDistance, FocalLength, LensIDNumber, LensFStops, MinFocalLength, MaxFocalLength,
+// MaxApertureAtMinFocal, MaxApertureAtMaxFocal, MCUVersion and EffectiveMaxAperture
+const LENSDATA_V1: [Option<usize>;12] = [None, None, None, None, Some(0x06), Some(0x07), Some(0x08),
+                                         Some(0x09), Some(0x0a), Some(0x0b), Some(0x0c), None];
+const LENSDATA_V101: [Option<usize>;12] = [Some(0x04), Some(0x05), Some(0x09), Some(0x0a), Some(0x0b),
+                                           Some(0x0c), Some(0x0d), Some(0x0e), Some(0x0f), Some(0x10),
+                                           Some(0x11), Some(0x12)];
+const LENSDATA_V204: [Option<usize>;12] = [Some(0x04), Some(0x05), Some(0x0a), Some(0x0b), Some(0x0c),
+                                           Some(0x0d), Some(0x0e), Some(0x0f), Some(0x10), Some(0x11),
+                                           Some(0x12), Some(0x13)];
+
+impl NikonMakernote {
+  pub(crate) fn from_tiff(tiff: &TiffIFD) -> Option<NikonMakernote> {
+    let mut mn = NikonMakernote {
+      serial: get_string(tiff, Tag::NefSerial),
+      shutter_count: tiff.find_entry(Tag::NefKey).filter(|e| e.typ() == 4 && e.count() > 0).map(|e| e.get_u32(0)),
+      shooting_mode: tiff.find_entry(Tag::NefShootingMode).filter(|e| e.typ() == 3 && e.count() > 0).map(|e| e.get_u16(0)),
+      ..Default::default()
+    };
+
+    if mn.shooting_mode.map(|mode| mode & 0x10 != 0).unwrap_or(false) {
+      mn.bracketing = Some(Bracketing {
+        exposure_offset: tiff.find_entry(Tag::NefBracketValue).filter(|e| e.typ() == 10 && e.count() > 0)
+          .map(|e| e.get_f32(0)).filter(|v| v.is_finite()),
+        ..Default::default()
+      });
+    }
+
+    if let Some(crop) = tiff.find_entry(Tag::NefCropHiSpeed).filter(|e| e.typ() == 3 && e.count() >= 7) {
+      let get = |idx: usize| crop.get_u16(idx) as u32;
+      mn.crop_mode = Some(crop.get_u16(0));
+      mn.crop_sensor_size = Some([get(1), get(2)]);
+      if get(3) > 0 && get(4) > 0 {
+        mn.crop = Some([get(5), get(6), get(3), get(4)]);
+      }
+    }
+
+    if let Some(levels) = tiff.find_entry(Tag::NefWB1) {
+      mn.parse_color_balance(levels, tiff);
+    }
+    if let Some(lens) = tiff.find_entry(Tag::NefLensData) {
+      mn.parse_lens_data(lens, tiff);
+    }
+    if let Some(ifd) = tiff.find_first_ifd(Tag::NefAFInfo2) {
+      if let Some(af) = ifd.find_entry(Tag::NefAFInfo2) {
+        mn.parse_af_info(af.get_data(), ifd.get_endian());
+      }
+    }
+
+    if mn == NikonMakernote::default() { None } else { Some(mn) }
+  }
+
+  fn parse_color_balance(&mut self, levels: &TiffEntry, tiff: &TiffIFD) {
+    let data = levels.get_data();
+    let version = match version(data) {
+      Some(v) => v,
+      None => return,
+    };
+    self.color_balance_version = Some(version);
+    let get = |idx: usize| levels.get_force_u16(idx) as f32;
+
+    self.wb_as_shot = match version {
+      0x100 if data.len() >= 80 => Some([get(36), get(38), get(37), f32::NAN]),
+      0x102 if data.len() >= 18 => Some([get(5), get(6), get(8), f32::NAN]),
+      0x103 if data.len() >= 26 => Some([get(10), get(11), get(12), f32::NAN]),
+      0x200..=0x216 => {
+        // Where the levels are in the decrypted data, from dcraw, with odd values meaning
+        // they are GRBG instead of RGGB. They're in the byte order of the makernote, like
+        // the rest of it
+        let pos = b"66666>666;6A;:;55"[decimal(version) - 200] - b'0';
+        let start = if version == 0x205 { 4 } else { 284 };
+        match decrypt(tiff, data, start, 324) {
+          Some(ref buf) if buf.len() >= (pos & !1) as usize + 8 => {
+            let off = (pos & !1) as usize;
+            let get = |idx: usize| levels.get_endian().ru16(buf, off+idx*2) as f32;
+            if pos & 1 == 0 {
+              Some([get(0), get(1), get(3), f32::NAN])
+            } else {
+              Some([get(1), get(0), get(2), f32::NAN])
+            }
+          },
+          _ => None,
+        }
+      },
+      _ => None,
+    };
+  }
+
+  // Only the area used by contrast detect AF in live view on DSLRs and by the mirrorless bodies
+  // is stored as coordinates, the phase detect points would need the layout of each AF module
+  fn parse_af_info(&mut self, data: &[u8], endian: Endian) {
+    let off = match version(data) {
+      Some(0x100) | Some(0x101) if data.len() > 0x1c && data[4] != 0 => 0x10,
+      Some(0x400) if data.len() > 0x4a => 0x3e,
+      _ => return,
+    };
+    let get = |idx: usize| endian.ru16(data, off + idx*2) as f32;
+    let (width, height) = (get(0), get(1));
+    let (x, y, w, h) = (get(2), get(3), get(4), get(5));
+    if width > 0.0 && height > 0.0 && w > 0.0 && h > 0.0 {
+      self.af_points.push(AfPoint {
+        x: x / width,
+        y: y / height,
+        width: w / width,
+        height: h / height,
+        selected: true,
+        in_focus: data[off + 12] == 1,
+      });
+    }
+  }
+
+  fn parse_lens_data(&mut self, lens: &TiffEntry, tiff: &TiffIFD) {
+    let data = lens.get_data();
+    let version = match version(data) {
+      Some(v) => v,
+      None => return,
+    };
+    self.lens_data_version = Some(version);
+
+    let (layout, decrypted) = match version {
+      0x100 => (&LENSDATA_V1, None),
+      0x101 => (&LENSDATA_V101, None),
+      0x201..=0x203 => (&LENSDATA_V101, decrypt(tiff, data, 4, data.len()-4)),
+      0x204 => (&LENSDATA_V204, decrypt(tiff, data, 4, data.len()-4)),
+      _ => return,
+    };
+    let buf = match decrypted {
+      Some(ref buf) => &buf[..],
+      None if version < 0x200 => &data[4..],
+      None => return,
+    };
+
+    // Offsets are from the start of the tag, including the 4 version bytes
+    let get = |idx: usize| layout[idx].and_then(|off| buf.get(off - 4)).cloned();
+    let focal = |v: u8| 5.0 * 2f32.powf(v as f32 / 24.0);
+    let aperture = |v: u8| 2f32.powf(v as f32 / 24.0);
+    let nonzero = |v: Option<u8>| v.filter(|&v| v != 0);
+
+    self.af_aperture = nonzero(get(1)).map(aperture);
+    self.focus_distance = nonzero(get(2)).map(|v| 0.01 * 10f32.powf(v as f32 / 40.0));
+    self.focal_length = nonzero(get(3)).map(focal);
+    self.lens_id_number = get(4);
+    self.lens_fstops = get(5).map(|v| v as f32 / 12.0);
+    self.min_focal_length = nonzero(get(6)).map(focal);
+    self.max_focal_length = nonzero(get(7)).map(focal);
+    self.max_aperture_at_min_focal = nonzero(get(8)).map(aperture);
+    self.max_aperture_at_max_focal = nonzero(get(9)).map(aperture);
+    self.mcu_version = get(10);
+    self.effective_max_aperture = nonzero(get(11)).map(aperture);
+  }
+}
+
+// The versions are 4 ASCII digits that we keep as nibbles so "0204" is 0x204
+fn version(data: &[u8]) -> Option<u16> {
+  if data.len() < 4 || !data[0..4].iter().all(|c| c.is_ascii_digit()) {
+    return None
+  }
+  Some(data[0..4].iter().fold(0, |v, &c| (v << 4) + (c - b'0') as u16))
+}
+
+fn decimal(version: u16) -> usize {
+  (0..4).rev().fold(0, |v, i| v*10 + ((version >> (i*4)) & 0xf) as usize)
+}
+
+// Decrypt up to len bytes starting at start with the key derived from the serial number and
+// the shutter count as done by Nikon for the ColorBalance and LensData sections
+fn decrypt(tiff: &TiffIFD, data: &[u8], start: usize, len: usize) -> Option<Vec<u8>> {
+  let end = (start + len).min(data.len());
+  if start >= end {
+    return None
+  }
+  let serial = tiff.find_entry(Tag::NefSerial)?.get_data();
+  let key = tiff.find_entry(Tag::NefKey)?.get_data();
+  if key.len() < 4 {
+    return None
+  }
+
+  let mut serialno: usize = 0;
+  for &c in serial {
+    if c == 0 { break }
+    serialno = serialno.wrapping_mul(10).wrapping_add(if c.is_ascii_digit() {
+      (c-b'0') as usize
+    } else {
+      (c%10) as usize
+    });
+  }
+  let keyno = (key[0]^key[1]^key[2]^key[3]) as usize;
+
+  let ci = WB_SERIALMAP[serialno & 0xff] as u32;
+  let mut cj = WB_KEYMAP[keyno & 0xff] as u32;
+  let mut ck: u32 = 0x60;
+  Some(data[start..end].iter().map(|&b| {
+    cj = cj.wrapping_add(ci * ck);
+    ck += 1;
+    b ^ (cj as u8)
+  }).collect())
+}
@@ -0,0 +1,166 @@
+use crate::decoders::tiff::*;
+use crate::decoders::exif::entry_string;
+use crate::decoders::makernotes::{Bracketing, CameraLevel, WbIlluminant, WbPreset};
+
+// The fixed temperature WB presets in ImageProcessing as pairs of R and B levels
+const KELVIN_PRESETS: [(u16, u32);12] = [
+  (0x0113, 3000), (0x0114, 3300), (0x0115, 3600), (0x0116, 3900), (0x0117, 4000), (0x0118, 4300),
+  (0x0119, 4500), (0x011a, 4800), (0x011b, 5300), (0x011c, 6000), (0x011d, 6600), (0x011e, 7500),
+];
+
+/// Information from the Olympus/OM System makernotes (Equipment, CameraSettings and
+/// ImageProcessing sections)
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OlympusMakernote {
+  /// camera serial number
+  pub serial: Option<String>,
+  /// lens type as the make, model and sub-model bytes packed into (make << 16 | model << 8 | sub)
+  pub lens_id: Option<u32>,
+  /// lens model as a string
+  pub lens_model: Option<String>,
+  /// lens serial number
+  pub lens_serial: Option<String>,
+  /// shortest focal length of the lens in mm
+  pub min_focal_length: Option<f32>,
+  /// longest focal length of the lens in mm
+  pub max_focal_length: Option<f32>,
+  /// maximum aperture (as an f-number) at the shortest focal length
+  pub max_aperture_at_min_focal: Option<f32>,
+  /// maximum aperture (as an f-number) at the longest focal length
+  pub max_aperture_at_max_focal: Option<f32>,
+  /// exposure mode code (1 = manual, 2 = program, 3 = aperture priority, 4 = shutter priority)
+  pub exposure_mode: Option<u16>,
+  /// focus mode code
+  pub focus_mode: Option<u16>,
+  /// white balance mode code
+  pub white_balance: Option<u16>,
+  /// white balance temperature in Kelvin when set manually
+  pub white_balance_temperature: Option<u32>,
+  /// drive mode code (0 = single, 2 = continuous, 3 = exposure bracketing, 4 = WB bracketing,
+  /// 5 = exposure and WB bracketing)
+  pub drive_mode: Option<u16>,
+  /// bracketing the image is part of
+  pub bracketing: Option<Bracketing>,
+  /// area of the sensor recorded as the image, as left, top, width and height
+  pub crop: Option<[u32;4]>,
+  /// aspect ratio the camera was set to as the code pair Olympus uses (e.g., (1,1) for 4:3,
+  /// (2,2) for 3:2, (3,3) for 16:9 and (4,4) for 6:6)
+  pub aspect_ratio: Option<(u8,u8)>,
+  /// area of the image that corresponds to the selected aspect ratio, as left, top, right and
+  /// bottom
+  pub aspect_frame: Option<[u16;4]>,
+  /// as shot white balance multipliers
+  pub wb_as_shot: Option<[f32;4]>,
+  /// white balance presets from ImageProcessing
+  pub wb_presets: Vec<WbPreset>,
+  /// sensor temperature in degrees Celsius
+  pub sensor_temperature: Option<i16>,
+  /// distortion correction parameters from ImageProcessing, as stored in the file
+  pub distortion_params: Option<[i16;4]>,
+  /// chromatic aberration correction parameters from ImageProcessing, as stored in the file
+  pub ca_params: Option<[i16;6]>,
+  /// roll and pitch from CameraSettings
+  pub level: Option<CameraLevel>,
+}
+
+impl OlympusMakernote {
+  pub(crate) fn from_tiff(tiff: &TiffIFD) -> Option<OlympusMakernote> {
+    let mut mn = OlympusMakernote::default();
+
+    // The temperature is in the main makernote IFD, some bodies write large values that aren't
+    // degrees so only keep the plausible ones
+    if let Some(main) = tiff.find_private_ifd(Tag::Makernote) {
+      mn.sensor_temperature = main.get_entry_raw(0x1500).filter(|e| e.typ() == 8 && e.count() > 0)
+        .map(|e| e.get_u16(0) as i16).filter(|&t| t > -40 && t < 80 && t != 0);
+    }
+
+    if let Some(equipment) = tiff.find_private_ifd(Tag::OlympusEquipment) {
+      let string = |tag: u16| equipment.get_entry_raw(tag).filter(|e| e.typ() == 2).and_then(|e| entry_string(e));
+      mn.serial = string(0x0101);
+      mn.lens_model = string(0x0203);
+      mn.lens_serial = string(0x0202);
+      if let Some(lens) = equipment.get_entry_raw(0x0201).filter(|e| e.count() >= 6) {
+        let d = lens.get_data();
+        let id = (d[0] as u32) << 16 | (d[2] as u32) << 8 | d[3] as u32;
+        if id != 0 {
+          mn.lens_id = Some(id);
+        }
+      }
+      let short = |tag: u16| short(equipment, tag, 0).filter(|&v| v != 0);
+      let aperture = |v: u16| 2f32.powf(v as f32 / 512.0);
+      mn.max_aperture_at_min_focal = short(0x0205).map(aperture);
+      mn.max_aperture_at_max_focal = short(0x0206).map(aperture);
+      mn.min_focal_length = short(0x0207).map(|v| v as f32);
+      mn.max_focal_length = short(0x0208).map(|v| v as f32);
+    }
+
+    if let Some(settings) = tiff.find_private_ifd(Tag::OlympusSettings) {
+      mn.exposure_mode = short(settings, 0x0200, 0);
+      mn.focus_mode = short(settings, 0x0301, 0);
+      mn.white_balance = short(settings, 0x0500, 0);
+      mn.white_balance_temperature = short(settings, 0x0501, 0).filter(|&v| v != 0).map(|v| v as u32);
+      mn.drive_mode = short(settings, 0x0600, 0);
+      // Tenths of a degree with the roll counterclockwise, each followed by 1 when the level
+      // had a valid reading
+      let angle = |tag: u16| signed(settings, tag, 0).filter(|_| signed(settings, tag, 1) == Some(1)).map(|v| v as f32 / 10.0);
+      if let (Some(roll), Some(pitch)) = (angle(0x0903), angle(0x0904)) {
+        mn.level = Some(CameraLevel { roll: -roll, pitch });
+      }
+      if let Some(3..=5) = mn.drive_mode {
+        mn.bracketing = Some(Bracketing {
+          position: short(settings, 0x0600, 1).filter(|&v| v > 0).map(|v| v as u32),
+          ..Default::default()
+        });
+      }
+    }
+
+    if let Some(imgproc) = tiff.find_private_ifd(Tag::OlympusImgProc) {
+      let rb = |tag: u16| -> Option<[f32;4]> {
+        match (short(imgproc, tag, 0), short(imgproc, tag, 1)) {
+          (Some(r), Some(b)) if r > 0 && b > 0 => Some([r as f32, 256.0, b as f32, f32::NAN]),
+          _ => None,
+        }
+      };
+      mn.wb_as_shot = rb(0x0100);
+      mn.wb_presets = KELVIN_PRESETS.iter().filter_map(|&(tag, temp)| {
+        rb(tag).map(|coeffs| WbPreset { illuminant: WbIlluminant::Kelvin, coeffs, temperature: Some(temp) })
+      }).collect();
+
+      let crop = [0x0612, 0x0613, 0x0614, 0x0615].iter().map(|&tag| short(imgproc, tag, 0)).collect::<Option<Vec<u16>>>();
+      if let Some(crop) = crop {
+        if crop[2] > 0 && crop[3] > 0 {
+          mn.crop = Some([crop[0] as u32, crop[1] as u32, crop[2] as u32, crop[3] as u32]);
+        }
+      }
+      if let Some(ratio) = imgproc.get_entry_raw(0x1112).filter(|e| e.typ() == 1 && e.count() >= 2) {
+        mn.aspect_ratio = Some((ratio.get_data()[0], ratio.get_data()[1]));
+      }
+      if let Some(frame) = (0..4).map(|i| short(imgproc, 0x1113, i)).collect::<Option<Vec<u16>>>() {
+        mn.aspect_frame = Some([frame[0], frame[1], frame[2], frame[3]]);
+      }
+      if let Some(dist) = (0..4).map(|i| signed(imgproc, 0x150a, i)).collect::<Option<Vec<i16>>>() {
+        mn.distortion_params = Some([dist[0], dist[1], dist[2], dist[3]]);
+      }
+      if let Some(ca) = (0..6).map(|i| signed(imgproc, 0x150c, i)).collect::<Option<Vec<i16>>>() {
+        mn.ca_params = Some([ca[0], ca[1], ca[2], ca[3], ca[4], ca[5]]);
+      }
+    }
+
+    if mn == OlympusMakernote::default() { None } else { Some(mn) }
+  }
+}
+
+fn short(ifd: &TiffIFD, tag: u16, idx: usize) -> Option<u16> {
+  match ifd.get_entry_raw(tag) {
+    Some(e) if e.typ() == 3 && e.count() > idx => Some(e.get_u16(idx)),
+    _ => None,
+  }
+}
+
+fn signed(ifd: &TiffIFD, tag: u16, idx: usize) -> Option<i16> {
+  match ifd.get_entry_raw(tag) {
+    Some(e) if (e.typ() == 3 || e.typ() == 8) && e.count() > idx => Some(e.get_u16(idx) as i16),
+    _ => None,
+  }
+}
@@ -0,0 +1,106 @@
+use crate::decoders::tiff::*;
+use crate::decoders::exif::entry_string;
+use crate::decoders::makernotes::{WbIlluminant, WbPreset};
+
+/// Distortion correction parameters Lumix bodies store in the RW2 DistortionInfo tag
+///
+/// Panasonic doesn't document these, they are kept as found in the file with the usual
+/// scaling to floats applied.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PanasonicDistortion {
+  /// if the camera applied the correction to its own JPEGs
+  pub enabled: bool,
+  /// scale factor to apply after correcting
+  pub scale: f32,
+  /// the polynomial parameters at positions 2, 4, 8, 9 and 11 of the tag
+  pub params: [f32;5],
+  /// number of terms used in the correction
+  pub n: i16,
+}
+
+/// Information from the Panasonic RW2 raw IFD and the makernote of its embedded JPEG
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PanasonicMakernote {
+  /// lens model as a string
+  pub lens_model: Option<String>,
+  /// lens serial number
+  pub lens_serial: Option<String>,
+  /// camera serial number
+  pub serial: Option<String>,
+  /// white balance mode code
+  pub white_balance: Option<u16>,
+  /// color temperature in Kelvin the camera used
+  pub color_temperature: Option<u32>,
+  /// focus mode code
+  pub focus_mode: Option<u16>,
+  /// image stabilization mode code
+  pub image_stabilization: Option<u16>,
+  /// white balance presets from the WBInfo2 tag
+  pub wb_presets: Vec<WbPreset>,
+  /// distortion correction parameters
+  pub distortion: Option<PanasonicDistortion>,
+  /// rotation as a TIFF orientation code
+  pub rotation: Option<u16>,
+}
+
+impl PanasonicMakernote {
+  pub(crate) fn from_tiff(tiff: &TiffIFD) -> Option<PanasonicMakernote> {
+    let mut mn = PanasonicMakernote::default();
+
+    // WBInfo2 is a count followed by sets of light source, R, G and B
+    if let Some(wb) = tiff.find_entry(Tag::PanaWBInfo2).filter(|e| e.typ() == 3 && e.count() > 0) {
+      let num = (wb.get_u16(0) as usize).min((wb.count()-1) / 4);
+      for i in 0..num {
+        let vals: Vec<u16> = (0..4).map(|j| wb.get_u16(1+i*4+j)).collect();
+        if vals[1] > 0 && vals[2] > 0 && vals[3] > 0 {
+          mn.wb_presets.push(WbPreset {
+            illuminant: WbIlluminant::from_light_source(vals[0]),
+            coeffs: [vals[1] as f32, vals[2] as f32, vals[3] as f32, f32::NAN],
+            temperature: None,
+          });
+        }
+      }
+    }
+
+    if let Some(dist) = tiff.find_entry(Tag::PanaDistortion).filter(|e| e.get_data().len() >= 26) {
+      let val = |idx: usize| dist.get_force_u16(idx) as i16;
+      let param = |idx: usize| val(idx) as f32 / 32768.0;
+      mn.distortion = Some(PanasonicDistortion {
+        enabled: val(7) & 0x0f == 1,
+        scale: 1.0 / (1.0 + param(5)),
+        params: [param(2), param(4), param(8), param(9), param(11)],
+        n: val(12),
+      });
+    }
+
+    // The lens and camera details are in the makernote of the EXIF in the embedded JPEG
+    if let Some(jpeg) = tiff.find_entry(Tag::PanaJpgFromRaw) {
+      let data = jpeg.get_data();
+      if let Some(pos) = data.windows(6).position(|w| w == b"Exif\0\0") {
+        if let Ok(exif) = TiffIFD::new_root(&data[pos+6..], 0) {
+          if let Some(makernote) = exif.find_private_ifd(Tag::Makernote) {
+            mn.parse_makernote(makernote);
+          }
+        }
+      }
+    }
+
+    if mn == PanasonicMakernote::default() { None } else { Some(mn) }
+  }
+
+  fn parse_makernote(&mut self, ifd: &TiffIFD) {
+    let string = |tag: u16| ifd.get_entry_raw(tag).filter(|e| e.typ() == 2).and_then(|e| entry_string(e));
+    let short = |tag: u16| ifd.get_entry_raw(tag).filter(|e| e.typ() == 3 && e.count() > 0).map(|e| e.get_u16(0));
+
+    self.lens_model = string(0x0051);
+    self.lens_serial = string(0x0052);
+    self.serial = ifd.get_entry_raw(0x0025).and_then(|e| entry_string(e));
+    self.white_balance = short(0x0003);
+    self.color_temperature = short(0x0044).filter(|&v| v > 0).map(|v| v as u32);
+    self.focus_mode = short(0x0007);
+    self.image_stabilization = short(0x001a);
+    self.rotation = short(0x0030);
+  }
+}
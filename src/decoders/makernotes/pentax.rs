@@ -0,0 +1,154 @@
+use crate::decoders::tiff::*;
+use crate::decoders::basics::*;
+use crate::decoders::exif::entry_string;
+use crate::decoders::makernotes::{Bracketing, CameraLevel, WbIlluminant, WbPreset};
+
+// The WB presets as RGGB levels
+const WB_PRESETS: [(u16, WbIlluminant);8] = [
+  (0x020d, WbIlluminant::Daylight),
+  (0x020e, WbIlluminant::Shade),
+  (0x020f, WbIlluminant::Cloudy),
+  (0x0210, WbIlluminant::Tungsten),
+  (0x0211, WbIlluminant::Fluorescent), // daylight fluorescent
+  (0x0212, WbIlluminant::Fluorescent), // neutral white fluorescent
+  (0x0213, WbIlluminant::Fluorescent), // cool white fluorescent
+  (0x0214, WbIlluminant::Flash),
+];
+
+/// Information from the Pentax makernotes, found in the EXIF of PEF files and in the
+/// DNGPrivateData of the DNGs Pentax cameras write
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PentaxMakernote {
+  /// camera serial number
+  pub serial: Option<String>,
+  /// number of shutter actuations when the image was taken, decrypted from the shot date
+  pub shutter_count: Option<u32>,
+  /// lens type as (lens series << 8 | lens number)
+  pub lens_id: Option<u32>,
+  /// if shake reduction was switched on
+  pub shake_reduction: Option<bool>,
+  /// if shake reduction managed to stabilize the image
+  pub sr_stabilized: Option<bool>,
+  /// focal length in mm shake reduction was using, for lenses that don't report it
+  pub sr_focal_length: Option<f32>,
+  /// drive mode as the four codes Pentax uses for the frame mode, self-timer, shutter
+  /// release and exposure mode
+  pub drive_mode: Option<[u8;4]>,
+  /// auto bracketing the image is part of
+  pub bracketing: Option<Bracketing>,
+  /// camera temperature in degrees Celsius
+  pub camera_temperature: Option<i8>,
+  /// white balance mode code
+  pub white_balance: Option<u16>,
+  /// as shot white balance multipliers
+  pub wb_as_shot: Option<[f32;4]>,
+  /// white balance presets
+  pub wb_presets: Vec<WbPreset>,
+  /// roll and pitch from LevelInfo
+  pub level: Option<CameraLevel>,
+  /// orientation code from LevelInfo (1 = horizontal, 2 = rotate 180, 3 = rotate 90 CW,
+  /// 4 = rotate 270 CW, plus 8 when the camera wasn't level)
+  pub level_orientation: Option<u8>,
+}
+
+impl PentaxMakernote {
+  pub(crate) fn from_tiff(tiff: &TiffIFD) -> Option<PentaxMakernote> {
+    if let Some(ifd) = tiff.find_private_ifd(Tag::Makernote) {
+      return Self::from_ifd(ifd)
+    }
+
+    // In-camera DNGs keep the makernote in the DNGPrivateData instead of the EXIF
+    if let Some(private) = tiff.find_entry(Tag::DNGPrivateArea) {
+      let data = private.get_data();
+      if data.len() > 10 && data[0..7] == b"PENTAX "[..] {
+        if let Ok(mn) = TiffIFD::new_makernote(data, 0, 0, 0, BIG_ENDIAN) {
+          if let Some(ifd) = mn.find_private_ifd(Tag::Makernote) {
+            return Self::from_ifd(ifd)
+          }
+        }
+      }
+    }
+
+    None
+  }
+
+  fn from_ifd(ifd: &TiffIFD) -> Option<PentaxMakernote> {
+    let mut mn = PentaxMakernote::default();
+    let raw = |tag: u16, len: usize| ifd.get_entry_raw(tag).map(|e| e.get_data()).filter(|d| d.len() >= len);
+    let rggb = |tag: u16| -> Option<[f32;4]> {
+      match ifd.get_entry_raw(tag) {
+        Some(e) if e.typ() == 3 && e.count() >= 4 => {
+          let v: Vec<u16> = (0..4).map(|i| e.get_u16(i)).collect();
+          if v[0] > 0 && v[1] > 0 && v[3] > 0 {
+            Some([v[0] as f32, v[1] as f32, v[3] as f32, f32::NAN])
+          } else { None }
+        },
+        _ => None,
+      }
+    };
+
+    mn.serial = ifd.get_entry_raw(0x0229).filter(|e| e.typ() == 2).and_then(|e| entry_string(e));
+
+    // The shutter count is encrypted with the date and time of the shot
+    if let (Some(count), Some(date), Some(time)) = (raw(0x005d, 4), raw(0x0006, 4), raw(0x0007, 3)) {
+      let time = (time[0] as u32) << 24 | (time[1] as u32) << 16 | (time[2] as u32) << 8;
+      let count = BEu32(count, 0) ^ BEu32(date, 0) ^ !time;
+      if count > 0 {
+        mn.shutter_count = Some(count);
+      }
+    }
+
+    if let Some(lens) = ifd.get_entry_raw(0x003f).filter(|e| e.typ() == 1 && e.count() >= 2) {
+      let d = lens.get_data();
+      let id = (d[0] as u32) << 8 | d[1] as u32;
+      if id != 0 {
+        mn.lens_id = Some(id);
+      }
+    }
+
+    // Newer bodies only store the result and the mode, older ones also the half press time and
+    // the focal length
+    if let Some(sr) = raw(0x005c, 2) {
+      mn.sr_stabilized = Some(sr[0] & 0x01 != 0);
+      mn.shake_reduction = Some(sr[1] & 0x01 != 0);
+      if sr.len() == 4 && sr[3] != 0 {
+        mn.sr_focal_length = Some(if sr[3] & 0x01 != 0 { sr[3] as f32 * 4.0 } else { sr[3] as f32 / 2.0 });
+      }
+    }
+
+    mn.camera_temperature = ifd.get_entry_raw(0x0047).filter(|e| e.typ() == 6 && e.count() > 0)
+      .map(|e| e.get_data()[0] as i8);
+
+    // Half degrees, both with the opposite sign of ours
+    if let Some(level) = raw(0x022b, 3) {
+      mn.level_orientation = Some(level[0] & 0x0f);
+      mn.level = Some(CameraLevel { roll: -(level[1] as i8 as f32) / 2.0, pitch: -(level[2] as i8 as f32) / 2.0 });
+    }
+
+    if let Some(drive) = raw(0x0034, 4) {
+      mn.drive_mode = Some([drive[0], drive[1], drive[2], drive[3]]);
+    }
+
+    // The step is in thirds of EV below 10 and in halves above
+    if let Some(bracket) = ifd.get_entry_raw(0x0018).filter(|e| e.typ() == 3 && e.count() >= 2) {
+      let frames = bracket.get_u16(1) as u32;
+      if frames > 1 {
+        let step = bracket.get_u16(0) as f32;
+        mn.bracketing = Some(Bracketing {
+          step: Some(if step < 10.0 { step / 3.0 } else { step - 9.5 }),
+          frames: Some(frames),
+          ..Default::default()
+        });
+      }
+    }
+
+    mn.white_balance = ifd.get_entry_raw(0x0019).filter(|e| e.typ() == 3 && e.count() > 0).map(|e| e.get_u16(0));
+    mn.wb_as_shot = rggb(0x0201);
+    mn.wb_presets = WB_PRESETS.iter().filter_map(|&(tag, illuminant)| {
+      rggb(tag).map(|coeffs| WbPreset { illuminant, coeffs, temperature: None })
+    }).collect();
+
+    if mn == PentaxMakernote::default() { None } else { Some(mn) }
+  }
+}
@@ -0,0 +1,144 @@
+use crate::decoders::tiff::*;
+use crate::decoders::basics::*;
+use crate::decoders::exif::get_string;
+use crate::decoders::makernotes::{AfPoint, WbIlluminant, WbPreset};
+
+// Bodies that use the newer layout of tag 0x9050, with the shutter count moved
+const TAG9050_NEW: [&str;25] = [
+  "ILCE-1", "ILCE-6100", "ILCE-6400", "ILCE-6600", "ILCE-6700", "ILCE-7C", "ILCE-7CM2", "ILCE-7CR",
+  "ILCE-7M3", "ILCE-7M4", "ILCE-7RM3", "ILCE-7RM3A", "ILCE-7RM4", "ILCE-7RM4A", "ILCE-7RM5",
+  "ILCE-7SM3", "ILCE-9", "ILCE-9M2", "DSC-RX10M4", "DSC-RX100M6", "DSC-RX100M7", "DSC-HX99",
+  "DSC-RX0M2", "ZV-E10", "ZV-1",
+];
+
+// The WB presets in the SR2SubIFD, the newer tags first and the ones used by older bodies after
+const SR2_PRESETS: [(u16, WbIlluminant, Option<u32>);13] = [
+  (0x7820, WbIlluminant::Daylight, None),
+  (0x7821, WbIlluminant::Cloudy, None),
+  (0x7822, WbIlluminant::Tungsten, None),
+  (0x7823, WbIlluminant::Flash, None),
+  (0x7824, WbIlluminant::Kelvin, Some(4500)),
+  (0x7825, WbIlluminant::Shade, None),
+  (0x7826, WbIlluminant::Fluorescent, None),
+  (0x7480, WbIlluminant::Daylight, None),
+  (0x7481, WbIlluminant::Cloudy, None),
+  (0x7482, WbIlluminant::Tungsten, None),
+  (0x7483, WbIlluminant::Flash, None),
+  (0x7484, WbIlluminant::Kelvin, Some(4500)),
+  (0x7486, WbIlluminant::Fluorescent, None),
+];
+
+/// Information from the Sony makernotes, including the enciphered 0x9050/0x9402 tags and the
+/// encrypted SR2SubIFD (0x7200) with the internal WB and calibration data
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SonyMakernote {
+  /// number of shutter actuations when the image was taken
+  pub shutter_count: Option<u32>,
+  /// temperature around the camera in degrees Celsius
+  pub ambient_temperature: Option<i8>,
+  /// focus position as recorded by the lens, larger values are further away but the scale
+  /// depends on the lens and focal length
+  pub focus_position: Option<u8>,
+  /// black levels in RGGB order
+  pub black_levels: Option<[u16;4]>,
+  /// white levels for R, G and B
+  pub white_levels: Option<[u16;3]>,
+  /// as shot white balance multipliers
+  pub wb_as_shot: Option<[f32;4]>,
+  /// white balance multipliers the camera's auto WB calculated
+  pub wb_auto: Option<[f32;4]>,
+  /// white balance presets stored in the SR2SubIFD
+  pub wb_presets: Vec<WbPreset>,
+  /// focus point from FocusLocation
+  pub af_points: Vec<AfPoint>,
+}
+
+impl SonyMakernote {
+  pub(crate) fn from_tiff(tiff: &TiffIFD) -> SonyMakernote {
+    let mut mn = SonyMakernote::default();
+    let model = get_string(tiff, Tag::Model).unwrap_or_default();
+
+    if let Some(entry) = tiff.find_entry(Tag::Sony9050) {
+      let data = decipher(entry.get_data());
+      let off = if TAG9050_NEW.contains(&model.as_ref()) { 0x3a } else { 0x32 };
+      if data.len() >= off + 4 {
+        let count = LEu32(&data, off) & 0x00ffffff;
+        if count > 0 {
+          mn.shutter_count = Some(count);
+        }
+      }
+    }
+
+    if let Some(entry) = tiff.find_entry(Tag::Sony9402) {
+      let data = decipher(entry.get_data());
+      // The temperature is only valid when this marker is set
+      if data.len() > 4 && data[2] == 255 {
+        mn.ambient_temperature = Some(data[4] as i8);
+      }
+      if data.len() > 0x2d && data[0x2d] != 0 {
+        mn.focus_position = Some(data[0x2d]);
+      }
+    }
+
+    // FocusLocation is the image size and the position of the focus point in it
+    if let Some(loc) = tiff.find_entry(Tag::SonyFocusLoc).filter(|e| e.typ() == 3 && e.count() >= 4) {
+      let get = |idx: usize| loc.get_u16(idx) as f32;
+      if get(0) > 0.0 && get(1) > 0.0 && (get(2) > 0.0 || get(3) > 0.0) {
+        mn.af_points.push(AfPoint {
+          x: get(2) / get(0),
+          y: get(3) / get(1),
+          width: 0.0,
+          height: 0.0,
+          selected: true,
+          in_focus: true,
+        });
+      }
+    }
+
+    mn
+  }
+
+  /// Read the WB and calibration data from the already decrypted SR2SubIFD
+  pub(crate) fn parse_sr2(&mut self, sr2: &TiffIFD) {
+    let shorts = |tag: u16, count: usize| -> Option<Vec<u16>> {
+      match sr2.get_entry_raw(tag) {
+        Some(e) if (e.typ() == 3 || e.typ() == 8) && e.count() >= count => {
+          Some((0..count).map(|i| e.get_force_u16(i)).collect())
+        },
+        _ => None,
+      }
+    };
+    let grbg = |v: Vec<u16>| [v[1] as f32, v[0] as f32, v[2] as f32, f32::NAN];
+    let rggb = |v: Vec<u16>| [v[0] as f32, v[1] as f32, v[3] as f32, f32::NAN];
+
+    self.black_levels = shorts(0x7310, 4).map(|v| [v[0], v[1], v[2], v[3]])
+      .or_else(|| shorts(0x7300, 4).map(|v| [v[1], v[0], v[3], v[2]]));
+    self.white_levels = shorts(0x787f, 3).map(|v| [v[0], v[1], v[2]]);
+    self.wb_as_shot = shorts(0x7303, 4).map(grbg).or_else(|| shorts(0x7313, 4).map(rggb));
+    self.wb_auto = shorts(0x7302, 4).map(grbg).or_else(|| shorts(0x7312, 4).map(rggb));
+
+    let mut presets: Vec<WbPreset> = Vec::new();
+    for &(tag, illuminant, temperature) in SR2_PRESETS.iter() {
+      if presets.iter().any(|p| p.illuminant == illuminant && p.temperature == temperature) {
+        continue
+      }
+      if let Some(v) = shorts(tag, 3) {
+        let v: Vec<f32> = v.iter().map(|&v| v as i16 as f32).collect();
+        if v.iter().all(|&v| v > 0.0) {
+          presets.push(WbPreset { illuminant, coeffs: [v[0], v[1], v[2], f32::NAN], temperature });
+        }
+      }
+    }
+    self.wb_presets = presets;
+  }
+}
+
+// Sony enciphers some tags by replacing each byte b < 249 by b^3 mod 249
+fn decipher(data: &[u8]) -> Vec<u8> {
+  let mut table: Vec<u8> = (0..=255).collect();
+  for b in 0..249u32 {
+    table[(b*b*b % 249) as usize] = b as u8;
+  }
+  data.iter().map(|&c| table[c as usize]).collect()
+}
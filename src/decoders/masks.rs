@@ -0,0 +1,36 @@
+/// A grayscale plane stored alongside the raw image, either a DNG 1.6 semantic mask (as in
+/// Apple ProRAW, with the areas of sky, skin, hair, etc.) or a DNG 1.5 depth map. The data is
+/// in the orientation of the raw image and usually at a lower resolution.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SemanticMask {
+  /// what the mask selects as given by the file (e.g., "Sky" or "Skin"), "Depth" for depth
+  /// maps
+  pub name: String,
+  /// identifies the instance when there are several masks for the same name, such as one
+  /// per person
+  pub instance_id: Option<String>,
+  /// width of the mask in pixels
+  pub width: usize,
+  /// height of the mask in pixels
+  pub height: usize,
+  /// part of the image the mask covers as top, left, bottom and right, the whole image when
+  /// not set
+  pub sub_area: Option<[u32;4]>,
+  /// distances the first and last depth values stand for, in the DepthUnits of the file
+  pub depth_range: Option<(f32, f32)>,
+  /// value that stands for a fully selected pixel or the farthest depth
+  pub whitelevel: u16,
+  /// the mask values in row major order
+  pub data: Vec<u16>,
+}
+
+impl SemanticMask {
+  /// Value of the mask at a pixel, from 0.0 to 1.0
+  pub fn get(&self, x: usize, y: usize) -> Option<f32> {
+    if x >= self.width || y >= self.height || self.whitelevel == 0 {
+      return None
+    }
+    self.data.get(y*self.width + x).map(|&v| v as f32 / self.whitelevel as f32)
+  }
+}
@@ -21,15 +21,19 @@ impl<'a> MefDecoder<'a> {
 }
 
 impl<'a> Decoder for MefDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "MEF"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let camera = self.rawloader.check_supported(&self.tiff)?;
     let raw = fetch_ifd!(&self.tiff, Tag::CFAPattern);
     let width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0);
     let height = fetch_tag!(raw, Tag::ImageLength).get_usize(0);
     let offset = fetch_tag!(raw, Tag::StripOffsets).get_usize(0);
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
 
-    let image = decode_12be(src, width, height, dummy);
+    let image = decode_12be(src, width, height, dummy)?;
     ok_image(camera, width, height, [NAN,NAN,NAN,NAN], image)
   }
 }
@@ -1,8 +1,17 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
-use std::io::{Read, BufReader, Seek, Cursor};
+use std::fmt;
+use std::io::{Read, BufReader, BufWriter, Write, Seek, Cursor};
 use std::fs::File;
 use std::panic;
-use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use rayon::prelude::*;
 use toml::Value;
 
 mod bmff;
@@ -10,16 +19,16 @@ use self::bmff::Bmff;
 
 macro_rules! fetch_tag {
   ($tiff:expr, $tag:expr) => (
-    $tiff.find_entry($tag).ok_or(
-      format!("Couldn't find tag {}",stringify!($tag)).to_string()
+    $tiff.find_entry($tag).ok_or_else(||
+      RawLoaderError::Corrupt(format!("Couldn't find tag {}",stringify!($tag)))
     )?
   );
 }
 
 macro_rules! fetch_ifd {
   ($tiff:expr, $tag:expr) => (
-    $tiff.find_first_ifd($tag).ok_or(
-      format!("Couldn't find ifd with tag {}",stringify!($tag)).to_string()
+    $tiff.find_first_ifd($tag).ok_or_else(||
+      RawLoaderError::Corrupt(format!("Couldn't find ifd with tag {}",stringify!($tag)))
     )?
   );
 }
@@ -27,49 +36,68 @@ macro_rules! fetch_ifd {
 macro_rules! alloc_image_plain {
   ($width:expr, $height:expr, $dummy: expr) => (
     {
-      if $width * $height > 500000000 || $width > 50000 || $height > 50000 {
-        panic!("rawloader: surely there's no such thing as a >500MP or >50000 px wide/tall image!");
-      }
+      $crate::decoders::check_size($width, $height)?;
       if $dummy {
         vec![0]
       } else {
-        vec![0; $width * $height]
+        $crate::decoders::take_spare($width.saturating_mul($height))?
       }
     }
   );
 }
 
-macro_rules! alloc_image {
+macro_rules! alloc_image_ok {
   ($width:expr, $height:expr, $dummy: expr) => (
     {
       let out = alloc_image_plain!($width, $height, $dummy);
       if $dummy {
-        return out
+        return Ok(out)
       }
       out
     }
   );
 }
 
-macro_rules! alloc_image_ok {
-  ($width:expr, $height:expr, $dummy: expr) => (
-    {
-      let out = alloc_image_plain!($width, $height, $dummy);
-      if $dummy {
-        return Ok(out)
-      }
-      out
+macro_rules! debug {
+  ($($arg:tt)*) => (
+    if $crate::decoders::verbosity() >= $crate::decoders::Verbosity::Debug {
+      eprintln!($($arg)*);
     }
   );
 }
 
+mod error;
+mod builder;
 mod image;
 mod basics;
-mod packed;
+pub mod packed;
 mod pumps;
-mod ljpeg;
+pub mod ljpeg;
 pub mod cfa;
-mod tiff;
+pub mod exif;
+pub mod lens;
+pub mod xmp;
+pub mod makernotes;
+pub mod opcodes;
+pub mod corrections;
+pub mod color;
+pub mod noise;
+pub mod dualpixel;
+pub mod masks;
+pub mod calibration;
+pub mod capabilities;
+pub mod thumbnail;
+pub mod rawref;
+pub mod validate;
+pub mod batch;
+#[cfg(feature = "serde")] mod json;
+mod develop;
+mod demosaic;
+#[cfg(feature = "ndarray")] pub mod arrays;
+mod icc;
+mod colortemp;
+pub mod tiff;
+pub mod writers;
 mod ciff;
 mod mrw;
 mod arw;
@@ -97,41 +125,152 @@ mod ari;
 mod x3f;
 use self::tiff::*;
 pub use self::image::*;
+pub use self::error::{RawLoaderError, DecodeWarning};
+pub use self::builder::*;
 mod unwrapped;
 
 pub static CAMERAS_TOML: &'static str = include_str!(concat!(env!("OUT_DIR"), "/all.toml"));
 pub static SAMPLE: &'static str = "\nPlease submit samples at https://raw.pixls.us/";
 pub static BUG: &'static str = "\nPlease file a bug with a sample file at https://github.com/pedrocr/rawloader/issues/new";
 
+/// A decoder for the files of a format, created by `RawLoader` for each file. Only `format()`
+/// and `image()` need to be implemented, see `RawLoader::register_decoder()` to add decoders
+/// for other formats.
 pub trait Decoder {
-  fn image(&self, dummy: bool) -> Result<RawImage, String>;
+  /// Name of the format, as in `rawloader::formats()` for the ones included in the library
+  fn format(&self) -> &'static str;
+
+  /// Decodes the main raw image of the file. When `dummy` is set only the metadata is needed
+  /// and the image data can be left out.
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError>;
+
+  /// Number of raw images in the file
+  fn image_count(&self) -> usize {
+    1
+  }
+
+  /// Decodes one of the raw images of the file, 0 being the one `image()` returns
+  fn image_at(&self, index: usize, dummy: bool) -> Result<RawImage, RawLoaderError> {
+    match index {
+      0 => self.image(dummy),
+      _ => Err(RawLoaderError::NoSuchImage { index, count: self.image_count() }),
+    }
+  }
+
+  /// Decodes both frames of a file from a sensor with dual gain readout
+  fn dual_gain(&self, _dummy: bool) -> Result<DualGainImage, RawLoaderError> {
+    Err(RawLoaderError::Unimplemented("This file doesn't have dual gain frames".to_string()))
+  }
+
+  /// Decodes the combined and split frames of a Dual Pixel raw
+  fn dual_pixel(&self, _dummy: bool) -> Result<dualpixel::DualPixelImage, RawLoaderError> {
+    Err(RawLoaderError::Unimplemented("This file doesn't have dual pixel frames".to_string()))
+  }
+}
+
+// The bytes of a Buffer, either read into memory, borrowed from the caller or mapped from a file
+enum BufferData<'a> {
+  Owned(Vec<u8>),
+  Borrowed(&'a [u8]),
+  #[cfg(all(feature = "mmap", unix))]
+  Mapped(memmap2::Mmap),
+}
+
+impl std::ops::Deref for BufferData<'_> {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self {
+      BufferData::Owned(data) => data,
+      BufferData::Borrowed(data) => data,
+      #[cfg(all(feature = "mmap", unix))]
+      BufferData::Mapped(map) => map,
+    }
+  }
+}
+
+impl Clone for BufferData<'_> {
+  fn clone(&self) -> Self {
+    match self {
+      BufferData::Borrowed(data) => BufferData::Borrowed(data),
+      _ => BufferData::Owned(self.to_vec()),
+    }
+  }
+}
+
+impl fmt::Debug for BufferData<'_> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      BufferData::Owned(data) => write!(f, "Owned({} bytes)", data.len()),
+      BufferData::Borrowed(data) => write!(f, "Borrowed({} bytes)", data.len()),
+      #[cfg(all(feature = "mmap", unix))]
+      BufferData::Mapped(map) => write!(f, "Mapped({} bytes)", map.len()),
+    }
+  }
 }
 
 /// Buffer to hold an image in memory with enough extra space at the end for speed optimizations
 #[derive(Debug, Clone)]
-pub struct Buffer {
-  buf: Vec<u8>,
+pub struct Buffer<'a> {
+  buf: BufferData<'a>,
   size: usize,
 }
 
-impl Buffer {
+impl<'a> Buffer<'a> {
   /// Creates a new buffer from anything that can be read
-  pub fn new(reader: &mut dyn Read) -> Result<Buffer, String> {
+  pub fn new(reader: &mut dyn Read) -> Result<Buffer<'a>, RawLoaderError> {
     let mut buffer = Vec::new();
-    if let Err(err) = reader.read_to_end(&mut buffer) {
-      return Err(format!("IOError: {}", err).to_string())
-    }
+    reader.read_to_end(&mut buffer)?;
     let size = buffer.len();
     buffer.extend([0;16].iter().cloned());
     Ok(Buffer {
-      buf: buffer,
+      buf: BufferData::Owned(buffer),
       size: size,
     })
   }
+
+  // Maps a file instead of reading it. The 16 bytes of padding come from the rest of the
+  // last page, which reads as zeros past the end of the file, so files that end too close
+  // to a page boundary aren't mapped and None is returned for them to be read instead.
+  #[cfg(all(feature = "mmap", unix))]
+  #[allow(unsafe_code)]
+  fn map(file: &File) -> Result<Option<Buffer<'a>>, RawLoaderError> {
+    const PAGE_SIZE: usize = 4096;
+    let size = file.metadata()?.len() as usize;
+    if size == 0 || size % PAGE_SIZE == 0 || PAGE_SIZE - size % PAGE_SIZE < 16 {
+      return Ok(None)
+    }
+    // Safety: the map is read only and lives as long as the Buffer. The file being
+    // modified while it's decoded is just as undefined as for any other mmap user.
+    let map = unsafe { memmap2::MmapOptions::new().len(size + 16).map(file)? };
+    Ok(Some(Buffer {
+      buf: BufferData::Mapped(map),
+      size,
+    }))
+  }
+
+  // Uses data as is, without the padding, which the pumps make up for by reading zeros past
+  // the end of it
+  fn borrowed(data: &'a [u8]) -> Buffer<'a> {
+    Buffer {
+      buf: BufferData::Borrowed(data),
+      size: data.len(),
+    }
+  }
+
+  fn into_vec(self) -> Vec<u8> {
+    match self.buf {
+      BufferData::Owned(data) => data,
+      BufferData::Borrowed(data) => data.to_vec(),
+      #[cfg(all(feature = "mmap", unix))]
+      BufferData::Mapped(map) => map.to_vec(),
+    }
+  }
 }
 
 /// Contains sanitized information about the raw image's properties
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Camera {
   pub make: String,
   pub model: String,
@@ -142,6 +281,7 @@ pub struct Camera {
   pub raw_width: usize,
   pub raw_height: usize,
   pub orientation: Orientation,
+  pub exif: exif::Exif,
   whitelevels: [u16;4],
   blacklevels: [u16;4],
   blackareah: (usize, usize),
@@ -192,7 +332,17 @@ impl Camera {
             self.crops[i] = val.as_integer().unwrap() as usize;
           }
         },
-        "color_pattern" => {self.cfa = cfa::CFA::new(&val.as_str().unwrap().to_string());},
+        "color_pattern" => {
+          let pattern = val.as_str().unwrap();
+          self.cfa = match ct.get("color_pattern_size") {
+            Some(size) => {
+              let dims = size.as_array().unwrap();
+              cfa::CFA::new_with_size(pattern, dims[0].as_integer().unwrap() as usize,
+                                               dims[1].as_integer().unwrap() as usize)
+            },
+            None => cfa::CFA::new(pattern),
+          };
+        },
         "bps" => {self.bps = val.as_integer().unwrap() as usize;},
         "wb_offset" => {self.wb_offset = val.as_integer().unwrap() as usize;},
         "filesize" => {self.filesize = val.as_integer().unwrap() as usize;},
@@ -232,6 +382,7 @@ impl Camera {
       highres_width: usize::max_value(),
       hints: Vec::new(),
       orientation: Orientation::Unknown,
+      exif: exif::Exif::default(),
     }
   }
 }
@@ -242,6 +393,7 @@ impl Camera {
 /// obtained from other metadata in the file.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 #[allow(missing_docs)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Orientation {
   Normal,
   HorizontalFlip,
@@ -298,6 +450,25 @@ impl Orientation {
     }
   }
 
+  // Position in the image with the orientation applied of the pixel at row and col of an
+  // image of width by height without it
+  pub(crate) fn oriented(&self, row: usize, col: usize, width: usize, height: usize) -> (usize, usize) {
+    let (transpose, hflip, vflip) = self.to_flips();
+    let row = if vflip { height - 1 - row } else { row };
+    let col = if hflip { width - 1 - col } else { col };
+    if transpose { (col, row) } else { (row, col) }
+  }
+
+  // Does the opposite of oriented(), with width and height still those of the image without
+  // the orientation applied
+  pub(crate) fn unoriented(&self, row: usize, col: usize, width: usize, height: usize) -> (usize, usize) {
+    let (transpose, hflip, vflip) = self.to_flips();
+    let (row, col) = if transpose { (col, row) } else { (row, col) };
+    let row = if vflip { height - 1 - row } else { row };
+    let col = if hflip { width - 1 - col } else { col };
+    (row, col)
+  }
+
   /// Does the opposite of to_flips()
   pub fn from_flips(flips: (bool, bool, bool)) -> Self {
     match flips {
@@ -328,31 +499,333 @@ impl Orientation {
   }
 }
 
-pub fn ok_image(camera: Camera, width: usize, height: usize, wb_coeffs: [f32;4], image: Vec<u16>) -> Result<RawImage,String> {
+pub fn ok_image(camera: Camera, width: usize, height: usize, wb_coeffs: [f32;4], image: Vec<u16>) -> Result<RawImage, RawLoaderError> {
   Ok(RawImage::new(camera, width, height, wb_coeffs, image, false))
 }
 
-pub fn ok_image_with_blacklevels(camera: Camera, width: usize, height: usize, wb_coeffs: [f32;4], blacks: [u16;4], image: Vec<u16>) -> Result<RawImage,String> {
+pub fn ok_image_with_blacklevels(camera: Camera, width: usize, height: usize, wb_coeffs: [f32;4], blacks: [u16;4], image: Vec<u16>) -> Result<RawImage, RawLoaderError> {
   let mut img = RawImage::new(camera, width, height, wb_coeffs, image, false);
   img.blacklevels = blacks;
   Ok(img)
 }
 
-pub fn ok_image_with_black_white(camera: Camera, width: usize, height: usize, wb_coeffs: [f32;4], black: u16, white: u16, image: Vec<u16>) -> Result<RawImage,String> {
+pub fn ok_image_with_black_white(camera: Camera, width: usize, height: usize, wb_coeffs: [f32;4], black: u16, white: u16, image: Vec<u16>) -> Result<RawImage, RawLoaderError> {
   let mut img = RawImage::new(camera, width, height, wb_coeffs, image, false);
   img.blacklevels = [black, black, black, black];
   img.whitelevels = [white, white, white, white];
   Ok(img)
 }
 
+/// Optional processing to be done to the image after it's been decoded
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+  /// interpolate over the defective pixels listed in the file's metadata
+  pub fix_bad_pixels: bool,
+  /// also look for stuck pixels statistically, adding them to `RawImage::bad_pixels` and
+  /// fixing them if `fix_bad_pixels` is set
+  pub detect_stuck_pixels: bool,
+  /// apply the processing the DNG opcode lists ask for (vignetting correction, bad pixels
+  /// and lens distortion), see `RawImage::apply_opcodes()`
+  pub apply_opcodes: bool,
+  /// apply the per unit sensor calibration of Phase One and Hasselblad backs (defects,
+  /// column blacks, linearization and flat fields), see `RawImage::apply_calibration()`
+  pub apply_calibration: bool,
+  /// rotate and flip the image so it's the right way up, see `RawImage::apply_orientation()`.
+  /// Done after all the other processing.
+  pub apply_orientation: bool,
+  /// when decoding a file also read its XMP sidecar if there is one, with its properties
+  /// taking precedence over the ones embedded in the file
+  pub xmp_sidecar: bool,
+  /// abort the decode with `RawLoaderError::Cancelled` once this is set to true, for
+  /// applications that no longer need an image that's being decoded. It's checked between
+  /// rows and between the processing steps.
+  pub cancel: Option<Arc<AtomicBool>>,
+  /// abort the decode with `RawLoaderError::TimedOut` if it takes longer than this, checked
+  /// the same way as `cancel`, to protect servers from files that take forever to decode.
+  /// Ignored on `wasm32-unknown-unknown`, which has no clock.
+  pub timeout: Option<Duration>,
+}
+
+thread_local! {
+  // Limit and verbosity of the RawLoader decoding in this thread, for the code that doesn't
+  // have access to it
+  static SETTINGS: Cell<(Limits, Verbosity, DecodeMode)> = const { Cell::new((Limits::DEFAULT, Verbosity::Quiet, DecodeMode::Lenient)) };
+  // Output of a previous decode_into() that the next image allocated can reuse
+  static SPARE: Cell<Vec<u16>> = const { Cell::new(Vec::new()) };
+  // Problems the decode running in this thread worked around
+  static WARNINGS: RefCell<Vec<DecodeWarning>> = const { RefCell::new(Vec::new()) };
+  // What aborts the decode running in this thread
+  static ABORT: RefCell<Abort> = const { RefCell::new(Abort { cancel: None, deadline: None }) };
+  // If the decode running in this thread leaves the rows that fail undecoded instead of failing
+  static RECOVER_ROWS: Cell<bool> = const { Cell::new(false) };
+  // Why the rows the decode running in this thread left undecoded failed
+  static FAILED_ROWS: RefCell<Option<RawLoaderError>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn limits() -> Limits {
+  SETTINGS.with(|s| s.get().0)
+}
+
+// Fails the decode if an image of this size, in values per row and rows, is over the limits.
+// It's called before the image is allocated.
+pub(crate) fn check_size(width: usize, height: usize) -> Result<(), RawLoaderError> {
+  let limits = limits();
+  let values = width.saturating_mul(height);
+  if values > limits.max_pixels || width > limits.max_width || height > limits.max_height ||
+     limits.max_output_bytes.is_some_and(|max| values.saturating_mul(2) > max) {
+    return Err(RawLoaderError::LimitExceeded(
+      format!("{}x{} image is over the size limit", width, height)
+    ))
+  }
+  Ok(())
+}
+
+pub(crate) fn verbosity() -> Verbosity {
+  SETTINGS.with(|s| s.get().1)
+}
+
+pub(crate) fn mode() -> DecodeMode {
+  SETTINGS.with(|s| s.get().2)
+}
+
+// Records a problem the decode running in this thread worked around, for the image it
+// returns, printing it if asked to
+pub(crate) fn warn(warning: DecodeWarning) {
+  if verbosity() >= Verbosity::Warnings {
+    eprintln!("rawloader: {}", warning);
+  }
+  WARNINGS.with(|w| {
+    let mut warnings = w.borrow_mut();
+    // The structure of a file can be parsed more than once
+    if !warnings.contains(&warning) {
+      warnings.push(warning);
+    }
+  });
+}
+
+fn take_warnings() -> Vec<DecodeWarning> {
+  WARNINGS.with(|w| w.take())
+}
+
+// Lenient decodes go on when rows of the image fail, as a file that's cut short makes them,
+// for the RawLoader to return the rows that did decode. Outside of a decode they still fail.
+pub(crate) fn recover_rows() -> bool {
+  RECOVER_ROWS.with(|r| r.get())
+}
+
+// Records that a lenient decode left rows of the image undecoded, keeping the first failure,
+// for the RawLoader to return the rest of the image if the file is cut short
+pub(crate) fn fail_rows(err: RawLoaderError) {
+  FAILED_ROWS.with(|f| {
+    f.borrow_mut().get_or_insert(err);
+  });
+}
+
+fn take_failed_rows() -> Option<RawLoaderError> {
+  FAILED_ROWS.with(|f| f.take())
+}
+
+// Where the data runs out in a TIFF based file that ends before the strips or tiles of one of
+// its images do, None if they're all there
+fn truncated_at(buffer: &Buffer) -> Option<usize> {
+  let tiff = TiffIFD::new_file(&buffer.buf[..buffer.size]).ok()?;
+  let chunks = [(Tag::StripOffsets, Tag::StripByteCounts), (Tag::TileOffsets, Tag::TileByteCounts)];
+  let cut = chunks.iter().any(|&(offsets, counts)| {
+    tiff.find_ifds_with_tag(offsets).iter().any(|ifd| {
+      match (ifd.find_entry(offsets), ifd.find_entry(counts)) {
+        (Some(offsets), Some(counts)) => (0..offsets.count().min(counts.count())).any(|i| {
+          offsets.get_usize(i).saturating_add(counts.get_usize(i)) > buffer.size
+        }),
+        _ => false,
+      }
+    })
+  });
+  cut.then_some(buffer.size)
+}
+
+// Handles a problem a decode can work around: a strict decode fails with it while a lenient
+// one records it as a warning and goes on
+pub(crate) fn tolerate(problem: String) -> Result<(), RawLoaderError> {
+  match mode() {
+    DecodeMode::Strict => Err(RawLoaderError::Corrupt(problem)),
+    DecodeMode::Lenient => {
+      warn(DecodeWarning::Tolerated(problem));
+      Ok(())
+    },
+  }
+}
+
+// The cancellation flag and the deadline of a decode
+#[derive(Debug, Clone)]
+pub(crate) struct Abort {
+  cancel: Option<Arc<AtomicBool>>,
+  deadline: Option<(Instant, Duration)>,
+}
+
+impl Abort {
+  fn new(options: &DecodeOptions) -> Abort {
+    Abort {
+      cancel: options.cancel.clone(),
+      // Browsers give no clock to check a deadline against
+      deadline: if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        None
+      } else {
+        options.timeout.map(|timeout| (Instant::now() + timeout, timeout))
+      },
+    }
+  }
+
+  // The decode running in this thread's
+  pub(crate) fn current() -> Abort {
+    ABORT.with(|a| a.borrow().clone())
+  }
+
+  // Why the decode has to stop, if it has to
+  pub(crate) fn reason(&self) -> Option<RawLoaderError> {
+    if self.cancel.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+      return Some(RawLoaderError::Cancelled)
+    }
+    match self.deadline {
+      Some((deadline, timeout)) if Instant::now() >= deadline => Some(RawLoaderError::TimedOut(timeout)),
+      _ => None,
+    }
+  }
+}
+
+// Fails the decode running in this thread if it was cancelled or is past its deadline
+pub(crate) fn check_abort() -> Result<(), RawLoaderError> {
+  match Abort::current().reason() {
+    Some(err) => Err(err),
+    None => Ok(()),
+  }
+}
+
+pub(crate) fn take_spare(size: usize) -> Result<Vec<u16>, RawLoaderError> {
+  let mut spare = SPARE.with(|s| s.take());
+  if spare.capacity() < size {
+    return alloc_vec(size, 0)
+  }
+  spare.clear();
+  spare.resize(size, 0);
+  Ok(spare)
+}
+
+// Aborts the decode running in this thread because the file is corrupt, for the decoders
+// that don't return a Result, unwinding to the RawLoader with the error
+#[cold]
+pub(crate) fn corrupt(msg: &str) -> ! {
+  panic::panic_any(RawLoaderError::Corrupt(msg.to_string()))
+}
+
+// Allocates the buffers whose size comes from the file without aborting the process when
+// there isn't enough memory for them, failing with an error instead
+pub(crate) fn alloc_vec<T: Clone>(size: usize, value: T) -> Result<Vec<T>, RawLoaderError> {
+  let mut out = Vec::new();
+  if out.try_reserve_exact(size).is_err() {
+    return Err(RawLoaderError::LimitExceeded(
+      format!("Couldn't allocate {} values for the image", size)
+    ))
+  }
+  out.resize(size, value);
+  Ok(out)
+}
+
+// Image data kept by a RawLoader between calls to decode_into() so they don't allocate
+#[derive(Clone, Default)]
+struct SpareData(Arc<Mutex<Vec<u16>>>);
+
+impl SpareData {
+  fn take(&self) -> Vec<u16> {
+    std::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+  }
+
+  fn put(&self, data: Vec<u16>) {
+    let mut spare = self.0.lock().unwrap_or_else(|e| e.into_inner());
+    if data.capacity() > spare.capacity() {
+      *spare = data;
+    }
+  }
+}
+
+impl fmt::Debug for SpareData {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "SpareData")
+  }
+}
+
+// Panics that carry a RawLoaderError (from corrupt()) are returned as is
+pub(crate) fn panic_error(payload: Box<dyn Any + Send>) -> RawLoaderError {
+  match payload.downcast::<RawLoaderError>() {
+    Ok(err) => *err,
+    Err(payload) => {
+      if cfg!(feature = "fuzzing") {
+        panic::resume_unwind(payload)
+      }
+      RawLoaderError::Corrupt(format!("Caught a panic while decoding.{}", BUG))
+    },
+  }
+}
+
+/// Selects what to decode from a file
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RawDecodeParams {
+  /// raw image to decode from files that have several, from 0 for the main one to
+  /// `RawImage::image_count` - 1
+  pub image_index: usize,
+}
+
+type SniffFn = dyn Fn(&[u8]) -> bool + Send + Sync;
+type CreateFn = dyn for<'b> Fn(&'b [u8]) -> Result<Box<dyn Decoder + 'b>, RawLoaderError> + Send + Sync;
+
+// A decoder from outside the library with the callback that recognizes its files
+#[derive(Clone)]
+struct ExternalDecoder {
+  sniff: Arc<SniffFn>,
+  create: Arc<CreateFn>,
+}
+
+impl fmt::Debug for ExternalDecoder {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "ExternalDecoder")
+  }
+}
+
+// Formats whose metadata is all found through TIFF entries, so a stream only needs to be
+// read until none of them point past what's been read
+const STREAMABLE: [&str; 16] = ["3FR", "ARW", "CR2", "DCR", "DCS", "DNG", "ERF", "KDC", "MEF",
+                                "MOS", "NEF", "NRW", "ORF", "PEF", "RW2", "SRW"];
+// How much of a stream is read at first, doubling each time it's not enough
+const STREAM_CHUNK: usize = 256 * 1024;
+
+enum StreamAttempt {
+  Done(Box<RawImage>),
+  ReadMore,
+  ReadAll,
+}
+
 /// The struct that holds all the info about the cameras and is able to decode a file
 #[derive(Debug, Clone)]
 pub struct RawLoader {
-  cameras: HashMap<(String,String,String),Camera>,
-  naked: HashMap<usize,Camera>,
+  // Shared so that cloning a loader to decode in other threads is cheap
+  cameras: Arc<HashMap<(String,String,String),Camera>>,
+  naked: Arc<HashMap<usize,Camera>>,
+  pool: Option<Arc<rayon::ThreadPool>>,
+  mode: DecodeMode,
+  verbosity: Verbosity,
+  limits: Limits,
+  max_file_size: Option<usize>,
+  sub_image: SubImage,
+  options: DecodeOptions,
+  external: Vec<ExternalDecoder>,
+  spare: SpareData,
 }
 
 impl RawLoader {
+  /// Configures a raw loader with other than the default settings of `new()`
+  pub fn builder() -> RawLoaderBuilder {
+    RawLoaderBuilder::default()
+  }
+
   /// Creates a new raw loader using the camera information included in the library
   pub fn new() -> RawLoader {
     let toml = match CAMERAS_TOML.parse::<Value>() {
@@ -360,8 +833,88 @@ impl RawLoader {
       Err(e) => panic!("Error parsing all.toml: {:?}", e),
     };
 
+    let mut loader = RawLoader{
+      cameras: Arc::new(HashMap::new()),
+      naked: Arc::new(HashMap::new()),
+      pool: None,
+      mode: DecodeMode::Lenient,
+      verbosity: Verbosity::Quiet,
+      limits: Limits::DEFAULT,
+      max_file_size: None,
+      sub_image: SubImage::Primary,
+      options: DecodeOptions::default(),
+      external: Vec::new(),
+      spare: SpareData::default(),
+    };
+    loader.add_cameras(Self::parse_cameras(toml.get("cameras").unwrap().as_array().unwrap()));
+    loader
+  }
+
+  /// Adds camera definitions to the ones included in the library, replacing the ones for the
+  /// same make, model and mode. `defs` can be the path to a definition file or a directory of
+  /// them, or the TOML itself, either of a single camera in the format of the files in
+  /// data/cameras or with an array of them under `[[cameras]]`.
+  ///
+  /// # Example
+  /// ```
+  /// let loader = rawloader::RawLoader::new().with_camera_defs(r#"
+  ///   make = "NewMake"
+  ///   model = "NewMake Z1"
+  ///   clean_make = "NewMake"
+  ///   clean_model = "Z1"
+  ///   whitepoint = 16383
+  ///   color_pattern = "RGGB"
+  /// "#).unwrap();
+  /// assert!(loader.is_supported("NewMake", "NewMake Z1"));
+  /// ```
+  pub fn with_camera_defs(mut self, defs: &str) -> Result<RawLoader, RawLoaderError> {
+    let path = Path::new(defs);
+    let mut sources = Vec::new();
+    if path.is_dir() {
+      let mut files = std::fs::read_dir(path)?.filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "toml")).collect::<Vec<_>>();
+      files.sort();
+      for file in files {
+        sources.push(std::fs::read_to_string(file)?);
+      }
+    } else if path.is_file() {
+      sources.push(std::fs::read_to_string(path)?);
+    } else {
+      sources.push(defs.to_string());
+    }
+
+    for source in sources {
+      let toml = source.parse::<Value>()
+        .map_err(|e| RawLoaderError::Corrupt(format!("Error parsing camera definitions: {}", e)))?;
+      let cameras = match toml.get("cameras").and_then(|c| c.as_array()) {
+        Some(cameras) => cameras.clone(),
+        None => vec![toml],
+      };
+      // The definitions are trusted by the parsing so anything malformed panics
+      let cams = panic::catch_unwind(|| Self::parse_cameras(&cameras))
+        .map_err(|_| RawLoaderError::Corrupt("Camera definitions have fields of the wrong type".to_string()))?;
+      if cams.iter().any(|cam| cam.make.is_empty() || cam.model.is_empty()) {
+        return Err(RawLoaderError::Corrupt("Camera definitions need a make and a model".to_string()))
+      }
+      self.add_cameras(cams);
+    }
+    Ok(self)
+  }
+
+  fn add_cameras(&mut self, cams: Vec<Camera>) {
+    let cameras = Arc::make_mut(&mut self.cameras);
+    let naked = Arc::make_mut(&mut self.naked);
+    for cam in cams {
+      cameras.insert((cam.make.clone(),cam.model.clone(),cam.mode.clone()), cam.clone());
+      if cam.filesize > 0 {
+        naked.insert(cam.filesize, cam);
+      }
+    }
+  }
+
+  fn parse_cameras(cameras: &[Value]) -> Vec<Camera> {
     let mut cams = Vec::new();
-    for camera in toml.get("cameras").unwrap().as_array().unwrap() {
+    for camera in cameras {
       // Create a list of all the camera modes including the base one
       let mut cammodes = Vec::new();
       let ct = camera.as_table().unwrap();
@@ -397,24 +950,94 @@ impl RawLoader {
       }
     }
 
-    let mut map = HashMap::new();
-    let mut naked = HashMap::new();
-    for cam in cams {
-      map.insert((cam.make.clone(),cam.model.clone(),cam.mode.clone()), cam.clone());
-      if cam.filesize > 0 {
-        naked.insert(cam.filesize, cam);
+    cams
+  }
+
+  /// Lists the cameras in the database sorted by their clean make and model, with each
+  /// name the files of a model may have as a separate entry
+  pub fn supported_cameras(&self) -> Vec<capabilities::SupportedCamera> {
+    let mut cameras: HashMap<(&str, &str), capabilities::SupportedCamera> = HashMap::new();
+    for cam in self.cameras.values() {
+      let entry = cameras.entry((&cam.make, &cam.model)).or_insert_with(|| capabilities::SupportedCamera {
+        clean_make: cam.clean_make.clone(),
+        clean_model: cam.clean_model.clone(),
+        make: cam.make.clone(),
+        model: cam.model.clone(),
+        modes: Vec::new(),
+      });
+      if !cam.mode.is_empty() {
+        entry.modes.push(cam.mode.clone());
       }
     }
-
-    RawLoader{
-      cameras: map,
-      naked: naked,
+    let mut cameras: Vec<capabilities::SupportedCamera> = cameras.into_values().collect();
+    for cam in cameras.iter_mut() {
+      cam.modes.sort();
     }
+    cameras.sort();
+    cameras
+  }
+
+  /// Checks if a camera is in the database, by the make and model as found in its files or
+  /// by the clean ones, ignoring case
+  pub fn is_supported(&self, make: &str, model: &str) -> bool {
+    let (make, model) = (make.trim(), model.trim());
+    self.cameras.values().any(|cam| {
+      (cam.make.eq_ignore_ascii_case(make) && cam.model.eq_ignore_ascii_case(model)) ||
+      (cam.clean_make.eq_ignore_ascii_case(make) && cam.clean_model.eq_ignore_ascii_case(model))
+    })
+  }
+
+  /// Adds a decoder for a format the library doesn't read. `sniff` is given the contents of
+  /// each file and when it recognizes one `create` makes the decoder for it. The decoders
+  /// registered are tried in order before the ones of the library so they can also replace
+  /// them.
+  ///
+  /// # Example
+  /// ```
+  /// use rawloader::{Decoder, RawImage, RawImageData, RawLoader, RawLoaderError};
+  ///
+  /// struct MyDecoder<'a> {
+  ///   data: &'a [u8],
+  /// }
+  ///
+  /// impl<'a> Decoder for MyDecoder<'a> {
+  ///   fn format(&self) -> &'static str {
+  ///     "MYRAW"
+  ///   }
+  ///
+  ///   fn image(&self, _dummy: bool) -> Result<RawImage, RawLoaderError> {
+  ///     let pixels = self.data[5..].iter().map(|&v| v as u16).collect();
+  ///     Ok(RawImage::from_data("Me", "Prototype", 2, 2, 1, RawImageData::Integer(pixels)))
+  ///   }
+  /// }
+  ///
+  /// fn create(data: &[u8]) -> Result<Box<dyn Decoder + '_>, RawLoaderError> {
+  ///   Ok(Box::new(MyDecoder { data }))
+  /// }
+  ///
+  /// let mut loader = RawLoader::new();
+  /// loader.register_decoder(|data| data.starts_with(b"MYRAW"), create);
+  /// let image = loader.decode(&mut &b"MYRAW\x01\x02\x03\x04"[..], false).unwrap();
+  /// assert_eq!(image.model, "Prototype");
+  /// ```
+  pub fn register_decoder<S, C>(&mut self, sniff: S, create: C)
+    where S: Fn(&[u8]) -> bool + Send + Sync + 'static,
+          C: for<'b> Fn(&'b [u8]) -> Result<Box<dyn Decoder + 'b>, RawLoaderError> + Send + Sync + 'static {
+    self.external.push(ExternalDecoder {
+      sniff: Arc::new(sniff),
+      create: Arc::new(create),
+    });
   }
 
   /// Returns a decoder for a given buffer
-  pub fn get_decoder<'b>(&'b self, buf: &'b Buffer) -> Result<Box<dyn Decoder+'b>, String> {
+  pub fn get_decoder<'b>(&'b self, buf: &'b Buffer) -> Result<Box<dyn Decoder+'b>, RawLoaderError> {
     let buffer = &buf.buf;
+
+    for external in &self.external {
+      if (external.sniff)(&buffer[..buf.size]) {
+        return (external.create)(&buffer[..buf.size])
+      }
+    }
     
     // Check for CR3 format using BMFF
     match Bmff::new(&buf.buf) {
@@ -451,7 +1074,14 @@ impl RawLoader {
       return Ok(dec as Box<dyn Decoder>);
     }
 
-    if let Ok(tiff) = TiffIFD::new_file(buffer) {
+    // Files over the limits, and TIFF files that break the format in strict mode, fail
+    // instead of being tried as the formats that aren't TIFF
+    let tiff = match TiffIFD::new_file(buffer) {
+      Err(err @ RawLoaderError::LimitExceeded(_)) => return Err(err),
+      Err(err) if self.mode == DecodeMode::Strict && (buffer[0..2] == b"II"[..] || buffer[0..2] == b"MM"[..]) => return Err(err),
+      tiff => tiff,
+    };
+    if let Ok(tiff) = tiff {
       if tiff.has_entry(Tag::DNGVersion) {
         return Ok(Box::new(dng::DngDecoder::new(buffer, tiff, self)))
       }
@@ -501,7 +1131,7 @@ impl RawLoader {
           "Canon EOS 3"                 => Ok(Box::new(cr3::Cr3Decoder::new(buffer, Some(tiff), None, self))),
           "Phase One A/S"               => use_decoder!(iiq::IiqDecoder, buffer, tiff, self),
 
-          make => Err(format!("Couldn't find a decoder for make \"{}\".{}", make, SAMPLE).to_string()),
+          make => Err(RawLoaderError::unsupported(make, "")),
         };
       } else if tiff.has_entry(Tag::Software) {
         // Last ditch effort to identify Leaf cameras without Make and Model
@@ -512,21 +1142,24 @@ impl RawLoader {
     }
 
     // If all else fails see if we match by filesize to one of those CHDK style files
-    if let Some(cam) = self.naked.get(&buf.size) {
-      return Ok(Box::new(nkd::NakedDecoder::new(buffer, cam.clone(), self)))
+    if self.mode == DecodeMode::Lenient {
+      if let Some(cam) = self.naked.get(&buf.size) {
+        warn(DecodeWarning::RecognizedBySize);
+        return Ok(Box::new(nkd::NakedDecoder::new(buffer, cam.clone(), self)))
+      }
     }
 
-    Err(format!("Couldn't find a decoder for this file.{}", SAMPLE).to_string())
+    Err(RawLoaderError::unsupported("", ""))
   }
 
-  fn check_supported_with_everything<'a>(&'a self, make: &str, model: &str, mode: &str) -> Result<Camera, String> {
+  fn check_supported_with_everything<'a>(&'a self, make: &str, model: &str, mode: &str) -> Result<Camera, RawLoaderError> {
     match self.cameras.get(&(make.to_string(),model.to_string(),mode.to_string())) {
       Some(cam) => Ok(cam.clone()),
-      None => Err(format!("Couldn't find camera \"{}\" \"{}\" mode \"{}\".{}", make, model, mode, SAMPLE)),
+      None => Err(RawLoaderError::unsupported(make, model)),
     }
   }
 
-  fn check_supported_with_mode<'a>(&'a self, tiff: &'a TiffIFD, mode: &str) -> Result<Camera, String> {
+  fn check_supported_with_mode<'a>(&'a self, tiff: &'a TiffIFD, mode: &str) -> Result<Camera, RawLoaderError> {
     let make = fetch_tag!(tiff, Tag::Make).get_str();
     let model = fetch_tag!(tiff, Tag::Model).get_str();
 
@@ -535,52 +1168,719 @@ impl RawLoader {
 
     // Lookup the orientation of the image for later image rotation
     camera.orientation = Orientation::from_tiff(tiff);
+    camera.exif = exif::Exif::from_tiff(tiff);
 
     Ok(camera)
   }
 
-  fn check_supported<'a>(&'a self, tiff: &'a TiffIFD) -> Result<Camera, String> {
+  fn check_supported<'a>(&'a self, tiff: &'a TiffIFD) -> Result<Camera, RawLoaderError> {
     self.check_supported_with_mode(tiff, "")
   }
 
-  fn decode_unsafe(&self, buffer: &Buffer, dummy: bool) -> Result<RawImage,String> {
+  fn decode_unsafe(&self, buffer: &Buffer, dummy: bool, params: &RawDecodeParams) -> Result<RawImage, RawLoaderError> {
     let decoder = self.get_decoder(&buffer)?;
-    decoder.image(dummy)
+    self.decode_frame(buffer, &*decoder, dummy, params)
   }
 
-  /// Decodes an input into a RawImage
-  pub fn decode(&self, reader: &mut dyn Read, dummy: bool) -> Result<RawImage,String> {
-    let buffer = Buffer::new(reader)?;
+  fn decode_frame(&self, buffer: &Buffer, decoder: &dyn Decoder, dummy: bool, params: &RawDecodeParams) -> Result<RawImage, RawLoaderError> {
+    let mut image = match params.image_index {
+      0 => self.decode_sub_image(decoder, dummy)?,
+      index => decoder.image_at(index, dummy)?,
+    };
+    image.image_count = decoder.image_count();
 
-    match panic::catch_unwind(|| {
-      self.decode_unsafe(&buffer, dummy)
-    }) {
-      Ok(val) => val,
-      Err(_) => Err(format!("Caught a panic while decoding.{}", BUG).to_string()),
+    // When the raw doesn't have its own profile look for one in the embedded previews
+    if image.exif.icc_profile.is_none() {
+      image.exif.icc_profile = icc::from_jpegs(&buffer.buf[..buffer.size]);
+    }
+    // Crop modes and aspect ratios set in the camera are how the photographer framed the image
+    if image.display_crop.is_none() {
+      let areas = image.areas();
+      image.display_crop = image.exif.makernote.as_ref().and_then(|mn| mn.aspect_crop(&areas));
     }
+    // Files without the Orientation tag may still have it in the makernotes or the XMP
+    if image.orientation == Orientation::Unknown {
+      let xmp = image.exif.xmp.as_ref().and_then(|xmp| xmp.properties.get("tiff:Orientation"))
+        .and_then(|v| v.trim().parse::<u16>().ok()).map(Orientation::from_u16);
+      if let Some(orientation) = image.exif.makernote.as_ref().and_then(|mn| mn.orientation()).or(xmp) {
+        image.orientation = orientation;
+      }
+    }
+    if image.wb_coeffs[0..3].iter().all(|c| c.is_nan()) {
+      warn(DecodeWarning::MissingWhiteBalance);
+    }
+    // Rows that failed because the file is cut short are returned as zeros with the others
+    if let Some(err) = take_failed_rows() {
+      let offset = truncated_at(buffer).ok_or(err)?;
+      warn(DecodeWarning::Truncated(offset));
+      image.partially_decoded = true;
+      image.failure_offset = Some(offset);
+    }
+    image.warnings.extend(take_warnings());
+    Ok(image)
   }
 
-  /// Decodes a file into a RawImage
-  pub fn decode_file(&self, path: &Path) -> Result<RawImage,String> {
-    let file = match File::open(path) {
-      Ok(val) => val,
-      Err(e) => {return Err(e.to_string())},
+  fn decode_sub_image(&self, decoder: &dyn Decoder, dummy: bool) -> Result<RawImage, RawLoaderError> {
+    let image = match self.sub_image {
+      SubImage::Primary => return decoder.image(dummy),
+      SubImage::HighGain => decoder.dual_gain(dummy).map(|img| img.high_gain),
+      SubImage::LowGain => decoder.dual_gain(dummy).map(|img| img.low_gain),
+      SubImage::DualPixelA => decoder.dual_pixel(dummy).map(|img| img.a),
+      SubImage::DualPixelB => decoder.dual_pixel(dummy).map(|img| img.b),
+    };
+    match image {
+      Err(err) if self.mode == DecodeMode::Lenient => {
+        warn(DecodeWarning::MainImageInstead(err.to_string()));
+        decoder.image(dummy)
+      },
+      image => image,
+    }
+  }
+
+  // Reads the whole input, up to the file size limit
+  fn read(&self, reader: &mut dyn Read) -> Result<Buffer<'static>, RawLoaderError> {
+    let buffer = match self.max_file_size {
+      Some(max) => Buffer::new(&mut reader.take(max as u64 + 1))?,
+      None => Buffer::new(reader)?,
+    };
+    match self.max_file_size {
+      Some(max) if buffer.size > max => Err(RawLoaderError::LimitExceeded(format!("file is over the {} bytes limit", max))),
+      _ => Ok(buffer),
+    }
+  }
+
+  // Runs a decode in the loader's thread pool if it has one, with its limits set and any
+  // panics turned into errors. The decode only reads from the loader and the buffer so
+  // nothing is left inconsistent by a panic.
+  fn run<T, F>(&self, decode: F) -> Result<T, RawLoaderError>
+    where T: Send, F: FnOnce() -> Result<T, RawLoaderError> + Send {
+    self.run_with(&self.options, decode)
+  }
+
+  // Same as run() with the cancellation flag and timeout of other options than the loader's
+  fn run_with<T, F>(&self, options: &DecodeOptions, decode: F) -> Result<T, RawLoaderError>
+    where T: Send, F: FnOnce() -> Result<T, RawLoaderError> + Send {
+    let settings = (self.limits, self.verbosity, self.mode);
+    let abort = Abort::new(options);
+    let run = move || {
+      SETTINGS.with(|s| s.set(settings));
+      let previous = ABORT.with(|a| a.replace(abort));
+      WARNINGS.with(|w| w.borrow_mut().clear());
+      FAILED_ROWS.with(|f| f.borrow_mut().take());
+      let recover = RECOVER_ROWS.with(|r| r.replace(settings.2 == DecodeMode::Lenient));
+      let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        check_abort()?;
+        let result = decode()?;
+        // The parts decoded in parallel stop when the decode is aborted without failing it
+        check_abort()?;
+        // Rows left undecoded that decode_frame() didn't find to be from a truncated file
+        if let Some(err) = take_failed_rows() {
+          return Err(err)
+        }
+        Ok(result)
+      })).unwrap_or_else(|payload| Err(panic_error(payload)));
+      ABORT.with(|a| *a.borrow_mut() = previous);
+      RECOVER_ROWS.with(|r| r.set(recover));
+      result
+    };
+    match self.pool {
+      Some(ref pool) => pool.install(run),
+      None => run(),
+    }
+  }
+
+  /// Finds the format of an input without decoding it
+  pub fn capabilities(&self, reader: &mut dyn Read) -> Result<&'static capabilities::FormatCapabilities, RawLoaderError> {
+    let buffer = self.read(reader)?;
+
+    self.run(|| {
+      let decoder = self.get_decoder(&buffer)?;
+      capabilities::for_format(decoder.format()).ok_or_else(|| RawLoaderError::unsupported("", ""))
+    })
+  }
+
+  /// Finds the format, camera and size of a file doing as little work as possible, for when
+  /// many files need to be sorted out. Anything that can't be found is left as None.
+  pub fn identify(&self, data: &[u8]) -> capabilities::FormatInfo {
+    let buffer = match self.read(&mut &data[..]) {
+      Ok(buffer) => buffer,
+      Err(_) => return capabilities::FormatInfo::default(),
+    };
+
+    // Files from unknown makes or unsupported cameras can still say what they are
+    let from_tiff = |info: &mut capabilities::FormatInfo| {
+      if let Ok(tiff) = TiffIFD::new_file(&buffer.buf) {
+        info.make = tiff.find_entry(Tag::Make).map(|e| e.get_str().to_string());
+        info.model = tiff.find_entry(Tag::Model).map(|e| e.get_str().to_string());
+      }
+    };
+
+    self.run(|| {
+      let mut info = capabilities::FormatInfo::default();
+      let decoder = match self.get_decoder(&buffer) {
+        Ok(decoder) => decoder,
+        Err(_) => {
+          from_tiff(&mut info);
+          return Ok(info)
+        },
+      };
+      info.format = capabilities::for_format(decoder.format());
+      // A dummy decode reads the metadata but skips the image data
+      match decoder.image(true) {
+        Ok(image) => {
+          info.make = Some(image.make);
+          info.model = Some(image.model);
+          info.width = Some(image.width);
+          info.height = Some(image.height);
+          info.supported = true;
+        },
+        Err(_) => from_tiff(&mut info),
+      }
+      Ok(info)
+    }).unwrap_or_default()
+  }
+
+  /// All the previews embedded in an input, in the order they're found in the file
+  pub fn thumbnails(&self, reader: &mut dyn Read) -> Result<Vec<thumbnail::Thumbnail>, RawLoaderError> {
+    let buffer = self.read(reader)?;
+    self.run(|| Ok(thumbnail::find(&buffer.buf[..buffer.size])))
+  }
+
+  /// Every image stored in an input, previews and raw images, with where they are in the
+  /// file in file order. Raw images are found through the TIFF structure of the file so for
+  /// formats that aren't TIFF based (CRW, CR3, MRW, RAF, X3F, etc) only previews are listed.
+  pub fn embedded_images(&self, reader: &mut dyn Read) -> Result<Vec<thumbnail::EmbeddedImage>, RawLoaderError> {
+    let buffer = self.read(reader)?;
+    self.run(|| Ok(thumbnail::embedded(&buffer.buf[..buffer.size])))
+  }
+
+  /// The smallest preview embedded in a file, None if it has none. Much faster than
+  /// decoding the raw for showing it in a gallery.
+  pub fn thumbnail(&self, path: &Path) -> Result<Option<thumbnail::Thumbnail>, RawLoaderError> {
+    let file = File::open(path)?;
+    let thumbs = self.thumbnails(&mut BufReader::new(file))?;
+    Ok(thumbs.into_iter().min_by_key(|thumb| thumb.pixels()))
+  }
+
+  /// The largest JPEG preview embedded in a file, None if it has none. Canon and Nikon
+  /// among others embed one at full resolution that can stand in for rendering the raw.
+  pub fn preview(&self, path: &Path) -> Result<Option<thumbnail::Thumbnail>, RawLoaderError> {
+    let file = File::open(path)?;
+    let thumbs = self.thumbnails(&mut BufReader::new(file))?;
+    Ok(thumbs.into_iter()
+      .filter(|thumb| matches!(thumb.data, thumbnail::ThumbnailData::Jpeg(_)))
+      .max_by_key(|thumb| thumb.pixels()))
+  }
+
+  /// Converts a raw file into a DNG, keeping the EXIF data, the makernote and the embedded
+  /// previews of the original as Adobe's converter does. See `dng::write()` for what's
+  /// written about the image itself.
+  pub fn convert_to_dng(&self, input: &Path, output: &Path) -> Result<(), RawLoaderError> {
+    let file = File::open(input)?;
+    let buffer = self.read(&mut BufReader::new(file))?;
+    let data = &buffer.buf[..buffer.size];
+    let image = self.decode_slice(data)?;
+    let originals = self.run(|| Ok(writers::dng::Originals::from_file(data)))?;
+    let mut out = BufWriter::new(File::create(output)?);
+    writers::dng::write_converted(&image, &originals, &writers::dng::WriteOptions::default(), &mut out)?;
+    out.flush()?;
+    Ok(())
+  }
+
+  /// Checks the structure of an input (IFDs, entries and strips of TIFF based formats, boxes of
+  /// CR3) and then decodes it, returning every problem found. An empty list means the file is
+  /// sound as far as rawloader can tell.
+  pub fn validate(&self, reader: &mut dyn Read) -> Result<Vec<validate::Problem>, RawLoaderError> {
+    let buffer = self.read(reader)?;
+    let mut problems = self.run(|| Ok(validate::check(&buffer.buf[..buffer.size])))?;
+    match self.decode_buffer(&buffer, false, &self.options, &RawDecodeParams::default()) {
+      Ok(image) => problems.extend(image.warnings.into_iter().map(validate::Problem::from_warning)),
+      Err(err) => problems.push(validate::Problem::undecodable(err.to_string())),
+    }
+    Ok(problems)
+  }
+
+  /// Checks the structure of a file and decodes it, returning every problem found
+  pub fn validate_file(&self, path: &Path) -> Result<Vec<validate::Problem>, RawLoaderError> {
+    let file = File::open(path)?;
+    self.validate(&mut BufReader::new(file))
+  }
+
+  /// Reads the metadata of an input without decoding the image data, which is much faster
+  /// than a full decode for any format
+  pub fn probe(&self, reader: &mut dyn Read) -> Result<RawMetadata, RawLoaderError> {
+    let buffer = self.read(reader)?;
+    self.run(|| {
+      let format = self.get_decoder(&buffer)?.format();
+      let image = self.decode_unsafe(&buffer, true, &RawDecodeParams::default())?;
+      Ok(RawMetadata::new(format, image))
+    })
+  }
+
+  /// Reads the metadata of a file without decoding the image data, including its XMP
+  /// sidecar when the loader's options ask for it
+  pub fn probe_file(&self, path: &Path) -> Result<RawMetadata, RawLoaderError> {
+    let file = File::open(path)?;
+    let mut metadata = self.probe(&mut BufReader::new(file))?;
+    if self.options.xmp_sidecar {
+      if let Some(sidecar) = xmp::Xmp::from_sidecar(path) {
+        metadata.exif.merge_xmp(sidecar);
+      }
+    }
+    Ok(metadata)
+  }
+
+  /// Decodes an input that can only be read once from start to end, such as stdin or a network
+  /// stream. Decoding the image data needs the whole file so in that case this is the same as
+  /// `decode()`. When only the metadata is needed (`dummy` is set) TIFF based formats are
+  /// read only as far as the metadata goes, leaving the image data and anything after it
+  /// unread, so color profiles in previews past that point aren't found.
+  pub fn decode_stream(&self, reader: &mut dyn Read, dummy: bool) -> Result<RawImage, RawLoaderError> {
+    if !dummy {
+      return self.decode(reader, false)
+    }
+
+    let mut data = Vec::new();
+    let mut want = STREAM_CHUNK;
+    loop {
+      if let Some(max) = self.max_file_size {
+        want = want.min(max + 1);
+      }
+      let missing = want - data.len();
+      if ((&mut *reader).take(missing as u64).read_to_end(&mut data)?) < missing {
+        break
+      }
+      if let Some(max) = self.max_file_size {
+        if data.len() > max {
+          return Err(RawLoaderError::LimitExceeded(format!("file is over the {} bytes limit", max)))
+        }
+      }
+
+      // Without the padding of a normal Buffer reading past what's there panics so there's
+      // no way for a decoder to mistake the end of the prefix for zeros
+      let size = data.len();
+      let buffer = Buffer { buf: BufferData::Owned(data), size };
+      let attempt = self.run(|| {
+        let decoder = self.get_decoder(&buffer)?;
+        if !STREAMABLE.contains(&decoder.format()) {
+          return Ok(StreamAttempt::ReadAll)
+        }
+        reset_past_end();
+        let image = self.decode_unsafe(&buffer, true, &RawDecodeParams::default())?;
+        Ok(if read_past_end() { StreamAttempt::ReadMore } else { StreamAttempt::Done(Box::new(image)) })
+      });
+      data = buffer.into_vec();
+      match attempt {
+        Ok(StreamAttempt::Done(image)) => return Ok(*image),
+        Ok(StreamAttempt::ReadAll) => {
+          reader.read_to_end(&mut data)?;
+          break
+        },
+        // Errors are most likely from the file being cut short so only the ones from the
+        // whole file count
+        Ok(StreamAttempt::ReadMore) | Err(_) => want *= 2,
+      }
+    }
+
+    self.decode(&mut &data[..], true)
+  }
+
+  /// Decodes a file held in memory into a RawImage with the loader's options, for when the
+  /// file comes from elsewhere than the filesystem, like in a browser. The file is decoded
+  /// where it is, without a copy.
+  pub fn decode_slice(&self, data: &[u8]) -> Result<RawImage, RawLoaderError> {
+    if let Some(max) = self.max_file_size.filter(|&max| data.len() > max) {
+      return Err(RawLoaderError::LimitExceeded(format!("file is over the {} bytes limit", max)))
+    }
+    self.decode_buffer(&Buffer::borrowed(data), false, &self.options, &RawDecodeParams::default())
+  }
+
+  /// Decodes an input into a RawImage with the loader's options
+  pub fn decode(&self, reader: &mut dyn Read, dummy: bool) -> Result<RawImage, RawLoaderError> {
+    self.decode_with_options(reader, dummy, &self.options)
+  }
+
+  /// Decodes an input into a RawImage doing the optional processing in `options`
+  pub fn decode_with_options(&self, reader: &mut dyn Read, dummy: bool, options: &DecodeOptions) -> Result<RawImage, RawLoaderError> {
+    self.decode_with_params(reader, dummy, options, &RawDecodeParams::default())
+  }
+
+  /// Decodes the raw image selected by `params` doing the optional processing in `options`
+  pub fn decode_with_params(&self, reader: &mut dyn Read, dummy: bool, options: &DecodeOptions, params: &RawDecodeParams) -> Result<RawImage, RawLoaderError> {
+    let buffer = self.read(reader)?;
+    self.decode_buffer(&buffer, dummy, options, params)
+  }
+
+  fn decode_buffer(&self, buffer: &Buffer, dummy: bool, options: &DecodeOptions, params: &RawDecodeParams) -> Result<RawImage, RawLoaderError> {
+    self.run_with(options, || {
+      let mut image = self.decode_unsafe(buffer, dummy, params)?;
+      if !dummy {
+        Self::postprocess(&mut image, options)?;
+      }
+      Ok(image)
+    })
+  }
+
+  /// Decodes an input with the loader's options into `out`, which must have exactly
+  /// `width*height*cpp` values, for pipelines that decode many images of the same size. The
+  /// RawImage returned has all the metadata but its `data` is left empty. Images with
+  /// floating point data fail with `RawLoaderError::Unimplemented`, use `decode_into_f32()`
+  /// for those.
+  ///
+  /// Besides `out` the loader keeps a buffer of the same size that the next decode reuses so
+  /// that decoding a sequence doesn't allocate the image data each time.
+  pub fn decode_into(&self, reader: &mut dyn Read, out: &mut [u16]) -> Result<RawImage, RawLoaderError> {
+    let mut image = self.decode_reusing(reader)?;
+    match image.data {
+      RawImageData::Integer(ref mut data) => {
+        let data = std::mem::take(data);
+        let result = if data.len() == out.len() {
+          out.copy_from_slice(&data);
+          Ok(image)
+        } else {
+          Err(RawLoaderError::OutputSize { expected: data.len(), got: out.len() })
+        };
+        self.spare.put(data);
+        result
+      },
+      RawImageData::Float(_) => Err(RawLoaderError::Unimplemented(
+        "Image data is floating point, use decode_into_f32()".to_string()
+      )),
+    }
+  }
+
+  /// Same as `decode_into()` but into floating point values, which works for all images as
+  /// integer data is converted
+  pub fn decode_into_f32(&self, reader: &mut dyn Read, out: &mut [f32]) -> Result<RawImage, RawLoaderError> {
+    let mut image = self.decode_reusing(reader)?;
+    let expected = image.data_len();
+    if expected != out.len() {
+      return Err(RawLoaderError::OutputSize { expected, got: out.len() })
+    }
+    match image.data {
+      RawImageData::Integer(ref mut data) => {
+        for (o, v) in out.iter_mut().zip(data.iter()) {
+          *o = *v as f32;
+        }
+        self.spare.put(std::mem::take(data));
+      },
+      RawImageData::Float(ref mut data) => {
+        out.copy_from_slice(data);
+        *data = Vec::new();
+      },
+    }
+    Ok(image)
+  }
+
+  // Decodes with the loader's spare buffer available for the image data
+  fn decode_reusing(&self, reader: &mut dyn Read) -> Result<RawImage, RawLoaderError> {
+    let buffer = self.read(reader)?;
+    self.run(|| {
+      SPARE.with(|s| s.set(self.spare.take()));
+      let image = self.decode_unsafe(&buffer, false, &RawDecodeParams::default());
+      self.spare.put(SPARE.with(|s| s.take()));
+      let mut image = image?;
+      Self::postprocess(&mut image, &self.options)?;
+      Ok(image)
+    })
+  }
+
+  /// Reads the metadata of a file held in memory and returns it with a reference to the image
+  /// data inside `input`, without decoding it into a copy. Only works for files stored as 16
+  /// bit samples, failing with `RawLoaderError::Unimplemented` for packed or compressed ones.
+  pub fn decode_ref<'a>(&self, input: &'a [u8]) -> Result<rawref::RawImageRef<'a>, RawLoaderError> {
+    if let Some(max) = self.max_file_size {
+      if input.len() > max {
+        return Err(RawLoaderError::LimitExceeded(format!("file is over the {} bytes limit", max)))
+      }
+    }
+    let buffer = Buffer::borrowed(input);
+    let (image, recorded) = self.run(|| {
+      rawref::start();
+      let image = self.decode_unsafe(&buffer, true, &RawDecodeParams::default());
+      Ok((image, rawref::finish()))
+    })?;
+    let mut image = image?;
+
+    // Decoders that transform the data after reading it, or read it from several places,
+    // can't be referenced so there must be a single read of exactly the image's size
+    let base = input.as_ptr() as usize;
+    let layout = match recorded[..] {
+      [(start, width, height, layout)] if width == image.width*image.cpp && height == image.height &&
+                                         start >= base && start < base + input.len() => {
+        let layout = rawref::SampleLayout { offset: start - base, ..layout };
+        let end = layout.offset + layout.stride*(height.max(1) - 1) + width*2;
+        if end > input.len() {
+          return Err(RawLoaderError::Corrupt("The image data is past the end of the file".to_string()))
+        }
+        layout
+      },
+      _ => return Err(RawLoaderError::Unimplemented("The image data isn't stored as 16 bit samples".to_string())),
     };
-    let mut buffered_file = BufReader::new(file);
-    self.decode(&mut buffered_file, false)
+    image.data = RawImageData::Integer(Vec::new());
+    Ok(rawref::RawImageRef { image, layout, input })
+  }
+
+  /// Decodes all the raw images of a multi-frame file (bursts, pixel shift, Dual Pixel raws,
+  /// DNGs with several resolutions) with the loader's options, in parallel. The file is read
+  /// once and its structure parsed once per thread instead of once per frame.
+  pub fn decode_all(&self, reader: &mut dyn Read) -> Result<Vec<RawImage>, RawLoaderError> {
+    let buffer = self.read(reader)?;
+    let count = self.run(|| Ok(self.get_decoder(&buffer)?.image_count()))?;
+    let settings = (self.limits, self.verbosity, self.mode);
+
+    self.run(|| {
+      let abort = Abort::current();
+      (0..count).into_par_iter().map_init(|| {
+        SETTINGS.with(|s| s.set(settings));
+        ABORT.with(|a| *a.borrow_mut() = abort.clone());
+        WARNINGS.with(|w| w.borrow_mut().clear());
+        self.get_decoder(&buffer).map_err(|err| RawLoaderError::Corrupt(err.to_string()))
+      }, |decoder, index| {
+        let decoder = decoder.as_ref().map_err(|err| RawLoaderError::Corrupt(err.to_string()))?;
+        let mut image = self.decode_frame(&buffer, &**decoder, false, &RawDecodeParams { image_index: index })?;
+        Self::postprocess(&mut image, &self.options)?;
+        Ok(image)
+      }).collect()
+    })
+  }
+
+  fn postprocess(image: &mut RawImage, options: &DecodeOptions) -> Result<(), RawLoaderError> {
+    check_abort()?;
+    if options.apply_calibration {
+      image.apply_calibration();
+      check_abort()?;
+    }
+    if options.apply_opcodes {
+      image.apply_opcodes();
+      check_abort()?;
+    }
+    if options.detect_stuck_pixels {
+      let mut stuck = image.find_stuck_pixels();
+      image.bad_pixels.append(&mut stuck);
+      image.bad_pixels.sort_unstable();
+      image.bad_pixels.dedup();
+      check_abort()?;
+    }
+    if options.fix_bad_pixels {
+      image.fix_bad_pixels();
+    }
+    if options.apply_orientation {
+      check_abort()?;
+      image.apply_orientation()?;
+    }
+    Ok(())
+  }
+
+  /// Decodes both frames of an input from a sensor with dual gain readout
+  pub fn decode_dual_gain(&self, reader: &mut dyn Read, dummy: bool) -> Result<DualGainImage, RawLoaderError> {
+    let buffer = self.read(reader)?;
+
+    self.run(|| {
+      let decoder = self.get_decoder(&buffer)?;
+      decoder.dual_gain(dummy)
+    })
+  }
+
+  /// Decodes the combined and split frames of a Dual Pixel raw
+  pub fn decode_dual_pixel(&self, reader: &mut dyn Read, dummy: bool) -> Result<dualpixel::DualPixelImage, RawLoaderError> {
+    let buffer = self.read(reader)?;
+
+    self.run(|| {
+      let decoder = self.get_decoder(&buffer)?;
+      decoder.dual_pixel(dummy)
+    })
+  }
+
+  /// Decodes a file into a RawImage with the loader's options
+  pub fn decode_file(&self, path: &Path) -> Result<RawImage, RawLoaderError> {
+    self.decode_file_with_options(path, &self.options)
+  }
+
+  /// Decodes a file into a RawImage doing the optional processing in `options`
+  pub fn decode_file_with_options(&self, path: &Path, options: &DecodeOptions) -> Result<RawImage, RawLoaderError> {
+    self.decode_file_reusing(path, options, &mut Vec::new())
+  }
+
+  // Decodes a file reading it into `scratch` when it isn't mapped, which is left with the
+  // file's bytes for the next call to reuse the allocation
+  fn decode_file_reusing(&self, path: &Path, options: &DecodeOptions, scratch: &mut Vec<u8>) -> Result<RawImage, RawLoaderError> {
+    let file = File::open(path)?;
+    #[cfg(all(feature = "mmap", unix))]
+    let mapped = match self.max_file_size {
+      Some(max) if file.metadata()?.len() > max as u64 =>
+        return Err(RawLoaderError::LimitExceeded(format!("file is over the {} bytes limit", max))),
+      _ => Buffer::map(&file)?,
+    };
+    #[cfg(not(all(feature = "mmap", unix)))]
+    let mapped: Option<Buffer> = None;
+
+    let mut image = match mapped {
+      Some(buffer) => self.decode_buffer(&buffer, false, options, &RawDecodeParams::default())?,
+      None => {
+        scratch.clear();
+        let limit = self.max_file_size.map_or(u64::MAX, |max| max as u64 + 1);
+        file.take(limit).read_to_end(scratch)?;
+        self.decode_data(scratch, options)?
+      },
+    };
+    Self::add_sidecar(&mut image, path, options);
+    Ok(image)
+  }
+
+  // Decodes a file read into `data`, giving it back after the decode
+  fn decode_data(&self, data: &mut Vec<u8>, options: &DecodeOptions) -> Result<RawImage, RawLoaderError> {
+    let size = data.len();
+    if let Some(max) = self.max_file_size.filter(|&max| size > max) {
+      return Err(RawLoaderError::LimitExceeded(format!("file is over the {} bytes limit", max)))
+    }
+    data.extend([0;16].iter());
+    let buffer = Buffer { buf: BufferData::Owned(std::mem::take(data)), size };
+    let image = self.decode_buffer(&buffer, false, options, &RawDecodeParams::default());
+    if let BufferData::Owned(buf) = buffer.buf {
+      *data = buf;
+    }
+    image
+  }
+
+  fn add_sidecar(image: &mut RawImage, path: &Path, options: &DecodeOptions) {
+    if options.xmp_sidecar {
+      if let Some(sidecar) = xmp::Xmp::from_sidecar(path) {
+        image.exif.merge_xmp(sidecar);
+      }
+    }
+  }
+
+  /// Decodes a file with the loader's options without blocking an async runtime. The file is
+  /// read with tokio's asynchronous I/O and decoded in its pool for blocking tasks. Needs the
+  /// `tokio` feature.
+  #[cfg(feature = "tokio")]
+  pub async fn decode_file_async(&self, path: &Path) -> Result<RawImage, RawLoaderError> {
+    if let Some(max) = self.max_file_size {
+      if tokio::fs::metadata(path).await?.len() > max as u64 {
+        return Err(RawLoaderError::LimitExceeded(format!("file is over the {} bytes limit", max)))
+      }
+    }
+    let mut data = tokio::fs::read(path).await?;
+    let (loader, path) = (self.clone(), path.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+      let mut image = loader.decode_data(&mut data, &loader.options)?;
+      Self::add_sidecar(&mut image, &path, &loader.options);
+      Ok(image)
+    }).await.unwrap_or_else(|err| Err(RawLoaderError::Corrupt(format!("Decode task failed: {}", err))))
+  }
+
+  /// Decodes files in the background, as many at a time as the loader has threads (see
+  /// `RawLoaderBuilder::threads()`), returning the results as they're done. The decodes share
+  /// the loader's thread pool so all of them together use no more than its threads, and each
+  /// of the threads reading files reuses its buffer from one file to the next. Setting the
+  /// `cancel` flag of `options` stops the batch after the decodes running at the time.
+  ///
+  /// Only a few decoded images are kept waiting to be taken from the iterator, so going
+  /// through the results as they come keeps the memory used bounded however many files there
+  /// are. Fails only if the threads can't be started.
+  pub fn decode_many<I, P>(&self, paths: I, options: &DecodeOptions) -> Result<batch::DecodeMany, RawLoaderError>
+    where I: IntoIterator<Item=P>, P: AsRef<Path> {
+    let jobs = self.pool.as_ref().map_or_else(rayon::current_num_threads, |pool| pool.current_num_threads());
+    let paths: Vec<PathBuf> = paths.into_iter().map(|path| path.as_ref().to_path_buf()).collect();
+    let queue = Arc::new(Mutex::new(paths.into_iter()));
+    let loader = Arc::new(self.clone());
+    let options = Arc::new(options.clone());
+    let (results, receiver) = mpsc::sync_channel(jobs);
+
+    for _ in 0..jobs.min(queue.lock().unwrap_or_else(|e| e.into_inner()).len()) {
+      let (queue, loader, options, results) = (queue.clone(), loader.clone(), options.clone(), results.clone());
+      thread::Builder::new().name("rawloader-batch".to_string()).spawn(move || {
+        let mut scratch = Vec::new();
+        loop {
+          if options.cancel.as_ref().is_some_and(|cancel| cancel.load(Ordering::Relaxed)) {
+            break
+          }
+          let path = match queue.lock().unwrap_or_else(|e| e.into_inner()).next() {
+            Some(path) => path,
+            None => break,
+          };
+          let image = loader.decode_file_reusing(&path, &options, &mut scratch);
+          if results.send((path, image)).is_err() {
+            break
+          }
+        }
+      })?;
+    }
+    Ok(batch::DecodeMany { results: receiver })
   }
 
   // Decodes an unwrapped input (just the image data with minimal metadata) into a RawImage
   // This is only useful for fuzzing really
   #[doc(hidden)]
-  pub fn decode_unwrapped(&self, reader: &mut dyn Read) -> Result<RawImageData,String> {
-    let buffer = Buffer::new(reader)?;
+  pub fn decode_unwrapped(&self, reader: &mut dyn Read) -> Result<RawImageData, RawLoaderError> {
+    let buffer = self.read(reader)?;
 
-    match panic::catch_unwind(|| {
+    self.run(|| {
       unwrapped::decode_unwrapped(&buffer)
-    }) {
-      Ok(val) => val,
-      Err(_) => Err(format!("Caught a panic while decoding.{}", BUG).to_string()),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::decoders::cfa::CFA;
+  use crate::decoders::writers::dng;
+
+  // An uncompressed 64x64 DNG with the strip of the raw image moved to where only `rows` rows
+  // of it are left before the end of the file, and optionally its byte count changed
+  fn moved_strip(rows: usize, count: Option<u32>) -> (Vec<u8>, usize) {
+    let data = RawImageData::Integer((0..64*64).map(|v| v as u16).collect());
+    let mut image = RawImage::from_data("Make", "Model", 64, 64, 1, data);
+    image.cfa = CFA::new("RGGB");
+    image.whitelevels = [65535; 4];
+    let options = dng::WriteOptions { compression: dng::Compression::Uncompressed, ..Default::default() };
+    let mut file = Vec::new();
+    dng::write_with_options(&image, &options, &mut file).unwrap();
+
+    let (offset_pos, count_pos) = {
+      let tiff = TiffIFD::new_file(&file).unwrap();
+      let raw = tiff.find_ifds_with_tag(Tag::StripOffsets).into_iter()
+        .find(|ifd| ifd.find_entry(Tag::NewSubFileType).is_some_and(|e| e.get_u32(0) == 0)).unwrap();
+      (raw.find_entry(Tag::StripOffsets).unwrap().doffset(), raw.find_entry(Tag::StripByteCounts).unwrap().doffset())
+    };
+    let offset = file.len() - rows*64*2;
+    file[offset_pos..offset_pos+4].copy_from_slice(&(offset as u32).to_le_bytes());
+    if let Some(count) = count {
+      file[count_pos..count_pos+4].copy_from_slice(&count.to_le_bytes());
     }
+    (file, offset)
+  }
+
+  #[test]
+  fn truncated_strip_keeps_the_rows_there() {
+    let (file, offset) = moved_strip(10, None);
+    let image = RawLoader::new().decode_slice(&file).unwrap();
+    assert!(image.partially_decoded);
+    assert_eq!(image.failure_offset, Some(file.len()));
+    assert!(image.warnings.contains(&DecodeWarning::Truncated(file.len())));
+    let data = match image.data {
+      RawImageData::Integer(data) => data,
+      RawImageData::Float(_) => unreachable!(),
+    };
+    assert_eq!(data.len(), 64*64);
+    let first: Vec<u16> = file[offset..offset+128].chunks_exact(2).map(|b| u16::from_le_bytes([b[0], b[1]])).collect();
+    assert_eq!(data[..64], first[..]);
+    assert!(data[11*64..].iter().all(|&v| v == 0));
+  }
+
+  #[test]
+  fn truncated_strip_fails_strict_decodes() {
+    let (file, _) = moved_strip(10, None);
+    let loader = RawLoader::builder().mode(DecodeMode::Strict).build();
+    assert!(matches!(loader.decode_slice(&file), Err(RawLoaderError::Corrupt(_))));
+  }
+
+  #[test]
+  fn failed_rows_of_complete_strips_fail() {
+    // The strip says it's all in the file, so the rows failing isn't from it being cut short
+    let (file, _) = moved_strip(10, Some(16));
+    assert!(matches!(RawLoader::new().decode_slice(&file), Err(RawLoaderError::Corrupt(_))));
   }
 }
@@ -23,7 +23,11 @@ impl<'a> MosDecoder<'a> {
 }
 
 impl<'a> Decoder for MosDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "MOS"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let make = self.xmp_tag("Make")?;
     let model_full = self.xmp_tag("Model")?.to_string();
     let model = model_full.split_terminator("(").next().unwrap();
@@ -33,20 +37,20 @@ impl<'a> Decoder for MosDecoder<'a> {
     let width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0);
     let height = fetch_tag!(raw, Tag::ImageLength).get_usize(0);
     let offset = fetch_tag!(raw, Tag::TileOffsets).get_usize(0);
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
 
     let image = match fetch_tag!(raw, Tag::Compression).get_usize(0) {
       1 => {
         if self.tiff.little_endian() {
-          decode_16le(src, width, height, dummy)
+          decode_16le(src, width, height, dummy)?
         } else {
-          decode_16be(src, width, height, dummy)
+          decode_16be(src, width, height, dummy)?
         }
       },
       7 | 99 => {
         self.decode_compressed(&camera, src, width, height, dummy)?
       },
-      x => return Err(format!("MOS: unsupported compression {}", x).to_string())
+      x => return Err(RawLoaderError::Unimplemented(format!("MOS: unsupported compression {}", x)))
     };
 
     ok_image(camera, width, height, self.get_wb()?, image)
@@ -54,7 +58,7 @@ impl<'a> Decoder for MosDecoder<'a> {
 }
 
 impl<'a> MosDecoder<'a> {
-  fn get_wb(&self) -> Result<[f32;4], String> {
+  fn get_wb(&self) -> Result<[f32;4], RawLoaderError> {
     let meta = fetch_tag!(self.tiff, Tag::LeafMetadata).get_data();
     let mut pos = 0;
     // We need at least 16+45+10 bytes for the NeutObj_neutrals section itself
@@ -77,21 +81,21 @@ impl<'a> MosDecoder<'a> {
     Ok([NAN,NAN,NAN,NAN])
   }
 
-  fn xmp_tag(&self, tag: &str) -> Result<String, String> {
+  fn xmp_tag(&self, tag: &str) -> Result<String, RawLoaderError> {
     let xmp = fetch_tag!(self.tiff, Tag::Xmp).get_str();
-    let error = format!("MOS: Couldn't find XMP tag {}", tag).to_string();
-    let start = xmp.find(&format!("<tiff:{}>",tag)).ok_or(error.clone())?;
-    let end   = xmp.find(&format!("</tiff:{}>",tag)).ok_or(error.clone())?;
+    let error = || RawLoaderError::Corrupt(format!("MOS: Couldn't find XMP tag {}", tag));
+    let start = xmp.find(&format!("<tiff:{}>",tag)).ok_or_else(error)?;
+    let end   = xmp.find(&format!("</tiff:{}>",tag)).ok_or_else(error)?;
 
     Ok(xmp[start+tag.len()+7..end].to_string())
   }
 
-  pub fn decode_compressed(&self, cam: &Camera, src: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>,String> {
+  pub fn decode_compressed(&self, cam: &Camera, src: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     let interlaced = cam.find_hint("interlaced");
     Self::do_decode(src, interlaced, width, height, dummy)
   }
 
-  pub(crate) fn do_decode(src: &[u8], interlaced:bool, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>,String> {
+  pub(crate) fn do_decode(src: &[u8], interlaced:bool, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     if dummy {
       return Ok(vec![0]);
     }
@@ -1,7 +1,6 @@
-use std::f32::NAN;
-
 use crate::decoders::*;
 use crate::decoders::tiff::*;
+use crate::decoders::makernotes::Makernote;
 use crate::decoders::basics::*;
 use crate::decoders::ljpeg::huffman::*;
 
@@ -41,45 +40,6 @@ const NIKON_TREE: [[[u8;16];3];6] = [
   ],
 ];
 
-// We use this for the D50 and D2X whacky WB "encryption"
-const WB_SERIALMAP: [u8;256] = [
-  0xc1,0xbf,0x6d,0x0d,0x59,0xc5,0x13,0x9d,0x83,0x61,0x6b,0x4f,0xc7,0x7f,0x3d,0x3d,
-  0x53,0x59,0xe3,0xc7,0xe9,0x2f,0x95,0xa7,0x95,0x1f,0xdf,0x7f,0x2b,0x29,0xc7,0x0d,
-  0xdf,0x07,0xef,0x71,0x89,0x3d,0x13,0x3d,0x3b,0x13,0xfb,0x0d,0x89,0xc1,0x65,0x1f,
-  0xb3,0x0d,0x6b,0x29,0xe3,0xfb,0xef,0xa3,0x6b,0x47,0x7f,0x95,0x35,0xa7,0x47,0x4f,
-  0xc7,0xf1,0x59,0x95,0x35,0x11,0x29,0x61,0xf1,0x3d,0xb3,0x2b,0x0d,0x43,0x89,0xc1,
-  0x9d,0x9d,0x89,0x65,0xf1,0xe9,0xdf,0xbf,0x3d,0x7f,0x53,0x97,0xe5,0xe9,0x95,0x17,
-  0x1d,0x3d,0x8b,0xfb,0xc7,0xe3,0x67,0xa7,0x07,0xf1,0x71,0xa7,0x53,0xb5,0x29,0x89,
-  0xe5,0x2b,0xa7,0x17,0x29,0xe9,0x4f,0xc5,0x65,0x6d,0x6b,0xef,0x0d,0x89,0x49,0x2f,
-  0xb3,0x43,0x53,0x65,0x1d,0x49,0xa3,0x13,0x89,0x59,0xef,0x6b,0xef,0x65,0x1d,0x0b,
-  0x59,0x13,0xe3,0x4f,0x9d,0xb3,0x29,0x43,0x2b,0x07,0x1d,0x95,0x59,0x59,0x47,0xfb,
-  0xe5,0xe9,0x61,0x47,0x2f,0x35,0x7f,0x17,0x7f,0xef,0x7f,0x95,0x95,0x71,0xd3,0xa3,
-  0x0b,0x71,0xa3,0xad,0x0b,0x3b,0xb5,0xfb,0xa3,0xbf,0x4f,0x83,0x1d,0xad,0xe9,0x2f,
-  0x71,0x65,0xa3,0xe5,0x07,0x35,0x3d,0x0d,0xb5,0xe9,0xe5,0x47,0x3b,0x9d,0xef,0x35,
-  0xa3,0xbf,0xb3,0xdf,0x53,0xd3,0x97,0x53,0x49,0x71,0x07,0x35,0x61,0x71,0x2f,0x43,
-  0x2f,0x11,0xdf,0x17,0x97,0xfb,0x95,0x3b,0x7f,0x6b,0xd3,0x25,0xbf,0xad,0xc7,0xc5,
-  0xc5,0xb5,0x8b,0xef,0x2f,0xd3,0x07,0x6b,0x25,0x49,0x95,0x25,0x49,0x6d,0x71,0xc7
-];
-
-const WB_KEYMAP: [u8;256] = [
-  0xa7,0xbc,0xc9,0xad,0x91,0xdf,0x85,0xe5,0xd4,0x78,0xd5,0x17,0x46,0x7c,0x29,0x4c,
-  0x4d,0x03,0xe9,0x25,0x68,0x11,0x86,0xb3,0xbd,0xf7,0x6f,0x61,0x22,0xa2,0x26,0x34,
-  0x2a,0xbe,0x1e,0x46,0x14,0x68,0x9d,0x44,0x18,0xc2,0x40,0xf4,0x7e,0x5f,0x1b,0xad,
-  0x0b,0x94,0xb6,0x67,0xb4,0x0b,0xe1,0xea,0x95,0x9c,0x66,0xdc,0xe7,0x5d,0x6c,0x05,
-  0xda,0xd5,0xdf,0x7a,0xef,0xf6,0xdb,0x1f,0x82,0x4c,0xc0,0x68,0x47,0xa1,0xbd,0xee,
-  0x39,0x50,0x56,0x4a,0xdd,0xdf,0xa5,0xf8,0xc6,0xda,0xca,0x90,0xca,0x01,0x42,0x9d,
-  0x8b,0x0c,0x73,0x43,0x75,0x05,0x94,0xde,0x24,0xb3,0x80,0x34,0xe5,0x2c,0xdc,0x9b,
-  0x3f,0xca,0x33,0x45,0xd0,0xdb,0x5f,0xf5,0x52,0xc3,0x21,0xda,0xe2,0x22,0x72,0x6b,
-  0x3e,0xd0,0x5b,0xa8,0x87,0x8c,0x06,0x5d,0x0f,0xdd,0x09,0x19,0x93,0xd0,0xb9,0xfc,
-  0x8b,0x0f,0x84,0x60,0x33,0x1c,0x9b,0x45,0xf1,0xf0,0xa3,0x94,0x3a,0x12,0x77,0x33,
-  0x4d,0x44,0x78,0x28,0x3c,0x9e,0xfd,0x65,0x57,0x16,0x94,0x6b,0xfb,0x59,0xd0,0xc8,
-  0x22,0x36,0xdb,0xd2,0x63,0x98,0x43,0xa1,0x04,0x87,0x86,0xf7,0xa6,0x26,0xbb,0xd6,
-  0x59,0x4d,0xbf,0x6a,0x2e,0xaa,0x2b,0xef,0xe6,0x78,0xb6,0x4e,0xe0,0x2f,0xdc,0x7c,
-  0xbe,0x57,0x19,0x32,0x7e,0x2a,0xd0,0xb8,0xba,0x29,0x00,0x3c,0x52,0x7d,0xa8,0x49,
-  0x3b,0x2d,0xeb,0x25,0x49,0xfa,0xa3,0xaa,0x39,0xa7,0xc5,0xa7,0x50,0x11,0x36,0xfb,
-  0xc6,0x67,0x4a,0xf5,0xa5,0x12,0x65,0x7e,0xb0,0xdf,0xaf,0x4e,0xb3,0x61,0x7f,0x2f
-];
-
 #[derive(Debug, Clone)]
 pub struct NefDecoder<'a> {
   buffer: &'a [u8],
@@ -98,7 +58,11 @@ impl<'a> NefDecoder<'a> {
 }
 
 impl<'a> Decoder for NefDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "NEF"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let raw = fetch_ifd!(&self.tiff, Tag::CFAPattern);
     let mut width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0);
     let height = fetch_tag!(raw, Tag::ImageLength).get_usize(0);
@@ -111,41 +75,42 @@ impl<'a> Decoder for NefDecoder<'a> {
 
     let offset = fetch_tag!(raw, Tag::StripOffsets).get_usize(0);
     let size = fetch_tag!(raw, Tag::StripByteCounts).get_usize(0);
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
     let mut cpp = 1;
-    let coeffs = self.get_wb()?;
+    let coeffs = self.get_wb(&camera)?;
 
     let image = if camera.model == "NIKON D100" {
       width = 3040;
-      decode_12be_wcontrol(src, width, height, dummy)
+      decode_12be_wcontrol(src, width, height, dummy)?
     } else {
       if compression == 1 || size == width*height*bps/8 {
         match bps {
           14 => if self.tiff.little_endian() {
-            decode_14le_unpacked(src, width, height, dummy)
+            decode_14le_unpacked(src, width, height, dummy)?
           } else {
-            decode_14be_unpacked(src, width, height, dummy)
+            decode_14be_unpacked(src, width, height, dummy)?
           },
           12 => if self.tiff.little_endian() {
-            decode_12le(src, width, height, dummy)
+            decode_12le(src, width, height, dummy)?
           } else {
-            decode_12be(src, width, height, dummy)
+            decode_12be(src, width, height, dummy)?
           },
-          x => return Err(format!("Don't know uncompressed bps {}", x).to_string()),
+          x => return Err(RawLoaderError::Unimplemented(format!("Don't know uncompressed bps {}", x))),
         }
       } else if size == width*height*3 {
         cpp = 3;
-        Self::decode_snef_compressed(src, coeffs, width, height, dummy)
+        Self::decode_snef_compressed(src, coeffs, width, height, dummy)?
       } else if compression == 34713 {
         self.decode_compressed(src, width, height, bps, dummy)?
       } else {
-        return Err(format!("NEF: Don't know compression {}", compression).to_string())
+        return Err(RawLoaderError::Unimplemented(format!("NEF: Don't know compression {}", compression)))
       }
     };
 
     let mut img = RawImage::new(camera, width, height, coeffs, image, false);
     if cpp == 3 {
       img.cpp = 3;
+      img.cfa = cfa::CFA::new("");
       img.blacklevels = [0,0,0,0];
       img.whitelevels = [65535,65535,65535,65535];
     }
@@ -154,64 +119,21 @@ impl<'a> Decoder for NefDecoder<'a> {
 }
 
 impl<'a> NefDecoder<'a> {
-  fn get_wb(&self) -> Result<[f32;4], String> {
+  fn get_wb(&self, camera: &Camera) -> Result<[f32;4], RawLoaderError> {
     if let Some(levels) = self.tiff.find_entry(Tag::NefWB0) {
-      Ok([levels.get_f32(0), 1.0, levels.get_f32(1), NAN])
-    } else if let Some(levels) = self.tiff.find_entry(Tag::NefWB1) {
-      let mut version: u32 = 0;
-      for i in 0..4 {
-        version = (version << 4) + (levels.get_data()[i]-b'0') as u32;
-      }
-      match version {
-        0x100 =>  Ok([levels.get_force_u16(36) as f32, levels.get_force_u16(38) as f32,
-                      levels.get_force_u16(37) as f32, NAN]),
-        0x103 =>  Ok([levels.get_force_u16(10) as f32, levels.get_force_u16(11) as f32,
-                      levels.get_force_u16(12) as f32, NAN]),
-        0x204 | 0x205 => {
-          let serial = fetch_tag!(self.tiff, Tag::NefSerial);
-          let data = serial.get_data();
-          let mut serialno = 0 as usize;
-          for i in 0..serial.count() {
-            if data[i] == 0 { break }
-            serialno = serialno*10 + if data[i] >= 48 && data[i] <= 57 { // "0" to "9"
-              (data[i]-48) as usize
-            } else {
-              (data[i]%10) as usize
-            };
-          }
-
-          // Get the "decryption" key
-          let keydata = fetch_tag!(self.tiff, Tag::NefKey).get_data();
-          let keyno = (keydata[0]^keydata[1]^keydata[2]^keydata[3]) as usize;
-
-          let src = if version == 0x204 {
-            &levels.get_data()[284..]
-          } else {
-            &levels.get_data()[4..]
-          };
-
-          let ci = WB_SERIALMAP[serialno & 0xff] as u32;
-          let mut cj = WB_KEYMAP[keyno & 0xff] as u32;
-          let mut ck = 0x60 as u32;
-          let mut buf = [0 as u8; 280];
-          for i in 0..280 {
-            cj += ci * ck;
-            ck += 1;
-            buf[i] = src[i] ^ (cj as u8);
-          }
-
-          let off = if version == 0x204 { 6 } else { 14 };
-          Ok([BEu16(&buf, off) as f32, BEu16(&buf, off+2) as f32,
-              BEu16(&buf, off+6) as f32, NAN])
-        },
-        x => Err(format!("NEF: Don't know about WB version 0x{:x}", x).to_string()),
+      Ok([levels.get_f32(0), 1.0, levels.get_f32(1), f32::NAN])
+    } else if let Some(Makernote::Nikon(ref nikon)) = camera.exif.makernote {
+      match (nikon.wb_as_shot, nikon.color_balance_version) {
+        (Some(wb), _) => Ok(wb),
+        (None, Some(x)) => Err(RawLoaderError::Unimplemented(format!("NEF: Don't know about WB version 0x{:x}", x))),
+        (None, None) => Err(RawLoaderError::Unimplemented("NEF: Don't know how to fetch WB".to_string())),
       }
     } else {
-      Err("NEF: Don't know how to fetch WB".to_string())
+      Err(RawLoaderError::Unimplemented("NEF: Don't know how to fetch WB".to_string()))
     }
   }
 
-  fn create_hufftable(num: usize) -> Result<HuffTable,String> {
+  fn create_hufftable(num: usize) -> Result<HuffTable, RawLoaderError> {
     let mut htable = HuffTable::empty();
 
     for i in 0..15 {
@@ -224,7 +146,7 @@ impl<'a> NefDecoder<'a> {
     Ok(htable)
   }
 
-  fn decode_compressed(&self, src: &[u8], width: usize, height: usize, bps: usize, dummy: bool) -> Result<Vec<u16>, String> {
+  fn decode_compressed(&self, src: &[u8], width: usize, height: usize, bps: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     let metaifd = fetch_ifd!(self.tiff, Tag::NefMeta1);
     let meta = if let Some(meta) = metaifd.find_entry(Tag::NefMeta2) {meta} else {
       fetch_tag!(metaifd, Tag::NefMeta1)
@@ -232,7 +154,7 @@ impl<'a> NefDecoder<'a> {
     Self::do_decode(src, meta.get_data(), metaifd.get_endian(), width, height, bps, dummy)
   }
 
-  pub(crate) fn do_decode(src: &[u8], meta: &[u8], endian: Endian, width: usize, height: usize, bps: usize, dummy: bool) -> Result<Vec<u16>, String> {
+  pub(crate) fn do_decode(src: &[u8], meta: &[u8], endian: Endian, width: usize, height: usize, bps: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     let mut out = alloc_image_ok!(width, height, dummy);
     let mut stream = ByteStream::new(meta, endian);
     let v0 = stream.get_u8();
@@ -264,7 +186,7 @@ impl<'a> NefDecoder<'a> {
     }
     let mut max = 1 << bps;
     let csize = stream.get_u16() as usize;
-    let mut split = 0 as usize;
+    let mut split: usize = 0;
     let step = if csize > 1 {
       max / (csize - 1)
     } else {
@@ -292,6 +214,7 @@ impl<'a> NefDecoder<'a> {
 
     let bps: u32 = bps as u32;
     for row in 0..height {
+      check_abort()?;
       if split > 0 && row == split {
         htable = Self::create_hufftable(huff_select+1)?;
       }
@@ -314,7 +237,7 @@ impl<'a> NefDecoder<'a> {
 
   // Decodes 12 bit data in an YUY2-like pattern (2 Luma, 1 Chroma per 2 pixels).
   // We un-apply the whitebalance, so output matches lossless.
-  pub(crate) fn decode_snef_compressed(src: &[u8], coeffs: [f32; 4], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+  pub(crate) fn decode_snef_compressed(src: &[u8], coeffs: [f32; 4], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     let inv_wb_r = (1024.0 / coeffs[0]) as i32;
     let inv_wb_b = (1024.0 / coeffs[2]) as i32;
 
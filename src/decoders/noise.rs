@@ -0,0 +1,159 @@
+use crate::decoders::*;
+use crate::decoders::tiff::*;
+
+// Number of signal levels the samples are grouped into when estimating
+const BINS: usize = 32;
+// Samples kept per level, plenty for a stable median
+const MAX_SAMPLES: usize = 4096;
+// Median of a chi-squared distribution with one degree of freedom, to turn the median of the
+// squared deviations into a variance
+const CHI2_MEDIAN: f64 = 0.4549;
+
+/// Noise model of one color where the variance of a pixel with signal x, normalized to 0.0 at
+/// the black level and 1.0 at the white level, is scale*x + offset. The scale comes from the
+/// shot noise and the offset from the read noise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NoiseModel {
+  /// variance per unit of signal
+  pub scale: f64,
+  /// variance at the black level
+  pub offset: f64,
+}
+
+impl NoiseModel {
+  /// Standard deviation of the noise at a normalized signal level
+  pub fn sigma(&self, x: f64) -> f64 {
+    (self.scale * x.max(0.0) + self.offset).max(0.0).sqrt()
+  }
+
+  // The NoiseProfile tag has a pair per plane in CFAPlaneColor order, or a single pair for
+  // all of them
+  pub(crate) fn from_tiff(tiff: &TiffIFD, planes: &[usize], ncolors: usize) -> Vec<NoiseModel> {
+    let entry = match tiff.find_entry(Tag::NoiseProfile) {
+      Some(e) if e.count() >= 2 && e.count() % 2 == 0 => e,
+      _ => return Vec::new(),
+    };
+    let pairs = entry.count() / 2;
+    let get = |i: usize| NoiseModel { scale: entry.get_f64(i*2), offset: entry.get_f64(i*2+1) };
+    if pairs == 1 {
+      return vec![get(0); ncolors]
+    }
+    let mut models = vec![NoiseModel { scale: 0.0, offset: 0.0 }; pairs.max(ncolors)];
+    for i in 0..pairs {
+      let idx = planes.get(i).cloned().unwrap_or(i);
+      if idx < models.len() {
+        models[idx] = get(i);
+      }
+    }
+    models.truncate(ncolors.max(1));
+    models
+  }
+}
+
+// The read noise comes from the masked areas when there are any. The shot noise comes from
+// the differences between each pixel and the next one of the same color in the row, grouped
+// by signal level and using the median at each level so edges and texture don't count.
+pub(crate) fn estimate(image: &RawImage) -> Option<Vec<NoiseModel>> {
+  if image.data_len() != image.width*image.height*image.cpp {
+    return None
+  }
+  let ncolors = if image.cpp == 1 { image.cfa.colors().len().max(1) } else { image.cpp };
+  let range = |color: usize| {
+    (image.whitelevels[color] as f64 - image.blacklevels[color] as f64).max(1.0)
+  };
+  let norm = |idx: usize, color: usize| (image.data.get_f32(idx) as f64 - image.blacklevels[color] as f64) / range(color);
+
+  let offsets = read_noise(image, ncolors, &norm);
+
+  let mut bins = vec![vec![Vec::new(); BINS]; ncolors];
+  let (top, bottom) = (image.crops[0], image.height.saturating_sub(image.crops[2]));
+  let (left, right) = (image.crops[3], image.width.saturating_sub(image.crops[1]));
+  for row in top..bottom {
+    for col in left..right {
+      for plane in 0..image.cpp {
+        let idx = (row*image.width + col)*image.cpp + plane;
+        let color = image.color_for_index(idx);
+        if color >= ncolors {
+          continue
+        }
+        let next = (col+1..(col+7).min(right))
+          .map(|ncol| (row*image.width + ncol)*image.cpp + plane)
+          .find(|&nidx| image.color_for_index(nidx) == color);
+        if let Some(nidx) = next {
+          let (a, b) = (norm(idx, color), norm(nidx, color));
+          let mean = (a + b) / 2.0;
+          // Stay away from clipping that flattens the noise
+          if (0.0..0.9).contains(&mean) {
+            let bin = &mut bins[color][(mean / 0.9 * BINS as f64) as usize];
+            if bin.len() < MAX_SAMPLES {
+              bin.push(((a - b) * (a - b) / 2.0, mean));
+            }
+          }
+        }
+      }
+    }
+  }
+
+  let mut models = Vec::new();
+  for (color, bins) in bins.iter_mut().enumerate() {
+    // Variance at each level from the median of the squared differences
+    let points: Vec<(f64, f64)> = bins.iter_mut().filter(|b| b.len() >= 64).map(|b| {
+      b.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap());
+      let variance = b[b.len()/2].0 / CHI2_MEDIAN;
+      let mean = b.iter().map(|s| s.1).sum::<f64>() / b.len() as f64;
+      (mean, variance)
+    }).collect();
+
+    let model = match offsets.get(color).cloned().flatten() {
+      Some(offset) => {
+        let (num, den) = points.iter().fold((0.0, 0.0), |(n, d), &(x, v)| (n + x*(v - offset), d + x*x));
+        if den <= 0.0 { return None }
+        NoiseModel { scale: (num / den).max(0.0), offset }
+      },
+      None => {
+        if points.len() < 2 { return None }
+        let n = points.len() as f64;
+        let (sx, sv) = points.iter().fold((0.0, 0.0), |(a, b), &(x, v)| (a + x, b + v));
+        let (mx, mv) = (sx / n, sv / n);
+        let (num, den) = points.iter().fold((0.0, 0.0), |(a, b), &(x, v)| (a + (x-mx)*(v-mv), b + (x-mx)*(x-mx)));
+        if den <= 0.0 { return None }
+        let scale = (num / den).max(0.0);
+        NoiseModel { scale, offset: (mv - scale*mx).max(0.0) }
+      },
+    };
+    if !model.scale.is_finite() || !model.offset.is_finite() {
+      return None
+    }
+    models.push(model);
+  }
+  Some(models)
+}
+
+fn read_noise<F>(image: &RawImage, ncolors: usize, norm: &F) -> Vec<Option<f64>>
+  where F: Fn(usize, usize) -> f64 {
+  let mut sums = vec![(0.0f64, 0.0f64, 0usize); ncolors];
+  for &(top, right, bottom, left) in &image.blackareas {
+    for row in (top as usize)..(bottom as usize).min(image.height) {
+      for col in (left as usize)..(right as usize).min(image.width) {
+        for plane in 0..image.cpp {
+          let idx = (row*image.width + col)*image.cpp + plane;
+          let color = image.color_for_index(idx);
+          if color < ncolors {
+            let v = norm(idx, color);
+            sums[color].0 += v;
+            sums[color].1 += v*v;
+            sums[color].2 += 1;
+          }
+        }
+      }
+    }
+  }
+  sums.iter().map(|&(sum, sumsq, count)| {
+    if count < 100 {
+      return None
+    }
+    let mean = sum / count as f64;
+    Some((sumsq / count as f64 - mean*mean).max(0.0))
+  }).collect()
+}
@@ -22,7 +22,11 @@ impl<'a> NrwDecoder<'a> {
 }
 
 impl<'a> Decoder for NrwDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "NRW"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let camera = self.rawloader.check_supported(&self.tiff)?;
     let data = self.tiff.find_ifds_with_tag(Tag::CFAPattern);
     let raw = data.iter().find(|&&ifd| {
@@ -31,16 +35,16 @@ impl<'a> Decoder for NrwDecoder<'a> {
     let width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0);
     let height = fetch_tag!(raw, Tag::ImageLength).get_usize(0);
     let offset = fetch_tag!(raw, Tag::StripOffsets).get_usize(0);
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
 
     let image = if camera.find_hint("coolpixsplit") {
-      decode_12be_interlaced_unaligned(src, width, height, dummy)
+      decode_12be_interlaced_unaligned(src, width, height, dummy)?
     } else if camera.find_hint("msb32") {
-      decode_12be_msb32(src, width, height, dummy)
+      decode_12be_msb32(src, width, height, dummy)?
     } else if camera.find_hint("unpacked") {
-      decode_16be(src, width, height, dummy)
+      decode_16be(src, width, height, dummy)?
     } else {
-      decode_12be(src, width, height, dummy)
+      decode_12be(src, width, height, dummy)?
     };
 
     let wb = self.get_wb(&camera)?;
@@ -49,7 +53,7 @@ impl<'a> Decoder for NrwDecoder<'a> {
 }
 
 impl<'a> NrwDecoder<'a> {
-  fn get_wb(&self, cam: &Camera) -> Result<[f32;4], String> {
+  fn get_wb(&self, cam: &Camera) -> Result<[f32;4], RawLoaderError> {
     if cam.find_hint("nowb") {
       Ok([NAN,NAN,NAN,NAN])
     } else if let Some(levels) = self.tiff.find_entry(Tag::NefWB0) {
@@ -71,7 +75,7 @@ impl<'a> NrwDecoder<'a> {
         Ok([BEu16(data,1248) as f32, 256.0, BEu16(data,1250) as f32, NAN])
       }
     } else {
-      Err("NRW: Don't know how to fetch WB".to_string())
+      Err(RawLoaderError::Unimplemented("NRW: Don't know how to fetch WB".to_string()))
     }
   }
 }
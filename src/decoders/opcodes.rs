@@ -0,0 +1,634 @@
+// Parsing of the DNG opcode lists, the processing steps a DNG asks the reader to apply to the
+// raw data at different stages. OpcodeList1 applies to the raw data as stored, OpcodeList2 to
+// the raw data after linearization and cropping to the ActiveArea, and OpcodeList3 to the
+// demosaiced image.
+
+use std::cmp;
+
+use crate::decoders::*;
+use crate::decoders::basics::*;
+use crate::decoders::tiff::*;
+
+/// The three opcode lists of a DNG
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DngOpcodes {
+  /// opcodes to apply to the raw data as stored in the file
+  pub list1: Vec<DngOpcode>,
+  /// opcodes to apply to the raw data after cropping to the active area
+  pub list2: Vec<DngOpcode>,
+  /// opcodes to apply to the demosaiced image
+  pub list3: Vec<DngOpcode>,
+}
+
+/// A single opcode from one of the lists
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DngOpcode {
+  /// opcode ID as assigned by the DNG specification
+  pub id: u32,
+  /// DNG version the opcode was written for
+  pub version: [u8;4],
+  /// the opcode can be skipped if the reader doesn't know how to apply it
+  pub optional: bool,
+  /// the opcode can be skipped when rendering previews
+  pub preview_skip: bool,
+  /// the parameters of the opcode
+  pub operation: Operation,
+}
+
+/// The operation of an opcode along with its parameters
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Operation {
+  /// Distortion and lateral chromatic aberration correction for rectilinear lenses
+  WarpRectilinear(WarpRectilinear),
+  /// Correction that maps a fisheye lens to a rectilinear projection
+  WarpFisheye(WarpFisheye),
+  /// Vignetting correction as a radial polynomial
+  FixVignetteRadial(FixVignetteRadial),
+  /// Pixels with the given value are defective
+  FixBadPixelsConstant {
+    /// value the defective pixels have
+    constant: u32,
+    /// which CFA position the top left pixel is in (0 = red, 1 = green in a red row,
+    /// 2 = green in a blue row, 3 = blue)
+    bayer_phase: u32,
+  },
+  /// List of defective pixels and rectangles
+  FixBadPixelsList(BadPixelList),
+  /// Crop the image to the given rectangle
+  TrimBounds {
+    /// rectangle to keep as top, left, bottom, right
+    bounds: [u32;4],
+  },
+  /// Multiply an area of the image by a smoothly varying gain, mostly used for vignetting
+  /// and color shading correction
+  GainMap(GainMap),
+  /// Any other opcode, with its parameters left as raw big endian data
+  Other(Vec<u8>),
+}
+
+/// The parameters of a WarpRectilinear opcode
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WarpRectilinear {
+  /// per plane radial (kr0 to kr3) and tangential (kt0, kt1) coefficients. A single set
+  /// applies to all planes
+  pub coefficients: Vec<[f64;6]>,
+  /// optical center relative to the image, (0.5, 0.5) being the center of the image
+  pub center: (f64, f64),
+}
+
+/// The parameters of a WarpFisheye opcode
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WarpFisheye {
+  /// per plane radial coefficients (kr0 to kr3). A single set applies to all planes
+  pub coefficients: Vec<[f64;4]>,
+  /// optical center relative to the image, (0.5, 0.5) being the center of the image
+  pub center: (f64, f64),
+}
+
+/// The parameters of a FixVignetteRadial opcode
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FixVignetteRadial {
+  /// coefficients of the gain polynomial 1 + k0*r^2 + k1*r^4 + ... + k4*r^10
+  pub coefficients: [f64;5],
+  /// optical center relative to the image, (0.5, 0.5) being the center of the image
+  pub center: (f64, f64),
+}
+
+/// The parameters of a FixBadPixelsList opcode
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BadPixelList {
+  /// which CFA position the top left pixel is in
+  pub bayer_phase: u32,
+  /// defective pixels as (row, column)
+  pub points: Vec<(u32,u32)>,
+  /// defective rectangles as top, left, bottom, right
+  pub rects: Vec<[u32;4]>,
+}
+
+/// The area and planes of the image an opcode applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OpcodeArea {
+  /// rectangle as top, left, bottom, right
+  pub bounds: [u32;4],
+  /// first plane it applies to
+  pub plane: u32,
+  /// number of planes it applies to
+  pub planes: u32,
+  /// only every row_pitch rows are affected
+  pub row_pitch: u32,
+  /// only every col_pitch columns are affected
+  pub col_pitch: u32,
+}
+
+/// The parameters of a GainMap opcode
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GainMap {
+  /// the area the gains apply to
+  pub area: OpcodeArea,
+  /// number of map points vertically
+  pub points_v: u32,
+  /// number of map points horizontally
+  pub points_h: u32,
+  /// vertical distance between map points relative to the image height
+  pub spacing_v: f64,
+  /// horizontal distance between map points relative to the image width
+  pub spacing_h: f64,
+  /// vertical position of the first map point relative to the image height
+  pub origin_v: f64,
+  /// horizontal position of the first map point relative to the image width
+  pub origin_h: f64,
+  /// number of planes in the map, if lower than the planes in the area the last one is
+  /// used for the rest
+  pub map_planes: u32,
+  /// the gains indexed by row, column and then plane
+  pub gains: Vec<f32>,
+}
+
+impl DngOpcodes {
+  pub(crate) fn from_tiff(raw: &TiffIFD) -> DngOpcodes {
+    let parse = |tag| raw.find_entry(tag).map(|e| parse_list(e.get_data())).unwrap_or_default();
+    DngOpcodes {
+      list1: parse(Tag::OpcodeList1),
+      list2: parse(Tag::OpcodeList2),
+      list3: parse(Tag::OpcodeList3),
+    }
+  }
+
+  /// Checks if none of the lists have any opcodes
+  pub fn is_empty(&self) -> bool {
+    self.list1.is_empty() && self.list2.is_empty() && self.list3.is_empty()
+  }
+
+  // The pixels of the FixBadPixelsList opcodes in full image coordinates
+  pub(crate) fn bad_pixels(&self, crops: &[usize;4], width: usize, height: usize) -> Vec<(u32,u32)> {
+    let (full, active) = frames(crops, width, height);
+    let mut pixels = Vec::new();
+    for (list, frame) in [(&self.list1, full), (&self.list2, active)].iter() {
+      for opcode in list.iter() {
+        if let Operation::FixBadPixelsList(ref bad) = opcode.operation {
+          pixels.append(&mut bad.pixels(*frame));
+        }
+      }
+    }
+    pixels.sort_unstable();
+    pixels.dedup();
+    pixels
+  }
+}
+
+impl BadPixelList {
+  fn pixels(&self, frame: Frame) -> Vec<(u32,u32)> {
+    let mut pixels = Vec::new();
+    for &(row, col) in self.points.iter() {
+      if (row as usize) < frame.height && (col as usize) < frame.width {
+        pixels.push(((row as usize + frame.top) as u32, (col as usize + frame.left) as u32));
+      }
+    }
+    for &[t, l, b, r] in self.rects.iter() {
+      for row in (t as usize)..cmp::min(b as usize, frame.height) {
+        for col in (l as usize)..cmp::min(r as usize, frame.width) {
+          pixels.push(((row + frame.top) as u32, (col + frame.left) as u32));
+        }
+      }
+    }
+    pixels
+  }
+}
+
+impl GainMap {
+  /// Gain for a position relative to the image (0.0 to 1.0 from top to bottom and left to
+  /// right), interpolating bilinearly between the map points
+  pub fn gain(&self, v: f64, h: f64, plane: usize) -> f32 {
+    let (points_v, points_h) = (self.points_v as usize, self.points_h as usize);
+    let map_planes = self.map_planes as usize;
+    if points_v == 0 || points_h == 0 || map_planes == 0 || self.gains.len() < points_v*points_h*map_planes {
+      return 1.0
+    }
+    let plane = cmp::min(plane, map_planes-1);
+    let index = |pos: f64, origin: f64, spacing: f64, points: usize| -> (usize, usize, f32) {
+      let p = if spacing > 0.0 { (pos - origin) / spacing } else { 0.0 };
+      let p = p.max(0.0).min((points-1) as f64);
+      let i = p.floor() as usize;
+      (i, cmp::min(i+1, points-1), (p - i as f64) as f32)
+    };
+    let (r0, r1, fr) = index(v, self.origin_v, self.spacing_v, points_v);
+    let (c0, c1, fc) = index(h, self.origin_h, self.spacing_h, points_h);
+    let get = |r: usize, c: usize| self.gains[(r*points_h + c)*map_planes + plane];
+    let top = get(r0, c0) * (1.0 - fc) + get(r0, c1) * fc;
+    let bottom = get(r1, c0) * (1.0 - fc) + get(r1, c1) * fc;
+    top * (1.0 - fr) + bottom * fr
+  }
+}
+
+// The area of the image an opcode list works on, in full image coordinates
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+  top: usize,
+  left: usize,
+  width: usize,
+  height: usize,
+}
+
+impl Frame {
+  fn center(&self, center: (f64, f64)) -> (f64, f64) {
+    (center.0 * (self.width as f64 - 1.0), center.1 * (self.height as f64 - 1.0))
+  }
+
+  // Distance from the center to the farthest corner, that radii are normalized to
+  fn max_radius(&self, cx: f64, cy: f64) -> f64 {
+    let dx = cx.max(self.width as f64 - 1.0 - cx);
+    let dy = cy.max(self.height as f64 - 1.0 - cy);
+    (dx*dx + dy*dy).sqrt().max(1.0)
+  }
+}
+
+// OpcodeList1 works on the stored image while the other two work after cropping to the
+// ActiveArea
+fn frames(crops: &[usize;4], width: usize, height: usize) -> (Frame, Frame) {
+  let full = Frame { top: 0, left: 0, width, height };
+  let active = Frame {
+    top: crops[0],
+    left: crops[3],
+    width: width.saturating_sub(crops[1] + crops[3]),
+    height: height.saturating_sub(crops[0] + crops[2]),
+  };
+  (full, active)
+}
+
+// Apply the opcodes that we know how to, removing them from the image so they don't get
+// applied twice. OpcodeList3 is meant for demosaiced data so it's only used on images that
+// already are.
+pub(crate) fn apply(image: &mut RawImage) {
+  if image.data_len() != image.width*image.height*image.cpp {
+    return
+  }
+  let (full, active) = frames(&image.crops, image.width, image.height);
+  let mut opcodes = std::mem::take(&mut image.opcodes);
+  opcodes.list1.retain(|op| !apply_opcode(image, op, full));
+  opcodes.list2.retain(|op| !apply_opcode(image, op, active));
+  if image.cpp > 1 {
+    opcodes.list3.retain(|op| !apply_opcode(image, op, active));
+  }
+  for opcode in opcodes.list1.iter().chain(&opcodes.list2).chain(&opcodes.list3) {
+    image.warnings.push(DecodeWarning::IgnoredOpcode(opcode.id));
+  }
+  image.opcodes = opcodes;
+}
+
+fn apply_opcode(image: &mut RawImage, opcode: &DngOpcode, frame: Frame) -> bool {
+  match opcode.operation {
+    Operation::FixBadPixelsList(ref bad) => image.interpolate_pixels(&bad.pixels(frame)),
+    Operation::FixBadPixelsConstant{constant, ..} => {
+      let mut pixels = Vec::new();
+      for row in frame.top..frame.top+frame.height {
+        for col in frame.left..frame.left+frame.width {
+          let idx = (row*image.width + col)*image.cpp;
+          if image.data.get_f32(idx) == constant as f32 {
+            pixels.push((row as u32, col as u32));
+          }
+        }
+      }
+      image.interpolate_pixels(&pixels);
+    },
+    Operation::GainMap(ref map) => apply_gain_map(image, map, frame),
+    Operation::FixVignetteRadial(ref vignette) => apply_vignette(image, vignette, frame),
+    Operation::WarpRectilinear(ref warp) => apply_warp(image, warp, frame),
+    _ => return false,
+  }
+  true
+}
+
+// Scale a value keeping the black level in place, as the opcodes are meant for data that
+// already has it subtracted
+fn scale(image: &mut RawImage, idx: usize, gain: f32) {
+  let black = image.blacklevels[image.color_for_index(idx)] as f32;
+  let val = image.data.get_f32(idx);
+  image.data.set_f32(idx, (val - black) * gain + black);
+}
+
+fn apply_gain_map(image: &mut RawImage, map: &GainMap, frame: Frame) {
+  let area = &map.area;
+  let bottom = cmp::min(area.bounds[2] as usize, frame.height);
+  let right = cmp::min(area.bounds[3] as usize, frame.width);
+  let planes = (area.plane as usize)..cmp::min((area.plane + area.planes) as usize, image.cpp);
+  for row in (area.bounds[0] as usize..bottom).step_by(cmp::max(area.row_pitch, 1) as usize) {
+    let v = (row as f64 + 0.5) / frame.height as f64;
+    for col in (area.bounds[1] as usize..right).step_by(cmp::max(area.col_pitch, 1) as usize) {
+      let h = (col as f64 + 0.5) / frame.width as f64;
+      let pos = ((row + frame.top)*image.width + col + frame.left)*image.cpp;
+      for plane in planes.clone() {
+        let gain = map.gain(v, h, plane - area.plane as usize);
+        scale(image, pos + plane, gain);
+      }
+    }
+  }
+}
+
+fn apply_vignette(image: &mut RawImage, vignette: &FixVignetteRadial, frame: Frame) {
+  let (cx, cy) = frame.center(vignette.center);
+  let radius = frame.max_radius(cx, cy);
+  let k = vignette.coefficients;
+  for row in 0..frame.height {
+    for col in 0..frame.width {
+      let (dx, dy) = ((col as f64 - cx) / radius, (row as f64 - cy) / radius);
+      let r2 = dx*dx + dy*dy;
+      let gain = 1.0 + r2*(k[0] + r2*(k[1] + r2*(k[2] + r2*(k[3] + r2*k[4]))));
+      let pos = ((row + frame.top)*image.width + col + frame.left)*image.cpp;
+      for plane in 0..image.cpp {
+        scale(image, pos + plane, gain as f32);
+      }
+    }
+  }
+}
+
+// Each output pixel is sampled from where the lens model says it came from. Mosaiced data
+// can only be sampled from the pixels of the same color so those get a wider tent filter. In
+// a 2x2 pattern they're taken from the same position in it, a grid the filter interpolates
+// bilinearly on, as otherwise the two greens of a Bayer pattern get blurred into each other.
+fn apply_warp(image: &mut RawImage, warp: &WarpRectilinear, frame: Frame) {
+  if warp.coefficients.is_empty() {
+    return
+  }
+  let source = image.clone();
+  let (cx, cy) = frame.center(warp.center);
+  let radius = frame.max_radius(cx, cy);
+  let reach: isize = if image.cpp == 1 { 2 } else { 1 };
+  let bayer = image.cpp == 1 && image.cfa.width == 2 && image.cfa.height == 2;
+
+  for row in 0..frame.height {
+    for col in 0..frame.width {
+      let (dx, dy) = ((col as f64 - cx) / radius, (row as f64 - cy) / radius);
+      let r2 = dx*dx + dy*dy;
+      for plane in 0..image.cpp {
+        let k = warp.coefficients[cmp::min(plane, warp.coefficients.len()-1)];
+        let f = k[0] + r2*(k[1] + r2*(k[2] + r2*k[3]));
+        let sx = cx + radius * (f*dx + k[4]*2.0*dx*dy + k[5]*(r2 + 2.0*dx*dx));
+        let sy = cy + radius * (f*dy + k[5]*2.0*dx*dy + k[4]*(r2 + 2.0*dy*dy));
+
+        let idx = ((row + frame.top)*image.width + col + frame.left)*image.cpp + plane;
+        let color = image.color_for_index(idx);
+        let (mut sum, mut weights) = (0.0f64, 0.0f64);
+        for srow in (sy.floor() as isize - reach + 1)..=(sy.floor() as isize + reach) {
+          for scol in (sx.floor() as isize - reach + 1)..=(sx.floor() as isize + reach) {
+            if srow < 0 || scol < 0 || srow as usize >= frame.height || scol as usize >= frame.width {
+              continue
+            }
+            let sidx = ((srow as usize + frame.top)*image.width + scol as usize + frame.left)*image.cpp + plane;
+            if source.color_for_index(sidx) != color ||
+               (bayer && ((srow - row as isize) % 2 != 0 || (scol - col as isize) % 2 != 0)) {
+              continue
+            }
+            let w = (reach as f64 - (srow as f64 - sy).abs()).max(0.0) *
+                    (reach as f64 - (scol as f64 - sx).abs()).max(0.0);
+            sum += source.data.get_f32(sidx) as f64 * w;
+            weights += w;
+          }
+        }
+        if weights > 0.0 {
+          image.data.set_f32(idx, (sum / weights) as f32);
+        }
+      }
+    }
+  }
+}
+
+// Opcode lists are always big endian no matter the endianness of the file
+struct Reader<'a> {
+  data: &'a [u8],
+  pos: usize,
+}
+
+impl<'a> Reader<'a> {
+  fn u32(&mut self) -> Option<u32> {
+    if self.pos + 4 > self.data.len() {
+      return None
+    }
+    self.pos += 4;
+    Some(BEu32(self.data, self.pos-4))
+  }
+
+  fn f32(&mut self) -> Option<f32> {
+    self.u32().map(f32::from_bits)
+  }
+
+  fn f64(&mut self) -> Option<f64> {
+    let hi = self.u32()? as u64;
+    let lo = self.u32()? as u64;
+    Some(f64::from_bits(hi << 32 | lo))
+  }
+
+  fn rect(&mut self) -> Option<[u32;4]> {
+    Some([self.u32()?, self.u32()?, self.u32()?, self.u32()?])
+  }
+}
+
+fn parse_list(data: &[u8]) -> Vec<DngOpcode> {
+  let mut opcodes = Vec::new();
+  let mut reader = Reader { data, pos: 0 };
+  let count = reader.u32().unwrap_or(0);
+  for _ in 0..count {
+    let (id, version, flags, size) = match (reader.u32(), reader.u32(), reader.u32(), reader.u32()) {
+      (Some(id), Some(version), Some(flags), Some(size)) => (id, version, flags, size as usize),
+      _ => break,
+    };
+    let start = reader.pos;
+    if start + size > data.len() {
+      break
+    }
+    reader.pos += size;
+
+    let params = &data[start..start+size];
+    let operation = parse_operation(id, params).unwrap_or_else(|| Operation::Other(params.to_vec()));
+    opcodes.push(DngOpcode {
+      id,
+      version: version.to_be_bytes(),
+      optional: flags & 1 != 0,
+      preview_skip: flags & 2 != 0,
+      operation,
+    });
+  }
+  opcodes
+}
+
+fn parse_operation(id: u32, params: &[u8]) -> Option<Operation> {
+  let mut r = Reader { data: params, pos: 0 };
+  Some(match id {
+    1 => {
+      let planes = r.u32()?;
+      let mut coefficients = Vec::new();
+      for _ in 0..planes {
+        coefficients.push([r.f64()?, r.f64()?, r.f64()?, r.f64()?, r.f64()?, r.f64()?]);
+      }
+      Operation::WarpRectilinear(WarpRectilinear { coefficients, center: (r.f64()?, r.f64()?) })
+    },
+    2 => {
+      let planes = r.u32()?;
+      let mut coefficients = Vec::new();
+      for _ in 0..planes {
+        coefficients.push([r.f64()?, r.f64()?, r.f64()?, r.f64()?]);
+      }
+      Operation::WarpFisheye(WarpFisheye { coefficients, center: (r.f64()?, r.f64()?) })
+    },
+    3 => Operation::FixVignetteRadial(FixVignetteRadial {
+      coefficients: [r.f64()?, r.f64()?, r.f64()?, r.f64()?, r.f64()?],
+      center: (r.f64()?, r.f64()?),
+    }),
+    4 => Operation::FixBadPixelsConstant { constant: r.u32()?, bayer_phase: r.u32()? },
+    5 => {
+      let bayer_phase = r.u32()?;
+      let (npoints, nrects) = (r.u32()?, r.u32()?);
+      let mut points = Vec::new();
+      for _ in 0..npoints {
+        points.push((r.u32()?, r.u32()?));
+      }
+      let mut rects = Vec::new();
+      for _ in 0..nrects {
+        rects.push(r.rect()?);
+      }
+      Operation::FixBadPixelsList(BadPixelList { bayer_phase, points, rects })
+    },
+    6 => Operation::TrimBounds { bounds: r.rect()? },
+    9 => {
+      let area = OpcodeArea {
+        bounds: r.rect()?,
+        plane: r.u32()?,
+        planes: r.u32()?,
+        row_pitch: r.u32()?,
+        col_pitch: r.u32()?,
+      };
+      let (points_v, points_h) = (r.u32()?, r.u32()?);
+      let (spacing_v, spacing_h) = (r.f64()?, r.f64()?);
+      let (origin_v, origin_h) = (r.f64()?, r.f64()?);
+      let map_planes = r.u32()?;
+      let count = points_v as usize * points_h as usize * map_planes as usize;
+      if count == 0 || r.pos + count*4 > params.len() {
+        return None
+      }
+      let gains = (0..count).map(|_| r.f32().unwrap()).collect();
+      Operation::GainMap(GainMap {
+        area, points_v, points_h, spacing_v, spacing_h, origin_v, origin_h, map_planes, gains,
+      })
+    },
+    _ => return None,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::decoders::cfa::CFA;
+
+  fn warp_opcode(coefficients: [f64;6]) -> DngOpcode {
+    DngOpcode {
+      id: 1,
+      version: [1, 3, 0, 0],
+      optional: true,
+      preview_skip: false,
+      operation: Operation::WarpRectilinear(WarpRectilinear { coefficients: vec![coefficients], center: (0.5, 0.5) }),
+    }
+  }
+
+  fn float_image(width: usize, height: usize, cpp: usize, value: impl Fn(usize, usize, usize) -> f32) -> RawImage {
+    let data = (0..width*height*cpp).map(|i| value(i / cpp / width, i / cpp % width, i % cpp)).collect();
+    RawImage::from_data("Make", "Model", width, height, cpp, RawImageData::Float(data))
+  }
+
+  fn floats(image: &RawImage) -> &[f32] {
+    match image.data {
+      RawImageData::Float(ref data) => data,
+      RawImageData::Integer(_) => unreachable!(),
+    }
+  }
+
+  #[test]
+  fn identity_warp_keeps_the_data() {
+    for cpp in [1, 3] {
+      let mut image = float_image(10, 8, cpp, |row, col, plane| (row*31 + col*7 + plane) as f32);
+      image.cfa = CFA::new("RGGB");
+      let before = floats(&image).to_vec();
+      image.opcodes.list2.push(warp_opcode([1.0, 0.0, 0.0, 0.0, 0.0, 0.0]));
+      apply(&mut image);
+      assert!(image.opcodes.list2.is_empty());
+      assert_eq!(floats(&image), &before[..]);
+    }
+  }
+
+  #[test]
+  fn radial_warp_samples_from_the_model() {
+    // Halving kr0 samples each pixel from halfway to the center, which with a linear ramp
+    // has a value that's easy to work out
+    let mut image = float_image(9, 7, 3, |row, col, plane| [col as f32, row as f32, 5.0][plane]);
+    image.opcodes.list2.push(warp_opcode([0.5, 0.0, 0.0, 0.0, 0.0, 0.0]));
+    apply(&mut image);
+    let data = floats(&image);
+    let (cx, cy) = (4.0, 3.0);
+    for row in 0..7 {
+      for col in 0..9 {
+        let pix = &data[(row*9 + col)*3..][..3];
+        assert!((pix[0] - (cx + (col as f32 - cx) * 0.5)).abs() < 1e-4, "{},{}: {:?}", row, col, pix);
+        assert!((pix[1] - (cy + (row as f32 - cy) * 0.5)).abs() < 1e-4, "{},{}: {:?}", row, col, pix);
+        assert!((pix[2] - 5.0).abs() < 1e-4);
+      }
+    }
+  }
+
+  #[test]
+  fn warp_of_a_mosaic_keeps_the_colors() {
+    // Each color has its own value, so sampling from pixels of other colors would show
+    let cfa = CFA::new("GBRG");
+    let code = |color: usize| 100.0 * (color as f32 + 1.0);
+    let mut image = float_image(20, 16, 1, |row, col, _| code(cfa.color_at(row, col)));
+    image.cfa = cfa.clone();
+    image.crops = [2, 3, 1, 1];
+    // Barrel distortion with some tangential terms, only on the active area
+    image.opcodes.list2.push(warp_opcode([0.9, 0.08, -0.02, 0.0, 0.01, -0.01]));
+    for row in 0..16 {
+      for col in 0..20 {
+        if row < 2 || row >= 15 || col < 1 || col >= 17 {
+          image.data.set_f32(row*20 + col, 1.0);
+        }
+      }
+    }
+    apply(&mut image);
+    let data = floats(&image);
+    for row in 0..16 {
+      for col in 0..20 {
+        let expected = if row < 2 || row >= 15 || col < 1 || col >= 17 { 1.0 } else { code(cfa.color_at(row, col)) };
+        assert!((data[row*20 + col] - expected).abs() < 1e-3, "{},{}: {}", row, col, data[row*20 + col]);
+      }
+    }
+  }
+
+  #[test]
+  fn gain_map_interpolates_bilinearly() {
+    let map = GainMap {
+      area: OpcodeArea { bounds: [0, 0, 10, 10], plane: 0, planes: 1, row_pitch: 1, col_pitch: 1 },
+      points_v: 2,
+      points_h: 2,
+      spacing_v: 1.0,
+      spacing_h: 1.0,
+      origin_v: 0.0,
+      origin_h: 0.0,
+      map_planes: 1,
+      gains: vec![1.0, 2.0, 3.0, 4.0],
+    };
+    assert_eq!(map.gain(0.0, 0.0, 0), 1.0);
+    assert_eq!(map.gain(0.0, 1.0, 0), 2.0);
+    assert_eq!(map.gain(1.0, 0.0, 0), 3.0);
+    assert_eq!(map.gain(1.0, 1.0, 0), 4.0);
+    assert_eq!(map.gain(0.5, 0.5, 0), 2.5);
+    assert_eq!(map.gain(0.25, 0.5, 0), 2.0);
+    // Outside of the map the closest points are used
+    assert_eq!(map.gain(-1.0, 2.0, 0), 2.0);
+    assert_eq!(GainMap { gains: Vec::new(), ..map }.gain(0.5, 0.5, 0), 1.0);
+  }
+}
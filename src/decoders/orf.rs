@@ -23,7 +23,11 @@ impl<'a> OrfDecoder<'a> {
 }
 
 impl<'a> Decoder for OrfDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "ORF"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let camera = self.rawloader.check_supported(&self.tiff)?;
     let raw = fetch_ifd!(&self.tiff, Tag::StripOffsets);
     let width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0);
@@ -41,24 +45,24 @@ impl<'a> Decoder for OrfDecoder<'a> {
       camera
     };
 
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
 
     let image = if size >= width*height*2 {
       if self.tiff.little_endian() {
-        decode_12le_unpacked_left_aligned(src, width, height, dummy)
+        decode_12le_unpacked_left_aligned(src, width, height, dummy)?
       } else {
-        decode_12be_unpacked_left_aligned(src, width, height, dummy)
+        decode_12be_unpacked_left_aligned(src, width, height, dummy)?
       }
     } else if size >= width*height/10*16 {
-      decode_12le_wcontrol(src, width, height, dummy)
+      decode_12le_wcontrol(src, width, height, dummy)?
     } else if size >= width*height*12/8 {
       if width < 3500 { // The interlaced stuff is all old and smaller
-        decode_12be_interlaced(src, width, height, dummy)
+        decode_12be_interlaced(src, width, height, dummy)?
       } else {
-        decode_12be_msb32(src, width, height, dummy)
+        decode_12be_msb32(src, width, height, dummy)?
       }
     } else {
-      OrfDecoder::decode_compressed(src, width, height, dummy)
+      OrfDecoder::decode_compressed(src, width, height, dummy)?
     };
 
     match self.get_blacks() {
@@ -77,8 +81,8 @@ impl<'a> OrfDecoder<'a> {
    * is based on the output of all previous pixel (bar the first four)
    */
 
-  pub fn decode_compressed(buf: &'a [u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
-    let mut out: Vec<u16> = alloc_image!(width, height, dummy);
+  pub fn decode_compressed(buf: &'a [u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+    let mut out: Vec<u16> = alloc_image_ok!(width, height, dummy);
 
     /* Build a table to quickly look up "high" value */
     let mut bittable: [u8; 4096] = [0; 4096];
@@ -95,6 +99,7 @@ impl<'a> OrfDecoder<'a> {
     let mut pump = BitPumpMSB::new(&buf[7..]);
 
     for row in 0..height {
+      check_abort()?;
       let mut acarry: [[i32; 3];2] = [[0; 3];2];
 
       for c in 0..width/2 {
@@ -156,15 +161,15 @@ impl<'a> OrfDecoder<'a> {
         }
       }
     }
-    out
+    Ok(out)
   }
 
-  fn get_blacks(&self) -> Result<[u16;4], String> {
+  fn get_blacks(&self) -> Result<[u16;4], RawLoaderError> {
     let blacks = fetch_tag!(self.tiff, Tag::OrfBlackLevels);
     Ok([blacks.get_u16(0), blacks.get_u16(1), blacks.get_u16(2), blacks.get_u16(3)])
   }
 
-  fn get_wb(&self) -> Result<[f32;4],String> {
+  fn get_wb(&self) -> Result<[f32;4], RawLoaderError> {
     let redmul = self.tiff.find_entry(Tag::OlympusRedMul);
     let bluemul = self.tiff.find_entry(Tag::OlympusBlueMul);
 
@@ -173,7 +178,7 @@ impl<'a> OrfDecoder<'a> {
     } else {
       let ifd = self.tiff.find_ifds_with_tag(Tag::OrfBlackLevels);
       if ifd.len() == 0 {
-        return Err("ORF: Couldn't find ImgProc IFD".to_string());
+        return Err(RawLoaderError::Corrupt("ORF: Couldn't find ImgProc IFD".to_string()));
       }
       let wbs = fetch_tag!(ifd[0], Tag::ImageWidth);
       Ok([wbs.get_f32(0), 256.0, wbs.get_f32(1),NAN])
@@ -1,8 +1,33 @@
+//! Unpacking of the bit packings raw data is stored in, into one u16 per sample. They're the
+//! routines the decoders use, so they're just as usable for frames that come from elsewhere,
+//! like a camera SDK or an astronomy camera.
+//!
+//! All of them take the packed data, the width and height of the image in samples and a
+//! `dummy` flag, and return the `width`\*`height` samples in row major order, decoding the
+//! rows in parallel. With `dummy` set the data isn't read and a single 0 is returned, which
+//! the decoders use to get the metadata of a file quickly.
+//!
+//! # Errors
+//! `RawLoaderError::LimitExceeded` if the image is over the size limit of the default
+//! `RawLoader` or there isn't enough memory for it.
+//!
+//! # Panics
+//! With a `RawLoaderError::Corrupt` payload if `buf` is too short for the image.
+//!
+//! # Example
+//! ```rust
+//! // two 12 bit samples, 0xabc and 0x123
+//! let packed = [0xab, 0xc1, 0x23];
+//! assert_eq!(rawloader::packed::decode_12be(&packed, 2, 1, false).unwrap(), vec![0xabc, 0x123]);
+//! ```
+
 use crate::decoders::basics::*;
+use crate::decoders::RawLoaderError;
+use crate::decoders::rawref;
 
-pub fn decode_8bit_wtable(buf: &[u8], tbl: &LookupTable, width: usize, height: usize, dummy: bool) -> Vec<u16> {
+pub(crate) fn decode_8bit_wtable(buf: &[u8], tbl: &LookupTable, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width)..];
+    let inb = rest(buf, row*width);
     let mut random = LEu32(inb, 0);
 
     for (o, i) in out.chunks_exact_mut(1).zip(inb.chunks_exact(1)) {
@@ -11,9 +36,10 @@ pub fn decode_8bit_wtable(buf: &[u8], tbl: &LookupTable, width: usize, height: u
   }))
 }
 
-pub fn decode_10le_lsb16(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 10 bit samples packed MSB first in 16 bit little endian words, 8 samples every 10 bytes
+pub fn decode_10le_lsb16(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*10/8)..];
+    let inb = rest(buf, row*width*10/8);
 
     for (o, i) in out.chunks_exact_mut(8).zip(inb.chunks_exact(10)) {
       let g1:  u16 = i[0] as u16;
@@ -39,9 +65,10 @@ pub fn decode_10le_lsb16(buf: &[u8], width: usize, height: usize, dummy: bool) -
   }))
 }
 
-pub fn decode_10le(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 10 bit samples packed MSB first, 4 samples every 5 bytes, as in DNGs
+pub fn decode_10le(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*10/8)..];
+    let inb = rest(buf, row*width*10/8);
 
     for (o, i) in out.chunks_exact_mut(4).zip(inb.chunks_exact(5)) {
       let g1:  u16 = i[0] as u16;
@@ -52,15 +79,16 @@ pub fn decode_10le(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<
 
       o[0] = g1 << 2  | g2 >> 6;
       o[1] = (g2 & 0x3f) << 4 | g3 >> 4;
-      o[2] = (g3 & 0x0f) << 6 | g3 >> 2;
+      o[2] = (g3 & 0x0f) << 6 | g4 >> 2;
       o[3] = (g4 & 0x03) << 8 | g5;
     }
   }))
 }
 
-pub fn decode_12be(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 12 bit samples packed MSB first, 2 samples every 3 bytes
+pub fn decode_12be(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*12/8)..];
+    let inb = rest(buf, row*width*12/8);
 
     for (o, i) in out.chunks_exact_mut(2).zip(inb.chunks_exact(3)) {
       let g1: u16 = i[0] as u16;
@@ -73,8 +101,10 @@ pub fn decode_12be(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<
   }))
 }
 
-pub fn decode_12be_msb16(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
-  let mut out: Vec<u16> = alloc_image!(width, height, dummy);
+/// 12 bit samples packed MSB first in 16 bit little endian words, 4 samples every 6 bytes.
+/// Rows aren't padded, so unlike the others it decodes the buffer as a single run of samples
+pub fn decode_12be_msb16(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+  let mut out: Vec<u16> = alloc_image_ok!(width, height, dummy);
 
   for (o, i) in out.chunks_exact_mut(4).zip(buf.chunks_exact(6)) {
     let g1:  u16 = i[ 0] as u16;
@@ -90,13 +120,15 @@ pub fn decode_12be_msb16(buf: &[u8], width: usize, height: usize, dummy: bool) -
     o[3] = ((g6 & 0x0f) << 8) | g5;
   }
 
-  out
+  Ok(out)
 }
 
-pub fn decode_12le_16bitaligned(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 12 bit samples packed MSB first, 2 samples every 3 bytes, with rows padded to an even
+/// number of bytes
+pub fn decode_12le_16bitaligned(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
   let stride = ((width*12/8+1) >> 1) << 1;
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[row*stride..];
+    let inb = rest(buf, row*stride);
     for (o, i) in out.chunks_exact_mut(2).zip(inb.chunks_exact(3)) {
       let g1:  u16 = i[ 0] as u16;
       let g2:  u16 = i[ 1] as u16;
@@ -108,8 +140,10 @@ pub fn decode_12le_16bitaligned(buf: &[u8], width: usize, height: usize, dummy:
   }))
 }
 
-pub fn decode_12be_msb32(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
-  let mut out: Vec<u16> = alloc_image!(width, height, dummy);
+/// 12 bit samples packed MSB first in 32 bit little endian words, 8 samples every 12 bytes.
+/// Rows aren't padded, so unlike the others it decodes the buffer as a single run of samples
+pub fn decode_12be_msb32(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+  let mut out: Vec<u16> = alloc_image_ok!(width, height, dummy);
 
   for (o, i) in out.chunks_exact_mut(8).zip(buf.chunks_exact(12)) {
     let g1:  u16 = i[ 0] as u16;
@@ -135,15 +169,17 @@ pub fn decode_12be_msb32(buf: &[u8], width: usize, height: usize, dummy: bool) -
     o[7] = ((g10 & 0x0f) << 8) | g9;
   }
 
-  out
+  Ok(out)
 }
 
-pub fn decode_12le_wcontrol(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 12 bit samples packed LSB first, 2 samples every 3 bytes, with a control byte after
+/// every 10 samples
+pub fn decode_12le_wcontrol(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
   // Calulate expected bytes per line.
   let perline = width * 12 / 8 + ((width+2) / 10);
 
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*perline)..];
+    let inb = rest(buf, row*perline);
 
     for (oc, ic) in out.chunks_exact_mut(10).zip(inb.chunks_exact(16)) {
       for (o, i) in oc.chunks_exact_mut(2).zip(ic.chunks_exact(3)) {
@@ -158,12 +194,14 @@ pub fn decode_12le_wcontrol(buf: &[u8], width: usize, height: usize, dummy: bool
   }))
 }
 
-pub fn decode_12be_wcontrol(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 12 bit samples packed MSB first, 2 samples every 3 bytes, with a control byte after
+/// every 10 samples
+pub fn decode_12be_wcontrol(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
   // Calulate expected bytes per line.
   let perline = width * 12 / 8 + ((width+2) / 10);
 
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*perline)..];
+    let inb = rest(buf, row*perline);
 
     for (oc, ic) in out.chunks_exact_mut(10).zip(inb.chunks_exact(16)) {
       for (o, i) in oc.chunks_exact_mut(2).zip(ic.chunks_exact(3)) {
@@ -179,15 +217,17 @@ pub fn decode_12be_wcontrol(buf: &[u8], width: usize, height: usize, dummy: bool
 }
 
 
-pub fn decode_12be_interlaced(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 12 bit samples packed MSB first, 2 samples every 3 bytes, with the even rows first and
+/// the odd ones after them starting at the next 2048 byte boundary
+pub fn decode_12be_interlaced(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
   let half = (height+1) >> 1;
   // Second field is 2048 byte aligned
   let second_field_offset = ((half*width*3/2 >> 11) + 1) << 11;
-  let second_field = &buf[second_field_offset..];
+  let second_field = rest(buf, second_field_offset);
 
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
     let off = row/2*width*12/8;
-    let inb = if (row % 2) == 0 { &buf[off..] } else { &second_field[off..] };
+    let inb = if (row % 2) == 0 { rest(buf, off) } else { rest(second_field, off) };
 
     for (o, i) in out.chunks_exact_mut(2).zip(inb.chunks_exact(3)) {
       let g1: u16 = i[0] as u16;
@@ -200,13 +240,15 @@ pub fn decode_12be_interlaced(buf: &[u8], width: usize, height: usize, dummy: bo
   }))
 }
 
-pub fn decode_12be_interlaced_unaligned(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 12 bit samples packed MSB first, 2 samples every 3 bytes, with the even rows first and
+/// the odd ones right after them
+pub fn decode_12be_interlaced_unaligned(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
   let half = (height+1) >> 1;
-  let second_field = &buf[half*width*12/8..];
+  let second_field = rest(buf, half*width*12/8);
 
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
     let off = row/2*width*12/8;
-    let inb = if (row % 2) == 0 { &buf[off..] } else { &second_field[off..] };
+    let inb = if (row % 2) == 0 { rest(buf, off) } else { rest(second_field, off) };
 
     for (o, i) in out.chunks_exact_mut(2).zip(inb.chunks_exact(3)) {
       let g1: u16 = i[0] as u16;
@@ -219,9 +261,10 @@ pub fn decode_12be_interlaced_unaligned(buf: &[u8], width: usize, height: usize,
   }))
 }
 
-pub fn decode_12le(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 12 bit samples packed LSB first, 2 samples every 3 bytes
+pub fn decode_12le(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*12/8)..];
+    let inb = rest(buf, row*width*12/8);
 
     for (o, i) in out.chunks_exact_mut(2).zip(inb.chunks_exact(3)) {
       let g1: u16 = i[0] as u16;
@@ -234,9 +277,11 @@ pub fn decode_12le(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<
   }))
 }
 
-pub fn decode_12le_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 12 bit samples in the low bits of 16 bit little endian words
+pub fn decode_12le_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+  rawref::record(buf, width, height, width*2, true, 0, 0x0fff);
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*2)..];
+    let inb = rest(buf, row*width*2);
 
     for (i, bytes) in (0..width).zip(inb.chunks_exact(2)) {
       out[i] = LEu16(bytes, 0) & 0x0fff;
@@ -244,9 +289,11 @@ pub fn decode_12le_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool
   }))
 }
 
-pub fn decode_12be_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 12 bit samples in the low bits of 16 bit big endian words
+pub fn decode_12be_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+  rawref::record(buf, width, height, width*2, false, 0, 0x0fff);
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*2)..];
+    let inb = rest(buf, row*width*2);
 
     for (i, bytes) in (0..width).zip(inb.chunks_exact(2)) {
       out[i] = BEu16(bytes, 0) & 0x0fff;
@@ -254,9 +301,11 @@ pub fn decode_12be_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool
   }))
 }
 
-pub fn decode_12be_unpacked_left_aligned(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 12 bit samples in the high bits of 16 bit big endian words
+pub fn decode_12be_unpacked_left_aligned(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+  rawref::record(buf, width, height, width*2, false, 4, 0x0fff);
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*2)..];
+    let inb = rest(buf, row*width*2);
 
     for (i, bytes) in (0..width).zip(inb.chunks_exact(2)) {
       out[i] = BEu16(bytes, 0) >> 4;
@@ -264,9 +313,11 @@ pub fn decode_12be_unpacked_left_aligned(buf: &[u8], width: usize, height: usize
   }))
 }
 
-pub fn decode_12le_unpacked_left_aligned(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 12 bit samples in the high bits of 16 bit little endian words
+pub fn decode_12le_unpacked_left_aligned(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+  rawref::record(buf, width, height, width*2, true, 4, 0x0fff);
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*2)..];
+    let inb = rest(buf, row*width*2);
 
     for (i, bytes) in (0..width).zip(inb.chunks_exact(2)) {
       out[i] = LEu16(bytes, 0) >> 4;
@@ -274,9 +325,11 @@ pub fn decode_12le_unpacked_left_aligned(buf: &[u8], width: usize, height: usize
   }))
 }
 
-pub fn decode_14le_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 14 bit samples in the low bits of 16 bit little endian words
+pub fn decode_14le_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+  rawref::record(buf, width, height, width*2, true, 0, 0x3fff);
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*2)..];
+    let inb = rest(buf, row*width*2);
 
     for (i, bytes) in (0..width).zip(inb.chunks_exact(2)) {
       out[i] = LEu16(bytes, 0) & 0x3fff;
@@ -284,9 +337,11 @@ pub fn decode_14le_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool
   }))
 }
 
-pub fn decode_14be_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 14 bit samples in the low bits of 16 bit big endian words
+pub fn decode_14be_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+  rawref::record(buf, width, height, width*2, false, 0, 0x3fff);
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*2)..];
+    let inb = rest(buf, row*width*2);
 
     for (i, bytes) in (0..width).zip(inb.chunks_exact(2)) {
       out[i] = BEu16(bytes, 0) & 0x3fff;
@@ -294,9 +349,11 @@ pub fn decode_14be_unpacked(buf: &[u8], width: usize, height: usize, dummy: bool
   }))
 }
 
-pub fn decode_16le(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 16 bit little endian samples
+pub fn decode_16le(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+  rawref::record(buf, width, height, width*2, true, 0, 0xffff);
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*2)..];
+    let inb = rest(buf, row*width*2);
 
     for (i, bytes) in (0..width).zip(inb.chunks_exact(2)) {
       out[i] = LEu16(bytes, 0);
@@ -304,9 +361,11 @@ pub fn decode_16le(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<
   }))
 }
 
-pub fn decode_16le_skiplines(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 16 bit little endian samples, of every other row only
+pub fn decode_16le_skiplines(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+  rawref::record(buf, width, height, width*4, true, 0, 0xffff);
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*4)..];
+    let inb = rest(buf, row*width*4);
 
     for (i, bytes) in (0..width).zip(inb.chunks_exact(2)) {
       out[i] = LEu16(bytes, 0);
@@ -314,9 +373,11 @@ pub fn decode_16le_skiplines(buf: &[u8], width: usize, height: usize, dummy: boo
   }))
 }
 
-pub fn decode_16be(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+/// 16 bit big endian samples
+pub fn decode_16be(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+  rawref::record(buf, width, height, width*2, false, 0, 0xffff);
   decode_threaded(width, height, dummy,&(|out: &mut [u16], row| {
-    let inb = &buf[(row*width*2)..];
+    let inb = rest(buf, row*width*2);
 
     for (i, bytes) in (0..width).zip(inb.chunks_exact(2)) {
       out[i] = BEu16(bytes, 0);
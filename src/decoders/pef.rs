@@ -23,19 +23,23 @@ impl<'a> PefDecoder<'a> {
 }
 
 impl<'a> Decoder for PefDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "PEF"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let camera = self.rawloader.check_supported(&self.tiff)?;
     let raw = fetch_ifd!(&self.tiff, Tag::StripOffsets);
     let width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0);
     let height = fetch_tag!(raw, Tag::ImageLength).get_usize(0);
     let offset = fetch_tag!(raw, Tag::StripOffsets).get_usize(0);
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
 
     let image = match fetch_tag!(raw, Tag::Compression).get_u32(0) {
-      1 => decode_16be(src, width, height, dummy),
-      32773 => decode_12be(src, width, height, dummy),
+      1 => decode_16be(src, width, height, dummy)?,
+      32773 => decode_12be(src, width, height, dummy)?,
       65535 => self.decode_compressed(src, width, height, dummy)?,
-      c => return Err(format!("PEF: Don't know how to read compression {}", c).to_string()),
+      c => return Err(RawLoaderError::Unimplemented(format!("PEF: Don't know how to read compression {}", c))),
     };
 
     let blacklevels = self.get_blacklevels().unwrap_or(camera.blacklevels);
@@ -44,7 +48,7 @@ impl<'a> Decoder for PefDecoder<'a> {
 }
 
 impl<'a> PefDecoder<'a> {
-  fn get_wb(&self) -> Result<[f32;4], String> {
+  fn get_wb(&self) -> Result<[f32;4], RawLoaderError> {
     let levels = fetch_tag!(self.tiff, Tag::PefWB);
     Ok([levels.get_f32(0), levels.get_f32(1), levels.get_f32(3), NAN])
   }
@@ -59,7 +63,7 @@ impl<'a> PefDecoder<'a> {
     }
   }
 
-  fn decode_compressed(&self, src: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>,String> {
+  fn decode_compressed(&self, src: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     if let Some(huff) = self.tiff.find_entry(Tag::PefHuffman) {
       Self::do_decode(src, Some((huff.get_data(), self.tiff.get_endian())), width, height, dummy)
     } else {
@@ -67,7 +71,7 @@ impl<'a> PefDecoder<'a> {
     }
   }
 
-  pub(crate) fn do_decode(src: &[u8], huff: Option<(&[u8], Endian)>, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>,String> {
+  pub(crate) fn do_decode(src: &[u8], huff: Option<(&[u8], Endian)>, width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     let mut out = alloc_image_ok!(width, height, dummy);
     let mut htable = HuffTable::empty();
 
@@ -131,6 +135,7 @@ impl<'a> PefDecoder<'a> {
     let mut pred_left2: i32;
 
     for row in 0..height {
+      check_abort()?;
       pred_up1[row & 1] += htable.huff_decode(&mut pump)?;
       pred_up2[row & 1] += htable.huff_decode(&mut pump)?;
       pred_left1 = pred_up1[row & 1];
@@ -1,4 +1,21 @@
 use crate::decoders::basics::*;
+use crate::decoders::RawLoaderError;
+
+// The bytes at pos for a pump to read ahead. Data borrowed from the caller doesn't have the
+// padding a Buffer puts after the file so up to as far as that would go reads as zeros.
+#[inline(always)]
+pub(crate) fn padded<const N: usize>(buf: &[u8], pos: usize) -> [u8; N] {
+  let mut bytes = [0; N];
+  match buf.get(pos..pos+N) {
+    Some(data) => bytes.copy_from_slice(data),
+    None if pos + N <= buf.len() + 16 => {
+      let rest = buf.get(pos..).unwrap_or(&[]);
+      bytes[..rest.len()].copy_from_slice(rest);
+    },
+    None => crate::decoders::corrupt("Tried to read past the end of the data"),
+  }
+  bytes
+}
 
 #[derive(Debug, Copy, Clone)]
 pub struct BitPumpLSB<'a> {
@@ -121,7 +138,7 @@ impl<'a> BitPump for BitPumpLSB<'a> {
   #[inline(always)]
   fn peek_bits(&mut self, num: u32) -> u32 {
     if num > self.nbits {
-      let inbits: u64 = LEu32(self.buffer, self.pos) as u64;
+      let inbits: u64 = u32::from_le_bytes(padded(self.buffer, self.pos)) as u64;
       self.bits = ((inbits << 32) | (self.bits << (32-self.nbits))) >> (32-self.nbits);
       self.pos += 4;
       self.nbits += 32;
@@ -140,7 +157,7 @@ impl<'a> BitPump for BitPumpMSB<'a> {
   #[inline(always)]
   fn peek_bits(&mut self, num: u32) -> u32 {
     if num > self.nbits {
-      let inbits: u64 = BEu32(self.buffer, self.pos) as u64;
+      let inbits: u64 = u32::from_be_bytes(padded(self.buffer, self.pos)) as u64;
       self.bits = (self.bits << 32) | inbits;
       self.pos += 4;
       self.nbits += 32;
@@ -159,7 +176,7 @@ impl<'a> BitPump for BitPumpMSB32<'a> {
   #[inline(always)]
   fn peek_bits(&mut self, num: u32) -> u32 {
     if num > self.nbits {
-      let inbits: u64 = LEu32(self.buffer, self.pos) as u64;
+      let inbits: u64 = u32::from_le_bytes(padded(self.buffer, self.pos)) as u64;
       self.bits = (self.bits << 32) | inbits;
       self.pos += 4;
       self.nbits += 32;
@@ -178,7 +195,7 @@ impl<'a> BitPump for BitPumpJPEG<'a> {
   #[inline(always)]
   fn peek_bits(&mut self, num: u32) -> u32 {
     if num > self.nbits && !self.finished {
-      if self.pos < self.buffer.len()-4 &&
+      if self.pos + 4 < self.buffer.len() &&
          self.buffer[self.pos+0] != 0xff &&
          self.buffer[self.pos+1] != 0xff &&
          self.buffer[self.pos+2] != 0xff &&
@@ -199,7 +216,7 @@ impl<'a> BitPump for BitPumpJPEG<'a> {
               let nextbyte = self.buffer[self.pos];
               if nextbyte != 0xff {
                 nextbyte
-              } else if self.buffer[self.pos+1] == 0x00 {
+              } else if self.buffer.get(self.pos+1) == Some(&0x00) {
                 self.pos += 1; // Skip the extra byte used to mark 255
                 nextbyte
               } else {
@@ -283,7 +300,7 @@ impl<'a> ByteStream<'a> {
   }
 
   #[inline(always)]
-  pub fn skip_to_marker(&mut self) -> Result<usize, String> {
+  pub fn skip_to_marker(&mut self) -> Result<usize, RawLoaderError> {
     let mut skip_count = 0;
     while !(self.buffer[self.pos] == 0xFF &&
             self.buffer[self.pos+1] != 0 &&
@@ -291,10 +308,32 @@ impl<'a> ByteStream<'a> {
       self.pos += 1;
       skip_count += 1;
       if self.pos >= self.buffer.len() {
-        return Err("No marker found inside rest of buffer".to_string())
+        return Err(RawLoaderError::Corrupt("No marker found inside rest of buffer".to_string()))
       }
     }
     self.pos += 1; // Make the next byte the marker
     Ok(skip_count+1)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pumps_read_to_the_end_of_unpadded_data() {
+    let data = [0xab, 0xcd, 0xef];
+    assert_eq!(BitPumpMSB::new(&data).get_bits(24), 0xabcdef);
+    assert_eq!(BitPumpMSB32::new(&data).get_bits(24), 0x00efcd);
+    assert_eq!(BitPumpLSB::new(&data).get_bits(24), 0xefcdab);
+    assert_eq!(BitPumpJPEG::new(&data).get_bits(24), 0xabcdef);
+  }
+
+  #[test]
+  fn padding_only_goes_so_far() {
+    assert_eq!(padded::<4>(&[1, 2], 1), [2, 0, 0, 0]);
+    assert_eq!(padded::<4>(&[1, 2], 14), [0; 4]);
+    let past = std::panic::catch_unwind(|| padded::<4>(&[1, 2], 15));
+    assert!(matches!(past.unwrap_err().downcast_ref::<RawLoaderError>(), Some(RawLoaderError::Corrupt(_))));
+  }
+}
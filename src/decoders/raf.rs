@@ -3,6 +3,8 @@ use std::f32::NAN;
 use crate::decoders::*;
 use crate::decoders::tiff::*;
 use crate::decoders::basics::*;
+use crate::decoders::opcodes::DngOpcodes;
+use crate::decoders::color::ColorMatrices;
 
 #[derive(Debug, Clone)]
 pub struct RafDecoder<'a> {
@@ -22,50 +24,97 @@ impl<'a> RafDecoder<'a> {
 }
 
 impl<'a> Decoder for RafDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "RAF"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let camera = self.rawloader.check_supported(&self.tiff)?;
-    let raw = fetch_ifd!(&self.tiff, Tag::RafOffsets);
-    let (width,height) = if raw.has_entry(Tag::RafImageWidth) {
-      (fetch_tag!(raw, Tag::RafImageWidth).get_usize(0),
-       fetch_tag!(raw, Tag::RafImageLength).get_usize(0))
-    } else {
-      let sizes = fetch_tag!(raw, Tag::ImageWidth);
-      (sizes.get_usize(1), sizes.get_usize(0))
-    };
-    let offset = fetch_tag!(raw, Tag::RafOffsets).get_usize(0) + raw.start_offset();
-    let bps = match raw.find_entry(Tag::RafBitsPerSample) {
-      Some(val) => val.get_u32(0) as usize,
-      None      => 16,
-    };
-    let src = &self.buffer[offset..];
+    let (width, height, bps, src) = self.get_raw()?;
 
     let image = if camera.find_hint("double_width") {
       // Some fuji SuperCCD cameras include a second raw image next to the first one
       // that is identical but darker to the first. The two combined can produce
-      // a higher dynamic range image. Right now we're ignoring it.
-      decode_16le_skiplines(src, width, height, dummy)
+      // a higher dynamic range image and can be fetched with dual_gain()
+      decode_16le_skiplines(src, width, height, dummy)?
     } else if camera.find_hint("jpeg32") {
-      decode_12be_msb32(src, width, height, dummy)
+      decode_12be_msb32(src, width, height, dummy)?
     } else {
       if src.len() < bps*width*height/8 {
-        return Err("RAF: Don't know how to decode compressed yet".to_string())
+        return Err(RawLoaderError::Unimplemented("RAF: Don't know how to decode compressed yet".to_string()))
       }
       match bps {
-        12 => decode_12le(src, width, height, dummy),
-        14 => decode_14le_unpacked(src, width, height, dummy),
+        12 => decode_12le(src, width, height, dummy)?,
+        14 => decode_14le_unpacked(src, width, height, dummy)?,
         16 => {
           if self.tiff.little_endian() {
-            decode_16le(src, width, height, dummy)
+            decode_16le(src, width, height, dummy)?
           } else {
-            decode_16be(src, width, height, dummy)
+            decode_16be(src, width, height, dummy)?
           }
         },
-        _ => {return Err(format!("RAF: Don't know how to decode bps {}", bps).to_string());},
+        _ => {return Err(RawLoaderError::Unimplemented(format!("RAF: Don't know how to decode bps {}", bps)));},
       }
     };
 
+    self.build_image(camera, width, height, image, dummy)
+  }
+
+  fn image_count(&self) -> usize {
+    match self.rawloader.check_supported(&self.tiff) {
+      Ok(camera) if camera.find_hint("double_width") => 2,
+      _ => 1,
+    }
+  }
+
+  fn image_at(&self, index: usize, dummy: bool) -> Result<RawImage, RawLoaderError> {
+    match index {
+      0 => self.image(dummy),
+      1 if self.image_count() == 2 => self.dual_gain(dummy).map(|img| img.low_gain),
+      _ => Err(RawLoaderError::NoSuchImage { index, count: self.image_count() }),
+    }
+  }
+
+  fn dual_gain(&self, dummy: bool) -> Result<DualGainImage, RawLoaderError> {
+    let camera = self.rawloader.check_supported(&self.tiff)?;
+    if !camera.find_hint("double_width") {
+      return Err(RawLoaderError::Corrupt("RAF: No dual gain frames in this file".to_string()))
+    }
+    let (width, height, _, src) = self.get_raw()?;
+    if src.len() < width*height*4 {
+      return Err(RawLoaderError::Corrupt("RAF: Dual gain data is truncated".to_string()))
+    }
+
+    let high = decode_16le_skiplines(src, width, height, dummy)?;
+    let low = decode_16le_skiplines(&src[width*2..], width, height, dummy)?;
+    Ok(DualGainImage::new(
+      self.build_image(camera.clone(), width, height, high, dummy)?,
+      self.build_image(camera, width, height, low, dummy)?,
+    ))
+  }
+}
+
+impl<'a> RafDecoder<'a> {
+  fn get_raw(&self) -> Result<(usize, usize, usize, &'a [u8]), RawLoaderError> {
+    let raw = fetch_ifd!(&self.tiff, Tag::RafOffsets);
+    let (width,height) = if raw.has_entry(Tag::RafImageWidth) {
+      (fetch_tag!(raw, Tag::RafImageWidth).get_usize(0),
+       fetch_tag!(raw, Tag::RafImageLength).get_usize(0))
+    } else {
+      let sizes = fetch_tag!(raw, Tag::ImageWidth);
+      (sizes.get_usize(1), sizes.get_usize(0))
+    };
+    let offset = fetch_tag!(raw, Tag::RafOffsets).get_usize(0) + raw.start_offset();
+    let bps = match raw.find_entry(Tag::RafBitsPerSample) {
+      Some(val) => val.get_u32(0) as usize,
+      None      => 16,
+    };
+    Ok((width, height, bps, rest(self.buffer, offset)))
+  }
+
+  fn build_image(&self, camera: Camera, width: usize, height: usize, image: Vec<u16>, dummy: bool) -> Result<RawImage, RawLoaderError> {
     if camera.find_hint("fuji_rotation") || camera.find_hint("fuji_rotation_alt") {
-      let (width, height, image) = RafDecoder::rotate_image(&image, &camera, width, height, dummy);
+      let (width, height, fuji_width, image) = RafDecoder::rotate_image(&image, &camera, width, height, dummy)?;
       Ok(RawImage {
         make: camera.make.clone(),
         model: camera.model.clone(),
@@ -78,20 +127,36 @@ impl<'a> Decoder for RafDecoder<'a> {
         data: RawImageData::Integer(image),
         blacklevels: camera.blacklevels,
         whitelevels: camera.whitelevels,
+        camera_blacklevels: camera.blacklevels,
+        camera_whitelevels: camera.whitelevels,
+        measured_blacklevels: None,
         xyz_to_cam: camera.xyz_to_cam,
+        color_matrices: ColorMatrices::from_single(camera.xyz_to_cam),
+        rendering_hints: RenderingHints::default(),
+        noise_profile: Vec::new(),
+        masks: Vec::new(),
+        calibration: None,
+        image_count: 1,
         cfa: camera.cfa.clone(),
         crops: [0,0,0,0],
+        default_crop: None,
+        display_crop: None,
+        geometry: SensorGeometry::Rotated45{fuji_width},
         blackareas: Vec::new(),
+        bad_pixels: Vec::new(),
+        opcodes: DngOpcodes::default(),
         orientation: camera.orientation,
+        exif: camera.exif,
+        partially_decoded: false,
+        failure_offset: None,
+        warnings: Vec::new(),
       })
     } else {
       ok_image(camera, width, height, self.get_wb()?, image)
     }
   }
-}
 
-impl<'a> RafDecoder<'a> {
-  fn get_wb(&self) -> Result<[f32;4], String> {
+  fn get_wb(&self) -> Result<[f32;4], RawLoaderError> {
     match self.tiff.find_entry(Tag::RafWBGRB) {
       Some(levels) => Ok([levels.get_f32(1), levels.get_f32(0), levels.get_f32(2), NAN]),
       None => {
@@ -101,7 +166,7 @@ impl<'a> RafDecoder<'a> {
     }
   }
 
-  fn rotate_image(src: &[u16], camera: &Camera, width: usize, height: usize, dummy: bool) -> (usize, usize, Vec<u16>) {
+  fn rotate_image(src: &[u16], camera: &Camera, width: usize, height: usize, dummy: bool) -> Result<(usize, usize, usize, Vec<u16>), RawLoaderError> {
     let x = camera.crops[3];
     let y = camera.crops[0];
     let cropwidth = width - camera.crops[1] - x;
@@ -123,7 +188,7 @@ impl<'a> RafDecoder<'a> {
         }
       }
 
-      (rotatedwidth, rotatedheight, out)
+      Ok((rotatedwidth, rotatedheight, rotatedwidth - cropheight - 1, out))
     } else {
       let rotatedwidth = cropwidth + cropheight/2;
       let rotatedheight = rotatedwidth-1;
@@ -140,7 +205,7 @@ impl<'a> RafDecoder<'a> {
         }
       }
 
-      (rotatedwidth, rotatedheight, out)
+      Ok((rotatedwidth, rotatedheight, cropwidth - 1, out))
     }
   }
 }
@@ -0,0 +1,80 @@
+use std::cell::RefCell;
+
+use crate::decoders::*;
+use crate::decoders::basics::*;
+
+/// How the samples of a `RawImageRef` are stored in its input, each one in 16 bits from which
+/// the value is `(sample >> shift) & mask`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SampleLayout {
+  /// position of the first sample in the input, in bytes
+  pub offset: usize,
+  /// distance from the start of a row to the start of the next, in bytes
+  pub stride: usize,
+  /// if the samples are little endian, otherwise they're big endian
+  pub little_endian: bool,
+  /// right shift from the stored sample to the value
+  pub shift: u32,
+  /// bits of the shifted sample that are the value
+  pub mask: u16,
+}
+
+// Where the decoding functions of uncompressed data were pointed at during a decode, as
+// the start of their input, their width and height, and the layout relative to that start
+pub(crate) type Recorded = (usize, usize, usize, SampleLayout);
+
+thread_local! {
+  static RECORDED: RefCell<Option<Vec<Recorded>>> = const { RefCell::new(None) };
+}
+
+pub(crate) fn record(buf: &[u8], width: usize, height: usize, stride: usize, little_endian: bool, shift: u32, mask: u16) {
+  RECORDED.with(|r| {
+    if let Some(ref mut recorded) = *r.borrow_mut() {
+      let layout = SampleLayout { offset: 0, stride, little_endian, shift, mask };
+      recorded.push((buf.as_ptr() as usize, width, height, layout));
+    }
+  });
+}
+
+pub(crate) fn start() {
+  RECORDED.with(|r| *r.borrow_mut() = Some(Vec::new()));
+}
+
+pub(crate) fn finish() -> Vec<Recorded> {
+  RECORDED.with(|r| r.borrow_mut().take()).unwrap_or_default()
+}
+
+/// A raw image whose data is read straight from the input instead of decoded into a copy,
+/// as returned by `RawLoader::decode_ref()` for files stored uncompressed
+#[derive(Debug, Clone)]
+pub struct RawImageRef<'a> {
+  /// all the metadata of the image, with `data` left empty
+  pub image: RawImage,
+  /// how the samples are stored in `input`
+  pub layout: SampleLayout,
+  /// the whole input the image was decoded from
+  pub input: &'a [u8],
+}
+
+impl<'a> RawImageRef<'a> {
+  /// Value at a row and column, with columns going up to `width`\*`cpp`
+  pub fn get(&self, row: usize, col: usize) -> u16 {
+    let pos = self.layout.offset + row*self.layout.stride + col*2;
+    let sample = if self.layout.little_endian { LEu16(self.input, pos) } else { BEu16(self.input, pos) };
+    (sample >> self.layout.shift) & self.layout.mask
+  }
+
+  /// The values of a row
+  pub fn row(&self, row: usize) -> impl Iterator<Item=u16> + '_ {
+    (0..self.image.width*self.image.cpp).map(move |col| self.get(row, col))
+  }
+
+  /// Copies the data into a regular RawImage
+  pub fn to_image(&self) -> RawImage {
+    let mut image = self.image.clone();
+    let data = (0..image.height).flat_map(|row| self.row(row)).collect();
+    image.data = RawImageData::Integer(data);
+    image
+  }
+}
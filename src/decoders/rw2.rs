@@ -22,7 +22,11 @@ impl<'a> Rw2Decoder<'a> {
 }
 
 impl<'a> Decoder for Rw2Decoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "RW2"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let width: usize;
     let height: usize;
     let image = {
@@ -32,21 +36,21 @@ impl<'a> Decoder for Rw2Decoder<'a> {
         width = fetch_tag!(raw, Tag::PanaWidth).get_usize(0);
         height = fetch_tag!(raw, Tag::PanaLength).get_usize(0);
         let offset = fetch_tag!(raw, Tag::PanaOffsets).get_usize(0);
-        let src = &self.buffer[offset..];
-        Rw2Decoder::decode_panasonic(src, width, height, true, dummy)
+        let src = rest(self.buffer, offset);
+        Rw2Decoder::decode_panasonic(src, width, height, true, dummy)?
       } else {
         let raw = fetch_ifd!(&self.tiff, Tag::StripOffsets);
         width = fetch_tag!(raw, Tag::PanaWidth).get_usize(0);
         height = fetch_tag!(raw, Tag::PanaLength).get_usize(0);
         let offset = fetch_tag!(raw, Tag::StripOffsets).get_usize(0);
-        let src = &self.buffer[offset..];
+        let src = rest(self.buffer, offset);
 
         if src.len() >= width*height*2 {
-          decode_12le_unpacked_left_aligned(src, width, height, dummy)
+          decode_12le_unpacked_left_aligned(src, width, height, dummy)?
         } else if src.len() >= width*height*3/2 {
-          decode_12le_wcontrol(src, width, height, dummy)
+          decode_12le_wcontrol(src, width, height, dummy)?
         } else {
-          Rw2Decoder::decode_panasonic(src, width, height, false, dummy)
+          Rw2Decoder::decode_panasonic(src, width, height, false, dummy)?
         }
       }
     };
@@ -70,7 +74,7 @@ impl<'a> Decoder for Rw2Decoder<'a> {
 }
 
 impl<'a> Rw2Decoder<'a> {
-  fn get_wb(&self) -> Result<[f32;4], String> {
+  fn get_wb(&self) -> Result<[f32;4], RawLoaderError> {
     if self.tiff.has_entry(Tag::PanaWBsR) && self.tiff.has_entry(Tag::PanaWBsB) {
       let r = fetch_tag!(self.tiff, Tag::PanaWBsR).get_u32(0) as f32;
       let b = fetch_tag!(self.tiff, Tag::PanaWBsB).get_u32(0) as f32;
@@ -83,11 +87,11 @@ impl<'a> Rw2Decoder<'a> {
       let b = fetch_tag!(self.tiff, Tag::PanaWBs2B).get_u32(0) as f32;
       Ok([r, g, b, NAN])
     } else {
-      Err("Couldn't find WB".to_string())
+      Err(RawLoaderError::Corrupt("Couldn't find WB".to_string()))
     }
   }
 
-  pub(crate) fn decode_panasonic(buf: &[u8], width: usize, height: usize, split: bool, dummy: bool) -> Vec<u16> {
+  pub(crate) fn decode_panasonic(buf: &[u8], width: usize, height: usize, split: bool, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     decode_threaded_multiline(width, height, 5, dummy, &(|out: &mut [u16], row| {
       let skip = ((width * row * 9) + (width/14 * 2 * row)) / 8;
       let blocks = skip / 0x4000;
@@ -156,7 +160,7 @@ impl<'a> BitPump for BitPumpPanasonic<'a> {
     if self.split {
       byte = (byte + 0x4000 - 0x2008) % 0x4000;
     }
-    let bits = LEu16(self.buffer, byte as usize + self.pos - 0x4000) as u32;
+    let bits = u16::from_le_bytes(padded(self.buffer, byte as usize + self.pos - 0x4000)) as u32;
     (bits >> ((self.nbits-num) & 7)) & (0x0ffffffffu32 >> (32-num))
   }
 
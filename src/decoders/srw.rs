@@ -23,7 +23,11 @@ impl<'a> SrwDecoder<'a> {
 }
 
 impl<'a> Decoder for SrwDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "SRW"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let camera = self.rawloader.check_supported(&self.tiff)?;
     let raw = fetch_ifd!(&self.tiff, Tag::StripOffsets);
     let width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0);
@@ -31,41 +35,41 @@ impl<'a> Decoder for SrwDecoder<'a> {
     let offset = fetch_tag!(raw, Tag::StripOffsets).get_usize(0);
     let compression = fetch_tag!(raw, Tag::Compression).get_u32(0);
     let bits = fetch_tag!(raw, Tag::BitsPerSample).get_u32(0);
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
 
     let image = match compression {
       32769 => match bits {
-        12 => decode_12le_unpacked(src, width, height, dummy),
-        14 => decode_14le_unpacked(src, width, height, dummy),
-         x => return Err(format!("SRW: Don't know how to handle bps {}", x).to_string()),
+        12 => decode_12le_unpacked(src, width, height, dummy)?,
+        14 => decode_14le_unpacked(src, width, height, dummy)?,
+         x => return Err(RawLoaderError::Unimplemented(format!("SRW: Don't know how to handle bps {}", x))),
       },
       32770 => {
         match raw.find_entry(Tag::SrwSensorAreas) {
           None => match bits {
             12 => {
               if camera.find_hint("little_endian") {
-                decode_12le(src, width, height, dummy)
+                decode_12le(src, width, height, dummy)?
               } else {
-                decode_12be(src, width, height, dummy)
+                decode_12be(src, width, height, dummy)?
               }
             },
-            14 => decode_14le_unpacked(src, width, height, dummy),
-             x => return Err(format!("SRW: Don't know how to handle bps {}", x).to_string()),
+            14 => decode_14le_unpacked(src, width, height, dummy)?,
+             x => return Err(RawLoaderError::Unimplemented(format!("SRW: Don't know how to handle bps {}", x))),
           },
           Some(x) => {
             let coffset = x.get_usize(0);
-            let loffsets = &self.buffer[coffset..];
-            SrwDecoder::decode_srw1(src, loffsets, width, height, dummy)
+            let loffsets = rest(self.buffer, coffset);
+            SrwDecoder::decode_srw1(src, loffsets, width, height, dummy)?
           }
         }
       }
       32772 => {
-       SrwDecoder::decode_srw2(src, width, height, dummy)
+       SrwDecoder::decode_srw2(src, width, height, dummy)?
       }
       32773 => {
-       SrwDecoder::decode_srw3(src, width, height, dummy)
+       SrwDecoder::decode_srw3(src, width, height, dummy)?
       }
-      x => return Err(format!("SRW: Don't know how to handle compression {}", x).to_string()),
+      x => return Err(RawLoaderError::Unimplemented(format!("SRW: Don't know how to handle compression {}", x))),
     };
 
     ok_image(camera, width, height, self.get_wb()?, image)
@@ -73,10 +77,11 @@ impl<'a> Decoder for SrwDecoder<'a> {
 }
 
 impl<'a> SrwDecoder<'a> {
-  pub fn decode_srw1(buf: &[u8], loffsets: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
-    let mut out: Vec<u16> = alloc_image!(width, height, dummy);
+  pub fn decode_srw1(buf: &[u8], loffsets: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+    let mut out: Vec<u16> = alloc_image_ok!(width, height, dummy);
 
     for row in 0..height {
+      check_abort()?;
       let mut len: [u32; 4] = [if row < 2 {7} else {4}; 4];
       let loffset = LEu32(loffsets, row*4) as usize;
       let mut pump = BitPumpMSB32::new(&buf[loffset..]);
@@ -137,11 +142,11 @@ impl<'a> SrwDecoder<'a> {
       }
     }
 
-    out
+    Ok(out)
   }
 
-  pub fn decode_srw2(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
-    let mut out: Vec<u16> = alloc_image!(width, height, dummy);
+  pub fn decode_srw2(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
+    let mut out: Vec<u16> = alloc_image_ok!(width, height, dummy);
 
     // This format has a variable length encoding of how many bits are needed
     // to encode the difference between pixels, we use a table to process it
@@ -175,6 +180,7 @@ impl<'a> SrwDecoder<'a> {
     let mut hpred: [i32;2] = [0,0];
     let mut pump = BitPumpMSB::new(buf);
     for row in 0..height {
+      check_abort()?;
       for col in 0..width {
         let diff = SrwDecoder::srw2_diff(&mut pump, &tbl);
         if col < 2 {
@@ -187,7 +193,7 @@ impl<'a> SrwDecoder<'a> {
       }
     }
 
-    out
+    Ok(out)
   }
 
   pub fn srw2_diff(pump: &mut BitPumpMSB, tbl: &[[u32;2];1024]) -> i32{
@@ -205,7 +211,7 @@ impl<'a> SrwDecoder<'a> {
     diff
   }
 
-  pub fn decode_srw3(buf: &[u8], width: usize, height: usize, dummy: bool) -> Vec<u16> {
+  pub fn decode_srw3(buf: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     // Decoder for third generation compressed SRW files (NX1)
     // Seriously Samsung just use lossless jpeg already, it compresses better too :)
 
@@ -213,7 +219,7 @@ impl<'a> SrwDecoder<'a> {
     // and Loring von Palleske (Samsung) for pointing to the open-source code of
     // Samsung's DNG converter at http://opensource.samsung.com/
 
-    let mut out: Vec<u16> = alloc_image!(width, height, dummy);
+    let mut out: Vec<u16> = alloc_image_ok!(width, height, dummy);
     let mut pump = BitPumpMSB32::new(buf);
 
     // Process the initial metadata bits, we only really use initVal, width and
@@ -249,6 +255,7 @@ impl<'a> SrwDecoder<'a> {
     // the actual difference bits
     let mut line_offset = 0;
     for row in 0..height {
+      check_abort()?;
       line_offset += pump.get_pos();
       // Align pump to 16byte boundary
       if (line_offset & 0x0f) != 0 {
@@ -293,7 +300,7 @@ impl<'a> SrwDecoder<'a> {
         }
 
         if row < 2 && motion != 7 {
-          panic!("SRW Decoder: At start of image and motion isn't 7. File corrupted?")
+          return Err(RawLoaderError::Corrupt("SRW Decoder: At start of image and motion isn't 7. File corrupted?".to_string()))
         }
 
         if motion == 7 {
@@ -305,7 +312,7 @@ impl<'a> SrwDecoder<'a> {
         } else {
           // The complex case, we now need to actually lookup one or two lines above
           if row < 2 {
-            panic!("SRW: Got a previous line lookup on first two lines. File corrupted?");
+            return Err(RawLoaderError::Corrupt("SRW: Got a previous line lookup on first two lines. File corrupted?".to_string()))
           }
           let motion_offset: [isize;7]  = [-4,-2,-2,0,0,2,4];
           let motion_average: [i32;7] = [ 0, 0, 1,0,1,0,0];
@@ -349,7 +356,7 @@ impl<'a> SrwDecoder<'a> {
             diff_bits_mode[colornum][0] = diff_bits_mode[colornum][1];
             diff_bits_mode[colornum][1] = diff_bits[i];
             if diff_bits[i] > bit_depth+1 {
-              panic!("SRW Decoder: Too many difference bits. File corrupted?");
+              return Err(RawLoaderError::Corrupt("SRW Decoder: Too many difference bits. File corrupted?".to_string()))
             }
           }
         }
@@ -371,14 +378,14 @@ impl<'a> SrwDecoder<'a> {
       }
     }
 
-    out
+    Ok(out)
   }
 
-  fn get_wb(&self) -> Result<[f32;4], String> {
+  fn get_wb(&self) -> Result<[f32;4], RawLoaderError> {
     let rggb_levels = fetch_tag!(self.tiff, Tag::SrwRGGBLevels);
     let rggb_blacks = fetch_tag!(self.tiff, Tag::SrwRGGBBlacks);
     if rggb_levels.count() != 4 || rggb_blacks.count() != 4 {
-      Err("SRW: RGGB Levels and Blacks don't have 4 elements".to_string())
+      Err(RawLoaderError::Corrupt("SRW: RGGB Levels and Blacks don't have 4 elements".to_string()))
     } else {
       let nlevels = &rggb_levels.copy_offset_from_parent(&self.buffer);
       let nblacks = &rggb_blacks.copy_offset_from_parent(&self.buffer);
@@ -4,6 +4,7 @@ use crate::decoders::*;
 use crate::decoders::tiff::*;
 use crate::decoders::ljpeg::*;
 use crate::decoders::basics::*;
+use crate::decoders::calibration::*;
 
 #[derive(Debug, Clone)]
 pub struct TfrDecoder<'a> {
@@ -23,31 +24,45 @@ impl<'a> TfrDecoder<'a> {
 }
 
 impl<'a> Decoder for TfrDecoder<'a> {
-  fn image(&self, dummy: bool) -> Result<RawImage,String> {
+  fn format(&self) -> &'static str {
+    "3FR"
+  }
+
+  fn image(&self, dummy: bool) -> Result<RawImage, RawLoaderError> {
     let camera = self.rawloader.check_supported(&self.tiff)?;
     let raw = fetch_ifd!(&self.tiff, Tag::WhiteLevel);
     let width = fetch_tag!(raw, Tag::ImageWidth).get_usize(0);
     let height = fetch_tag!(raw, Tag::ImageLength).get_usize(0);
     let offset = fetch_tag!(raw, Tag::StripOffsets).get_usize(0);
-    let src = &self.buffer[offset..];
+    let src = rest(self.buffer, offset);
 
     let image = if camera.find_hint("uncompressed") {
-      decode_16le(src, width, height, dummy)
+      decode_16le(src, width, height, dummy)?
     } else {
       self.decode_compressed(src, width, height, dummy)?
     };
 
-    ok_image(camera, width, height, self.get_wb()?, image)
+    let mut img = ok_image(camera, width, height, self.get_wb()?, image)?;
+    // The only per unit calibration 3FR files carry is a linearization table in the raw IFD
+    if let Some(lin) = raw.find_entry(Tag::Linearization).filter(|e| e.count() > 1) {
+      let table = (0..65536).map(|i| lin.get_u32(i.min(lin.count()-1)) as u16).collect();
+      img.calibration = Some(SensorCalibration {
+        scale: 1,
+        curves: vec![CalibrationCurve { first_col: 0, table }],
+        ..Default::default()
+      });
+    }
+    Ok(img)
   }
 }
 
 impl<'a> TfrDecoder<'a> {
-  fn get_wb(&self) -> Result<[f32;4], String> {
+  fn get_wb(&self) -> Result<[f32;4], RawLoaderError> {
     let levels = fetch_tag!(self.tiff, Tag::AsShotNeutral);
     Ok([1.0/levels.get_f32(0),1.0/levels.get_f32(1),1.0/levels.get_f32(2),NAN])
   }
 
-  fn decode_compressed(&self, src: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>,String> {
+  fn decode_compressed(&self, src: &[u8], width: usize, height: usize, dummy: bool) -> Result<Vec<u16>, RawLoaderError> {
     let mut out = alloc_image_ok!(width, height, dummy);
     let decompressor = LjpegDecompressor::new_full(src, true, false)?;
     decompressor.decode(&mut out, 0, width, width, height, dummy)?;
@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+
+use crate::decoders::basics::*;
+use crate::decoders::tiff::*;
+
+/// How the pixels of a `Thumbnail` are stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ThumbnailData {
+  /// a complete JPEG file as embedded in the raw
+  Jpeg(Vec<u8>),
+  /// 8 bit RGB pixels, `width`\*`height`\*3 of them in row major order
+  Rgb(Vec<u8>),
+}
+
+/// A preview image embedded in a raw file by the camera
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Thumbnail {
+  /// width of the preview in pixels
+  pub width: usize,
+  /// height of the preview in pixels
+  pub height: usize,
+  /// where the preview starts in the file, in bytes
+  pub offset: usize,
+  /// the preview itself
+  pub data: ThumbnailData,
+}
+
+impl Thumbnail {
+  /// Number of pixels of the preview
+  pub fn pixels(&self) -> usize {
+    self.width * self.height
+  }
+
+  /// Size of the preview in the file, in bytes
+  pub fn len(&self) -> usize {
+    match self.data {
+      ThumbnailData::Jpeg(ref data) | ThumbnailData::Rgb(ref data) => data.len(),
+    }
+  }
+
+  /// If the preview takes no bytes, which never happens for the ones found in files
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+/// What an `EmbeddedImage` is
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EmbeddedKind {
+  /// a preview stored as a JPEG file
+  JpegPreview,
+  /// a preview stored as uncompressed 8 bit RGB
+  RgbPreview,
+  /// raw image data, of the main image or of another frame
+  Raw,
+}
+
+/// An image stored in a raw file, as listed by `rawloader::embedded_images()`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EmbeddedImage {
+  /// what the image is
+  pub kind: EmbeddedKind,
+  /// width of the image in pixels
+  pub width: usize,
+  /// height of the image in pixels
+  pub height: usize,
+  /// where the image data starts in the file, in bytes
+  pub offset: usize,
+  /// size of the image data in bytes, from its start to the end of its last strip or tile
+  pub length: usize,
+}
+
+// Every preview of the file, in the order they're found. JPEGs are found by scanning for
+// them, which works the same for all formats, and uncompressed RGB ones through the TIFF
+// IFDs that point to them.
+pub(crate) fn find(buf: &[u8]) -> Vec<Thumbnail> {
+  let tiff = TiffIFD::new_file(buf).ok();
+  let skip = tiff.as_ref().map(lossy_raw_offsets).unwrap_or_default();
+
+  let mut thumbs = Vec::new();
+  let mut pos = 0;
+  while pos + 4 <= buf.len() {
+    if buf[pos] == 0xff && buf[pos+1] == 0xd8 && buf[pos+2] == 0xff && !skip.contains(&pos) {
+      if let Some((width, height, end)) = jpeg_at(buf, pos) {
+        thumbs.push(Thumbnail { width, height, offset: pos, data: ThumbnailData::Jpeg(buf[pos..end].to_vec()) });
+      }
+    }
+    pos += 1;
+  }
+
+  if let Some(tiff) = tiff {
+    thumbs.extend(rgb_thumbnails(&tiff, buf));
+  }
+  thumbs
+}
+
+// The previews and the raw images of a file in file order. Raw images are only found through
+// the TIFF IFDs, so for formats that aren't TIFF based only previews are listed.
+pub(crate) fn embedded(buf: &[u8]) -> Vec<EmbeddedImage> {
+  let mut images: Vec<EmbeddedImage> = find(buf).iter().map(|thumb| EmbeddedImage {
+    kind: match thumb.data {
+      ThumbnailData::Jpeg(_) => EmbeddedKind::JpegPreview,
+      ThumbnailData::Rgb(_) => EmbeddedKind::RgbPreview,
+    },
+    width: thumb.width,
+    height: thumb.height,
+    offset: thumb.offset,
+    length: thumb.len(),
+  }).collect();
+
+  if let Ok(tiff) = TiffIFD::new_file(buf) {
+    let mut seen: HashSet<usize> = images.iter().map(|image| image.offset).collect();
+    for ifd in tiff.find_ifds_with_tag(Tag::ImageWidth) {
+      let (width, height) = match (fetch(ifd, Tag::ImageWidth), fetch(ifd, Tag::ImageLength)) {
+        (Some(width), Some(height)) if width > 0 && height > 0 => (width, height),
+        _ => continue,
+      };
+      let (offsets, counts) = match ifd.get_entry_raw(Tag::TileOffsets as u16) {
+        Some(offsets) => (offsets, ifd.get_entry_raw(Tag::TileByteCounts as u16)),
+        None => match ifd.get_entry_raw(Tag::StripOffsets as u16) {
+          Some(offsets) => (offsets, ifd.get_entry_raw(Tag::StripByteCounts as u16)),
+          None => continue,
+        },
+      };
+      let counts = match counts {
+        Some(counts) if counts.count() == offsets.count() && offsets.count() > 0 => counts,
+        _ => continue,
+      };
+      let start = (0..offsets.count()).map(|i| offsets.get_usize(i)).min().unwrap_or(0);
+      let end = (0..offsets.count()).map(|i| offsets.get_usize(i).saturating_add(counts.get_usize(i))).max().unwrap_or(0);
+      if end > buf.len() || !seen.insert(start) {
+        continue
+      }
+      images.push(EmbeddedImage { kind: EmbeddedKind::Raw, width, height, offset: start, length: end - start });
+    }
+  }
+
+  images.sort_by_key(|image| image.offset);
+  images
+}
+
+// Walks the segments of a JPEG to find its size and where it ends. Only the baseline,
+// extended and progressive ones are previews, the lossless ones being raw data.
+fn jpeg_at(buf: &[u8], start: usize) -> Option<(usize, usize, usize)> {
+  let mut size = None;
+  let mut pos = start + 2;
+  loop {
+    if pos + 4 > buf.len() || buf[pos] != 0xff {
+      return None
+    }
+    let marker = buf[pos+1];
+    match marker {
+      0xff => { pos += 1; continue },
+      0xd9 => return size.map(|(width, height)| (width, height, pos + 2)),
+      _ => {},
+    }
+    let len = BEu16(buf, pos+2) as usize;
+    if len < 2 {
+      return None
+    }
+    match marker {
+      0xc0..=0xc2 => {
+        if len < 8 || pos + 10 > buf.len() {
+          return None
+        }
+        let (height, width, components) = (BEu16(buf, pos+5) as usize, BEu16(buf, pos+7) as usize, buf[pos+9]);
+        if width < 16 || height < 16 || (components != 1 && components != 3) {
+          return None
+        }
+        size = Some((width, height));
+      },
+      0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => return None,
+      0xda if size.is_none() => return None,
+      _ => {},
+    }
+    pos += 2 + len;
+    if marker == 0xda {
+      // The entropy coded data goes on until a marker other than a restart
+      while pos + 1 < buf.len() && !(buf[pos] == 0xff && buf[pos+1] != 0 && !(0xd0..=0xd7).contains(&buf[pos+1])) {
+        pos += 1;
+      }
+    }
+  }
+}
+
+// DNGs with lossy compression store the raw data as baseline JPEG tiles, which aren't previews
+fn lossy_raw_offsets(tiff: &TiffIFD) -> HashSet<usize> {
+  let mut offsets = HashSet::new();
+  for ifd in tiff.find_ifds_with_tag(Tag::Compression) {
+    let raw = fetch(ifd, Tag::NewSubFileType).unwrap_or(0) == 0;
+    if !raw || fetch(ifd, Tag::Compression) != Some(34892) {
+      continue
+    }
+    for tag in [Tag::TileOffsets, Tag::StripOffsets].iter() {
+      if let Some(entry) = ifd.get_entry_raw(*tag as u16) {
+        offsets.extend((0..entry.count()).map(|i| entry.get_usize(i)));
+      }
+    }
+  }
+  offsets
+}
+
+fn fetch(ifd: &TiffIFD, tag: Tag) -> Option<usize> {
+  ifd.get_entry_raw(tag as u16).map(|e| e.get_usize(0))
+}
+
+// Uncompressed 8 bit RGB previews, in a single strip or in strips stored one after the other
+fn rgb_thumbnails(tiff: &TiffIFD, buf: &[u8]) -> Vec<Thumbnail> {
+  let mut seen = HashSet::new();
+  let mut thumbs = Vec::new();
+  for ifd in tiff.find_ifds_with_tag(Tag::StripOffsets) {
+    let (width, height) = match (fetch(ifd, Tag::ImageWidth), fetch(ifd, Tag::ImageLength)) {
+      (Some(width), Some(height)) if width > 0 && height > 0 => (width, height),
+      _ => continue,
+    };
+    if fetch(ifd, Tag::Compression) != Some(1) || fetch(ifd, Tag::PhotometricInt) != Some(2) ||
+       fetch(ifd, Tag::SamplesPerPixel) != Some(3) || fetch(ifd, Tag::BitsPerSample) != Some(8) {
+      continue
+    }
+    let offset = match fetch(ifd, Tag::StripOffsets) {
+      Some(offset) => offset,
+      None => continue,
+    };
+    let size = match width.checked_mul(height * 3) {
+      Some(size) => size,
+      None => continue,
+    };
+    if offset.checked_add(size).is_none_or(|end| end > buf.len()) || !seen.insert(offset) {
+      continue
+    }
+    thumbs.push(Thumbnail { width, height, offset, data: ThumbnailData::Rgb(buf[offset..offset+size].to_vec()) });
+  }
+  thumbs
+}
@@ -1,107 +1,234 @@
+//! Parsing of the TIFF structure most raw formats are built on, as used by the decoders.
+//! Besides the IFDs of the main chain it follows the sub IFDs, EXIF IFD, GPS IFD and
+//! makernotes so that entries can be found wherever they are.
+//!
+//! # Example
+//! ```rust,no_run
+//! use rawloader::tiff::{Tag, TiffIFD};
+//!
+//! let data = std::fs::read("path/to/your/file.NEF").unwrap();
+//! let tiff = TiffIFD::parse(&data).unwrap();
+//! if let Some(model) = tiff.find_entry(Tag::Model) {
+//!   println!("Model: {}", model.get_str());
+//! }
+//! for ifd in tiff.find_ifds_with_tag(Tag::StripOffsets) {
+//!   println!("Image data at {}", ifd.find_entry(Tag::StripOffsets).unwrap().get_u32(0));
+//! }
+//! ```
+
 use std::collections::HashMap;
+use std::panic;
 use std::str;
 
 use crate::decoders::basics::*;
-
-#[derive(Debug, Copy, Clone, PartialEq, enumn::N)]
-#[repr(u16)]
-pub enum Tag {
-  PanaWidth        = 0x0002,
-  PanaLength       = 0x0003,
-  NefWB0           = 0x000C,
-  PanaWBsR         = 0x0011,
-  PanaWBsB         = 0x0012,
-  NrwWB            = 0x0014,
-  NefSerial        = 0x001d,
-  PanaWBs2R        = 0x0024,
-  PanaWBs2G        = 0x0025,
-  PanaWBs2B        = 0x0026,
-  Cr2PowerShotWB   = 0x0029,
-  NewSubFileType   = 0x00FE,
-  Cr2OldOffset     = 0x0081,
-  NefMeta1         = 0x008c,
-  NefMeta2         = 0x0096,
-  NefWB1           = 0x0097,
-  Cr2OldWB         = 0x00A4,
-  NefKey           = 0x00a7,
-  ImageWidth       = 0x0100,
-  ImageLength      = 0x0101,
-  BitsPerSample    = 0x0102,
-  Compression      = 0x0103,
-  PhotometricInt   = 0x0106,
-  Make             = 0x010F,
-  Model            = 0x0110,
-  StripOffsets     = 0x0111,
-  Orientation      = 0x0112,
-  SamplesPerPixel  = 0x0115,
-  StripByteCounts  = 0x0117,
-  PanaOffsets      = 0x0118,
-  GrayResponse     = 0x0123,
-  Software         = 0x0131,
-  TileWidth        = 0x0142,
-  TileLength       = 0x0143,
-  TileOffsets      = 0x0144,
-  SubIFDs          = 0x014A,
-  PefBlackLevels   = 0x0200,
-  PefWB            = 0x0201,
-  PefHuffman       = 0x0220,
-  Xmp              = 0x02BC,
-  DcrWB            = 0x03FD,
-  OrfBlackLevels   = 0x0600,
-  DcrLinearization = 0x090D,
-  EpsonWB          = 0x0E80,
-  KodakWB          = 0x0F00,
-  OlympusRedMul    = 0x1017,
-  OlympusBlueMul   = 0x1018,
-  OlympusImgProc   = 0x2040,
-  RafOldWB         = 0x2ff0,
-  Cr2ColorData     = 0x4001,
-  SonyCurve        = 0x7010,
-  SonyOffset       = 0x7200,
-  SonyLength       = 0x7201,
-  SonyKey          = 0x7221,
-  SonyGRBG         = 0x7303,
-  SonyRGGB         = 0x7313,
-  CFAPattern       = 0x828E,
-  KodakIFD         = 0x8290,
-  LeafMetadata     = 0x8606,
-  ExifIFDPointer   = 0x8769,
-  Makernote        = 0x927C,
-  SrwSensorAreas   = 0xA010,
-  SrwRGGBLevels    = 0xA021,
-  SrwRGGBBlacks    = 0xA028,
-  Cr2Id            = 0xc5d8,
-  DNGVersion       = 0xC612,
-  Linearization    = 0xC618,
-  BlackLevels      = 0xC61A,
-  WhiteLevel       = 0xC61D,
-  ColorMatrix1     = 0xC621,
-  ColorMatrix2     = 0xC622,
-  AsShotNeutral    = 0xC628,
-  DNGPrivateArea   = 0xC634,
-  Cr2StripeWidths  = 0xC640,
-  ActiveArea       = 0xC68D,
-  MaskedAreas      = 0xC68E,
-  RafRawSubIFD     = 0xF000,
-  RafImageWidth    = 0xF001,
-  RafImageLength   = 0xF002,
-  RafBitsPerSample = 0xF003,
-  RafOffsets       = 0xF007,
-  RafWBGRB         = 0xF00E,
-  KdcWB            = 0xFA2A,
-  KdcWidth         = 0xFD00,
-  KdcLength        = 0xFD01,
-  KdcOffset        = 0xFD04,
-  KdcIFD           = 0xFE00,
+use crate::decoders::{RawLoaderError, corrupt, panic_error};
+
+// enumn derives an undocumented Tag::n(), which is documented on Tag instead
+#[allow(missing_docs)]
+mod tags {
+  /// The tags the decoders know about, named after the TIFF, EXIF and DNG specifications and,
+  /// with a prefix for the format or make, the private tags of the raw formats. `Tag::n()`
+  /// finds the tag for a number.
+  #[derive(Debug, Copy, Clone, PartialEq, enumn::N)]
+  #[repr(u16)]
+  pub enum Tag {
+    Cr2CamSettings   = 0x0001,
+    PanaWidth        = 0x0002,
+    PanaLength       = 0x0003,
+    Cr2ShotInfo      = 0x0004,
+    NefWB0           = 0x000C,
+    PanaWBsR         = 0x0011,
+    PanaWBsB         = 0x0012,
+    NrwWB            = 0x0014,
+    NefBracketValue  = 0x0019,
+    NefCropHiSpeed   = 0x001B,
+    NefSerial        = 0x001d,
+    PanaWBs2R        = 0x0024,
+    PanaWBs2G        = 0x0025,
+    PanaWBs2B        = 0x0026,
+    PanaWBInfo2      = 0x0027,
+    Cr2PowerShotWB   = 0x0029,
+    PanaJpgFromRaw   = 0x002E,
+    PefLensType      = 0x003F,
+    NewSubFileType   = 0x00FE,
+    Cr2OldOffset     = 0x0081,
+    NefLens          = 0x0084,
+    NefShootingMode  = 0x0089,
+    NefMeta1         = 0x008c,
+    Cr2LensModel     = 0x0095,
+    NefMeta2         = 0x0096,
+    NefWB1           = 0x0097,
+    NefLensData      = 0x0098,
+    Cr2OldWB         = 0x00A4,
+    NefKey           = 0x00a7,
+    NefAFInfo2       = 0x00b7,
+    ImageWidth       = 0x0100,
+    ImageLength      = 0x0101,
+    BitsPerSample    = 0x0102,
+    Compression      = 0x0103,
+    PhotometricInt   = 0x0106,
+    ImageDescription = 0x010E,
+    Make             = 0x010F,
+    Model            = 0x0110,
+    StripOffsets     = 0x0111,
+    Orientation      = 0x0112,
+    SamplesPerPixel  = 0x0115,
+    RowsPerStrip     = 0x0116,
+    StripByteCounts  = 0x0117,
+    PanaOffsets      = 0x0118,
+    PanaDistortion   = 0x0119,
+    PlanarConfig     = 0x011C,
+    GrayResponse     = 0x0123,
+    Software         = 0x0131,
+    DateTime         = 0x0132,
+    Artist           = 0x013B,
+    Predictor        = 0x013D,
+    TileWidth        = 0x0142,
+    TileLength       = 0x0143,
+    TileOffsets      = 0x0144,
+    TileByteCounts   = 0x0145,
+    SubIFDs          = 0x014A,
+    SampleFormat     = 0x0153,
+    PefBlackLevels   = 0x0200,
+    PefWB            = 0x0201,
+    PefHuffman       = 0x0220,
+    Xmp              = 0x02BC,
+    DcrWB            = 0x03FD,
+    OrfBlackLevels   = 0x0600,
+    DcrLinearization = 0x090D,
+    EpsonWB          = 0x0E80,
+    KodakWB          = 0x0F00,
+    OlympusRedMul    = 0x1017,
+    OlympusBlueMul   = 0x1018,
+    RafWBAuto        = 0x2000,
+    OlympusEquipment = 0x2010,
+    OlympusSettings  = 0x2020,
+    SonyFocusLoc     = 0x2027,
+    OlympusImgProc   = 0x2040,
+    RafWBDaylight    = 0x2100,
+    RafWBCloudy      = 0x2200,
+    RafWBFluorescent = 0x2300,
+    RafWBTungsten    = 0x2400,
+    RafOldWB         = 0x2ff0,
+    Cr2ColorData     = 0x4001,
+    Cr2LensInfo      = 0x4019,
+    Rating           = 0x4746,
+    SonyCurve        = 0x7010,
+    SonyOffset       = 0x7200,
+    SonyLength       = 0x7201,
+    SonyKey          = 0x7221,
+    SonyGRBG         = 0x7303,
+    SonyRGGB         = 0x7313,
+    CFARepeatDim     = 0x828D,
+    CFAPattern       = 0x828E,
+    KodakIFD         = 0x8290,
+    Copyright        = 0x8298,
+    ExposureTime     = 0x829A,
+    FNumber          = 0x829D,
+    LeafMetadata     = 0x8606,
+    ExifIFDPointer   = 0x8769,
+    IccProfile       = 0x8773,
+    ExposureProgram  = 0x8822,
+    GPSInfo          = 0x8825,
+    ISOSpeed         = 0x8827,
+    DateTimeOriginal = 0x9003,
+    OffsetTimeOrig   = 0x9011,
+    Sony9050         = 0x9050,
+    ExposureBias     = 0x9204,
+    MeteringMode     = 0x9207,
+    Flash            = 0x9209,
+    FocalLength      = 0x920A,
+    Makernote        = 0x927C,
+    UserComment      = 0x9286,
+    SubSecTimeOrig   = 0x9291,
+    Sony9402         = 0x9402,
+    SrwSensorAreas   = 0xA010,
+    SrwRGGBLevels    = 0xA021,
+    SrwRGGBBlacks    = 0xA028,
+    BodySerial       = 0xA431,
+    LensInfo         = 0xA432,
+    LensMake         = 0xA433,
+    LensModel        = 0xA434,
+    SonyLensType     = 0xB027,
+    Cr2Id            = 0xc5d8,
+    DNGVersion       = 0xC612,
+    DNGBackVersion   = 0xC613,
+    UniqueCamModel   = 0xC614,
+    CFAPlaneColor    = 0xC616,
+    CFALayout        = 0xC617,
+    Linearization    = 0xC618,
+    BlackRepeatDim   = 0xC619,
+    BlackLevels      = 0xC61A,
+    WhiteLevel       = 0xC61D,
+    DefaultCropOrig  = 0xC61F,
+    DefaultCropSize  = 0xC620,
+    ColorMatrix1     = 0xC621,
+    ColorMatrix2     = 0xC622,
+    CameraCalib1     = 0xC623,
+    CameraCalib2     = 0xC624,
+    AnalogBalance    = 0xC627,
+    AsShotNeutral    = 0xC628,
+    BaselineExposure = 0xC62A,
+    BaselineNoise    = 0xC62B,
+    BaselineSharp    = 0xC62C,
+    LinearRespLimit  = 0xC62E,
+    CameraSerial     = 0xC62F,
+    DNGLensInfo      = 0xC630,
+    DNGPrivateArea   = 0xC634,
+    Cr2StripeWidths  = 0xC640,
+    CalibIlluminant1 = 0xC65A,
+    CalibIlluminant2 = 0xC65B,
+    ActiveArea       = 0xC68D,
+    MaskedAreas      = 0xC68E,
+    ForwardMatrix1   = 0xC714,
+    ForwardMatrix2   = 0xC715,
+    OpcodeList1      = 0xC740,
+    OpcodeList2      = 0xC741,
+    OpcodeList3      = 0xC74E,
+    NoiseProfile     = 0xC761,
+    DefaultUserCrop  = 0xC7B5,
+    DepthNear        = 0xC7EA,
+    DepthFar         = 0xC7EB,
+    SemanticName     = 0xCD2E,
+    SemanticInstID   = 0xCD30,
+    MaskSubArea      = 0xCD38,
+    RafRawSubIFD     = 0xF000,
+    RafImageWidth    = 0xF001,
+    RafImageLength   = 0xF002,
+    RafBitsPerSample = 0xF003,
+    RafOffsets       = 0xF007,
+    RafWBGRB         = 0xF00E,
+    KdcWB            = 0xFA2A,
+    KdcWidth         = 0xFD00,
+    KdcLength        = 0xFD01,
+    KdcOffset        = 0xFD04,
+    KdcIFD           = 0xFE00,
+  }
 }
+pub use self::tags::Tag;
 
                           // 0-1-2-3-4-5-6-7-8-9-10-11-12-13
 const DATASHIFTS: [u8;14] = [0,0,0,1,2,3,0,0,1,2, 3, 2, 3, 2];
 
+thread_local! {
+  // Set when an entry was skipped for pointing past the end of the buffer, which when
+  // decoding the start of a stream means more of it needs to be read
+  static PAST_END: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+pub(crate) fn reset_past_end() {
+  PAST_END.with(|p| p.set(false));
+}
+
+pub(crate) fn read_past_end() -> bool {
+  PAST_END.with(|p| p.get())
+}
+
 fn t (tag: Tag) -> u16 {
   tag as u16
 }
 
+/// An entry of an IFD, with its data in the file
 #[derive(Debug, Copy, Clone)]
 pub struct TiffEntry<'a> {
   tag: u16,
@@ -113,25 +240,63 @@ pub struct TiffEntry<'a> {
   endian: Endian,
 }
 
+// How much of the file to parse
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Parse {
+  // entries of known tags, following sub IFDs
+  Known,
+  // all the entries of a single IFD
+  Flat,
+  // all the entries, following sub IFDs
+  All,
+}
+
+/// An IFD with its entries and the IFDs found through it: the chain of IFDs for the root of
+/// a file, and sub IFDs, the EXIF IFD and makernotes for the others
 #[derive(Debug, Clone)]
 pub struct TiffIFD<'a> {
   entries: HashMap<u16,TiffEntry<'a>>,
   subifds: Vec<TiffIFD<'a>>,
+  // The GPS IFD has its own tag numbering so it's kept apart from the other IFDs
+  gps: Option<Box<TiffIFD<'a>>>,
+  // Makernote sub IFDs that also use their own numbering, by the tag that points to them
+  private: HashMap<u16,TiffIFD<'a>>,
   nextifd: usize,
   start_offset: usize,
   endian: Endian,
 }
 
 impl<'a> TiffIFD<'a> {
-  pub fn new_file(buf: &'a[u8]) -> Result<TiffIFD<'a>, String> {
+  /// Parses a file with all its entries, including those of tags that aren't in `Tag`. For
+  /// RAF files it's the TIFF structures they embed that are parsed.
+  pub fn parse(buf: &'a[u8]) -> Result<TiffIFD<'a>, RawLoaderError> {
+    TiffIFD::new_file_with(buf, Parse::All)
+  }
+
+  /// Parses a file keeping only the entries of the tags in `Tag`, as the decoders do
+  pub fn new_file(buf: &'a[u8]) -> Result<TiffIFD<'a>, RawLoaderError> {
+    TiffIFD::new_file_with(buf, Parse::Known)
+  }
+
+  // Files cut short or with offsets past their end make the parsing panic, which is returned
+  // as an error instead
+  fn new_file_with(buf: &'a[u8], mode: Parse) -> Result<TiffIFD<'a>, RawLoaderError> {
+    if buf.len() < 8 {
+      return Err(RawLoaderError::Corrupt("TIFF: file is too short".to_string()))
+    }
+    panic::catch_unwind(panic::AssertUnwindSafe(|| TiffIFD::parse_file(buf, mode)))
+      .unwrap_or_else(|payload| Err(panic_error(payload)))
+  }
+
+  fn parse_file(buf: &'a[u8], mode: Parse) -> Result<TiffIFD<'a>, RawLoaderError> {
     if buf[0..8] == b"FUJIFILM"[..] {
-      let ifd1 = TiffIFD::new_root(buf, (BEu32(buf, 84)+12) as usize)?;
+      let ifd1 = TiffIFD::new_root_with(buf, (BEu32(buf, 84)+12) as usize, mode)?;
       let endian = ifd1.get_endian();
       let mut subifds = vec![ifd1];
       let mut entries = HashMap::new();
 
       let ioffset = BEu32(buf, 100) as usize;
-      match TiffIFD::new_root(buf, ioffset) {
+      match TiffIFD::new_root_with(buf, ioffset, mode) {
         Ok(val) => {subifds.push(val);}
         Err(_) => {
           entries.insert(Tag::RafOffsets as u16, TiffEntry{
@@ -153,26 +318,32 @@ impl<'a> TiffIFD<'a> {
       Ok(TiffIFD {
         entries: entries,
         subifds: subifds,
+        gps: None,
+        private: HashMap::new(),
         nextifd: 0,
         start_offset: 0,
         endian: endian,
       })
     } else {
-      TiffIFD::new_root(buf, 0)
+      TiffIFD::new_root_with(buf, 0, mode)
     }
   }
 
-  pub fn new_root(buf: &'a[u8], offset: usize) -> Result<TiffIFD<'a>, String> {
+  pub(crate) fn new_root(buf: &'a[u8], offset: usize) -> Result<TiffIFD<'a>, RawLoaderError> {
+    TiffIFD::new_root_with(buf, offset, Parse::Known)
+  }
+
+  fn new_root_with(buf: &'a[u8], offset: usize, mode: Parse) -> Result<TiffIFD<'a>, RawLoaderError> {
     let mut subifds = Vec::new();
 
     let endian = match LEu16(buf, offset) {
       0x4949 => LITTLE_ENDIAN,
       0x4d4d => BIG_ENDIAN,
-      x => {return Err(format!("TIFF: don't know marker 0x{:x}", x).to_string())},
+      x => {return Err(RawLoaderError::Corrupt(format!("TIFF: don't know marker 0x{:x}", x)))},
     };
     let mut nextifd = endian.ru32(buf, offset+4) as usize;
     for _ in 0..100 { // Never read more than 100 IFDs
-      let ifd = TiffIFD::new(&buf[offset..], nextifd, 0, offset, 0, endian)?;
+      let ifd = TiffIFD::new_ifd(&buf[offset..], nextifd, 0, offset, 0, endian, mode)?;
       nextifd = ifd.nextifd;
       subifds.push(ifd);
       if nextifd == 0 {
@@ -183,39 +354,82 @@ impl<'a> TiffIFD<'a> {
     Ok(TiffIFD {
       entries: HashMap::new(),
       subifds: subifds,
+      gps: None,
+      private: HashMap::new(),
       nextifd: 0,
       start_offset: offset,
       endian: endian,
     })
   }
 
-  pub fn new(buf: &'a[u8], offset: usize, base_offset: usize, start_offset: usize, depth: u32, e: Endian) -> Result<TiffIFD<'a>, String> {
+  pub(crate) fn new(buf: &'a[u8], offset: usize, base_offset: usize, start_offset: usize, depth: u32, e: Endian) -> Result<TiffIFD<'a>, RawLoaderError> {
+    TiffIFD::new_ifd(buf, offset, base_offset, start_offset, depth, e, Parse::Known)
+  }
+
+  // Parse a single IFD keeping all its entries, for private IFDs with their own tag numbering
+  pub(crate) fn new_unfiltered(buf: &'a[u8], offset: usize, base_offset: usize, start_offset: usize, e: Endian) -> Result<TiffIFD<'a>, RawLoaderError> {
+    TiffIFD::new_ifd(buf, offset, base_offset, start_offset, 0, e, Parse::Flat)
+  }
+
+  fn new_ifd(buf: &'a[u8], offset: usize, base_offset: usize, start_offset: usize, depth: u32, e: Endian, mode: Parse) -> Result<TiffIFD<'a>, RawLoaderError> {
     let mut entries = HashMap::new();
     let mut subifds = Vec::new();
+    let mut gps = None;
+    let mut private = HashMap::new();
 
     let num = e.ru16(buf, offset); // Directory entries in this IFD
-    if num > 4000 {
-      return Err(format!("too many entries in IFD ({})", num).to_string())
+    let max = crate::decoders::limits().max_tiff_entries;
+    if num as usize > max {
+      return Err(RawLoaderError::LimitExceeded(format!("IFD has {} entries, over the limit of {}", num, max)))
     }
     for i in 0..num {
       let entry_offset: usize = offset + 2 + (i as usize)*12;
-      if Tag::n(e.ru16(buf, entry_offset)).is_none() {
+      if mode == Parse::Known && Tag::n(e.ru16(buf, entry_offset)).is_none() {
         // Skip entries we don't know about to speedup decoding
         continue;
       }
+      if !TiffEntry::is_valid(buf, entry_offset, base_offset, e) {
+        // Skip entries that point outside of the file
+        crate::decoders::tolerate(format!("TIFF: entry for tag 0x{:04x} points outside of the file", e.ru16(buf, entry_offset)))?;
+        continue;
+      }
       let entry = TiffEntry::new(buf, entry_offset, base_offset, offset, e);
 
-      if entry.tag == t(Tag::SubIFDs)
+      if mode == Parse::Flat {
+        entries.insert(entry.tag, entry);
+      } else if entry.tag == t(Tag::GPSInfo) {
+        if depth < 10 {
+          let ifd = TiffIFD::new_ifd(buf, entry.get_u32(0) as usize, base_offset, start_offset, depth+1, e, Parse::Flat);
+          match ifd {
+            Ok(val) => {gps = Some(Box::new(val));},
+            Err(err) => {
+              // Ignore unparsable IFDs
+              crate::decoders::tolerate(format!("TIFF: can't parse the IFD of tag 0x{:04x}: {}", entry.tag, err))?;
+              entries.insert(entry.tag, entry);
+            },
+          }
+        }
+      } else if entry.tag == t(Tag::SubIFDs)
       || entry.tag == t(Tag::ExifIFDPointer)
       || entry.tag == t(Tag::RafRawSubIFD)
       || entry.tag == t(Tag::KodakIFD)
       || entry.tag == t(Tag::KdcIFD) {
         if depth < 10 { // Avoid infinite looping IFDs
           for i in 0..entry.count {
-            let ifd = TiffIFD::new(buf, entry.get_u32(i as usize) as usize, base_offset, start_offset, depth+1, e);
+            let ifd = TiffIFD::new_ifd(buf, entry.get_u32(i as usize) as usize, base_offset, start_offset, depth+1, e, mode);
             match ifd {
               Ok(val) => {subifds.push(val);},
-              Err(_) => {entries.insert(entry.tag, entry);}, // Ignore unparsable IFDs
+              Err(err) => {
+                // Ignore unparsable IFDs
+                crate::decoders::tolerate(format!("TIFF: can't parse the IFD of tag 0x{:04x}: {}", entry.tag, err))?;
+                entries.insert(entry.tag, entry);
+              },
+            }
+          }
+          // Keep all of the EXIF IFD so it can be passed on as it was
+          if entry.tag == t(Tag::ExifIFDPointer) {
+            if let Ok(ifd) = TiffIFD::new_unfiltered(buf, entry.get_u32(0) as usize, base_offset, start_offset, e) {
+              private.insert(entry.tag, ifd);
             }
           }
         }
@@ -224,7 +438,11 @@ impl<'a> TiffIFD<'a> {
           let ifd = TiffIFD::new_makernote(buf, entry.doffset(), base_offset, depth+1, e);
           match ifd {
             Ok(val) => {subifds.push(val);},
-            Err(_) => {entries.insert(entry.tag, entry);}, // Ignore unparsable IFDs
+            Err(err) => {
+              // Ignore unparsable IFDs
+              crate::decoders::tolerate(format!("TIFF: can't parse the IFD of tag 0x{:04x}: {}", entry.tag, err))?;
+              entries.insert(entry.tag, entry);
+            },
           }
         }
       } else {
@@ -235,13 +453,15 @@ impl<'a> TiffIFD<'a> {
     Ok(TiffIFD {
       entries: entries,
       subifds: subifds,
+      gps,
+      private,
       nextifd: e.ru32(buf, offset + (2+num*12) as usize) as usize,
       start_offset: start_offset,
       endian: e,
     })
   }
 
-  pub fn new_makernote(buf: &'a[u8], offset: usize, base_offset: usize, depth: u32, e: Endian) -> Result<TiffIFD<'a>, String> {
+  pub(crate) fn new_makernote(buf: &'a[u8], offset: usize, base_offset: usize, depth: u32, e: Endian) -> Result<TiffIFD<'a>, RawLoaderError> {
     let mut off = 0;
     let data = &buf[offset..];
     let mut endian = e;
@@ -264,11 +484,57 @@ impl<'a> TiffIFD<'a> {
           let iprocifd = TiffIFD::new(&buf[offset+ioff..], 0, ioff, 0, depth, endian)?;
           mainifd.subifds.push(iprocifd);
         }
+
+        // Keep the full Equipment, CameraSettings and ImgProc sections for the makernote parser
+        for tag in [Tag::OlympusEquipment, Tag::OlympusSettings, Tag::OlympusImgProc].iter() {
+          let ioff = match mainifd.find_entry(*tag) {
+            Some(entry) if entry.typ() == 4 || entry.typ() == 13 => entry.get_usize(0),
+            _ => 0,
+          };
+          if ioff != 0 && offset+ioff < buf.len() {
+            if let Ok(ifd) = TiffIFD::new_unfiltered(&buf[offset+ioff..], 0, ioff, 0, endian) {
+              mainifd.private.insert(t(*tag), ifd);
+            }
+          }
+        }
+      }
+
+      if let Ok(all) = TiffIFD::new_unfiltered(buf, offset+off, base_offset, 0, endian) {
+        mainifd.private.insert(t(Tag::Makernote), all);
       }
 
       return Ok(mainifd)
     }
 
+    // Panasonic starts the makernote with its own name and uses its own tag numbering so keep
+    // all the entries around for the makernote parser
+    if data[0..9] == b"Panasonic"[..] {
+      let mut ifd = TiffIFD::new(buf, offset+12, base_offset, 0, depth, endian)?;
+      if let Ok(all) = TiffIFD::new_unfiltered(buf, offset+12, base_offset, 0, endian) {
+        ifd.private.insert(t(Tag::Makernote), all);
+      }
+      return Ok(ifd)
+    }
+
+    // Fuji starts the makernote with its name and the offset of the IFD, always little endian
+    // and with offsets relative to the start of the makernote. Its tag numbering clashes with
+    // the tags we look for elsewhere so only keep it for the makernote parser
+    if data[0..8] == b"FUJIFILM"[..] {
+      let ioff = LEu32(data, 8) as usize;
+      let all = TiffIFD::new_unfiltered(data, ioff, 0, 0, LITTLE_ENDIAN)?;
+      let mut private = HashMap::new();
+      private.insert(t(Tag::Makernote), all);
+      return Ok(TiffIFD {
+        entries: HashMap::new(),
+        subifds: Vec::new(),
+        gps: None,
+        private,
+        nextifd: 0,
+        start_offset: 0,
+        endian: LITTLE_ENDIAN,
+      })
+    }
+
     // Epson starts the makernote with its own name
     if data[0..5] == b"EPSON"[..] {
       off += 8;
@@ -283,7 +549,11 @@ impl<'a> TiffIFD<'a> {
     if data[0..6] == b"PENTAX"[..] {
       off += 8;
       let endian = if data[off..off+2] == b"II"[..] {LITTLE_ENDIAN} else {BIG_ENDIAN};
-      return TiffIFD::new(&buf[offset..], 10, base_offset, 0, depth, endian)
+      let mut ifd = TiffIFD::new(&buf[offset..], 10, base_offset, 0, depth, endian)?;
+      if let Ok(all) = TiffIFD::new_unfiltered(&buf[offset..], 10, base_offset, 0, endian) {
+        ifd.private.insert(t(Tag::Makernote), all);
+      }
+      return Ok(ifd)
     }
 
     if data[0..7] == b"Nikon\0\x02"[..] {
@@ -301,14 +571,22 @@ impl<'a> TiffIFD<'a> {
       endian = BIG_ENDIAN;
     }
 
-    TiffIFD::new(buf, offset+off, base_offset, 0, depth, endian)
+    let mut ifd = TiffIFD::new(buf, offset+off, base_offset, 0, depth, endian)?;
+
+    // Keep all the entries around for the makernote parsers, brands reuse the tag numbers
+    if let Ok(all) = TiffIFD::new_unfiltered(buf, offset+off, base_offset, 0, endian) {
+      ifd.private.insert(t(Tag::Makernote), all);
+    }
+
+    Ok(ifd)
   }
 
-  pub fn new_fuji(buf: &'a[u8], offset: usize) -> Result<TiffIFD<'a>, String> {
+  pub(crate) fn new_fuji(buf: &'a[u8], offset: usize) -> Result<TiffIFD<'a>, RawLoaderError> {
     let mut entries = HashMap::new();
     let num = BEu32(buf, offset); // Directory entries in this IFD
-    if num > 4000 {
-      return Err(format!("too many entries in IFD ({})", num).to_string())
+    let max = crate::decoders::limits().max_tiff_entries;
+    if num as usize > max {
+      return Err(RawLoaderError::LimitExceeded(format!("IFD has {} entries, over the limit of {}", num, max)))
     }
     let mut off = offset+4;
     for _ in 0..num {
@@ -324,9 +602,10 @@ impl<'a> TiffIFD<'a> {
           data: &buf[off+4..off+8],
           endian: BIG_ENDIAN,
         });
-      } else if tag == t(Tag::RafOldWB) {
-        entries.insert(t(Tag::RafOldWB), TiffEntry {
-          tag: t(Tag::RafOldWB),
+      } else if tag == t(Tag::RafOldWB) || tag == t(Tag::RafWBAuto) || tag == t(Tag::RafWBDaylight)
+             || tag == t(Tag::RafWBCloudy) || tag == t(Tag::RafWBFluorescent) || tag == t(Tag::RafWBTungsten) {
+        entries.insert(tag, TiffEntry {
+          tag,
           typ: 3, // Short
           count: 4,
           parent_offset: 0,
@@ -341,12 +620,15 @@ impl<'a> TiffIFD<'a> {
     Ok(TiffIFD {
       entries: entries,
       subifds: Vec::new(),
+      gps: None,
+      private: HashMap::new(),
       nextifd: 0,
       start_offset: 0,
       endian: BIG_ENDIAN,
     })
   }
 
+  /// Finds an entry in this IFD or, when it's not there, in the IFDs found through it
   pub fn find_entry(&self, tag: Tag) -> Option<&TiffEntry> {
     if self.entries.contains_key(&t(tag)) {
       self.entries.get(&t(tag))
@@ -361,24 +643,25 @@ impl<'a> TiffIFD<'a> {
     }
   }
 
+  /// If `find_entry()` finds the tag
   pub fn has_entry(&self, tag: Tag) -> bool {
     self.find_entry(tag).is_some()
   }
 
+  /// This IFD and the ones found through it that have an entry for the tag, parents before
+  /// their sub IFDs
   pub fn find_ifds_with_tag(&self, tag: Tag) -> Vec<&TiffIFD> {
     let mut ifds = Vec::new();
     if self.entries.contains_key(&t(tag)) {
       ifds.push(self);
     }
     for ifd in &self.subifds {
-      if ifd.entries.contains_key(&t(tag)) {
-        ifds.push(ifd);
-      }
       ifds.extend(ifd.find_ifds_with_tag(tag));
     }
     ifds
   }
 
+  /// The first of `find_ifds_with_tag()`
   pub fn find_first_ifd(&self, tag: Tag) -> Option<&TiffIFD> {
     let ifds = self.find_ifds_with_tag(tag);
     if ifds.len() == 0 {
@@ -388,22 +671,56 @@ impl<'a> TiffIFD<'a> {
     }
   }
 
-  pub fn get_endian(&self) -> Endian { self.endian }
+  /// The GPS IFD, which has a numbering of its own so its entries are only found through it
+  pub fn find_gps_ifd(&self) -> Option<&TiffIFD<'a>> {
+    match self.gps {
+      Some(ref gps) => Some(gps),
+      None => self.subifds.iter().filter_map(|ifd| ifd.find_gps_ifd()).next(),
+    }
+  }
+
+  // Get an entry by its raw number, only in this IFD, for IFDs that don't use the Tag numbering
+  // Find one of the makernote sub IFDs kept apart by the tag that pointed to it
+  pub(crate) fn find_private_ifd(&self, tag: Tag) -> Option<&TiffIFD<'a>> {
+    match self.private.get(&t(tag)) {
+      Some(ifd) => Some(ifd),
+      None => self.subifds.iter().filter_map(|ifd| ifd.find_private_ifd(tag)).next(),
+    }
+  }
+
+  /// Gets an entry of this IFD only by its number, for tags that aren't in `Tag`
+  pub fn get_entry_raw(&self, tag: u16) -> Option<&TiffEntry<'a>> {
+    self.entries.get(&tag)
+  }
+
+  /// All the entries of this IFD in tag order
+  pub fn get_entries(&self) -> Vec<&TiffEntry<'a>> {
+    let mut entries: Vec<&TiffEntry<'a>> = self.entries.values().collect();
+    entries.sort_by_key(|e| e.tag);
+    entries
+  }
+
+  /// The IFDs found through this one: the chain of IFDs of a file for its root, and sub
+  /// IFDs, the EXIF IFD and makernotes for the others
+  pub fn subifds(&self) -> &[TiffIFD<'a>] {
+    &self.subifds
+  }
+
+  /// Offset of the next IFD in the chain, 0 for the last one
+  pub fn next_ifd(&self) -> usize { self.nextifd }
+
+  pub(crate) fn get_endian(&self) -> Endian { self.endian }
+  /// If the IFD is little endian, otherwise it's big endian
   pub fn little_endian(&self) -> bool { self.endian.little() }
+  /// Where in the file the TIFF structure the offsets are relative to starts
   pub fn start_offset(&self) -> usize { self.start_offset }
 }
 
 impl<'a> TiffEntry<'a> {
-  pub fn new(buf: &'a[u8], offset: usize, base_offset: usize, parent_offset: usize, e: Endian) -> TiffEntry<'a> {
+  pub(crate) fn new(buf: &'a[u8], offset: usize, base_offset: usize, parent_offset: usize, e: Endian) -> TiffEntry<'a> {
     let tag = e.ru16(buf, offset);
-    let mut typ = e.ru16(buf, offset+2);
+    let typ = TiffEntry::fixed_type(e.ru16(buf, offset+2));
     let count = e.ru32(buf, offset+4) as usize;
-
-    // If we don't know the type assume byte data
-    if typ == 0 || typ > 13 {
-      typ = 1;
-    }
-
     let bytesize: usize = count << DATASHIFTS[typ as usize];
     let doffset: usize = if bytesize <= 4 {
       offset + 8
@@ -422,47 +739,99 @@ impl<'a> TiffEntry<'a> {
     }
   }
 
-  pub fn copy_with_new_data(&self, data: &'a[u8]) -> TiffEntry<'a> {
+  // If we don't know the type assume byte data
+  fn fixed_type(typ: u16) -> u16 {
+    if typ == 0 || typ > 13 { 1 } else { typ }
+  }
+
+  // Check that the entry's data is within the buffer so it can be created without panicking
+  fn is_valid(buf: &[u8], offset: usize, base_offset: usize, e: Endian) -> bool {
+    let typ = TiffEntry::fixed_type(e.ru16(buf, offset+2));
+    let count = e.ru32(buf, offset+4) as usize;
+    let bytesize = match count.checked_mul(1 << DATASHIFTS[typ as usize]) {
+      Some(val) => val,
+      None => return false,
+    };
+    let doffset = if bytesize <= 4 {
+      offset + 8
+    } else {
+      match (e.ru32(buf, offset+8) as usize).checked_sub(base_offset) {
+        Some(val) => val,
+        None => return false,
+      }
+    };
+    match doffset.checked_add(bytesize) {
+      Some(end) if end <= buf.len() => true,
+      Some(_) => {
+        PAST_END.with(|p| p.set(true));
+        false
+      },
+      None => false,
+    }
+  }
+
+  pub(crate) fn copy_with_new_data(&self, data: &'a[u8]) -> TiffEntry<'a> {
     let mut copy = self.clone();
     copy.data = data;
     copy
   }
 
-  pub fn copy_offset_from_parent(&self, buffer: &'a[u8]) -> TiffEntry<'a> {
+  pub(crate) fn copy_offset_from_parent(&self, buffer: &'a[u8]) -> TiffEntry<'a> {
     self.copy_with_new_data(&buffer[self.parent_offset+self.doffset..])
   }
 
+  /// Number of the tag, see `Tag::n()` for the `Tag`
+  pub fn tag(&self) -> u16 { self.tag }
+  pub(crate) fn get_endian(&self) -> Endian { self.endian }
+  /// Offset of the data, relative to the TIFF structure for data that didn't fit in the entry
   pub fn doffset(&self) -> usize { self.doffset }
+  /// Offset in the file of the TIFF structure `doffset()` is relative to
   pub fn parent_offset(&self) -> usize { self.parent_offset }
+  /// Number of values
   pub fn count(&self) -> usize { self.count }
-  //pub fn typ(&self) -> u16 { self.typ }
-
+  /// TIFF type of the values (1 for bytes, 3 for shorts, 4 for longs, 5 for rationals, etc),
+  /// with unknown types read as bytes
+  pub fn typ(&self) -> u16 { self.typ }
+
+  /// Value at an index as an integer, from bytes or shorts.
+  ///
+  /// # Panics
+  /// If the index is past `count()` or the type is bigger than a short
   pub fn get_u16(&self, idx: usize) -> u16 {
     match self.typ {
       1                  => self.data[idx] as u16,
       3 | 8              => self.get_force_u16(idx),
-      _ => panic!("Trying to read typ {} for a u32", self.typ),
+      _ => corrupt(&format!("TIFF: Trying to read typ {} for a u32", self.typ)),
     }
   }
 
+  /// Value at an index as an integer, from bytes, shorts or longs.
+  ///
+  /// # Panics
+  /// If the index is past `count()` or the type isn't an integer
   pub fn get_u32(&self, idx: usize) -> u32 {
     match self.typ {
       1 | 3 | 8          => self.get_u16(idx) as u32,
       4 | 7 | 9 | 13     => self.get_force_u32(idx),
-      _ => panic!("Trying to read typ {} for a u32", self.typ),
+      _ => corrupt(&format!("TIFF: Trying to read typ {} for a u32", self.typ)),
     }
   }
 
+  /// Same as `get_u32()` for offsets and sizes
   pub fn get_usize(&self, idx: usize) -> usize { self.get_u32(idx) as usize }
 
-  pub fn get_force_u32(&self, idx: usize) -> u32 {
+  pub(crate) fn get_force_u32(&self, idx: usize) -> u32 {
     self.endian.ru32(self.data, idx*4)
   }
 
-  pub fn get_force_u16(&self, idx: usize) -> u16 {
+  pub(crate) fn get_force_u16(&self, idx: usize) -> u16 {
     self.endian.ru16(self.data, idx*2)
   }
 
+  /// Value at an index as a float, from rationals or integers.
+  ///
+  /// # Panics
+  /// Same as `get_u32()`
   pub fn get_f32(&self, idx: usize) -> f32 {
     if self.typ == 5 { // Rational
       let a = self.endian.ru32(self.data, idx*8) as f32;
@@ -477,6 +846,23 @@ impl<'a> TiffEntry<'a> {
     }
   }
 
+  /// Value at an index as a double, from doubles, rationals or integers
+  ///
+  /// # Panics
+  /// Same as `get_u32()`
+  pub fn get_f64(&self, idx: usize) -> f64 {
+    if self.typ == 12 { // Double
+      let (a, b) = (self.endian.ru32(self.data, idx*8) as u64, self.endian.ru32(self.data, idx*8+4) as u64);
+      f64::from_bits(if self.endian.little() { b << 32 | a } else { a << 32 | b })
+    } else {
+      self.get_f32(idx) as f64
+    }
+  }
+
+  /// The data as a string, up to the first null and trimmed.
+  ///
+  /// # Panics
+  /// If it isn't valid UTF-8
   pub fn get_str(&self) -> &str {
     // Truncate the string when there are \0 bytes
     let len = match self.data.iter().position(|&x| x == 0) {
@@ -485,10 +871,11 @@ impl<'a> TiffEntry<'a> {
     };
     match str::from_utf8(&self.data[0..len]) {
       Ok(val) => val.trim(),
-      Err(err) => std::panic::panic_any(err),
+      Err(err) => corrupt(&format!("TIFF: String isn't valid UTF-8: {}", err)),
     }
   }
 
+  /// The raw bytes of the data
   pub fn get_data(&self) -> &[u8] {
     self.data
   }
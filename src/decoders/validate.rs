@@ -0,0 +1,219 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::decoders::DecodeWarning;
+use crate::decoders::basics::*;
+use crate::decoders::tiff::Tag;
+
+/// What a `Problem` found by `rawloader::validate()` is
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ProblemKind {
+  /// an IFD starts or ends outside the file
+  IfdOutOfRange,
+  /// an IFD is pointed to again by itself or by one of the IFDs it points to
+  IfdLoop,
+  /// the data of an IFD entry is outside the file
+  EntryOutOfRange,
+  /// a strip or tile of image data is outside the file
+  StripOutOfRange,
+  /// an IFD has a different number of strip or tile offsets than byte counts
+  StripCountMismatch,
+  /// two strips or tiles of the same image overlap
+  StripOverlap,
+  /// a box of an ISO BMFF file (CR3) is too small or goes past the end of its parent
+  BoxOutOfRange,
+  /// the image can't be decoded
+  Undecodable,
+  /// the image decodes but only by working around a problem
+  Warning(DecodeWarning),
+}
+
+/// A problem with the structure of a file, as found by `rawloader::validate()`
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Problem {
+  /// what the problem is
+  pub kind: ProblemKind,
+  /// where in the file the structure with the problem is, in bytes, when it's known
+  pub offset: Option<usize>,
+  /// description of the problem
+  pub message: String,
+}
+
+impl fmt::Display for Problem {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.offset {
+      Some(offset) => write!(f, "{} (at {})", self.message, offset),
+      None => write!(f, "{}", self.message),
+    }
+  }
+}
+
+impl Problem {
+  fn new(kind: ProblemKind, offset: usize, message: String) -> Problem {
+    Problem { kind, offset: Some(offset), message }
+  }
+
+  pub(crate) fn from_warning(warning: DecodeWarning) -> Problem {
+    let offset = match warning {
+      DecodeWarning::Truncated(offset) => Some(offset),
+      _ => None,
+    };
+    Problem { message: warning.to_string(), kind: ProblemKind::Warning(warning), offset }
+  }
+
+  pub(crate) fn undecodable(message: String) -> Problem {
+    Problem { kind: ProblemKind::Undecodable, offset: None, message }
+  }
+}
+
+// Checks the structure of a file without going through the parsers of the decoders, which
+// skip over what they can't use. Files that are neither TIFF based nor ISO BMFF only get
+// checked by decoding them.
+pub(crate) fn check(buf: &[u8]) -> Vec<Problem> {
+  let mut problems = Vec::new();
+  if buf.len() >= 8 && &buf[4..8] == b"ftyp" {
+    check_boxes(buf, 0, buf.len(), 0, &mut problems);
+  } else if buf.len() >= 8 && (&buf[0..2] == b"II" || &buf[0..2] == b"MM") {
+    let endian = if &buf[0..2] == b"II" { LITTLE_ENDIAN } else { BIG_ENDIAN };
+    check_ifds(buf, endian, endian.ru32(buf, 4) as usize, &mut problems);
+  }
+  problems
+}
+
+fn in_range(buf: &[u8], offset: usize, size: usize) -> bool {
+  offset.checked_add(size).is_some_and(|end| end <= buf.len())
+}
+
+// Where the data of an IFD entry is and how big it is, None if the entry itself isn't in the file
+fn entry_data(buf: &[u8], e: Endian, entry: usize) -> Option<(usize, usize)> {
+  if !in_range(buf, entry, 12) {
+    return None
+  }
+  let typesize = match e.ru16(buf, entry+2) {
+    3 | 8 => 2,
+    4 | 9 | 11 | 13 => 4,
+    5 | 10 | 12 => 8,
+    _ => 1,
+  };
+  let size = (e.ru32(buf, entry+4) as usize).saturating_mul(typesize);
+  let offset = if size <= 4 { entry + 8 } else { e.ru32(buf, entry+8) as usize };
+  Some((offset, size))
+}
+
+// The values of an entry of shorts or longs, empty if its data isn't in the file
+fn entry_values(buf: &[u8], e: Endian, entry: usize) -> Vec<usize> {
+  let (offset, size) = match entry_data(buf, e, entry) {
+    Some(data) if in_range(buf, data.0, data.1) => data,
+    _ => return Vec::new(),
+  };
+  match e.ru16(buf, entry+2) {
+    3 | 8 => (0..size/2).map(|i| e.ru16(buf, offset + i*2) as usize).collect(),
+    4 | 9 | 13 => (0..size/4).map(|i| e.ru32(buf, offset + i*4) as usize).collect(),
+    _ => Vec::new(),
+  }
+}
+
+fn check_ifds(buf: &[u8], e: Endian, first: usize, problems: &mut Vec<Problem>) {
+  let mut seen = HashSet::new();
+  let mut pending = vec![first];
+  while let Some(offset) = pending.pop() {
+    if !seen.insert(offset) {
+      problems.push(Problem::new(ProblemKind::IfdLoop, offset, "IFD is pointed to more than once".to_string()));
+      continue
+    }
+    if seen.len() > 1000 {
+      return
+    }
+    if !in_range(buf, offset, 2) || !in_range(buf, offset, 2 + e.ru16(buf, offset) as usize * 12 + 4) {
+      problems.push(Problem::new(ProblemKind::IfdOutOfRange, offset, "IFD goes past the end of the file".to_string()));
+      continue
+    }
+    let num = e.ru16(buf, offset) as usize;
+
+    let mut strips = Vec::new();
+    for i in 0..num {
+      let entry = offset + 2 + i*12;
+      let tag = e.ru16(buf, entry);
+      if let Some((doffset, size)) = entry_data(buf, e, entry) {
+        if !in_range(buf, doffset, size) {
+          problems.push(Problem::new(ProblemKind::EntryOutOfRange, entry,
+            format!("data of tag 0x{:04x} goes past the end of the file", tag)));
+          continue
+        }
+      }
+      if tag == Tag::SubIFDs as u16 || tag == Tag::ExifIFDPointer as u16 || tag == Tag::GPSInfo as u16 {
+        pending.extend(entry_values(buf, e, entry).into_iter().filter(|&ifd| ifd != 0));
+      }
+      for (offsets, counts) in [(Tag::StripOffsets, Tag::StripByteCounts), (Tag::TileOffsets, Tag::TileByteCounts)].iter() {
+        if tag == *offsets as u16 {
+          let counts = (0..num).map(|j| offset + 2 + j*12).find(|&other| e.ru16(buf, other) == *counts as u16);
+          strips.push((entry, entry_values(buf, e, entry), counts.map(|counts| entry_values(buf, e, counts))));
+        }
+      }
+    }
+    for (entry, offsets, counts) in strips {
+      check_strips(buf, entry, &offsets, counts.as_deref(), problems);
+    }
+
+    let next = e.ru32(buf, offset + 2 + num*12) as usize;
+    if next != 0 {
+      pending.push(next);
+    }
+  }
+}
+
+fn check_strips(buf: &[u8], entry: usize, offsets: &[usize], counts: Option<&[usize]>, problems: &mut Vec<Problem>) {
+  let counts = match counts {
+    Some(counts) if counts.len() == offsets.len() => counts,
+    _ => {
+      problems.push(Problem::new(ProblemKind::StripCountMismatch, entry,
+        format!("{} strip or tile offsets without as many byte counts", offsets.len())));
+      return
+    },
+  };
+  let mut strips: Vec<(usize, usize)> = offsets.iter().cloned().zip(counts.iter().cloned()).collect();
+  for &(offset, size) in &strips {
+    if !in_range(buf, offset, size) {
+      problems.push(Problem::new(ProblemKind::StripOutOfRange, offset,
+        format!("strip or tile of {} bytes goes past the end of the file", size)));
+    }
+  }
+  strips.sort_unstable();
+  for pair in strips.windows(2) {
+    if pair[0].0.saturating_add(pair[0].1) > pair[1].0 && pair[0].1 > 0 && pair[1].1 > 0 {
+      problems.push(Problem::new(ProblemKind::StripOverlap, pair[1].0,
+        format!("strip or tile overlaps the one at {}", pair[0].0)));
+    }
+  }
+}
+
+// Boxes that are made of other boxes
+const CONTAINERS: [&[u8;4];5] = [b"moov", b"trak", b"mdia", b"minf", b"stbl"];
+
+fn check_boxes(buf: &[u8], start: usize, end: usize, depth: u32, problems: &mut Vec<Problem>) {
+  let mut pos = start;
+  while pos < end {
+    if pos + 8 > end {
+      problems.push(Problem::new(ProblemKind::BoxOutOfRange, pos, "box header goes past the end of its parent".to_string()));
+      return
+    }
+    let (size, header) = match BEu32(buf, pos) {
+      0 => (end - pos, 8),
+      1 if pos + 16 <= end => ((u64::from(BEu32(buf, pos+8)) << 32 | u64::from(BEu32(buf, pos+12))) as usize, 16),
+      1 => (0, 16),
+      size => (size as usize, 8),
+    };
+    let typ = &buf[pos+4..pos+8];
+    if size < header || pos.saturating_add(size) > end {
+      problems.push(Problem::new(ProblemKind::BoxOutOfRange, pos,
+        format!("box \"{}\" of {} bytes doesn't fit in its parent", String::from_utf8_lossy(typ), size)));
+      return
+    }
+    if depth < 16 && CONTAINERS.iter().any(|c| &c[..] == typ) {
+      check_boxes(buf, pos + header, pos + size, depth + 1, problems);
+    }
+    pos += size;
+  }
+}
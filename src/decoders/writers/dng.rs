@@ -0,0 +1,568 @@
+//! Writing of raw images as DNG files, so anything rawloader can decode can be converted into
+//! the format most raw processors read.
+//!
+//! # Example
+//! ```rust,no_run
+//! let image = rawloader::decode_file("path/to/your/file.NEF").unwrap();
+//! let mut out = std::fs::File::create("file.dng").unwrap();
+//! rawloader::dng::write(&image, &mut out).unwrap();
+//! ```
+//!
+//! The file has the image data, compressed as lossless JPEG or for floating point data with
+//! Deflate unless `WriteOptions` asks otherwise, together with what's needed to process it: the
+//! CFA, the black and white levels, the white balance, the color matrices, the crops, the
+//! masked areas, the orientation and the EXIF data, all of it as in the original file when
+//! the decoder kept it in `Exif::raw_exif`. The DNG opcodes, the noise profile and the
+//! semantic masks of images that were DNGs to begin with are left out.
+//!
+//! `rawloader::convert_to_dng()` also carries over the makernote and the embedded previews.
+
+use std::io::Write;
+use rayon::prelude::*;
+
+use crate::decoders::*;
+use crate::decoders::basics::LITTLE_ENDIAN;
+use crate::decoders::exif::{Exif, blob_entry};
+use crate::decoders::thumbnail::{self, Thumbnail, ThumbnailData};
+use crate::decoders::tiff::{Tag, TiffIFD};
+use crate::decoders::writers::ifd::*;
+use crate::decoders::writers::ljpeg;
+
+// The size of the tiles of compressed images, the same Adobe's converter uses
+const TILE_SIZE: usize = 256;
+
+// Linear sRGB D65 from XYZ, the color matrix of RGB images that don't have one
+const XYZ_TO_RGB: [[f32;3];4] = [
+  [ 3.240479, -1.53715,  -0.498535 ],
+  [-0.969256,  1.875992,  0.041556 ],
+  [ 0.055648, -0.204043,  1.057311 ],
+  [ 0.0,       0.0,       0.0      ],
+];
+
+/// Write an image as a DNG file. Images with a CFA are written as such, RGB and monochrome
+/// images as linear raw data. Floating point data is written as floating point, like the
+/// HDR merges of raw processors, with the same black and white levels.
+///
+/// ```
+/// use rawloader::{RawImage, RawImageData, CFA};
+/// let mut image = RawImage::from_data("Make", "Model", 4, 2, 1, RawImageData::Integer(vec![1, 2, 3, 4, 5, 6, 7, 8]));
+/// image.cfa = CFA::new("RGGB");
+/// image.whitelevels = [4095; 4];
+/// let mut dng = Vec::new();
+/// rawloader::dng::write(&image, &mut dng).unwrap();
+///
+/// let decoded = rawloader::decode_slice(&dng).unwrap();
+/// assert_eq!((decoded.width, decoded.height, decoded.whitelevels[0]), (4, 2, 4095));
+/// assert_eq!(decoded.cfa.to_string(), "RGGB");
+/// assert!(matches!(decoded.data, RawImageData::Integer(ref data) if data[..4] == [1, 2, 3, 4]));
+/// ```
+pub fn write(image: &RawImage, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+  write_with_options(image, &WriteOptions::default(), out)
+}
+
+/// How the image data is stored in a DNG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+  /// plain 16 bit values, for tools that can't read anything else
+  Uncompressed,
+  /// lossless JPEG in 256x256 tiles like cameras and Adobe's converter write, usually around
+  /// half the size of the uncompressed data. Floating point data, which lossless JPEG can't
+  /// store, is compressed with `Deflate` instead.
+  #[default]
+  LosslessJpeg,
+  /// Deflate in 256x256 tiles with the floating point predictor, only for floating point data
+  /// as DNG doesn't allow it for integers
+  Deflate,
+}
+
+/// Options for writing DNGs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteOptions {
+  /// how the image data is stored, lossless JPEG by default
+  pub compression: Compression,
+  /// write floating point data as 16 bit half floats, half the size of 32 bit ones but with
+  /// only 11 bits of precision. Integer data isn't affected.
+  pub half_float: bool,
+}
+
+/// Same as `write()` with options for how the file is written
+///
+/// ```
+/// use rawloader::{RawImage, RawImageData, CFA};
+/// use rawloader::dng::WriteOptions;
+/// let data = vec![0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 4.0, 8.0];
+/// let mut image = RawImage::from_data("Make", "Model", 4, 2, 1, RawImageData::Float(data.clone()));
+/// image.cfa = CFA::new("RGGB");
+/// image.whitelevels = [1; 4];
+/// let mut dng = Vec::new();
+/// let options = WriteOptions { half_float: true, ..Default::default() };
+/// rawloader::dng::write_with_options(&image, &options, &mut dng).unwrap();
+///
+/// let decoded = rawloader::decode_slice(&dng).unwrap();
+/// assert!(matches!(decoded.data, RawImageData::Float(ref values) if *values == data));
+/// ```
+pub fn write_with_options(image: &RawImage, options: &WriteOptions, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+  write_converted(image, &Originals::default(), options, out)
+}
+
+// What a conversion carries over from the original file besides the image
+#[derive(Debug, Default)]
+pub(crate) struct Originals {
+  // the smallest preview and the largest JPEG one, which are often the same
+  thumbnail: Option<Thumbnail>,
+  preview: Option<Thumbnail>,
+  // the makernote, if it's little endian and where it was in the file
+  makernote: Option<(Vec<u8>, bool, u32)>,
+}
+
+impl Originals {
+  pub(crate) fn from_file(buf: &[u8]) -> Originals {
+    let thumbs = thumbnail::find(buf);
+    let preview = thumbs.iter()
+      .filter(|thumb| matches!(thumb.data, ThumbnailData::Jpeg(_)))
+      .max_by_key(|thumb| thumb.pixels()).cloned();
+    let thumbnail = thumbs.into_iter().min_by_key(|thumb| thumb.pixels());
+
+    let makernote = TiffIFD::new_file(buf).ok().and_then(|tiff| {
+      let entry = tiff.find_private_ifd(Tag::ExifIFDPointer)?.get_entry_raw(Tag::Makernote as u16)?;
+      let offset = entry.parent_offset() + entry.doffset();
+      Some((entry.get_data().to_vec(), entry.get_endian().little(), offset as u32))
+    });
+    Originals { thumbnail, preview, makernote }
+  }
+}
+
+// Writes the image with whatever was kept from the original file. Like Adobe's converter the
+// smallest preview goes in IFD0 and the raw and the largest preview in its sub IFDs.
+pub(crate) fn write_converted(image: &RawImage, originals: &Originals, options: &WriteOptions, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+  let raw = raw_ifd(image, options)?;
+  let mut ifd0 = match originals.thumbnail {
+    Some(ref thumbnail) => {
+      let mut ifd0 = preview_ifd(thumbnail);
+      let mut subs = vec![raw];
+      if let Some(ref preview) = originals.preview {
+        if preview != thumbnail {
+          subs.push(preview_ifd(preview));
+        }
+      }
+      ifd0.sub_ifds(Tag::SubIFDs, subs);
+      ifd0
+    },
+    None => raw,
+  };
+  main_tags(image, &mut ifd0);
+
+  // Adobe's layout for keeping the makernote, with its original offset so the offsets in it
+  // can still be followed
+  if let Some((ref data, little, offset)) = originals.makernote {
+    let mut private = b"Adobe\0MakN".to_vec();
+    private.extend_from_slice(&((data.len() + 6) as u32).to_be_bytes());
+    private.extend_from_slice(if little { b"II" } else { b"MM" });
+    private.extend_from_slice(&offset.to_be_bytes());
+    private.extend_from_slice(data);
+    ifd0.byte(Tag::DNGPrivateArea, &private);
+  }
+
+  let file = write_tiff(vec![ifd0]);
+  out.write_all(&file)?;
+  Ok(())
+}
+
+fn preview_ifd(preview: &Thumbnail) -> IfdWriter {
+  let mut ifd = IfdWriter::new();
+  ifd.long(Tag::NewSubFileType, &[1]);
+  ifd.long(Tag::ImageWidth, &[preview.width as u32]);
+  ifd.long(Tag::ImageLength, &[preview.height as u32]);
+  ifd.short(Tag::BitsPerSample, &[8, 8, 8]);
+  ifd.short(Tag::SamplesPerPixel, &[3]);
+  ifd.short(Tag::PlanarConfig, &[1]);
+  ifd.long(Tag::RowsPerStrip, &[preview.height as u32]);
+  match preview.data {
+    ThumbnailData::Jpeg(ref data) => {
+      ifd.short(Tag::Compression, &[7]);
+      ifd.short(Tag::PhotometricInt, &[6]);
+      ifd.strips(vec![data.clone()]);
+    },
+    ThumbnailData::Rgb(ref data) => {
+      ifd.short(Tag::Compression, &[1]);
+      ifd.short(Tag::PhotometricInt, &[2]);
+      ifd.strips(vec![data.clone()]);
+    },
+  }
+  ifd
+}
+
+// The tags about the camera and the shot that DNG wants in IFD0, wherever the raw is
+fn main_tags(image: &RawImage, ifd: &mut IfdWriter) {
+  // The descriptive tags of the original go first so the ones written here take precedence
+  let original = image.exif.raw_exif.as_ref().and_then(|exif| TiffIFD::new_file(exif).ok());
+  if let Some(ref tiff) = original {
+    for entry in tiff.get_entries() {
+      if entry.tag() != Tag::ExifIFDPointer as u16 && entry.tag() != Tag::GPSInfo as u16 {
+        let (tag, typ, count, data) = blob_entry(entry, LITTLE_ENDIAN);
+        ifd.raw(tag, typ, count as usize, data);
+      }
+    }
+    if let Some(gps) = tiff.find_gps_ifd() {
+      let mut gps_ifd = IfdWriter::new();
+      for entry in gps.get_entries() {
+        let (tag, typ, count, data) = blob_entry(entry, LITTLE_ENDIAN);
+        gps_ifd.raw(tag, typ, count as usize, data);
+      }
+      ifd.sub_ifds(Tag::GPSInfo, vec![gps_ifd]);
+    }
+  }
+
+  ifd.byte(Tag::DNGVersion, &[1, 4, 0, 0]);
+  ifd.byte(Tag::DNGBackVersion, &[1, 1, 0, 0]);
+  ifd.ascii(Tag::Make, &image.make);
+  ifd.ascii(Tag::Model, &image.model);
+  ifd.ascii(Tag::UniqueCamModel, &format!("{} {}", image.clean_make, image.clean_model));
+  ifd.ascii(Tag::Software, concat!("rawloader ", env!("CARGO_PKG_VERSION")));
+  ifd.short(Tag::Orientation, &[match image.orientation {
+    Orientation::Unknown => 1,
+    orientation => orientation.to_u16(),
+  }]);
+  if let Some(ref artist) = image.exif.artist {
+    ifd.ascii(Tag::Artist, artist);
+  }
+  if let Some(ref copyright) = image.exif.copyright {
+    ifd.ascii(Tag::Copyright, copyright);
+  }
+  if let Some(ref serial) = image.exif.serial {
+    ifd.ascii(Tag::CameraSerial, serial);
+  }
+
+  let colors = if image.cpp == 1 && !image.is_monochrome() { image.cfa.colors().len() } else { image.cpp };
+  if colors > 1 {
+    write_color(image, colors, ifd);
+  }
+
+  let hints = &image.rendering_hints;
+  if let Some(exposure) = hints.baseline_exposure {
+    ifd.srational(Tag::BaselineExposure, &[exposure as f64]);
+  }
+  if let Some(noise) = hints.baseline_noise {
+    ifd.rational(Tag::BaselineNoise, &[noise as f64]);
+  }
+  if let Some(sharpness) = hints.baseline_sharpness {
+    ifd.rational(Tag::BaselineSharp, &[sharpness as f64]);
+  }
+  if let Some(limit) = hints.linear_response_limit {
+    ifd.rational(Tag::LinearRespLimit, &[limit as f64]);
+  }
+
+  let exif = exif_ifd(&image.exif, original.as_ref());
+  if !exif.is_empty() {
+    ifd.sub_ifds(Tag::ExifIFDPointer, vec![exif]);
+  }
+}
+
+// The IFD with the image data and how to read it
+fn raw_ifd(image: &RawImage, options: &WriteOptions) -> Result<IfdWriter, RawLoaderError> {
+  if image.cpp != 1 && image.cpp != 3 {
+    return Err(RawLoaderError::Unimplemented(format!("writing images with {} components per pixel as DNG", image.cpp)))
+  }
+  if image.width == 0 || image.height == 0 || image.data_len() != image.width * image.height * image.cpp {
+    return Err(RawLoaderError::Corrupt("image has no data to write".to_string()))
+  }
+  let cfa = image.cpp == 1 && !image.is_monochrome();
+
+  let mut ifd = IfdWriter::new();
+  ifd.long(Tag::NewSubFileType, &[0]);
+  ifd.long(Tag::ImageWidth, &[image.width as u32]);
+  ifd.long(Tag::ImageLength, &[image.height as u32]);
+  ifd.short(Tag::PhotometricInt, &[if cfa { 32803 } else { 34892 }]);
+  ifd.short(Tag::SamplesPerPixel, &[image.cpp as u16]);
+  ifd.short(Tag::PlanarConfig, &[1]);
+  let (width, height, cpp) = (image.width, image.height, image.cpp);
+  match image.data {
+    RawImageData::Integer(ref data) => {
+      ifd.short(Tag::BitsPerSample, &vec![16; cpp]);
+      match options.compression {
+        Compression::Uncompressed => {
+          ifd.short(Tag::Compression, &[1]);
+          ifd.long(Tag::RowsPerStrip, &[height as u32]);
+          ifd.strips(vec![data.iter().flat_map(|v| v.to_le_bytes()).collect()]);
+        },
+        Compression::LosslessJpeg => {
+          ifd.short(Tag::Compression, &[7]);
+          ifd.long(Tag::TileWidth, &[TILE_SIZE as u32]);
+          ifd.long(Tag::TileLength, &[TILE_SIZE as u32]);
+          ifd.tiles(tiles(data, width, height, cpp, |pixels| {
+            // Single component images are coded as two components of half the width, as DNG
+            // recommends so each component of a CFA gets its own predictions
+            if cpp == 1 {
+              ljpeg::compress(&pixels, TILE_SIZE / 2, TILE_SIZE, 2)
+            } else {
+              ljpeg::compress(&pixels, TILE_SIZE, TILE_SIZE, cpp)
+            }
+          }));
+        },
+        Compression::Deflate => {
+          return Err(RawLoaderError::Unimplemented("writing integer data as DNG with Deflate".to_string()))
+        },
+      }
+    },
+    RawImageData::Float(ref data) => {
+      let bits = if options.half_float { 16 } else { 32 };
+      let to_bits = |val: f32| -> u32 { if bits == 16 { f32_to_f16(val) as u32 } else { val.to_bits() } };
+      ifd.short(Tag::BitsPerSample, &vec![bits; cpp]);
+      ifd.short(Tag::SampleFormat, &vec![3; cpp]);
+      match options.compression {
+        Compression::Uncompressed => {
+          ifd.short(Tag::Compression, &[1]);
+          ifd.long(Tag::RowsPerStrip, &[height as u32]);
+          ifd.strips(vec![data.iter().flat_map(|&v| to_bits(v).to_le_bytes()[..bits as usize / 8].to_vec()).collect()]);
+        },
+        Compression::LosslessJpeg | Compression::Deflate => {
+          ifd.short(Tag::Compression, &[8]);
+          ifd.short(Tag::Predictor, &[3]);
+          ifd.long(Tag::TileWidth, &[TILE_SIZE as u32]);
+          ifd.long(Tag::TileLength, &[TILE_SIZE as u32]);
+          ifd.tiles(tiles(data, width, height, cpp, |pixels| {
+            let bytes = bits as usize / 8;
+            let stride = TILE_SIZE * cpp;
+            let mut planes = vec![0u8; pixels.len() * bytes];
+            for (line, out) in pixels.chunks_exact(stride).zip(planes.chunks_exact_mut(stride * bytes)) {
+              // The bytes of the values in planes from the most significant one, and then the
+              // differences with the byte of the previous pixel
+              for (i, &val) in line.iter().enumerate() {
+                let val = to_bits(val);
+                for byte in 0..bytes {
+                  out[byte*stride + i] = (val >> (8 * (bytes - 1 - byte))) as u8;
+                }
+              }
+              for i in (cpp..out.len()).rev() {
+                out[i] = out[i].wrapping_sub(out[i-cpp]);
+              }
+            }
+            miniz_oxide::deflate::compress_to_vec_zlib(&planes, 6)
+          }));
+        },
+      }
+    },
+  }
+
+  if cfa {
+    let codes = image.cfa.colors().iter().map(|color| {
+      color.to_tiff().ok_or_else(|| RawLoaderError::Unimplemented(format!("writing {} CFA pixels as DNG", color.name())))
+    }).collect::<Result<Vec<u8>, RawLoaderError>>()?;
+    let (rows, cols) = (image.cfa.height, image.cfa.width);
+    let pattern: Vec<u8> = (0..rows*cols).map(|i| codes[image.cfa.color_at(i / cols, i % cols)]).collect();
+    ifd.short(Tag::CFARepeatDim, &[rows as u16, cols as u16]);
+    ifd.byte(Tag::CFAPattern, &pattern);
+    ifd.byte(Tag::CFAPlaneColor, &codes);
+    ifd.short(Tag::CFALayout, &[1]);
+  }
+  write_levels(image, cfa, &mut ifd);
+  write_crops(image, &mut ifd);
+  Ok(ifd)
+}
+
+// Compresses the image in tiles, with the ones on the right and bottom edges padded by
+// repeating the last column and row
+fn tiles<T, F>(data: &[T], width: usize, height: usize, cpp: usize, compress: F) -> Vec<Vec<u8>>
+  where T: Copy + Send + Sync, F: Fn(Vec<T>) -> Vec<u8> + Sync {
+  let rows = height.div_ceil(TILE_SIZE);
+  let cols = width.div_ceil(TILE_SIZE);
+  (0..rows*cols).into_par_iter().map(|tile| {
+    let (top, left) = ((tile / cols) * TILE_SIZE, (tile % cols) * TILE_SIZE);
+    let mut pixels = Vec::with_capacity(TILE_SIZE * TILE_SIZE * cpp);
+    for row in top..top+TILE_SIZE {
+      let row = row.min(height - 1);
+      for col in left..left+TILE_SIZE {
+        let pos = (row * width + col.min(width - 1)) * cpp;
+        pixels.extend_from_slice(&data[pos..pos+cpp]);
+      }
+    }
+    compress(pixels)
+  }).collect()
+}
+
+// The closest half float, rounding to even, with what doesn't fit going to infinity or zero
+fn f32_to_f16(val: f32) -> u16 {
+  let bits = val.to_bits();
+  let sign = ((bits >> 16) & 0x8000) as u16;
+  let exp = ((bits >> 23) & 0xff) as i32;
+  let mant = bits & 0x7fffff;
+  let round = |val: u32, shift: u32| -> u32 {
+    let (out, rem, half) = (val >> shift, val & ((1 << shift) - 1), 1 << (shift - 1));
+    if rem > half || (rem == half && out & 1 == 1) { out + 1 } else { out }
+  };
+  if exp == 0xff {
+    return sign | 0x7c00 | if mant != 0 { 0x200 } else { 0 }
+  }
+  let exp = exp - 127 + 15;
+  if exp >= 0x1f {
+    sign | 0x7c00
+  } else if exp <= 0 {
+    if exp < -10 {
+      sign
+    } else {
+      sign | round(mant | 0x800000, (14 - exp) as u32) as u16
+    }
+  } else {
+    // Rounding up can carry into the exponent, up to infinity, which is what's wanted
+    sign | round(((exp as u32) << 23) | mant, 13) as u16
+  }
+}
+
+// The levels are per color for us but per position in a repeating pattern for DNG
+fn write_levels(image: &RawImage, cfa: bool, ifd: &mut IfdWriter) {
+  if cfa {
+    let (rows, cols) = (image.cfa.height, image.cfa.width);
+    let blacks: Vec<u32> = (0..rows*cols).map(|i| image.blacklevels[image.cfa.color_at(i / cols, i % cols)] as u32).collect();
+    let white = (0..image.cfa.colors().len()).map(|c| image.whitelevels[c]).max().unwrap_or(u16::MAX);
+    ifd.short(Tag::BlackRepeatDim, &[rows as u16, cols as u16]);
+    ifd.long(Tag::BlackLevels, &blacks);
+    ifd.long(Tag::WhiteLevel, &[white as u32]);
+  } else {
+    ifd.short(Tag::BlackRepeatDim, &[1, 1]);
+    ifd.long(Tag::BlackLevels, &image.blacklevels[..image.cpp].iter().map(|&v| v as u32).collect::<Vec<u32>>());
+    ifd.long(Tag::WhiteLevel, &image.whitelevels[..image.cpp].iter().map(|&v| v as u32).collect::<Vec<u32>>());
+  }
+}
+
+// DNG has the default crop relative to the active area and the user crop in fractions of the
+// default crop
+fn write_crops(image: &RawImage, ifd: &mut IfdWriter) {
+  let areas = image.areas();
+  let active = areas.active_area;
+  ifd.long(Tag::ActiveArea, &[active.y as u32, active.x as u32,
+                              (active.y + active.height) as u32, (active.x + active.width) as u32]);
+  if image.default_crop.is_some() || image.display_crop.is_some() {
+    let crop = areas.default_crop;
+    ifd.long(Tag::DefaultCropOrig, &[(crop.x - active.x) as u32, (crop.y - active.y) as u32]);
+    ifd.long(Tag::DefaultCropSize, &[crop.width as u32, crop.height as u32]);
+    if image.display_crop.is_some() {
+      let user = areas.display_crop;
+      let (w, h) = (crop.width as f64, crop.height as f64);
+      ifd.rational(Tag::DefaultUserCrop, &[
+        (user.y - crop.y) as f64 / h, (user.x - crop.x) as f64 / w,
+        (user.y + user.height - crop.y) as f64 / h, (user.x + user.width - crop.x) as f64 / w,
+      ]);
+    }
+  }
+  if !image.blackareas.is_empty() {
+    let areas: Vec<u32> = image.blackareas.iter()
+      .flat_map(|&(top, right, bottom, left)| [top as u32, left as u32, bottom as u32, right as u32])
+      .collect();
+    ifd.long(Tag::MaskedAreas, &areas);
+  }
+}
+
+fn write_color(image: &RawImage, colors: usize, ifd: &mut IfdWriter) {
+  let rows = |m: &[[f32;3];4]| -> Vec<f64> { m.iter().take(colors).flatten().map(|&v| v as f64).collect() };
+  let matrices = &image.color_matrices;
+  match matrices.color_matrix1 {
+    Some(ref matrix) => {
+      ifd.srational(Tag::ColorMatrix1, &rows(matrix));
+      ifd.short(Tag::CalibIlluminant1, &[matrices.calibration_illuminant1.unwrap_or(21)]);
+      if let Some(ref matrix) = matrices.color_matrix2 {
+        ifd.srational(Tag::ColorMatrix2, &rows(matrix));
+        ifd.short(Tag::CalibIlluminant2, &[matrices.calibration_illuminant2.unwrap_or(17)]);
+      }
+    },
+    None => {
+      let matrix = if image.has_color_matrix() {
+        image.xyz_to_cam
+      } else if image.cpp == 3 {
+        XYZ_TO_RGB
+      } else {
+        image.cfa.ideal_xyz_to_cam()
+      };
+      ifd.srational(Tag::ColorMatrix1, &rows(&matrix));
+      ifd.short(Tag::CalibIlluminant1, &[21]);
+    },
+  }
+
+  let forward = |m: &[[f32;4];3]| -> Vec<f64> { m.iter().flat_map(|row| row[..colors].to_vec()).map(|v| v as f64).collect() };
+  if let Some(ref matrix) = matrices.forward_matrix1 {
+    ifd.srational(Tag::ForwardMatrix1, &forward(matrix));
+  }
+  if let Some(ref matrix) = matrices.forward_matrix2 {
+    ifd.srational(Tag::ForwardMatrix2, &forward(matrix));
+  }
+  let calibration = |m: &[[f32;4];4]| -> Vec<f64> { m[..colors].iter().flat_map(|row| row[..colors].to_vec()).map(|v| v as f64).collect() };
+  if let Some(ref matrix) = matrices.camera_calibration1 {
+    ifd.srational(Tag::CameraCalib1, &calibration(matrix));
+  }
+  if let Some(ref matrix) = matrices.camera_calibration2 {
+    ifd.srational(Tag::CameraCalib2, &calibration(matrix));
+  }
+  if let Some(balance) = matrices.analog_balance {
+    ifd.rational(Tag::AnalogBalance, &balance[..colors].iter().map(|&v| v as f64).collect::<Vec<f64>>());
+  }
+
+  // The neutral is the inverse of the white balance, scaled so the largest value is 1
+  let wb = &image.wb_coeffs[..colors];
+  if wb.iter().all(|v| v.is_finite() && *v > 0.0) {
+    let min = wb.iter().cloned().fold(f32::INFINITY, f32::min);
+    ifd.rational(Tag::AsShotNeutral, &wb.iter().map(|&v| (min / v) as f64).collect::<Vec<f64>>());
+  }
+}
+
+// The EXIF IFD of the original with the fields we know written over it, as they may have been
+// filled in from the makernotes or the XMP
+fn exif_ifd(exif: &Exif, original: Option<&TiffIFD>) -> IfdWriter {
+  let mut ifd = IfdWriter::new();
+  if let Some(original) = original.and_then(|tiff| tiff.find_private_ifd(Tag::ExifIFDPointer)) {
+    for entry in original.get_entries() {
+      let (tag, typ, count, data) = blob_entry(entry, LITTLE_ENDIAN);
+      ifd.raw(tag, typ, count as usize, data);
+    }
+  }
+  if let Some(time) = exif.exposure_time {
+    ifd.rational(Tag::ExposureTime, &[time as f64]);
+  }
+  if let Some(aperture) = exif.aperture {
+    ifd.rational(Tag::FNumber, &[aperture as f64]);
+  }
+  if let Some(program) = exif.exposure_program {
+    ifd.short(Tag::ExposureProgram, &[program.to_u16()]);
+  }
+  if let Some(iso) = exif.iso {
+    ifd.short(Tag::ISOSpeed, &[iso.min(u16::MAX as u32) as u16]);
+  }
+  if let Some(time) = exif.capture_time {
+    ifd.ascii(Tag::DateTimeOriginal, &format!("{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+      time.year, time.month, time.day, time.hour, time.minute, time.second));
+    if let Some(ns) = time.nanosecond {
+      let frac = format!("{:09}", ns);
+      let frac = frac.trim_end_matches('0');
+      ifd.ascii(Tag::SubSecTimeOrig, if frac.is_empty() { "0" } else { frac });
+    }
+    if let Some(offset) = time.utc_offset {
+      let sign = if offset < 0 { '-' } else { '+' };
+      ifd.ascii(Tag::OffsetTimeOrig, &format!("{}{:02}:{:02}", sign, offset.abs() / 60, offset.abs() % 60));
+    }
+  }
+  if let Some(bias) = exif.exposure_bias {
+    ifd.srational(Tag::ExposureBias, &[bias as f64]);
+  }
+  if let Some(mode) = exif.metering_mode {
+    ifd.short(Tag::MeteringMode, &[mode.to_u16()]);
+  }
+  if let Some(focal) = exif.focal_length {
+    ifd.rational(Tag::FocalLength, &[focal as f64]);
+  }
+  if let Some(ref serial) = exif.serial {
+    ifd.ascii(Tag::BodySerial, serial);
+  }
+  if let Some(ref lens) = exif.lens {
+    if let Some(ref make) = lens.make {
+      ifd.ascii(Tag::LensMake, make);
+    }
+    if let Some(ref model) = lens.model {
+      ifd.ascii(Tag::LensModel, model);
+    }
+    if let (Some(min), Some(max)) = (lens.min_focal_length, lens.max_focal_length) {
+      // Unknown apertures are written as 0 as EXIF asks
+      let wide = lens.max_aperture_at_min_focal.unwrap_or(0.0);
+      let tele = lens.max_aperture_at_max_focal.unwrap_or(0.0);
+      ifd.rational(Tag::LensInfo, &[min as f64, max as f64, wide as f64, tele as f64]);
+    }
+  }
+  ifd
+}
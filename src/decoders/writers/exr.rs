@@ -0,0 +1,65 @@
+// OpenEXR output of linear floating point data through the exr crate, for VFX and HDR tools
+
+use std::io::{self, Seek, Write};
+use ::exr::prelude::{AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, SmallVec, WritableImage};
+
+use crate::decoders::*;
+use crate::decoders::develop;
+use crate::decoders::image::orient_data;
+
+pub(crate) fn rgb<W: Write + Seek>(image: &RawImage, out: &mut W) -> Result<(), RawLoaderError> {
+  let (area, data) = develop::rgb_linear(image)?;
+  let orientation = match image.orientation {
+    Orientation::Unknown => Orientation::Normal,
+    orientation => orientation,
+  };
+  let (width, height) = if orientation.to_flips().0 { (area.height, area.width) } else { (area.width, area.height) };
+  let data = orient_data(&data, orientation, area.width, area.height, width, 3)?;
+  write(image, width, height, &["R", "G", "B"], &data, out)
+}
+
+pub(crate) fn raw<W: Write + Seek>(image: &RawImage, out: &mut W) -> Result<(), RawLoaderError> {
+  let names: &[&str] = match image.cpp {
+    1 => &["Y"],
+    3 => &["R", "G", "B"],
+    cpp => return Err(RawLoaderError::Unimplemented(format!("EXR output of an image with {} components per pixel", cpp))),
+  };
+  let area = image.areas().active_area;
+  if area.is_empty() || image.data_len() != image.width * image.height * image.cpp {
+    return Err(RawLoaderError::Corrupt("image has no data to convert".to_string()))
+  }
+  let mut data = Vec::with_capacity(area.width * area.height * image.cpp);
+  for row in area.y..area.y+area.height {
+    let start = (row * image.width + area.x) * image.cpp;
+    data.extend((start..start + area.width * image.cpp).map(|idx| image.data.get_f32(idx)));
+  }
+  write(image, area.width, area.height, names, &data, out)
+}
+
+// Writes interleaved data as a channel for each name, with the metadata EXR has room for
+fn write<W: Write + Seek>(image: &RawImage, width: usize, height: usize, names: &[&str], data: &[f32], out: &mut W) -> Result<(), RawLoaderError> {
+  let channels: SmallVec<[AnyChannel<FlatSamples>; 4]> = names.iter().enumerate().map(|(c, name)| {
+    let samples = data.iter().skip(c).step_by(names.len()).copied().collect();
+    AnyChannel::new(*name, FlatSamples::F32(samples))
+  }).collect();
+
+  let mut attributes = LayerAttributes::default();
+  attributes.software_name = Some(concat!("rawloader ", env!("CARGO_PKG_VERSION")).into());
+  attributes.comments = Some(format!("{} {}", image.clean_make, image.clean_model).as_str().into());
+  let exif = &image.exif;
+  attributes.exposure = exif.exposure_time;
+  attributes.aperture = exif.aperture;
+  attributes.iso_speed = exif.iso.map(|iso| iso as f32);
+  if let Some(time) = exif.capture_time {
+    attributes.capture_date = Some(format!("{:04}:{:02}:{:02} {:02}:{:02}:{:02}",
+      time.year, time.month, time.day, time.hour, time.minute, time.second).as_str().into());
+    // EXR has the offset to UTC in seconds and with the opposite sign
+    attributes.utc_offset = time.utc_offset.map(|offset| -(offset as f32) * 60.0);
+  }
+
+  let layer = Layer::new((width, height), attributes, Encoding::FAST_LOSSLESS, AnyChannels::sort(channels));
+  Image::from_layer(layer).write().to_buffered(out).map_err(|err| match err {
+    ::exr::error::Error::Io(err) => RawLoaderError::Io(err),
+    err => RawLoaderError::Io(io::Error::new(io::ErrorKind::Other, err.to_string())),
+  })
+}
@@ -0,0 +1,194 @@
+// FITS output of the sensor data for astrophotography tools, with the keywords they read for
+// the exposure, the sensor temperature, the time and the Bayer pattern
+
+use std::io::Write;
+
+use crate::decoders::*;
+use crate::decoders::exif::Timestamp;
+
+const BLOCK: usize = 2880;
+
+// The undemosaiced data inside crops as a single image, or the planes of RGB images
+pub(crate) fn cfa(image: &RawImage, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+  let area = area(image)?;
+  let planes: Vec<Vec<f32>> = (0..image.cpp).map(|c| {
+    plane(image, area.width, area.height, |row, col| ((area.y + row) * image.width + area.x + col) * image.cpp + c)
+  }).collect();
+  let names = if image.cpp == 3 { vec!['R', 'G', 'B'] } else { Vec::new() };
+
+  let mut header = header(image, area.width, area.height, &names);
+  let cfa = image.cfa_for_crop(area.x, area.y);
+  if image.cpp == 1 && cfa.width == 2 && cfa.height == 2 && !cfa.is_monochrome() {
+    header.push(card("BAYERPAT", &string(&cfa.to_string()), "Bayer pattern from the top left"));
+    header.push(card("XBAYROFF", "0", "X offset of the Bayer pattern"));
+    header.push(card("YBAYROFF", "0", "Y offset of the Bayer pattern"));
+  }
+  write(image, header, &planes, out)
+}
+
+// Each position of a 2x2 CFA as its own plane of half the size
+pub(crate) fn channels(image: &RawImage, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+  if image.cpp != 1 {
+    return cfa(image, out)
+  }
+  let area = area(image)?;
+  let cfa = image.cfa_for_crop(area.x, area.y);
+  if cfa.width != 2 || cfa.height != 2 || cfa.is_monochrome() {
+    return Err(RawLoaderError::Unimplemented(format!("splitting a {}x{} CFA into channels", cfa.width, cfa.height)))
+  }
+  let (width, height) = (area.width / 2, area.height / 2);
+  let mut planes = Vec::new();
+  let mut names = Vec::new();
+  for (r, c) in [(0, 0), (0, 1), (1, 0), (1, 1)] {
+    planes.push(plane(image, width, height, |row, col| (area.y + row*2 + r) * image.width + area.x + col*2 + c));
+    names.push(cfa.cfa_color_at(r, c).map_or('?', |color| color.to_char()));
+  }
+  let header = header(image, width, height, &names);
+  write(image, header, &planes, out)
+}
+
+fn area(image: &RawImage) -> Result<Rect, RawLoaderError> {
+  let area = image.areas().active_area;
+  if area.is_empty() || image.data_len() != image.width * image.height * image.cpp {
+    return Err(RawLoaderError::Corrupt("image has no data to convert".to_string()))
+  }
+  Ok(area)
+}
+
+// The values at the index of each row and column of a plane
+fn plane<F: Fn(usize, usize) -> usize>(image: &RawImage, width: usize, height: usize, index: F) -> Vec<f32> {
+  let mut out = Vec::with_capacity(width * height);
+  for row in 0..height {
+    for col in 0..width {
+      out.push(image.data.get_f32(index(row, col)));
+    }
+  }
+  out
+}
+
+// A header card, the value right aligned as FITS wants for everything but strings
+fn card(key: &str, value: &str, comment: &str) -> String {
+  let card = if value.starts_with('\'') {
+    format!("{:<8}= {:<20} / {}", key, value, comment)
+  } else {
+    format!("{:<8}= {:>20} / {}", key, value, comment)
+  };
+  format!("{:<80.80}", card)
+}
+
+fn string(val: &str) -> String {
+  let val: String = val.chars().filter(|c| c.is_ascii() && !c.is_ascii_control()).collect();
+  format!("'{:<8}'", val.replace('\'', "''"))
+}
+
+fn real(val: f32) -> String {
+  let val = format!("{}", val);
+  if val.contains('.') { val } else { val + ".0" }
+}
+
+fn date(time: &Timestamp) -> String {
+  string(&format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}", time.year, time.month, time.day, time.hour, time.minute, time.second))
+}
+
+// The same time in UTC, moving through the days of the civil calendar
+fn to_utc(time: &Timestamp, offset: i16) -> Timestamp {
+  let (y, m) = if time.month <= 2 { (time.year as i64 - 1, time.month as i64 + 9) } else { (time.year as i64, time.month as i64 - 3) };
+  let era = y.div_euclid(400);
+  let yoe = y - era * 400;
+  let doe = yoe * 365 + yoe / 4 - yoe / 100 + (153 * m + 2) / 5 + time.day as i64 - 1;
+  let days = era * 146097 + doe;
+  let minutes = days * 1440 + time.hour as i64 * 60 + time.minute as i64 - offset as i64;
+
+  let (days, minutes) = (minutes.div_euclid(1440), minutes.rem_euclid(1440));
+  let era = days.div_euclid(146097);
+  let doe = days - era * 146097;
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = doy - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+  Timestamp {
+    year: year as u16,
+    month: month as u8,
+    day: day as u8,
+    hour: (minutes / 60) as u8,
+    minute: (minutes % 60) as u8,
+    utc_offset: Some(0),
+    ..*time
+  }
+}
+
+// The cards up to the data description, followed by the ones about the shot
+fn header(image: &RawImage, width: usize, height: usize, names: &[char]) -> Vec<String> {
+  let float = matches!(image.data, RawImageData::Float(_));
+  let mut cards = vec![
+    card("SIMPLE", "T", "conforms to the FITS standard"),
+    card("BITPIX", if float { "-32" } else { "16" }, if float { "32 bit floats" } else { "16 bit integers" }),
+    card("NAXIS", if names.is_empty() { "2" } else { "3" }, "number of axes"),
+    card("NAXIS1", &width.to_string(), "width"),
+    card("NAXIS2", &height.to_string(), "height"),
+  ];
+  if !names.is_empty() {
+    cards.push(card("NAXIS3", &names.len().to_string(), "number of channels"));
+  }
+  if !float {
+    cards.push(card("BZERO", "32768", "offset for unsigned 16 bit values"));
+    cards.push(card("BSCALE", "1", "no scaling of the values"));
+  }
+  for (i, name) in names.iter().enumerate() {
+    cards.push(card(&format!("CHANNEL{}", i + 1), &string(&name.to_string()), &format!("color of plane {}", i + 1)));
+  }
+  cards.push(card("ROWORDER", &string("TOP-DOWN"), "the first row is the top of the image"));
+  cards.push(card("INSTRUME", &string(&format!("{} {}", image.clean_make, image.clean_model)), "camera"));
+  cards.push(card("SWCREATE", &string(concat!("rawloader ", env!("CARGO_PKG_VERSION"))), "software that wrote the file"));
+  let colors = if image.cpp == 1 { image.cfa.colors().len().clamp(1, 4) } else { image.cpp.min(4) };
+  let white = image.whitelevels[..colors].iter().max().cloned().unwrap_or(0);
+  let black = image.blacklevels[..colors].iter().min().cloned().unwrap_or(0);
+  cards.push(card("DATAMAX", &white.to_string(), "white level"));
+  cards.push(card("BLKLEVEL", &black.to_string(), "black level"));
+
+  let exif = &image.exif;
+  if let Some(time) = exif.exposure_time.filter(|v| v.is_finite()) {
+    cards.push(card("EXPTIME", &real(time), "exposure time in seconds"));
+    cards.push(card("EXPOSURE", &real(time), "exposure time in seconds"));
+  }
+  if let Some(iso) = exif.iso {
+    cards.push(card("ISOSPEED", &iso.to_string(), "ISO speed"));
+  }
+  if let Some(temp) = exif.temperature.filter(|v| v.is_finite()) {
+    cards.push(card("CCD-TEMP", &real(temp), "sensor temperature in degrees C"));
+  }
+  if let Some(focal) = exif.focal_length.filter(|v| v.is_finite()) {
+    cards.push(card("FOCALLEN", &real(focal), "focal length in mm"));
+  }
+  if let Some(aperture) = exif.aperture.filter(|v| v.is_finite()) {
+    cards.push(card("FNUMBER", &real(aperture), "aperture as an f-number"));
+  }
+  if let Some(ref time) = exif.capture_time {
+    cards.push(card("DATE-LOC", &date(time), "local time of the start of the exposure"));
+    if let Some(offset) = time.utc_offset {
+      cards.push(card("DATE-OBS", &date(&to_utc(time, offset)), "UTC time of the start of the exposure"));
+    }
+  }
+  cards.push(format!("{:<80}", "END"));
+  cards
+}
+
+fn write(image: &RawImage, header: Vec<String>, planes: &[Vec<f32>], out: &mut dyn Write) -> Result<(), RawLoaderError> {
+  let float = matches!(image.data, RawImageData::Float(_));
+  let mut buf: Vec<u8> = header.concat().into_bytes();
+  buf.resize(buf.len().div_ceil(BLOCK) * BLOCK, b' ');
+  for plane in planes {
+    for &val in plane {
+      if float {
+        buf.extend_from_slice(&val.to_be_bytes());
+      } else {
+        buf.extend_from_slice(&((val as i32 - 32768) as i16).to_be_bytes());
+      }
+    }
+  }
+  buf.resize(buf.len().div_ceil(BLOCK) * BLOCK, 0);
+  out.write_all(&buf)?;
+  Ok(())
+}
@@ -0,0 +1,173 @@
+// Building of TIFF structures for the writers, always little endian
+
+use crate::decoders::basics::*;
+use crate::decoders::tiff::Tag;
+
+// An IFD entry as its tag, type, count and data already in the output byte order
+pub(crate) type BlobEntry = (u16, u16, u32, Vec<u8>);
+
+pub(crate) fn ifd_size(entries: &[BlobEntry]) -> usize {
+  let data: usize = entries.iter().map(|entry| entry.3.len()).filter(|&len| len > 4).map(|len| len + len % 2).sum();
+  2 + entries.len() * 12 + 4 + data
+}
+
+// Write an IFD at the end of out followed by the values that don't fit in the entries
+pub(crate) fn write_ifd(out: &mut Vec<u8>, entries: &[BlobEntry], next: u32, e: Endian) {
+  let mut data_offset = out.len() + 2 + entries.len() * 12 + 4;
+  out.extend_from_slice(&e.wu16(entries.len() as u16));
+  for (tag, typ, count, data) in entries {
+    out.extend_from_slice(&e.wu16(*tag));
+    out.extend_from_slice(&e.wu16(*typ));
+    out.extend_from_slice(&e.wu32(*count));
+    if data.len() <= 4 {
+      out.extend_from_slice(data);
+      out.resize(out.len() + 4 - data.len(), 0);
+    } else {
+      out.extend_from_slice(&e.wu32(data_offset as u32));
+      data_offset += data.len() + data.len() % 2;
+    }
+  }
+  out.extend_from_slice(&e.wu32(next));
+  for (_, _, _, data) in entries {
+    if data.len() > 4 {
+      out.extend_from_slice(data);
+      if data.len() % 2 == 1 {
+        out.push(0);
+      }
+    }
+  }
+}
+
+// Closest fraction to a value, with exact ones for integers and for the 1/x of shutter times
+fn fraction(val: f64, max: f64) -> (f64, f64) {
+  if !val.is_finite() {
+    return (0.0, 1.0)
+  }
+  if val.fract() == 0.0 {
+    return (val.clamp(-max, max), 1.0)
+  }
+  let inverse = 1.0 / val.abs();
+  if val.abs() < 1.0 && (inverse - inverse.round()).abs() < 1e-4 * inverse {
+    return (val.signum(), inverse.round())
+  }
+  let den = (max / val.abs().max(1.0)).min(1_000_000.0).floor();
+  ((val * den).round(), den)
+}
+
+// An IFD being put together, with the IFDs it points to and the image data it stores
+#[derive(Debug, Default)]
+pub(crate) struct IfdWriter {
+  entries: Vec<BlobEntry>,
+  // IFDs pointed to by the entry of each tag, like SubIFDs or ExifIFDPointer
+  subs: Vec<(u16, Vec<IfdWriter>)>,
+  // the chunks of image data with the tags of their offsets and byte counts
+  chunks: Option<(Tag, Tag, Vec<Vec<u8>>)>,
+}
+
+impl IfdWriter {
+  pub(crate) fn new() -> IfdWriter {
+    IfdWriter::default()
+  }
+
+  pub(crate) fn is_empty(&self) -> bool {
+    self.entries.is_empty() && self.subs.is_empty() && self.chunks.is_none()
+  }
+
+  fn entry(&mut self, tag: u16, typ: u16, count: usize, data: Vec<u8>) {
+    self.entries.retain(|entry| entry.0 != tag);
+    self.entries.push((tag, typ, count as u32, data));
+  }
+
+  // An entry copied as is, for carrying over the ones of other files
+  pub(crate) fn raw(&mut self, tag: u16, typ: u16, count: usize, data: Vec<u8>) {
+    self.entry(tag, typ, count, data);
+  }
+
+  pub(crate) fn byte(&mut self, tag: Tag, vals: &[u8]) {
+    self.entry(tag as u16, 1, vals.len(), vals.to_vec());
+  }
+
+  pub(crate) fn ascii(&mut self, tag: Tag, val: &str) {
+    let mut data = val.as_bytes().to_vec();
+    data.push(0);
+    self.entry(tag as u16, 2, data.len(), data);
+  }
+
+  pub(crate) fn short(&mut self, tag: Tag, vals: &[u16]) {
+    self.entry(tag as u16, 3, vals.len(), vals.iter().flat_map(|v| v.to_le_bytes()).collect());
+  }
+
+  pub(crate) fn long(&mut self, tag: Tag, vals: &[u32]) {
+    self.entry(tag as u16, 4, vals.len(), vals.iter().flat_map(|v| v.to_le_bytes()).collect());
+  }
+
+  pub(crate) fn rational(&mut self, tag: Tag, vals: &[f64]) {
+    let data = vals.iter().flat_map(|&v| {
+      let (num, den) = fraction(v.max(0.0), u32::MAX as f64);
+      [(num as u32).to_le_bytes(), (den as u32).to_le_bytes()].concat()
+    }).collect();
+    self.entry(tag as u16, 5, vals.len(), data);
+  }
+
+  pub(crate) fn srational(&mut self, tag: Tag, vals: &[f64]) {
+    let data = vals.iter().flat_map(|&v| {
+      let (num, den) = fraction(v, i32::MAX as f64);
+      [(num as i32).to_le_bytes(), (den as i32).to_le_bytes()].concat()
+    }).collect();
+    self.entry(tag as u16, 10, vals.len(), data);
+  }
+
+  // The IFDs the entry of a tag points to, like the ones of SubIFDs or ExifIFDPointer
+  pub(crate) fn sub_ifds(&mut self, tag: Tag, ifds: Vec<IfdWriter>) {
+    self.subs.retain(|sub| sub.0 != tag as u16);
+    self.subs.push((tag as u16, ifds));
+  }
+
+  // The image data as strips, with the tags for them set when the IFD is written
+  pub(crate) fn strips(&mut self, strips: Vec<Vec<u8>>) {
+    self.chunks = Some((Tag::StripOffsets, Tag::StripByteCounts, strips));
+  }
+
+  // The image data as tiles, left to right and top to bottom
+  pub(crate) fn tiles(&mut self, tiles: Vec<Vec<u8>>) {
+    self.chunks = Some((Tag::TileOffsets, Tag::TileByteCounts, tiles));
+  }
+
+  // Writes the image data, then the IFDs this one points to and finally itself, returning
+  // where it is so whatever points to it can be written afterwards
+  fn write(mut self, out: &mut Vec<u8>, next: u32) -> u32 {
+    if let Some((offsets_tag, counts_tag, chunks)) = self.chunks.take() {
+      let mut offsets = Vec::with_capacity(chunks.len());
+      for chunk in &chunks {
+        offsets.push(out.len() as u32);
+        out.extend_from_slice(chunk);
+        if out.len() % 2 == 1 {
+          out.push(0);
+        }
+      }
+      self.long(offsets_tag, &offsets);
+      self.long(counts_tag, &chunks.iter().map(|c| c.len() as u32).collect::<Vec<u32>>());
+    }
+    for (tag, ifds) in std::mem::take(&mut self.subs) {
+      let offsets: Vec<u32> = ifds.into_iter().map(|ifd| ifd.write(out, 0)).collect();
+      self.entry(tag, 4, offsets.len(), offsets.iter().flat_map(|v| v.to_le_bytes()).collect());
+    }
+    self.entries.sort_by_key(|entry| entry.0);
+    let offset = out.len() as u32;
+    write_ifd(out, &self.entries, next, LITTLE_ENDIAN);
+    offset
+  }
+}
+
+// A whole TIFF file made of a chain of IFDs
+pub(crate) fn write_tiff(chain: Vec<IfdWriter>) -> Vec<u8> {
+  let mut out = b"II".to_vec();
+  out.extend_from_slice(&LITTLE_ENDIAN.wu16(42));
+  out.extend_from_slice(&LITTLE_ENDIAN.wu32(0));
+  let mut next = 0;
+  for ifd in chain.into_iter().rev() {
+    next = ifd.write(&mut out, next);
+  }
+  out[4..8].copy_from_slice(&LITTLE_ENDIAN.wu32(next));
+  out
+}
@@ -0,0 +1,248 @@
+// Encoder for lossless JPEG (LJ92) as DNG uses it, 16 bit precision with predictor 1 and a
+// Huffman table made for each stream, the counterpart of the decoder in ljpeg
+
+// Compresses width x height pixels of interleaved components into a whole stream, SOI to EOI
+pub(crate) fn compress(data: &[u16], width: usize, height: usize, components: usize) -> Vec<u8> {
+  // The differences with the pixel to the left, or the one above at the start of the lines
+  let stride = width * components;
+  let mut diffs = Vec::with_capacity(stride * height);
+  for row in 0..height {
+    for pos in row*stride..(row+1)*stride {
+      let pred = if pos >= row*stride + components {
+        data[pos - components]
+      } else if row > 0 {
+        data[pos - stride]
+      } else {
+        1 << 15
+      };
+      diffs.push(data[pos].wrapping_sub(pred) as i16);
+    }
+  }
+
+  let mut freqs = [0usize; 17];
+  for &diff in &diffs {
+    freqs[category(diff)] += 1;
+  }
+  let (bits, vals) = huffman_table(&freqs);
+  let codes = huffman_codes(&bits, &vals);
+
+  let mut out = vec![0xff, 0xd8];
+  let mut sof = vec![16, (height >> 8) as u8, height as u8, (width >> 8) as u8, width as u8, components as u8];
+  for c in 0..components {
+    sof.extend_from_slice(&[c as u8 + 1, 0x11, 0]);
+  }
+  segment(&mut out, 0xc3, &sof);
+  let mut dht = vec![0];
+  dht.extend_from_slice(&bits);
+  dht.extend_from_slice(&vals);
+  segment(&mut out, 0xc4, &dht);
+  let mut sos = vec![components as u8];
+  for c in 0..components {
+    sos.extend_from_slice(&[c as u8 + 1, 0]);
+  }
+  sos.extend_from_slice(&[1, 0, 0]);
+  segment(&mut out, 0xda, &sos);
+
+  let mut writer = BitWriter { out, acc: 0, nbits: 0 };
+  for &diff in &diffs {
+    let cat = category(diff);
+    let (code, len) = codes[cat];
+    writer.put(code, len);
+    // 16 bit differences can only be -32768 and have no extra bits
+    if cat > 0 && cat < 16 {
+      let extra = if diff < 0 { diff as i32 - 1 } else { diff as i32 };
+      writer.put(extra as u32 & ((1 << cat) - 1), cat as u32);
+    }
+  }
+  let mut out = writer.finish();
+  out.extend_from_slice(&[0xff, 0xd9]);
+  out
+}
+
+// The number of bits of a difference, the symbol it's coded with
+fn category(diff: i16) -> usize {
+  16 - diff.unsigned_abs().leading_zeros() as usize
+}
+
+fn segment(out: &mut Vec<u8>, marker: u8, data: &[u8]) {
+  out.extend_from_slice(&[0xff, marker]);
+  out.extend_from_slice(&((data.len() + 2) as u16).to_be_bytes());
+  out.extend_from_slice(data);
+}
+
+// The code lengths of an optimal table limited to 16 bits, as the BITS and HUFFVAL lists of a
+// DHT segment, following Annex K.2 of the JPEG standard
+fn huffman_table(freqs: &[usize; 17]) -> ([u8; 16], Vec<u8>) {
+  // An extra symbol that's the least frequent so it gets the code that's all ones, which
+  // isn't allowed, and is dropped at the end
+  let mut freq = freqs.to_vec();
+  freq.push(1);
+  let n = freq.len();
+  let mut sizes = vec![0usize; n];
+  let mut others: Vec<Option<usize>> = vec![None; n];
+
+  loop {
+    // The two least frequent symbols, the later one on ties
+    let least = |skip: Option<usize>| -> Option<usize> {
+      (0..n).filter(|&i| freq[i] > 0 && Some(i) != skip).fold(None, |best, i| match best {
+        Some(b) if freq[b] < freq[i] => Some(b),
+        _ => Some(i),
+      })
+    };
+    let v1 = match least(None) { Some(v) => v, None => break };
+    let v2 = match least(Some(v1)) { Some(v) => v, None => break };
+
+    freq[v1] += freq[v2];
+    freq[v2] = 0;
+    let mut v = v1;
+    sizes[v] += 1;
+    while let Some(next) = others[v] {
+      v = next;
+      sizes[v] += 1;
+    }
+    others[v] = Some(v2);
+    let mut v = v2;
+    sizes[v] += 1;
+    while let Some(next) = others[v] {
+      v = next;
+      sizes[v] += 1;
+    }
+  }
+
+  // Move the codes longer than 16 bits up the tree
+  let mut counts = [0usize; 33];
+  for &size in &sizes {
+    if size > 0 {
+      counts[size] += 1;
+    }
+  }
+  for i in (17..33).rev() {
+    while counts[i] > 0 {
+      let mut j = i - 2;
+      while counts[j] == 0 {
+        j -= 1;
+      }
+      counts[i] -= 2;
+      counts[i-1] += 1;
+      counts[j+1] += 2;
+      counts[j] -= 1;
+    }
+  }
+  let mut longest = 16;
+  while counts[longest] == 0 {
+    longest -= 1;
+  }
+  counts[longest] -= 1;
+
+  let mut bits = [0u8; 16];
+  for i in 0..16 {
+    bits[i] = counts[i+1] as u8;
+  }
+  // The symbols by the length they had before the limiting, which keeps the order right
+  let mut vals: Vec<usize> = (0..17).filter(|&i| sizes[i] > 0).collect();
+  vals.sort_by_key(|&i| sizes[i]);
+  (bits, vals.into_iter().map(|v| v as u8).collect())
+}
+
+// The code and its length for each symbol from BITS and HUFFVAL
+fn huffman_codes(bits: &[u8; 16], vals: &[u8]) -> [(u32, u32); 17] {
+  let mut codes = [(0, 0); 17];
+  let mut code = 0;
+  let mut pos = 0;
+  for len in 1..=16 {
+    for _ in 0..bits[len-1] {
+      codes[vals[pos] as usize] = (code, len as u32);
+      code += 1;
+      pos += 1;
+    }
+    code <<= 1;
+  }
+  codes
+}
+
+// Bits packed MSB first with a zero byte stuffed after every 0xff
+struct BitWriter {
+  out: Vec<u8>,
+  acc: u64,
+  nbits: u32,
+}
+
+impl BitWriter {
+  fn put(&mut self, bits: u32, len: u32) {
+    self.acc = (self.acc << len) | bits as u64;
+    self.nbits += len;
+    while self.nbits >= 8 {
+      self.nbits -= 8;
+      let byte = (self.acc >> self.nbits) as u8;
+      self.out.push(byte);
+      if byte == 0xff {
+        self.out.push(0);
+      }
+    }
+    self.acc &= (1 << self.nbits) - 1;
+  }
+
+  // Pads the last byte with ones
+  fn finish(mut self) -> Vec<u8> {
+    if self.nbits > 0 {
+      let pad = 8 - self.nbits;
+      self.put((1 << pad) - 1, pad);
+    }
+    self.out
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::decoders::ljpeg::LjpegDecompressor;
+
+  fn roundtrip(data: &[u16], width: usize, height: usize, components: usize) {
+    let stream = compress(data, width, height, components);
+    let decompressor = LjpegDecompressor::new(&stream).unwrap();
+    assert_eq!((decompressor.width(), decompressor.height()), (width*components, height));
+    assert_eq!((decompressor.components(), decompressor.precision(), decompressor.predictor()), (components, 16, 1));
+    assert_eq!(decompressor.decode_image().unwrap(), data);
+  }
+
+  // Noise over the whole 16 bit range, so every difference category gets used
+  fn noise(len: usize) -> Vec<u16> {
+    let mut state = 0x2545_f491u32;
+    (0..len).map(|_| {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      (state >> (state % 16)) as u16
+    }).collect()
+  }
+
+  #[test]
+  fn noise_roundtrips() {
+    for components in 2..=4 {
+      for (width, height) in [(1, 1), (7, 5), (64, 3)] {
+        roundtrip(&noise(width*height*components), width, height, components);
+      }
+    }
+  }
+
+  #[test]
+  fn extreme_differences_roundtrip() {
+    // 0 to 32768 and back is the -32768 difference with no extra bits, 0 to 65535 is -1
+    let data = [0, 0, 32768, 32768, 0, 0, 65535, 65535, 0, 1, 65535, 32767];
+    roundtrip(&data, 3, 2, 2);
+  }
+
+  #[test]
+  fn flat_data_roundtrips() {
+    // A single difference category, whose table still needs a code that isn't all ones
+    roundtrip(&[32768; 24], 4, 3, 2);
+    roundtrip(&[1234; 24], 4, 2, 3);
+  }
+
+  #[test]
+  fn stuffed_bytes_roundtrip() {
+    // The largest differences everywhere give runs of ones in the bits, and 0xff bytes
+    let data: Vec<u16> = (0..200).map(|i| if i % 2 == 0 { 0x7fff } else { 0xffff - (i % 7) }).collect();
+    roundtrip(&data, 25, 4, 2);
+  }
+}
@@ -0,0 +1,8 @@
+//! Writers of raw images into other file formats
+
+pub mod dng;
+pub(crate) mod ifd;
+mod ljpeg;
+pub(crate) mod tiff;
+#[cfg(feature = "exr")] pub(crate) mod exr;
+pub(crate) mod fits;
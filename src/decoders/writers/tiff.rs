@@ -0,0 +1,42 @@
+// Plain 16 bit TIFFs of the undemosaiced data or a developed image, for looking at the data in
+// any image viewer or loading it in scientific tools
+
+use std::io::Write;
+
+use crate::decoders::*;
+use crate::decoders::develop;
+use crate::decoders::tiff::Tag;
+use crate::decoders::writers::ifd::*;
+
+pub(crate) fn cfa(image: &RawImage, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+  let (area, data) = develop::cfa_data(image)?;
+  write(image, &area, 1, &data, Orientation::Normal, out)
+}
+
+pub(crate) fn rgb(image: &RawImage, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+  let (area, data) = develop::rgb_data(image)?;
+  write(image, &area, 3, &data, image.orientation, out)
+}
+
+fn write(image: &RawImage, area: &Rect, cpp: usize, data: &[u16], orientation: Orientation, out: &mut dyn Write) -> Result<(), RawLoaderError> {
+  let mut ifd = IfdWriter::new();
+  ifd.long(Tag::NewSubFileType, &[0]);
+  ifd.long(Tag::ImageWidth, &[area.width as u32]);
+  ifd.long(Tag::ImageLength, &[area.height as u32]);
+  ifd.short(Tag::BitsPerSample, &vec![16; cpp]);
+  ifd.short(Tag::Compression, &[1]);
+  ifd.short(Tag::PhotometricInt, &[if cpp == 3 { 2 } else { 1 }]);
+  ifd.ascii(Tag::Make, &image.make);
+  ifd.ascii(Tag::Model, &image.model);
+  ifd.short(Tag::Orientation, &[match orientation {
+    Orientation::Unknown => 1,
+    orientation => orientation.to_u16(),
+  }]);
+  ifd.short(Tag::SamplesPerPixel, &[cpp as u16]);
+  ifd.long(Tag::RowsPerStrip, &[area.height as u32]);
+  ifd.short(Tag::PlanarConfig, &[1]);
+  ifd.ascii(Tag::Software, concat!("rawloader ", env!("CARGO_PKG_VERSION")));
+  ifd.strips(vec![data.iter().flat_map(|v| v.to_le_bytes()).collect()]);
+  out.write_all(&write_tiff(vec![ifd]))?;
+  Ok(())
+}
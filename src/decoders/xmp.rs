@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::decoders::tiff::*;
+use crate::decoders::{RawImage, Rect};
+
+/// Editing metadata from an XMP packet, either embedded in the file or from a sidecar
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Xmp {
+  /// star rating (xmp:Rating), usually 0 to 5 with -1 for rejected
+  pub rating: Option<i32>,
+  /// color label (xmp:Label)
+  pub label: Option<String>,
+  /// title (dc:title)
+  pub title: Option<String>,
+  /// all the simple properties in the packet by their qualified name (e.g., "xmp:Rating"),
+  /// with list values joined by ", "
+  pub properties: BTreeMap<String,String>,
+}
+
+impl Xmp {
+  /// Parse an XMP packet. This is not a full XML parser, it only understands the usual
+  /// layout of XMP packets with properties either as attributes of rdf:Description or as
+  /// elements with simple text or rdf:Alt/Seq/Bag lists.
+  ///
+  /// # Example
+  /// ```
+  /// use rawloader::Xmp;
+  /// let xmp = Xmp::parse(r#"<x:xmpmeta><rdf:RDF><rdf:Description xmp:Rating="4">
+  ///   <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Sunset</rdf:li></rdf:Alt></dc:title>
+  ///   </rdf:Description></rdf:RDF></x:xmpmeta>"#);
+  /// assert_eq!(xmp.rating, Some(4));
+  /// assert_eq!(xmp.title, Some("Sunset".to_string()));
+  /// ```
+  pub fn parse(packet: &str) -> Xmp {
+    let mut properties: BTreeMap<String,String> = BTreeMap::new();
+    let mut stack: Vec<String> = Vec::new();
+    let mut pos = 0;
+
+    while let Some(start) = packet[pos..].find('<') {
+      let start = pos + start;
+      let text = &packet[pos..start];
+      let end = match packet[start..].find('>') {
+        Some(end) => start + end,
+        None => break,
+      };
+      let tag = &packet[start+1..end];
+      pos = end + 1;
+
+      if !text.trim().is_empty() {
+        if let Some(key) = Self::text_key(&stack) {
+          let val = unescape(text.trim());
+          properties.entry(key).and_modify(|v| {v.push_str(", "); v.push_str(&val)}).or_insert(val.clone());
+        }
+      }
+
+      if tag.starts_with('?') || tag.starts_with('!') {
+        continue
+      } else if let Some(name) = tag.strip_prefix('/') {
+        // Pop up to and including the matching element
+        if let Some(idx) = stack.iter().rposition(|n| n == name.trim()) {
+          stack.truncate(idx);
+        }
+      } else {
+        let selfclosing = tag.ends_with('/');
+        let tag = tag.trim_end_matches('/');
+        let name = tag.split_whitespace().next().unwrap_or("").to_string();
+        for (key, val) in attributes(tag) {
+          if name == "rdf:Description" && !key.starts_with("xmlns") && !key.starts_with("rdf:") {
+            properties.insert(key, val);
+          }
+        }
+        if !selfclosing {
+          stack.push(name);
+        }
+      }
+    }
+
+    Xmp {
+      rating: properties.get("xmp:Rating").and_then(|v| v.parse::<f32>().ok()).map(|v| v as i32),
+      label: properties.get("xmp:Label").cloned(),
+      title: properties.get("dc:title").cloned(),
+      properties,
+    }
+  }
+
+  /// Add the properties of another packet on top of these ones, as when combining the packet
+  /// embedded in the file with a sidecar
+  pub fn merge(&mut self, other: &Xmp) {
+    for (key, val) in &other.properties {
+      self.properties.insert(key.clone(), val.clone());
+    }
+    if other.rating.is_some() { self.rating = other.rating; }
+    if other.label.is_some() { self.label = other.label.clone(); }
+    if other.title.is_some() { self.title = other.title.clone(); }
+  }
+
+  /// Read the sidecar of a raw file, trying both the raw file name with the extension replaced
+  /// (IMG_0001.xmp) and with .xmp appended (IMG_0001.CR2.xmp) as different tools use each
+  pub fn from_sidecar(path: &Path) -> Option<Xmp> {
+    let mut appended = path.as_os_str().to_owned();
+    appended.push(".xmp");
+    let candidates = [path.with_extension("xmp"), path.with_extension("XMP"), appended.into()];
+    candidates.iter()
+      .filter_map(|p| fs::read(p).ok())
+      .map(|data| Xmp::parse(&String::from_utf8_lossy(&data)))
+      .next()
+  }
+
+  pub(crate) fn from_tiff(tiff: &TiffIFD) -> Option<Xmp> {
+    tiff.find_entry(Tag::Xmp).map(|entry| Xmp::parse(&String::from_utf8_lossy(entry.get_data())))
+  }
+
+  // Which property some text belongs to, either the element it's directly in or the one
+  // holding the rdf:Alt/Seq/Bag list it's an item of
+  fn text_key(stack: &[String]) -> Option<String> {
+    let len = stack.len();
+    let key = if len >= 3 && stack[len-1] == "rdf:li" {
+      &stack[len-3]
+    } else if len >= 1 {
+      &stack[len-1]
+    } else {
+      return None
+    };
+    if key.contains(':') && !key.starts_with("rdf:") && !key.starts_with("x:") {
+      Some(key.clone())
+    } else {
+      None
+    }
+  }
+}
+
+// Write the metadata of an image as attributes of a single rdf:Description
+pub(crate) fn generate(image: &RawImage) -> String {
+  let exif = &image.exif;
+  let mut props: Vec<(&str, String)> = vec![
+    ("tiff:Make", escape(&image.make)),
+    ("tiff:Model", escape(&image.model)),
+    ("tiff:ImageWidth", image.width.to_string()),
+    ("tiff:ImageLength", image.height.to_string()),
+  ];
+  if image.orientation.to_u16() != 0 {
+    props.push(("tiff:Orientation", image.orientation.to_u16().to_string()));
+  }
+
+  if let Some(time) = exif.capture_time {
+    props.push(("exif:DateTimeOriginal", time.to_string()));
+  }
+  if let Some(time) = exif.exposure_time {
+    props.push(("exif:ExposureTime", if time > 0.0 && time < 1.0 {
+      format!("1/{}", (1.0 / time).round())
+    } else {
+      rational(time)
+    }));
+  }
+  if let Some(aperture) = exif.aperture {
+    props.push(("exif:FNumber", rational(aperture)));
+  }
+  if let Some(iso) = exif.iso {
+    props.push(("exifEX:PhotographicSensitivity", iso.to_string()));
+  }
+  if let Some(bias) = exif.exposure_bias {
+    props.push(("exif:ExposureBiasValue", rational(bias)));
+  }
+  if let Some(focal) = exif.focal_length {
+    props.push(("exif:FocalLength", rational(focal)));
+  }
+  if let Some(mode) = exif.metering_mode {
+    props.push(("exif:MeteringMode", mode.to_u16().to_string()));
+  }
+  if let Some(program) = exif.exposure_program {
+    if program.to_u16() != 0 {
+      props.push(("exif:ExposureProgram", program.to_u16().to_string()));
+    }
+  }
+  if let Some(ref serial) = exif.serial {
+    props.push(("exifEX:BodySerialNumber", escape(serial)));
+  }
+
+  if let Some(ref lens) = exif.lens {
+    if let Some(ref make) = lens.make {
+      props.push(("exifEX:LensMake", escape(make)));
+    }
+    if let Some(ref model) = lens.model {
+      props.push(("exifEX:LensModel", escape(model)));
+      props.push(("aux:Lens", escape(model)));
+    }
+    let ranges = [lens.min_focal_length, lens.max_focal_length, lens.max_aperture_at_min_focal,
+                  lens.max_aperture_at_max_focal];
+    if ranges.iter().all(|v| v.is_some()) {
+      let vals: Vec<String> = ranges.iter().map(|v| rational(v.unwrap())).collect();
+      props.push(("aux:LensInfo", vals.join(" ")));
+    }
+  }
+
+  if let Some(ref gps) = exif.gps {
+    if let Some(lat) = gps.latitude {
+      props.push(("exif:GPSLatitude", coordinate(lat, 'N', 'S')));
+    }
+    if let Some(lon) = gps.longitude {
+      props.push(("exif:GPSLongitude", coordinate(lon, 'E', 'W')));
+    }
+    if let Some(alt) = gps.altitude {
+      props.push(("exif:GPSAltitudeRef", if alt < 0.0 { "1" } else { "0" }.to_string()));
+      props.push(("exif:GPSAltitude", rational(alt.abs() as f32)));
+    }
+    if let Some(ref timestamp) = gps.timestamp {
+      props.push(("exif:GPSTimeStamp", escape(timestamp)));
+    }
+  }
+
+  if let Some(rating) = exif.rating {
+    props.push(("xmp:Rating", rating.to_string()));
+  }
+  if let Some(ref label) = exif.label {
+    props.push(("xmp:Label", escape(label)));
+  }
+
+  let crop = image.areas().display_crop;
+  if crop != Rect::new(0, 0, image.width, image.height) && image.width > 0 && image.height > 0 {
+    let (width, height) = (image.width as f64, image.height as f64);
+    props.push(("crs:HasCrop", "True".to_string()));
+    props.push(("crs:CropTop", format!("{:.6}", crop.y as f64 / height)));
+    props.push(("crs:CropLeft", format!("{:.6}", crop.x as f64 / width)));
+    props.push(("crs:CropBottom", format!("{:.6}", (crop.y + crop.height) as f64 / height)));
+    props.push(("crs:CropRight", format!("{:.6}", (crop.x + crop.width) as f64 / width)));
+    props.push(("crs:CropAngle", "0".to_string()));
+  }
+
+  let mut out = String::new();
+  out.push_str("<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n");
+  out.push_str("<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n");
+  out.push_str(" <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n");
+  out.push_str("  <rdf:Description rdf:about=\"\"\n");
+  for (prefix, ns) in NAMESPACES.iter() {
+    out.push_str(&format!("    xmlns:{}=\"{}\"\n", prefix, ns));
+  }
+  for (name, val) in props {
+    out.push_str(&format!("    {}=\"{}\"\n", name, val));
+  }
+  out.push_str("  />\n");
+  out.push_str(" </rdf:RDF>\n");
+  out.push_str("</x:xmpmeta>\n");
+  out.push_str("<?xpacket end=\"w\"?>\n");
+  out
+}
+
+const NAMESPACES: [(&str, &str);6] = [
+  ("tiff", "http://ns.adobe.com/tiff/1.0/"),
+  ("exif", "http://ns.adobe.com/exif/1.0/"),
+  ("exifEX", "http://cipa.jp/exif/1.0/"),
+  ("aux", "http://ns.adobe.com/exif/1.0/aux/"),
+  ("xmp", "http://ns.adobe.com/xap/1.0/"),
+  ("crs", "http://ns.adobe.com/camera-raw-settings/1.0/"),
+];
+
+// XMP rationals, with enough precision for the usual exposure values
+fn rational(val: f32) -> String {
+  for denom in [1, 10, 100].iter() {
+    let num = val * *denom as f32;
+    if (num - num.round()).abs() < 0.001 {
+      return format!("{}/{}", num.round() as i64, denom)
+    }
+  }
+  format!("{}/1000", (val * 1000.0).round() as i64)
+}
+
+// XMP GPS coordinates are degrees and decimal minutes followed by the direction
+fn coordinate(val: f64, positive: char, negative: char) -> String {
+  let dir = if val < 0.0 { negative } else { positive };
+  let val = val.abs();
+  format!("{},{:.6}{}", val.trunc(), val.fract() * 60.0, dir)
+}
+
+fn escape(val: &str) -> String {
+  val.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn attributes(tag: &str) -> Vec<(String,String)> {
+  let mut out = Vec::new();
+  let mut rest = match tag.find(char::is_whitespace) {
+    Some(idx) => &tag[idx..],
+    None => return out,
+  };
+  while let Some(eq) = rest.find('=') {
+    let key = rest[..eq].trim().to_string();
+    let after = rest[eq+1..].trim_start();
+    let quote = match after.chars().next() {
+      Some(q) if q == '"' || q == '\'' => q,
+      _ => break,
+    };
+    let val = match after[1..].find(quote) {
+      Some(end) => &after[1..end+1],
+      None => break,
+    };
+    out.push((key, unescape(val)));
+    rest = &after[val.len()+2..];
+  }
+  out
+}
+
+fn unescape(val: &str) -> String {
+  val.replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"")
+     .replace("&apos;", "'").replace("&amp;", "&")
+}
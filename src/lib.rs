@@ -34,6 +34,34 @@
 //!   }
 //! }
 //! ```
+//!
+//! # Malformed files
+//!
+//! Files that don't make sense give `RawLoaderError::Corrupt`, images too big for the limits
+//! of `RawLoader::builder()` or for the memory there is give `RawLoaderError::LimitExceeded`.
+//! Most of these errors are returned as such. The readers of the values of the file can't
+//! return them, so a file cut short panics inside the decode instead, and any panic there is
+//! caught and turned into an error. The size limits, cancellation and timeouts are always
+//! returned.
+//!
+//! Catching the panics needs unwinding, so the binary using this crate has to be built with
+//! the default `panic = "unwind"`. That's set in the profile of the final binary, the one in
+//! this crate's `Cargo.toml` doesn't apply to the crates that depend on it. With
+//! `panic = "abort"` a malformed file aborts the process. The panics are caught after the
+//! panic hook has run, so the default hook still prints them to stderr. The `fuzzing` feature
+//! stops catching the panics that are bugs, for the fuzz targets in `fuzz/` to find them.
+//!
+//! The modules for parsing the formats (`tiff`, `ljpeg`, `packed`) don't have that safety net
+//! and document what panics in them.
+//!
+//! # WebAssembly
+//!
+//! The crate builds for `wasm32-unknown-unknown` to decode files in a browser. There's no
+//! filesystem or threads there, so files are given with `decode_slice()`, decodes run in a
+//! single thread and `DecodeOptions::timeout` is ignored. The functions that take paths fail
+//! with `RawLoaderError::Io`, as does `decode_many()`. That target can't unwind so the panics
+//! the section above talks about abort instead, and some malformed files trap the module
+//! rather than returning an error.
 
 #![deny(
   missing_docs,
@@ -48,49 +76,68 @@
 use lazy_static::lazy_static;
 
 mod decoders;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "python")]
+pub mod python;
 pub use decoders::RawImage;
 pub use decoders::RawImageData;
+pub use decoders::{RawPixels, RawSamples};
+pub use decoders::DualGainImage;
+pub use decoders::RawMetadata;
+pub use decoders::rawref::{RawImageRef, SampleLayout};
+pub use decoders::thumbnail::{Thumbnail, ThumbnailData, EmbeddedImage, EmbeddedKind};
+pub use decoders::validate::{Problem, ProblemKind};
+pub use decoders::batch::DecodeMany;
+#[cfg(feature = "ndarray")] pub use decoders::arrays::RawArrayView;
+pub use decoders::tiff;
+pub use decoders::packed;
+pub use decoders::ljpeg;
+pub use decoders::writers::dng;
+pub use decoders::DecodeOptions;
+pub use decoders::RawDecodeParams;
+pub use decoders::capabilities::{FormatCapabilities, FormatInfo, SupportedCamera, formats};
 pub use decoders::Orientation;
+pub use decoders::SensorGeometry;
+pub use decoders::RenderingHints;
+pub use decoders::{Rect, ImageAreas};
 pub use decoders::cfa::CFA;
+pub use decoders::cfa::CFAColor;
+pub use decoders::cfa::CFAPattern;
+pub use decoders::exif::Exif;
+pub use decoders::exif::MeteringMode;
+pub use decoders::exif::{ExposureProgram, Flash, FlashMode};
+pub use decoders::exif::GpsInfo;
+pub use decoders::exif::Timestamp;
+pub use decoders::lens::LensInfo;
+pub use decoders::lens::{register_lens, lens_name};
+pub use decoders::xmp::Xmp;
+pub use decoders::opcodes::{DngOpcodes, DngOpcode, Operation, OpcodeArea};
+pub use decoders::opcodes::{WarpRectilinear, WarpFisheye, FixVignetteRadial, BadPixelList, GainMap};
+pub use decoders::corrections::{LensCorrections, CorrectionSource};
+pub use decoders::color::ColorMatrices;
+pub use decoders::noise::NoiseModel;
+pub use decoders::dualpixel::{DualPixelImage, DisparityMap};
+pub use decoders::masks::SemanticMask;
+pub use decoders::calibration::{SensorCalibration, SensorDefect, CalibrationCurve, FlatField};
+pub use decoders::makernotes::{Makernote, WbPreset, WbIlluminant, AfPoint, DriveMode, Bracketing, CameraLevel};
+pub use decoders::makernotes::{CanonMakernote, NikonMakernote, SonyMakernote, OlympusMakernote};
+pub use decoders::makernotes::{PanasonicMakernote, PanasonicDistortion, FujiMakernote, PentaxMakernote};
 #[doc(hidden)] pub use decoders::Buffer;
-#[doc(hidden)] pub use decoders::RawLoader;
+pub use decoders::RawLoader;
+pub use decoders::Decoder;
+pub use decoders::{RawLoaderBuilder, Verbosity, SubImage, DecodeMode};
+pub use decoders::{DEFAULT_MAX_PIXELS, DEFAULT_MAX_DIMENSION, DEFAULT_MAX_TIFF_ENTRIES};
+pub use decoders::RawLoaderError;
+pub use decoders::DecodeWarning;
 
 lazy_static! {
   static ref LOADER: RawLoader = RawLoader::new();
 }
 
 use std::path::Path;
-use std::error::Error;
-use std::fmt;
 use std::io::Read;
 
-/// Error type for any reason for the decode to fail
-#[derive(Debug)]
-pub struct RawLoaderError {
-  msg: String,
-}
-
-impl fmt::Display for RawLoaderError {
-  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(f, "RawLoaderError: \"{}\"", self.msg)
-  }
-}
-
-impl Error for RawLoaderError {
-  // Implement description so that older versions of rust still work
-  fn description(&self) -> &str {
-    "description() is deprecated; use Display"
-  }
-}
-
-impl RawLoaderError {
-  fn new(msg: String) -> Self {
-    Self {
-      msg,
-    }
-  }
-}
-
 /// Take a path to a raw file and return a decoded image or an error
 ///
 /// # Example
@@ -101,7 +148,7 @@ impl RawLoaderError {
 /// };
 /// ```
 pub fn decode_file<P: AsRef<Path>>(path: P) -> Result<RawImage,RawLoaderError> {
-  LOADER.decode_file(path.as_ref()).map_err(|err| RawLoaderError::new(err))
+  LOADER.decode_file(path.as_ref())
 }
 
 /// Take a readable source and return a decoded image or an error
@@ -115,7 +162,307 @@ pub fn decode_file<P: AsRef<Path>>(path: P) -> Result<RawImage,RawLoaderError> {
 /// };
 /// ```
 pub fn decode(reader: &mut dyn Read) -> Result<RawImage,RawLoaderError> {
-  LOADER.decode(reader, false).map_err(|err| RawLoaderError::new(err))
+  LOADER.decode(reader, false)
+}
+
+/// Take a raw file held in memory and return a decoded image or an error
+///
+/// # Example
+/// ```rust,ignore
+/// // bytes of a file the user dropped on a web page
+/// let image = rawloader::decode_slice(&bytes).unwrap();
+/// ```
+pub fn decode_slice(data: &[u8]) -> Result<RawImage,RawLoaderError> {
+  LOADER.decode_slice(data)
+}
+
+/// Take a path to a raw file and return a decoded image with some optional processing applied
+///
+/// # Example
+/// ```rust,ignore
+/// let options = rawloader::DecodeOptions { xmp_sidecar: true, ..Default::default() };
+/// let image = rawloader::decode_file_with_options("path/to/your/file.RAW", &options).unwrap();
+/// ```
+pub fn decode_file_with_options<P: AsRef<Path>>(path: P, options: &DecodeOptions) -> Result<RawImage,RawLoaderError> {
+  LOADER.decode_file_with_options(path.as_ref(), options)
+}
+
+/// Take a path to a raw file and decode it without blocking the async runtime. Needs the
+/// `tokio` feature.
+///
+/// # Example
+/// ```rust,ignore
+/// let image = rawloader::decode_file_async("path/to/your/file.RAW").await?;
+/// ```
+#[cfg(feature = "tokio")]
+pub async fn decode_file_async<P: AsRef<Path>>(path: P) -> Result<RawImage,RawLoaderError> {
+  LOADER.decode_file_async(path.as_ref()).await
+}
+
+/// Take a list of paths to raw files and decode them in parallel, returning the images as
+/// they're done. See `RawLoader::decode_many()`.
+///
+/// # Example
+/// ```rust,ignore
+/// for (path, image) in rawloader::decode_many(&paths, &rawloader::DecodeOptions::default()).unwrap() {
+///   match image {
+///     Ok(image) => println!("{}: {}x{}", path.display(), image.width, image.height),
+///     Err(e) => eprintln!("{}: {}", path.display(), e),
+///   }
+/// }
+/// ```
+pub fn decode_many<I, P>(paths: I, options: &DecodeOptions) -> Result<DecodeMany,RawLoaderError>
+  where I: IntoIterator<Item=P>, P: AsRef<Path> {
+  LOADER.decode_many(paths, options)
+}
+
+/// Take a readable source and return a decoded image with some optional processing applied
+///
+/// # Example
+/// ```rust,ignore
+/// let mut file = File::open(path).unwrap();
+/// let options = rawloader::DecodeOptions { fix_bad_pixels: true, ..Default::default() };
+/// let image = rawloader::decode_with_options(&mut file, &options).unwrap();
+/// ```
+pub fn decode_with_options(reader: &mut dyn Read, options: &DecodeOptions) -> Result<RawImage,RawLoaderError> {
+  LOADER.decode_with_options(reader, false, options)
+}
+
+/// Take a readable source and find out what can be extracted from it without decoding it
+///
+/// # Example
+/// ```rust,ignore
+/// let mut file = File::open(path).unwrap();
+/// let caps = rawloader::capabilities(&mut file).unwrap();
+/// if caps.multi_frame {
+///   ... offer to pick a frame ...
+/// }
+/// ```
+pub fn capabilities(reader: &mut dyn Read) -> Result<&'static FormatCapabilities,RawLoaderError> {
+  LOADER.capabilities(reader)
+}
+
+/// Take the contents of a file and find its format, camera and size without decoding it
+///
+/// # Example
+/// ```rust,ignore
+/// let data = std::fs::read(path).unwrap();
+/// let info = rawloader::identify(&data);
+/// if let Some(format) = info.format {
+///   println!("{} file from a {:?} {:?}", format.format, info.make, info.model);
+/// }
+/// ```
+pub fn identify(data: &[u8]) -> FormatInfo {
+  LOADER.identify(data)
+}
+
+/// List all the cameras rawloader supports
+///
+/// # Example
+/// ```
+/// for cam in rawloader::supported_cameras() {
+///   println!("{} {} {:?}", cam.clean_make, cam.clean_model, cam.modes);
+/// }
+/// ```
+pub fn supported_cameras() -> Vec<SupportedCamera> {
+  LOADER.supported_cameras()
+}
+
+/// Check if files from a camera can be decoded, by the make and model as found in the EXIF
+/// or the clean ones of `supported_cameras()`
+///
+/// # Example
+/// ```
+/// assert!(rawloader::is_supported("Canon", "Canon EOS 5D Mark II"));
+/// ```
+pub fn is_supported(make: &str, model: &str) -> bool {
+  LOADER.is_supported(make, model)
+}
+
+/// Capabilities of the formats that use a file extension, to check a file before reading it
+///
+/// # Example
+/// ```
+/// let caps = rawloader::capabilities_for_extension("RAF");
+/// assert!(caps.iter().all(|c| c.dual_gain));
+/// ```
+pub fn capabilities_for_extension(extension: &str) -> Vec<&'static FormatCapabilities> {
+  decoders::capabilities::for_extension(extension)
+}
+
+/// Take a source that can only be read once, like stdin or a network stream, and return the
+/// image with only its metadata, reading no more than needed for most TIFF based formats
+///
+/// # Example
+/// ```rust,ignore
+/// let image = rawloader::decode_stream_dummy(&mut std::io::stdin()).unwrap();
+/// println!("{} {} {}x{}", image.clean_make, image.clean_model, image.width, image.height);
+/// ```
+pub fn decode_stream_dummy(reader: &mut dyn Read) -> Result<RawImage,RawLoaderError> {
+  LOADER.decode_stream(reader, true)
+}
+
+/// Take a path to a raw file and return its metadata without decoding the image data
+///
+/// # Example
+/// ```rust,ignore
+/// let meta = rawloader::probe_file(path).unwrap();
+/// println!("{:?} {} {} {}x{} {}", meta.format.map(|f| f.format), meta.clean_make, meta.clean_model, meta.width, meta.height, meta.cfa);
+/// ```
+pub fn probe_file<P: AsRef<Path>>(path: P) -> Result<RawMetadata,RawLoaderError> {
+  LOADER.probe_file(path.as_ref())
+}
+
+/// Take a path to a raw file and return its smallest embedded preview without decoding the raw
+///
+/// # Example
+/// ```rust,ignore
+/// if let Some(thumb) = rawloader::thumbnail(path).unwrap() {
+///   match thumb.data {
+///     rawloader::ThumbnailData::Jpeg(jpeg) => std::fs::write("thumb.jpg", jpeg).unwrap(),
+///     rawloader::ThumbnailData::Rgb(rgb) => show(thumb.width, thumb.height, &rgb),
+///   }
+/// }
+/// ```
+pub fn thumbnail<P: AsRef<Path>>(path: P) -> Result<Option<Thumbnail>,RawLoaderError> {
+  LOADER.thumbnail(path.as_ref())
+}
+
+/// Take a path to a raw file and check its structure and that it decodes, for archive integrity
+/// checks. Returns the problems found, an empty list when there are none.
+///
+/// # Example
+/// ```rust,ignore
+/// for problem in rawloader::validate(path).unwrap() {
+///   println!("{}: {:?} {}", path, problem.kind, problem);
+/// }
+/// ```
+pub fn validate<P: AsRef<Path>>(path: P) -> Result<Vec<Problem>,RawLoaderError> {
+  LOADER.validate_file(path.as_ref())
+}
+
+/// Take a path to a raw file and return its largest embedded JPEG preview
+///
+/// # Example
+/// ```rust,ignore
+/// if let Some(preview) = rawloader::preview(path).unwrap() {
+///   if let rawloader::ThumbnailData::Jpeg(jpeg) = preview.data {
+///     std::fs::write("preview.jpg", jpeg).unwrap();
+///   }
+/// }
+/// ```
+pub fn preview<P: AsRef<Path>>(path: P) -> Result<Option<Thumbnail>,RawLoaderError> {
+  LOADER.preview(path.as_ref())
+}
+
+/// Take a path to a raw file and write it out as a DNG, with the EXIF data, makernote and
+/// embedded previews of the original
+///
+/// # Example
+/// ```rust,ignore
+/// rawloader::convert_to_dng("path/to/your/file.NEF", "path/to/your/file.dng").unwrap();
+/// ```
+pub fn convert_to_dng<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> Result<(),RawLoaderError> {
+  LOADER.convert_to_dng(input.as_ref(), output.as_ref())
+}
+
+/// Take a readable source and list the previews and raw images stored in it
+///
+/// # Example
+/// ```rust,ignore
+/// for image in rawloader::embedded_images(&mut file).unwrap() {
+///   println!("{:?} {}x{} at {} ({} bytes)", image.kind, image.width, image.height, image.offset, image.length);
+/// }
+/// ```
+pub fn embedded_images(reader: &mut dyn Read) -> Result<Vec<EmbeddedImage>,RawLoaderError> {
+  LOADER.embedded_images(reader)
+}
+
+/// Take a readable source and return its metadata without decoding the image data
+pub fn probe(reader: &mut dyn Read) -> Result<RawMetadata,RawLoaderError> {
+  LOADER.probe(reader)
+}
+
+/// Take a readable source and return one of the raw images of files that have several
+///
+/// # Example
+/// ```rust,ignore
+/// let mut file = File::open(path).unwrap();
+/// let params = rawloader::RawDecodeParams { image_index: 1 };
+/// let image = rawloader::decode_with_params(&mut file, &params).unwrap();
+/// println!("Image 1 of {}", image.image_count);
+/// ```
+pub fn decode_with_params(reader: &mut dyn Read, params: &RawDecodeParams) -> Result<RawImage,RawLoaderError> {
+  LOADER.decode_with_params(reader, false, &DecodeOptions::default(), params)
+}
+
+/// Take a readable source and decode the image data into a buffer of the right size, reusing
+/// the memory of the previous decode. See `RawLoader::decode_into()`.
+///
+/// # Example
+/// ```rust,ignore
+/// let mut out = vec![0u16; width*height];
+/// for path in paths {
+///   let image = rawloader::decode_into(&mut File::open(path).unwrap(), &mut out).unwrap();
+///   process(&image, &out);
+/// }
+/// ```
+pub fn decode_into(reader: &mut dyn Read, out: &mut [u16]) -> Result<RawImage,RawLoaderError> {
+  LOADER.decode_into(reader, out)
+}
+
+/// Same as `decode_into()` for floating point output, see `RawLoader::decode_into_f32()`
+pub fn decode_into_f32(reader: &mut dyn Read, out: &mut [f32]) -> Result<RawImage,RawLoaderError> {
+  LOADER.decode_into_f32(reader, out)
+}
+
+/// Take a file already in memory and return its image data as a reference into it instead
+/// of a decoded copy, for files stored as 16 bit samples. See `RawLoader::decode_ref()`.
+///
+/// # Example
+/// ```rust,ignore
+/// let input = std::fs::read(path).unwrap();
+/// let image = rawloader::decode_ref(&input).unwrap();
+/// let first: Vec<u16> = image.row(0).collect();
+/// ```
+pub fn decode_ref(input: &[u8]) -> Result<RawImageRef<'_>,RawLoaderError> {
+  LOADER.decode_ref(input)
+}
+
+/// Take a readable source and return all of its raw images, see `RawLoader::decode_all()`
+///
+/// # Example
+/// ```rust,ignore
+/// let frames = rawloader::decode_all(&mut file).unwrap();
+/// println!("{} frames of {}x{}", frames.len(), frames[0].width, frames[0].height);
+/// ```
+pub fn decode_all(reader: &mut dyn Read) -> Result<Vec<RawImage>,RawLoaderError> {
+  LOADER.decode_all(reader)
+}
+
+/// Take a readable source from a camera with dual gain readout and return both frames
+///
+/// # Example
+/// ```rust,ignore
+/// let mut file = File::open(path).unwrap();
+/// let frames = rawloader::decode_dual_gain(&mut file).unwrap();
+/// let merged = frames.merge();
+/// ```
+pub fn decode_dual_gain(reader: &mut dyn Read) -> Result<DualGainImage,RawLoaderError> {
+  LOADER.decode_dual_gain(reader, false)
+}
+
+/// Take a readable source from a Canon Dual Pixel raw and return the normal image along with
+/// the images of each half of the photosites
+///
+/// # Example
+/// ```rust,ignore
+/// let mut file = File::open(path).unwrap();
+/// let frames = rawloader::decode_dual_pixel(&mut file).unwrap();
+/// let disparity = frames.disparity(64).unwrap();
+/// ```
+pub fn decode_dual_pixel(reader: &mut dyn Read) -> Result<DualPixelImage,RawLoaderError> {
+  LOADER.decode_dual_pixel(reader, false)
 }
 
 // Used to force lazy_static initializations. Useful for fuzzing.
@@ -128,11 +475,11 @@ pub fn force_initialization() {
 // with all their TIFF and other crazyness
 #[doc(hidden)]
 pub fn decode_unwrapped(reader: &mut dyn Read) -> Result<RawImageData,RawLoaderError> {
-  LOADER.decode_unwrapped(reader).map_err(|err| RawLoaderError::new(err))
+  LOADER.decode_unwrapped(reader)
 }
 
 // Used for fuzzing everything but the decoders themselves
 #[doc(hidden)]
 pub fn decode_dummy(reader: &mut dyn Read) -> Result<RawImage,RawLoaderError> {
-  LOADER.decode(reader, true).map_err(|err| RawLoaderError::new(err))
+  LOADER.decode(reader, true)
 }
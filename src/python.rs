@@ -0,0 +1,123 @@
+//! Python module, with the images as numpy arrays and their metadata as dicts. Needs the
+//! `python` feature and is built with maturin from `pyproject.toml`:
+//!
+//! ```sh
+//! maturin develop --release
+//! ```
+//!
+//! # Example
+//! ```python
+//! import rawloader
+//!
+//! data, meta = rawloader.decode_file("photo.nef")
+//! print(meta["clean_make"], meta["clean_model"], data.shape, data.dtype)
+//! top, right, bottom, left = meta["crops"]
+//! cropped = data[top:data.shape[0]-bottom, left:data.shape[1]-right]
+//! ```
+//!
+//! `decode_file()` and `decode()` return the image data as a numpy array of `uint16`, or
+//! `float32` for floating point raws, of shape `(height, width)`, or `(height, width, cpp)`
+//! for images with more than one component per pixel. `metadata_file()` returns just the
+//! dict without decoding the image data. Files that can't be decoded raise
+//! `rawloader.RawLoaderError`, and `OSError` when they can't be read.
+
+// The #[pyfunction] wrappers convert the errors of PyResult into themselves
+#![allow(clippy::useless_conversion)]
+
+use std::path::PathBuf;
+
+use numpy::{PyArray1, PyArrayMethods};
+use pyo3::exceptions::PyOSError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::{RawImage, RawImageData, LOADER};
+
+mod exceptions {
+  #![allow(missing_docs, unexpected_cfgs)]
+  pyo3::create_exception!(rawloader, RawLoaderError, pyo3::exceptions::PyException,
+    "A file that couldn't be decoded");
+}
+pub use self::exceptions::RawLoaderError;
+
+fn error(err: crate::RawLoaderError) -> PyErr {
+  match err {
+    crate::RawLoaderError::Io(err) => PyOSError::new_err(err.to_string()),
+    err => RawLoaderError::new_err(err.to_string()),
+  }
+}
+
+fn metadata<'py>(py: Python<'py>, image: &RawImage) -> PyResult<Bound<'py, PyDict>> {
+  let meta = PyDict::new_bound(py);
+  meta.set_item("make", &image.make)?;
+  meta.set_item("model", &image.model)?;
+  meta.set_item("clean_make", &image.clean_make)?;
+  meta.set_item("clean_model", &image.clean_model)?;
+  meta.set_item("width", image.width)?;
+  meta.set_item("height", image.height)?;
+  meta.set_item("cpp", image.cpp)?;
+  meta.set_item("wb_coeffs", image.wb_coeffs.to_vec())?;
+  meta.set_item("whitelevels", image.whitelevels.to_vec())?;
+  meta.set_item("blacklevels", image.blacklevels.to_vec())?;
+  meta.set_item("xyz_to_cam", image.xyz_to_cam.iter().map(|row| row.to_vec()).collect::<Vec<_>>())?;
+  meta.set_item("crops", image.crops.to_vec())?;
+  meta.set_item("cfa", &image.cfa.name)?;
+  meta.set_item("orientation", image.orientation.to_u16())?;
+  meta.set_item("image_count", image.image_count)?;
+  meta.set_item("warnings", image.warnings.iter().map(|w| w.to_string()).collect::<Vec<_>>())?;
+  Ok(meta)
+}
+
+// Moves the image data into a numpy array of the image's shape
+fn data(py: Python, image: &mut RawImage) -> PyResult<PyObject> {
+  let shape = match image.cpp {
+    1 => vec![image.height, image.width],
+    cpp => vec![image.height, image.width, cpp],
+  };
+  Ok(match image.data {
+    RawImageData::Integer(ref mut data) =>
+      PyArray1::from_vec_bound(py, std::mem::take(data)).reshape(shape)?.into_any().unbind(),
+    RawImageData::Float(ref mut data) =>
+      PyArray1::from_vec_bound(py, std::mem::take(data)).reshape(shape)?.into_any().unbind(),
+  })
+}
+
+fn result(py: Python, image: Result<RawImage, crate::RawLoaderError>) -> PyResult<(PyObject, Bound<PyDict>)> {
+  let mut image = image.map_err(error)?;
+  let meta = metadata(py, &image)?;
+  Ok((data(py, &mut image)?, meta))
+}
+
+/// Decodes the file at a path, returning the image data and the metadata
+#[pyfunction]
+fn decode_file(py: Python, path: PathBuf) -> PyResult<(PyObject, Bound<PyDict>)> {
+  let image = py.allow_threads(|| LOADER.decode_file(&path));
+  result(py, image)
+}
+
+/// Decodes a file held in memory, returning the image data and the metadata
+#[pyfunction]
+fn decode<'py>(py: Python<'py>, data: &[u8]) -> PyResult<(PyObject, Bound<'py, PyDict>)> {
+  let image = py.allow_threads(|| LOADER.decode_slice(data));
+  result(py, image)
+}
+
+/// Reads the metadata of the file at a path without decoding the image data
+#[pyfunction]
+fn metadata_file(py: Python, path: PathBuf) -> PyResult<Bound<PyDict>> {
+  let image = py.allow_threads(|| {
+    let file = std::fs::File::open(&path)?;
+    LOADER.decode(&mut std::io::BufReader::new(file), true)
+  }).map_err(error)?;
+  metadata(py, &image)
+}
+
+/// The `rawloader` Python module
+#[pymodule]
+fn rawloader(py: Python, module: &Bound<PyModule>) -> PyResult<()> {
+  module.add_function(wrap_pyfunction!(decode_file, module)?)?;
+  module.add_function(wrap_pyfunction!(decode, module)?)?;
+  module.add_function(wrap_pyfunction!(metadata_file, module)?)?;
+  module.add("RawLoaderError", py.get_type_bound::<RawLoaderError>())?;
+  Ok(())
+}